@@ -20,6 +20,7 @@
 #endif // #ifdef CONFIG_KSU_SUSFS
 
 #include "feature/kernel_umount.h"
+#include "uapi/supercall.h"
 #include "klog.h" // IWYU pragma: keep
 #include "compat/kernel_compat.h"
 #include "policy/allowlist.h"
@@ -89,21 +90,33 @@ static void ksu_sys_umount(const char *mnt, int flags)
 
 #endif
 
-void try_umount(const char *mnt, int flags)
+bool try_umount(const char *mnt, int flags)
 {
     struct path path;
     int err = kern_path(mnt, 0, &path);
     if (err) {
-        return;
+        return false;
     }
 
     if (path.dentry != path.mnt->mnt_root) {
         // it is not root mountpoint, maybe umounted by others already.
         path_put(&path);
-        return;
+        return false;
     }
 
     ksu_umount_mnt(mnt, &path, flags);
+    return true;
+}
+
+static bool umount_entry_applies(const struct mount_entry *entry, uid_t uid)
+{
+    if ((entry->cond & KSU_UMOUNT_COND_NON_ROOT_ONLY) && ksu_is_allow_uid(uid))
+        return false;
+
+    if ((entry->cond & KSU_UMOUNT_COND_USER_ID) && uid / PER_USER_RANGE != entry->user_id)
+        return false;
+
+    return true;
 }
 
 #ifdef CONFIG_KSU_SUSFS
@@ -167,8 +180,15 @@ int ksu_handle_umount(uid_t old_uid, uid_t new_uid)
 
     down_read(&mount_list_lock);
     list_for_each_entry (entry, &mount_list, list) {
+        if (!umount_entry_applies(entry, new_uid))
+            continue;
+
         pr_info("%s: unmounting: %s flags 0x%x\n", __func__, entry->umountable, entry->flags);
-        try_umount(entry->umountable, entry->flags);
+        if (try_umount(entry->umountable, entry->flags)) {
+            // statistics only, readers tolerate a stale last_uid
+            atomic_inc(&entry->hits);
+            WRITE_ONCE(entry->last_uid, new_uid);
+        }
     }
     up_read(&mount_list_lock);
 
@@ -2,6 +2,7 @@
 #define __KSU_H_KERNEL_UMOUNT
 
 #include <linux/types.h>
+#include <linux/atomic.h>
 #include <linux/list.h>
 #include <linux/rwsem.h>
 
@@ -15,11 +16,16 @@ int ksu_handle_umount(uid_t old_uid, uid_t new_uid);
 struct mount_entry {
     char *umountable;
     unsigned int flags;
+    atomic_t hits; // times it was unmounted for a process
+    int last_uid; // uid of the last process, -1 if never
+    u32 cond; // KSU_UMOUNT_COND_*, 0 = always
+    u32 user_id; // with KSU_UMOUNT_COND_USER_ID
     struct list_head list;
 };
 extern struct list_head mount_list;
 extern struct rw_semaphore mount_list_lock;
 
-void try_umount(const char *mnt, int flags);
+// returns true if mnt was a mount point and unmounting was attempted
+bool try_umount(const char *mnt, int flags);
 
 #endif
@@ -255,14 +255,36 @@ static bool is_uid_exist(uid_t uid, char *package, void *data)
     return exist;
 }
 
+// the appid packages.list reports for package, -ENOENT if it isn't installed
+static int package_app_id(const char *package, void *data)
+{
+    struct list_head *list = (struct list_head *)data;
+    struct uid_data *np;
+
+    list_for_each_entry (np, list, list) {
+        if (strncmp(np->package, package, KSU_MAX_PACKAGE_NAME) == 0)
+            return np->uid % PER_USER_RANGE;
+    }
+    return -ENOENT;
+}
+
+struct track_throne_result {
+    int ret;
+    u32 remapped;
+    u32 pruned;
+};
+
 struct track_throne_struct {
     unsigned int flags;
+    // filled in when not NULL, only for synchronous calls
+    struct track_throne_result *result;
 };
 
 void do_track_throne(void *data)
 {
     struct track_throne_struct *tts = (struct track_throne_struct *)data;
     unsigned int flags = tts->flags;
+    struct track_throne_result *result = tts->result;
     kfree(tts);
 
     struct list_head uid_list;
@@ -273,6 +295,7 @@ void do_track_throne(void *data)
     loff_t line_start = 0;
     char buf[KSU_MAX_PACKAGE_NAME];
     bool need_search = flags & TRACK_THRONE_FORCE_SEARCH_MGR;
+    int remapped, pruned;
 
     // init uid list head, bitmap
     unsigned long *curr_app_id_map = NULL;
@@ -287,6 +310,8 @@ void do_track_throne(void *data)
     curr_app_id_map = bitmap_zalloc(MAX_APP_ID, GFP_KERNEL);
     if (!curr_app_id_map) {
         pr_err("track_throne: failed to allocate curr_app_id_map\n");
+        if (result)
+            result->ret = -ENOMEM;
         return;
     }
 
@@ -294,6 +319,8 @@ void do_track_throne(void *data)
     if (!diff_map) {
         pr_err("track_throne: failed to allocate diff_map\n");
         bitmap_free(curr_app_id_map); // Free allocated memory when failed
+        if (result)
+            result->ret = -ENOMEM;
         return;
     }
     INIT_LIST_HEAD(&uid_list);
@@ -308,6 +335,8 @@ void do_track_throne(void *data)
         fp = filp_open(SYSTEM_PACKAGES_LIST_PATH, O_RDONLY, 0);
         if (IS_ERR(fp)) {
             pr_err("%s: open " SYSTEM_PACKAGES_LIST_PATH " failed: %ld\n", __func__, PTR_ERR(fp));
+            if (result)
+                result->ret = PTR_ERR(fp);
             goto out;
         }
     }
@@ -335,6 +364,8 @@ void do_track_throne(void *data)
         data = kzalloc(sizeof(struct uid_data), GFP_KERNEL);
         if (!data) {
             filp_close(fp, 0);
+            if (result)
+                result->ret = -ENOMEM;
             goto out;
         }
 
@@ -405,8 +436,14 @@ void do_track_throne(void *data)
     }
 
 prune:
+    // reinstalled packages keep their profiles under the new uid
+    remapped = ksu_remap_allowlist(package_app_id, &uid_list);
     // then prune the allowlist
-    ksu_prune_allowlist(is_uid_exist, &uid_list);
+    pruned = ksu_prune_allowlist(is_uid_exist, &uid_list);
+    if (result) {
+        result->remapped = remapped;
+        result->pruned = pruned;
+    }
 out:
     // free uid_list
     list_for_each_entry_safe (np, n, &uid_list, list) {
@@ -435,6 +472,24 @@ void track_throne(unsigned int flags)
     }
 }
 
+int ksu_rescan_uids(u32 *remapped, u32 *pruned)
+{
+    struct track_throne_result result = { 0 };
+    struct track_throne_struct *tts = kzalloc(sizeof(struct track_throne_struct), GFP_KERNEL);
+
+    if (!tts)
+        return -ENOMEM;
+    tts->flags = TRACK_THRONE_FORCE_SYNCHRONOUS;
+    tts->result = &result;
+    do_track_throne(tts);
+
+    if (result.ret)
+        return result.ret;
+    *remapped = result.remapped;
+    *pruned = result.pruned;
+    return 0;
+}
+
 // for 6.8- kernel, we can use LSM hook in manual hook
 // 6.8+, we use pkg_observer
 #if LINUX_VERSION_CODE < KERNEL_VERSION(6, 8, 0) && !defined(CONFIG_KSU_TRACEPOINT_HOOK)
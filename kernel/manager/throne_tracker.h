@@ -1,6 +1,8 @@
 #ifndef __KSU_H_THRONE_TRACKER
 #define __KSU_H_THRONE_TRACKER
 
+#include <linux/types.h>
+
 #define TRACK_THRONE_PRUNE_ONLY (1 << 0)
 #define TRACK_THRONE_FORCE_SEARCH_MGR (1 << 1)
 #define TRACK_THRONE_FROM_RENAMEAT (1 << 2)
@@ -18,10 +20,17 @@ static inline void ksu_throne_tracker_exit(void)
 static inline void track_throne(unsigned int flags)
 {
 }
+
+static inline int ksu_rescan_uids(u32 *remapped, u32 *pruned)
+{
+    return -EOPNOTSUPP;
+}
 #else
 void ksu_throne_tracker_init(void);
 void ksu_throne_tracker_exit(void);
 void track_throne(unsigned int flags);
+// re-read packages.list now, moving profiles of reinstalled packages to their new uid
+int ksu_rescan_uids(u32 *remapped, u32 *pruned);
 #endif
 
 #endif
@@ -40,6 +40,7 @@ static inline int __must_check ksu_kref_get_unless_zero(struct kref *kref)
 #include "manager/manager_identity.h"
 #include "infra/su_mount_ns.h"
 #include "compat/kernel_compat.h"
+#include "uapi/supercall.h"
 
 #define FILE_MAGIC 0x7f4b5355 // ' KSU', u32
 #define FILE_FORMAT_VERSION 4 // u32
@@ -346,6 +347,74 @@ bool ksu_uid_should_umount(uid_t uid)
 #endif
 }
 
+int ksu_set_uid_umount(uid_t uid, const char *key, u8 mode)
+{
+    // app_profile is too large for the stack
+    struct app_profile *profile;
+    struct app_profile *cur;
+    int ret;
+
+    profile = kzalloc(sizeof(*profile), GFP_KERNEL);
+    if (!profile)
+        return -ENOMEM;
+
+    rcu_read_lock();
+    cur = ksu_get_app_profile(uid);
+    rcu_read_unlock();
+    if (cur) {
+        // a profile of another package means the uid was reassigned, don't inherit it
+        if (!key[0] || !strcmp(cur->key, key))
+            memcpy(profile, cur, sizeof(*profile));
+        else
+            pr_info("set_uid_umount: uid %d moved from %s to %s\n", uid, cur->key, key);
+        ksu_put_app_profile(cur);
+    }
+
+    if (!profile->version) {
+        if (!key[0]) {
+            ret = -ENOENT;
+            goto out;
+        }
+        profile->version = KSU_APP_PROFILE_VER;
+        strncpy(profile->key, key, sizeof(profile->key) - 1);
+        profile->curr_uid = uid;
+        profile->nrp_config.use_default = true;
+    }
+
+    if (profile->allow_su) {
+        // modules are never unmounted for root apps
+        ret = -EINVAL;
+        goto out;
+    }
+
+    profile->nrp_config.use_default = mode == KSU_UID_UMOUNT_DEFAULT;
+    profile->nrp_config.profile.umount_modules = mode == KSU_UID_UMOUNT_ON;
+    ret = ksu_set_app_profile(profile);
+
+out:
+    kfree(profile);
+    return ret;
+}
+
+u8 ksu_get_uid_umount_mode(uid_t uid, bool *allow_su)
+{
+    struct app_profile *profile;
+    u8 mode = KSU_UID_UMOUNT_DEFAULT;
+
+    *allow_su = false;
+    rcu_read_lock();
+    profile = ksu_get_app_profile(uid);
+    rcu_read_unlock();
+    if (!profile)
+        return mode;
+
+    *allow_su = profile->allow_su;
+    if (!profile->allow_su && !profile->nrp_config.use_default)
+        mode = profile->nrp_config.profile.umount_modules ? KSU_UID_UMOUNT_ON : KSU_UID_UMOUNT_OFF;
+    ksu_put_app_profile(profile);
+    return mode;
+}
+
 void ksu_put_app_profile(struct app_profile *profile)
 {
     struct perm_data *p = container_of(profile, struct perm_data, profile);
@@ -575,16 +644,16 @@ void ksu_load_allow_list(void)
     ksu_run_in_init_if_possible(do_ksu_load_allow_list, NULL);
 }
 
-void ksu_prune_allowlist(bool (*is_uid_valid)(uid_t, char *, void *), void *data)
+int ksu_prune_allowlist(bool (*is_uid_valid)(uid_t, char *, void *), void *data)
 {
     struct perm_data *np = NULL;
     struct hlist_node *tmp;
     int i;
-    bool modified = false;
+    int pruned = 0;
 
     if (!ksu_boot_completed) {
         pr_info("boot not completed, skip prune\n");
-        return;
+        return 0;
     }
 
     mutex_lock(&allowlist_mutex);
@@ -594,7 +663,7 @@ void ksu_prune_allowlist(bool (*is_uid_valid)(uid_t, char *, void *), void *data
         // we use this uid for special cases, don't prune it!
         bool is_preserved_uid = uid == KSU_APP_PROFILE_PRESERVE_UID;
         if (!is_preserved_uid && !is_uid_valid(uid, package, data)) {
-            modified = true;
+            ++pruned;
             pr_info("prune uid: %d, package: %s\n", uid, package);
             hlist_del_rcu(&np->list);
             put_perm_data(np);
@@ -603,10 +672,82 @@ void ksu_prune_allowlist(bool (*is_uid_valid)(uid_t, char *, void *), void *data
     }
     mutex_unlock(&allowlist_mutex);
 
-    if (modified) {
+    if (pruned) {
+        smp_mb();
+        ksu_persistent_allow_list();
+    }
+    return pruned;
+}
+
+// must be called with allowlist_mutex held
+static bool uid_in_allowlist_locked(uid_t uid)
+{
+    struct perm_data *p;
+
+    hash_for_each_possible (allow_list, p, list, uid) {
+        if (p->profile.curr_uid == uid)
+            return true;
+    }
+    return false;
+}
+
+int ksu_remap_allowlist(int (*app_id_of)(const char *, void *), void *data)
+{
+    struct perm_data *np = NULL, *new_np;
+    struct hlist_node *tmp;
+    int i;
+    int remapped = 0;
+
+    if (!ksu_boot_completed) {
+        pr_info("boot not completed, skip remap\n");
+        return 0;
+    }
+
+    mutex_lock(&allowlist_mutex);
+    hash_for_each_safe (allow_list, i, tmp, np, list) {
+        uid_t uid = np->profile.curr_uid;
+        uid_t new_uid;
+        int app_id;
+
+        if (uid == KSU_APP_PROFILE_PRESERVE_UID)
+            continue;
+        // a package name says nothing about who signed it, a root grant must not follow it
+        // to whatever got installed under that name, leave it for the prune
+        if (np->profile.allow_su)
+            continue;
+        app_id = app_id_of(np->profile.key, data);
+        if (app_id < 0 || app_id == uid % PER_USER_RANGE)
+            continue;
+
+        // keep the user of the profile, only the appid changes on reinstall
+        new_uid = uid - uid % PER_USER_RANGE + app_id;
+        if (uid_in_allowlist_locked(new_uid)) {
+            pr_warn("remap uid: %d -> %d for %s, new uid has a profile already\n", uid, new_uid, np->profile.key);
+            continue;
+        }
+
+        new_np = kzalloc(sizeof(struct perm_data), GFP_KERNEL);
+        if (!new_np) {
+            pr_err("remap uid: alloc failed\n");
+            break;
+        }
+        kref_init(&new_np->ref);
+        memcpy(&new_np->profile, &np->profile, sizeof(np->profile));
+        new_np->profile.curr_uid = new_uid;
+
+        pr_info("remap uid: %d -> %d, package: %s\n", uid, new_uid, np->profile.key);
+        hlist_del_rcu(&np->list);
+        put_perm_data(np);
+        hash_add_rcu(allow_list, &new_np->list, new_uid);
+        ++remapped;
+    }
+    mutex_unlock(&allowlist_mutex);
+
+    if (remapped) {
         smp_mb();
         ksu_persistent_allow_list();
     }
+    return remapped;
 }
 
 void __init ksu_allowlist_init(void)
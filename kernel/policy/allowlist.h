@@ -30,7 +30,10 @@ bool __ksu_is_allow_uid_for_current(uid_t uid);
 
 bool ksu_get_allow_list(int *array, u16 length, u16 *out_length, u16 *out_total, bool allow);
 
-void ksu_prune_allowlist(bool (*is_uid_exist)(uid_t, char *, void *), void *data);
+// returns the number of profiles pruned
+int ksu_prune_allowlist(bool (*is_uid_exist)(uid_t, char *, void *), void *data);
+// move profiles to the appid app_id_of() reports for their package, returns the number moved
+int ksu_remap_allowlist(int (*app_id_of)(const char *, void *), void *data);
 void ksu_persistent_allow_list();
 
 // should be called with rcu read lock
@@ -40,6 +43,9 @@ void ksu_put_app_profile(struct app_profile *);
 int ksu_set_app_profile(struct app_profile *);
 
 bool ksu_uid_should_umount(uid_t uid);
+// change only the umount part of the uid's profile, key names the package owning the uid
+int ksu_set_uid_umount(uid_t uid, const char *key, u8 mode);
+u8 ksu_get_uid_umount_mode(uid_t uid, bool *allow_su);
 struct root_profile *ksu_get_root_profile(uid_t uid);
 // only used to put the root_profile returned by ksu_get_root_profile
 void ksu_put_root_profile(struct root_profile *);
@@ -135,6 +135,20 @@ void disable_seccomp(void)
 #endif
 }
 
+// diagnostic mode, profile restrictions are logged instead of applied. never persisted, every boot starts enforcing
+static bool ksu_profile_permissive = false;
+
+bool ksu_is_profile_permissive(void)
+{
+    return READ_ONCE(ksu_profile_permissive);
+}
+
+void ksu_set_profile_permissive(bool permissive)
+{
+    WRITE_ONCE(ksu_profile_permissive, permissive);
+    pr_info("app profile enforcement: %s\n", permissive ? "permissive" : "enforcing");
+}
+
 int escape_with_root_profile(void)
 {
     int ret = 0;
@@ -144,6 +158,9 @@ int escape_with_root_profile(void)
     struct task_struct *t;
     struct root_profile *profile = NULL;
     struct user_struct *new_user;
+    bool permissive = ksu_is_profile_permissive();
+    u64 caps;
+    const char *domain;
 
     cred = prepare_creds();
     if (!cred) {
@@ -162,6 +179,17 @@ int escape_with_root_profile(void)
     }
 
     profile = ksu_get_root_profile(ksu_get_uid_t(cred->uid));
+    caps = profile->capabilities.effective;
+    domain = profile->selinux_domain;
+    if (unlikely(permissive)) {
+        pr_info("permissive: uid %d profile uid=%d gid=%d groups=%u caps=0x%llx domain=%s ns=%d flags=0x%llx, "
+                "applying full caps and %s\n",
+                ksu_get_uid_t(cred->uid), profile->uid, profile->gid, profile->groups_count,
+                profile->capabilities.effective, profile->selinux_domain, profile->namespaces, profile->flags,
+                KERNEL_SU_CONTEXT);
+        caps = (1ULL << (CAP_LAST_CAP + 1)) - 1;
+        domain = KERNEL_SU_CONTEXT;
+    }
 
     ksu_get_uid_t(cred->uid) = profile->uid;
     ksu_get_uid_t(cred->suid) = profile->uid;
@@ -212,19 +240,19 @@ int escape_with_root_profile(void)
     // setup capabilities
     // we need CAP_DAC_READ_SEARCH becuase `/data/adb/ksud` is not accessible for non root process
     // we add it here but don't add it to cap_inhertiable, it would be dropped automaticly after exec!
-    u64 cap_for_ksud = profile->capabilities.effective | CAP_DAC_READ_SEARCH;
+    u64 cap_for_ksud = caps | CAP_DAC_READ_SEARCH;
     memcpy(&cred->cap_effective, &cap_for_ksud, sizeof(cred->cap_effective));
-    memcpy(&cred->cap_permitted, &profile->capabilities.effective, sizeof(cred->cap_permitted));
-    memcpy(&cred->cap_bset, &profile->capabilities.effective, sizeof(cred->cap_bset));
+    memcpy(&cred->cap_permitted, &caps, sizeof(cred->cap_permitted));
+    memcpy(&cred->cap_bset, &caps, sizeof(cred->cap_bset));
 
     setup_groups(profile, cred);
-    setup_selinux(profile->selinux_domain, cred);
+    setup_selinux(domain, cred);
 
     commit_creds(cred);
 
     disable_seccomp();
 
-    if (profile->flags & FLAG_KSU_NO_NEW_PRIVS) {
+    if ((profile->flags & FLAG_KSU_NO_NEW_PRIVS) && !permissive) {
         set_thread_flag(TIF_KSU_DISABLE_ESCAPE_WITH_ROOT);
     }
 
@@ -13,6 +13,10 @@
 int escape_with_root_profile(void);
 
 void disable_seccomp(void);
+
+// Permissive diagnostic mode, granted apps get full root and their profile is only logged
+bool ksu_is_profile_permissive(void);
+void ksu_set_profile_permissive(bool permissive);
 void escape_to_root_for_init(void);
 
 #endif
@@ -26,6 +26,7 @@
 #include "hook/tp_marker.h"
 #endif
 #include "feature/dynamic_manager.h"
+#include "manager/throne_tracker.h"
 #include "policy/app_profile.h"
 
 #ifdef CONFIG_KSU_TOOLKIT_SUPPORT
@@ -173,6 +174,10 @@ static int do_report_event(void __user *arg)
         on_module_mounted();
         break;
     }
+    case EVENT_SERVICES_STARTED: {
+        pr_info("services started\n");
+        break;
+    }
     default:
         break;
     }
@@ -425,6 +430,148 @@ static int do_set_app_profile(void __user *arg)
     return ret;
 }
 
+static int do_set_uid_umount(void __user *arg)
+{
+#ifdef CONFIG_KSU_DISABLE_POLICY
+    return -EOPNOTSUPP;
+#endif
+
+    struct ksu_set_uid_umount_cmd cmd;
+    int ret;
+
+    if (copy_from_user(&cmd, arg, sizeof(cmd))) {
+        pr_err("set_uid_umount: copy_from_user failed\n");
+        return -EFAULT;
+    }
+
+    if (cmd.mode > KSU_UID_UMOUNT_DEFAULT)
+        return -EINVAL;
+    cmd.key[sizeof(cmd.key) - 1] = '\0';
+
+    ret = ksu_set_uid_umount(cmd.uid, cmd.key, cmd.mode);
+    if (!ret) {
+        ksu_persistent_allow_list();
+#ifdef CONFIG_KSU_TRACEPOINT_HOOK
+        ksu_mark_running_process();
+#endif
+    }
+
+    return ret;
+}
+
+static int do_rescan_uids(void __user *arg)
+{
+    struct ksu_rescan_uids_cmd cmd = { 0 };
+    int ret;
+
+    ret = ksu_rescan_uids(&cmd.remapped, &cmd.pruned);
+    if (ret)
+        return ret;
+
+#ifdef CONFIG_KSU_TRACEPOINT_HOOK
+    if (cmd.remapped)
+        ksu_mark_running_process();
+#endif
+
+    if (copy_to_user(arg, &cmd, sizeof(cmd))) {
+        pr_err("rescan_uids: copy_to_user failed\n");
+        return -EFAULT;
+    }
+
+    return 0;
+}
+
+#if defined(CONFIG_KSU_SUSFS) && defined(KSU_COMPAT_HAS_SUSFS_STATE)
+// exported by SuSFS releases that can report their state
+extern void susfs_get_entry_counts(u32 *sus_path, u32 *sus_path_loop, u32 *sus_mount, u32 *sus_kstat,
+                                   u32 *open_redirect, u32 *sus_map);
+extern bool susfs_is_log_enabled;
+extern bool susfs_hide_sus_mnts_for_non_su_procs;
+extern bool susfs_is_avc_log_spoofing_enabled;
+
+static int do_get_susfs_state(void __user *arg)
+{
+    struct ksu_get_susfs_state_cmd cmd = { 0 };
+
+    susfs_get_entry_counts(&cmd.sus_path, &cmd.sus_path_loop, &cmd.sus_mount, &cmd.sus_kstat, &cmd.open_redirect,
+                           &cmd.sus_map);
+    cmd.log_enabled = susfs_is_log_enabled;
+    cmd.hide_sus_mnts_for_non_su_procs = susfs_hide_sus_mnts_for_non_su_procs;
+    cmd.avc_log_spoofing = susfs_is_avc_log_spoofing_enabled;
+
+    if (copy_to_user(arg, &cmd, sizeof(cmd))) {
+        pr_err("get_susfs_state: copy_to_user failed\n");
+        return -EFAULT;
+    }
+
+    return 0;
+}
+#else
+static int do_get_susfs_state(void __user *arg)
+{
+    return -EOPNOTSUPP;
+}
+#endif
+
+static int do_get_enforce_mode(void __user *arg)
+{
+    struct ksu_enforce_mode_cmd cmd = { 0 };
+
+    cmd.mode = ksu_is_profile_permissive() ? KSU_ENFORCE_MODE_PERMISSIVE : KSU_ENFORCE_MODE_ENFORCING;
+
+    if (copy_to_user(arg, &cmd, sizeof(cmd))) {
+        pr_err("get_enforce_mode: copy_to_user failed\n");
+        return -EFAULT;
+    }
+
+    return 0;
+}
+
+static int do_set_enforce_mode(void __user *arg)
+{
+    struct ksu_enforce_mode_cmd cmd;
+
+    if (copy_from_user(&cmd, arg, sizeof(cmd))) {
+        pr_err("set_enforce_mode: copy_from_user failed\n");
+        return -EFAULT;
+    }
+
+    if (cmd.mode != KSU_ENFORCE_MODE_ENFORCING && cmd.mode != KSU_ENFORCE_MODE_PERMISSIVE)
+        return -EINVAL;
+
+    // Going back to enforcing only takes privileges away, any root caller may do it.
+    // Permissive hands every granted app full root, a uid 0 app under a restricted
+    // profile must not be able to lift its own restrictions, so only the manager or init.
+    if (cmd.mode == KSU_ENFORCE_MODE_PERMISSIVE && !is_manager() && !is_init(current_cred())) {
+        pr_warn("set_enforce_mode: permissive denied for uid %d\n", ksu_get_uid_t(current_uid()));
+        return -EPERM;
+    }
+
+    ksu_set_profile_permissive(cmd.mode == KSU_ENFORCE_MODE_PERMISSIVE);
+    return 0;
+}
+
+static int do_get_uid_umount(void __user *arg)
+{
+    struct ksu_get_uid_umount_cmd cmd;
+    bool allow_su;
+
+    if (copy_from_user(&cmd, arg, sizeof(cmd))) {
+        return -EFAULT;
+    }
+
+    cmd.mode = ksu_get_uid_umount_mode(cmd.uid, &allow_su);
+    cmd.allow_su = allow_su;
+    cmd.umount = ksu_uid_should_umount(cmd.uid);
+
+    if (copy_to_user(arg, &cmd, sizeof(cmd))) {
+        pr_err("get_uid_umount: copy_to_user failed\n");
+        return -EFAULT;
+    }
+
+    return 0;
+}
+
 static int do_get_feature(void __user *arg)
 {
     struct ksu_get_feature_cmd cmd;
@@ -660,6 +807,9 @@ static int ksu_umount_list_getlist(struct ksu_manage_try_umount_cmd *cmd, bool l
 
     struct mount_entry *entry;
     char __user *user_buf = (char __user *)cmd->arg;
+    // flags carries the buffer size, 0 for callers that don't pass it
+    size_t capacity = cmd->flags;
+    size_t written = 0;
     size_t len;
 
     down_read(&mount_list_lock);
@@ -667,11 +817,18 @@ static int ksu_umount_list_getlist(struct ksu_manage_try_umount_cmd *cmd, bool l
     list_for_each_entry (entry, &mount_list, list) {
         len = strlen(entry->umountable) + 1; // +1 for \0
 
+        // the list grew since userspace asked for its size
+        if (capacity && written + len + (legacy ? 0 : sizeof(entry->flags)) > capacity) {
+            up_read(&mount_list_lock);
+            return -ENOSPC;
+        }
+
         if (copy_to_user(user_buf, entry->umountable, len)) {
             up_read(&mount_list_lock);
             return -EFAULT;
         }
         user_buf += len;
+        written += len;
 
         if (!legacy) {
             // non-legacy mode, includes flags too.
@@ -681,6 +838,7 @@ static int ksu_umount_list_getlist(struct ksu_manage_try_umount_cmd *cmd, bool l
                 return -EFAULT;
             }
             user_buf += sizeof(entry->flags);
+            written += sizeof(entry->flags);
         }
     }
 
@@ -688,9 +846,119 @@ static int ksu_umount_list_getlist(struct ksu_manage_try_umount_cmd *cmd, bool l
     return 0;
 }
 
+static int ksu_umount_list_add(const char *path, u32 flags, u32 cond, u32 user_id)
+{
+    struct mount_entry *new_entry, *entry;
+
+    new_entry = kzalloc(sizeof(*new_entry), GFP_KERNEL);
+    if (!new_entry)
+        return -ENOMEM;
+    new_entry->last_uid = -1;
+
+    new_entry->umountable = kstrdup(path, GFP_KERNEL);
+    if (!new_entry->umountable) {
+        kfree(new_entry);
+        return -ENOMEM;
+    }
+
+    down_write(&mount_list_lock);
+
+    // disallow dupes
+    // if this gets too many, we can consider moving this whole task to a kthread
+    list_for_each_entry (entry, &mount_list, list) {
+        if (!strcmp(entry->umountable, path)) {
+            pr_info("cmd_manage_try_umount: %s is already here!\n", path);
+            up_write(&mount_list_lock);
+            kfree(new_entry->umountable);
+            kfree(new_entry);
+            return -EEXIST;
+        }
+    }
+
+    new_entry->flags = flags;
+    new_entry->cond = cond;
+    new_entry->user_id = user_id;
+
+    // debug
+    list_add(&new_entry->list, &mount_list);
+    up_write(&mount_list_lock);
+    pr_info("cmd_manage_try_umount: %s added! cond: 0x%x\n", path, cond);
+
+    return 0;
+}
+
+static int ksu_umount_list_add_batch(struct ksu_manage_try_umount_cmd *cmd)
+{
+    struct ksu_umount_batch_entry __user *user_entries = (struct ksu_umount_batch_entry __user *)cmd->arg;
+    struct ksu_umount_batch_entry entry;
+    char buf[256];
+    int added = 0;
+    u32 i;
+
+    if (!cmd->arg)
+        return -EFAULT;
+    if (cmd->flags > KSU_UMOUNT_BATCH_MAX)
+        return -E2BIG;
+
+    for (i = 0; i < cmd->flags; i++) {
+        if (copy_from_user(&entry, &user_entries[i], sizeof(entry)))
+            return -EFAULT;
+
+        if (entry.add.cond & ~(KSU_UMOUNT_COND_NON_ROOT_ONLY | KSU_UMOUNT_COND_USER_ID)) {
+            entry.status = -EINVAL;
+        } else {
+            long len = strncpy_from_user(buf, (const char __user *)entry.add.path, sizeof(buf));
+            buf[sizeof(buf) - 1] = '\0';
+            if (len <= 0)
+                entry.status = -EFAULT;
+            else if (len == sizeof(buf))
+                entry.status = -ENAMETOOLONG;
+            else
+                entry.status = ksu_umount_list_add(buf, entry.add.flags, entry.add.cond, entry.add.user_id);
+        }
+
+        if (!entry.status)
+            added++;
+        if (copy_to_user(&user_entries[i].status, &entry.status, sizeof(entry.status)))
+            return -EFAULT;
+    }
+
+    return added;
+}
+
+static int ksu_umount_list_getstats(struct ksu_manage_try_umount_cmd *cmd)
+{
+    if (!cmd->arg)
+        return -EFAULT;
+
+    struct ksu_umount_stat __user *user_stats = (struct ksu_umount_stat __user *)cmd->arg;
+    struct ksu_umount_stat stat;
+    struct mount_entry *entry;
+    int count = 0;
+
+    down_read(&mount_list_lock);
+    list_for_each_entry (entry, &mount_list, list) {
+        if (count >= cmd->flags)
+            break;
+
+        memset(&stat, 0, sizeof(stat));
+        strncpy(stat.path, entry->umountable, sizeof(stat.path) - 1);
+        stat.hits = atomic_read(&entry->hits);
+        stat.last_uid = READ_ONCE(entry->last_uid);
+        if (copy_to_user(&user_stats[count], &stat, sizeof(stat))) {
+            up_read(&mount_list_lock);
+            return -EFAULT;
+        }
+        count++;
+    }
+    up_read(&mount_list_lock);
+
+    return count;
+}
+
 static int manage_try_umount(void __user *arg)
 {
-    struct mount_entry *new_entry, *entry, *tmp;
+    struct mount_entry *entry, *tmp;
     struct ksu_manage_try_umount_cmd cmd;
     char buf[256] = { 0 };
 
@@ -716,48 +984,38 @@ static int manage_try_umount(void __user *arg)
         long len = strncpy_from_user(buf, (const char __user *)cmd.arg, 256);
         if (len <= 0)
             return -EFAULT;
+        // never register a truncated path, it would unmount whatever it happens to match
+        if (len == sizeof(buf))
+            return -ENAMETOOLONG;
 
         buf[sizeof(buf) - 1] = '\0';
 
-        new_entry = kzalloc(sizeof(*new_entry), GFP_KERNEL);
-        if (!new_entry)
-            return -ENOMEM;
+        return ksu_umount_list_add(buf, cmd.flags, 0, 0);
+    }
 
-        new_entry->umountable = kstrdup(buf, GFP_KERNEL);
-        if (!new_entry->umountable) {
-            kfree(new_entry);
-            return -ENOMEM;
-        }
+    case KSU_UMOUNT_ADD_EXT: {
+        struct ksu_umount_add_ext ext;
 
-        down_write(&mount_list_lock);
+        if (copy_from_user(&ext, (const void __user *)cmd.arg, sizeof(ext)))
+            return -EFAULT;
 
-        // disallow dupes
-        // if this gets too many, we can consider moving this whole task to a kthread
-        list_for_each_entry (entry, &mount_list, list) {
-            if (!strcmp(entry->umountable, buf)) {
-                pr_info("cmd_manage_try_umount: %s is already here!\n", buf);
-                up_write(&mount_list_lock);
-                kfree(new_entry->umountable);
-                kfree(new_entry);
-                return -EEXIST;
-            }
-        }
+        if (ext.cond & ~(KSU_UMOUNT_COND_NON_ROOT_ONLY | KSU_UMOUNT_COND_USER_ID))
+            return -EINVAL;
 
-        // now check flags and add
-        // this also serves as a null check
-        if (cmd.flags)
-            new_entry->flags = cmd.flags;
-        else
-            new_entry->flags = 0;
+        long len = strncpy_from_user(buf, (const char __user *)ext.path, 256);
+        if (len <= 0)
+            return -EFAULT;
+        if (len == sizeof(buf))
+            return -ENAMETOOLONG;
 
-        // debug
-        list_add(&new_entry->list, &mount_list);
-        up_write(&mount_list_lock);
-        pr_info("cmd_manage_try_umount: %s added!\n", buf);
+        buf[sizeof(buf) - 1] = '\0';
 
-        return 0;
+        return ksu_umount_list_add(buf, ext.flags, ext.cond, ext.user_id);
     }
 
+    case KSU_UMOUNT_ADD_BATCH:
+        return ksu_umount_list_add_batch(&cmd);
+
     // this is just strcmp'd wipe anyway
     case KSU_UMOUNT_DEL: {
         long len = strncpy_from_user(buf, (const char __user *)cmd.arg, sizeof(buf) - 1);
@@ -800,6 +1058,10 @@ static int manage_try_umount(void __user *arg)
     case KSU_UMOUNT_GETLIST_NEW: {
         return ksu_umount_list_getlist(&cmd, false);
     }
+
+    case KSU_UMOUNT_GETSTATS: {
+        return ksu_umount_list_getstats(&cmd);
+    }
     default: {
         pr_err("cmd_manage_try_umount: invalid operation %u\n", cmd.mode);
         return -EINVAL;
@@ -985,6 +1247,31 @@ static int do_get_kernel_patch_implement(void __user *arg)
     return 0;
 }
 
+// 107. GET_CAPABILITIES - Get what this kernel supports
+static int do_get_capabilities(void __user *arg)
+{
+    struct ksu_get_capabilities_cmd cmd = { 0 };
+
+    cmd.caps = KSU_CAP_UMOUNT_LIST | KSU_CAP_UMOUNT_STATS | KSU_CAP_UMOUNT_COND | KSU_CAP_ROOT_PROFILE_FLAGS |
+               KSU_CAP_DYNAMIC_MANAGER | KSU_CAP_UMOUNT_BATCH | KSU_CAP_UID_UMOUNT | KSU_CAP_ENFORCE_MODE;
+#ifndef CONFIG_KSU_DISABLE_MANAGER
+    cmd.caps |= KSU_CAP_RESCAN_UIDS;
+#endif
+#ifdef CONFIG_KSU_SUSFS
+    cmd.caps |= KSU_CAP_SUSFS;
+#endif
+#if defined(CONFIG_KSU_SUSFS) && defined(KSU_COMPAT_HAS_SUSFS_STATE)
+    cmd.caps |= KSU_CAP_SUSFS_STATE;
+#endif
+
+    if (copy_to_user(arg, &cmd, sizeof(cmd))) {
+        pr_err("get_capabilities: copy_to_user failed\n");
+        return -EFAULT;
+    }
+
+    return 0;
+}
+
 #ifdef CONFIG_KSU_SUSFS
 int ksu_handle_susfs_cmd(unsigned int cmd, void __user **arg)
 {
@@ -1367,6 +1654,48 @@ static const struct ksu_ioctl_cmd_map ksu_ioctl_handlers[] = {
         .handler = do_get_kernel_patch_implement, 
         .perm_check = manager_or_root 
     },
+    { 
+        .cmd = KSU_IOCTL_GET_CAPABILITIES, 
+        .name = "GET_CAPABILITIES", 
+        .handler = do_get_capabilities, 
+        .perm_check = manager_or_root 
+    },
+    { 
+        .cmd = KSU_IOCTL_SET_UID_UMOUNT, 
+        .name = "SET_UID_UMOUNT", 
+        .handler = do_set_uid_umount, 
+        .perm_check = manager_or_root 
+    },
+    { 
+        .cmd = KSU_IOCTL_GET_UID_UMOUNT, 
+        .name = "GET_UID_UMOUNT", 
+        .handler = do_get_uid_umount, 
+        .perm_check = manager_or_root 
+    },
+    { 
+        .cmd = KSU_IOCTL_RESCAN_UIDS, 
+        .name = "RESCAN_UIDS", 
+        .handler = do_rescan_uids, 
+        .perm_check = manager_or_root 
+    },
+    { 
+        .cmd = KSU_IOCTL_GET_SUSFS_STATE, 
+        .name = "GET_SUSFS_STATE", 
+        .handler = do_get_susfs_state, 
+        .perm_check = manager_or_root 
+    },
+    {
+        .cmd = KSU_IOCTL_GET_ENFORCE_MODE,
+        .name = "GET_ENFORCE_MODE",
+        .handler = do_get_enforce_mode,
+        .perm_check = manager_or_root
+    },
+    {
+        .cmd = KSU_IOCTL_SET_ENFORCE_MODE,
+        .name = "SET_ENFORCE_MODE",
+        .handler = do_set_enforce_mode,
+        .perm_check = manager_or_root
+    },
     { 
         .cmd = 0, 
         .name = NULL, 
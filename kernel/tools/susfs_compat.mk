@@ -6,3 +6,10 @@ ifeq ($(shell grep -q "ksu_selinux_hide_running" $(srctree)/security/selinux/hoo
 $(info -- $(REPO_NAME)/susfs_feature_check: selinux_hide manual hook found)
 ccflags-y += -DKSU_COMPAT_HAS_SUSFS_FEATURE_SELINUX_HIDE
 endif
+
+# susfs state query
+# only susfs releases exporting susfs_get_entry_counts can report how many entries are registered
+ifeq ($(shell grep -q "susfs_get_entry_counts" $(srctree)/fs/susfs.c; echo $$?),0)
+$(info -- $(REPO_NAME)/susfs_feature_check: susfs_get_entry_counts found)
+ccflags-y += -DKSU_COMPAT_HAS_SUSFS_STATE
+endif
@@ -307,6 +307,16 @@ NativeBridge(uidShouldUmount, jboolean, jint uid) {
 	return uid_should_umount(uid);
 }
 
+NativeBridge(setUidUmount, jboolean, jint uid, jstring key, jint mode) {
+	if (!key) {
+		return false;
+	}
+	const char *cKey = GetEnvironment()->GetStringUTFChars(env, key, nullptr);
+	bool result = set_uid_umount(uid, cKey, (uint8_t) mode);
+	GetEnvironment()->ReleaseStringUTFChars(env, key, cKey);
+	return result;
+}
+
 NativeBridgeNP(isSuEnabled, jboolean) {
 	return is_su_enabled();
 }
@@ -171,6 +171,14 @@ bool set_app_profile(const struct app_profile *profile) {
     return legacy_set_app_profile(profile);
 }
 
+bool set_uid_umount(int uid, const char *key, uint8_t mode) {
+    struct ksu_set_uid_umount_cmd cmd = {};
+    cmd.uid = uid;
+    cmd.mode = mode;
+    strncpy(cmd.key, key, sizeof(cmd.key) - 1);
+    return ksuctl(KSU_IOCTL_SET_UID_UMOUNT, &cmd) == 0;
+}
+
 int get_app_profile(struct app_profile *profile) {
     struct ksu_get_app_profile_cmd cmd = {.profile = *profile};
     int ret = ksuctl(KSU_IOCTL_GET_APP_PROFILE, &cmd);
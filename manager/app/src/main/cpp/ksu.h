@@ -34,6 +34,8 @@ void get_full_version(char* buff);
 
 bool set_app_profile(const struct app_profile *profile);
 
+bool set_uid_umount(int uid, const char *key, uint8_t mode);
+
 int get_app_profile(struct app_profile* profile);
 
 void get_hook_type(char *buff);
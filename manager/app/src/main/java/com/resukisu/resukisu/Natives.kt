@@ -103,6 +103,18 @@ object Natives {
 
     external fun uidShouldUmount(uid: Int): Boolean
 
+    const val UID_UMOUNT_OFF = 0
+    const val UID_UMOUNT_ON = 1
+    const val UID_UMOUNT_DEFAULT = 2
+
+    /**
+     * Change only whether modules are unmounted for [uid], leaving the rest of its profile alone.
+     * @param key the package owning [uid]
+     * @param mode one of [UID_UMOUNT_OFF], [UID_UMOUNT_ON], [UID_UMOUNT_DEFAULT]
+     * @return false if failed, e.g. on kernels without this call.
+     */
+    external fun setUidUmount(uid: Int, key: String, mode: Int): Boolean
+
     /**
      * Get the profile of the given package.
      * @param key usually the package name
@@ -186,7 +186,7 @@ fun AppProfileScreen(
                             return@launch
                         }
                     }
-                    if (!Natives.setAppProfile(it)) {
+                    if (!setUmountOnly(profile, it) && !Natives.setAppProfile(it)) {
                         snackBarHost.showSnackbar(failToUpdateAppProfile.format(appGroup.uid))
                     } else {
                         profile = it
@@ -197,6 +197,22 @@ fun AppProfileScreen(
     }
 }
 
+/**
+ * Apply [new] with the lightweight umount call when it differs from [old] only in whether
+ * modules are unmounted, returns false when the full profile has to be set instead.
+ */
+private fun setUmountOnly(old: Natives.Profile, new: Natives.Profile): Boolean {
+    if (new.allowSu || old.allowSu) return false
+    val umountOnly = old.copy(nonRootUseDefault = new.nonRootUseDefault, umountModules = new.umountModules)
+    if (umountOnly != new) return false
+    val mode = when {
+        new.nonRootUseDefault -> Natives.UID_UMOUNT_DEFAULT
+        new.umountModules -> Natives.UID_UMOUNT_ON
+        else -> Natives.UID_UMOUNT_OFF
+    }
+    return Natives.setUidUmount(new.currentUid, new.name, mode)
+}
+
 @OptIn(ExperimentalMaterial3ExpressiveApi::class)
 @Composable
 private fun AppProfileInner(
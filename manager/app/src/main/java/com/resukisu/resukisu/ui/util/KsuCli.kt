@@ -162,6 +162,119 @@ suspend fun isOfficialSignature(): Boolean = withContext(Dispatchers.IO) {
         .orEmpty() == "size: 0x377, hash: d3469712b6214462764a1d8d3e5cbe1d6819a0b629791b9f4101867821f1df64"
 }
 
+data class KernelFeatures(
+    /** Raw KSU_CAP_* bitmap, null if the kernel predates the query */
+    val capabilities: Long? = null,
+    val knownCapabilities: List<String> = emptyList(),
+    val unknownCapabilityBits: List<Int> = emptyList(),
+    val features: List<String> = emptyList(),
+    val unknownFeatures: List<Int> = emptyList(),
+) {
+    fun hasCapability(name: String): Boolean = knownCapabilities.contains(name)
+}
+
+suspend fun getKernelFeatures(): KernelFeatures? = withContext(Dispatchers.IO) {
+    val shell = getRootShell()
+    val result = shell.newJob()
+        .add("${getKsuDaemonPath()} debug features --json")
+        .to(ArrayList<String>(), null)
+        .exec()
+    if (!result.isSuccess) return@withContext null
+
+    runCatching {
+        val obj = JSONObject(result.out.joinToString("\n"))
+        fun strings(key: String) = obj.optJSONArray(key)?.let { arr ->
+            List(arr.length()) { arr.getString(it) }
+        }.orEmpty()
+        fun ints(key: String) = obj.optJSONArray(key)?.let { arr ->
+            List(arr.length()) { arr.getInt(it) }
+        }.orEmpty()
+        KernelFeatures(
+            capabilities = if (obj.isNull("capabilities")) null else obj.getLong("capabilities"),
+            knownCapabilities = strings("known_capabilities"),
+            unknownCapabilityBits = ints("unknown_capability_bits"),
+            features = strings("features"),
+            unknownFeatures = ints("unknown_features"),
+        )
+    }.getOrNull()
+}
+
+/**
+ * Make the kernel re-read packages.list after an app was installed or removed, so root
+ * grants and profiles follow a reinstalled app to its new uid.
+ *
+ * @return the number of profiles moved to a new uid and the number pruned, null on failure
+ */
+fun rescanUids(): Pair<Int, Int>? {
+    val result = getRootShell().newJob()
+        .add("${getKsuDaemonPath()} uid rescan --json")
+        .to(ArrayList<String>(), null)
+        .exec()
+    if (!result.isSuccess) return null
+    return runCatching {
+        val obj = JSONObject(result.out.joinToString("\n"))
+        obj.getInt("remapped") to obj.getInt("pruned")
+    }.getOrNull()
+}
+
+data class DriverHeartbeat(
+    /** ok, failing or replaced */
+    val state: String,
+    val checked: String,
+    val lastOk: String?,
+    val failures: Int,
+)
+
+/** What the ksud heartbeat daemon last saw, null if it never ran */
+suspend fun getDriverHeartbeat(): DriverHeartbeat? = withContext(Dispatchers.IO) {
+    val shell = getRootShell()
+    val result = shell.newJob()
+        .add("${getKsuDaemonPath()} debug heartbeat --json")
+        .to(ArrayList<String>(), null)
+        .exec()
+    if (!result.isSuccess) return@withContext null
+
+    runCatching {
+        val obj = JSONObject(result.out.joinToString("\n"))
+        DriverHeartbeat(
+            state = obj.getString("state"),
+            checked = obj.getString("checked"),
+            lastOk = if (obj.isNull("last_ok")) null else obj.getString("last_ok"),
+            failures = obj.getInt("failures"),
+        )
+    }.getOrNull()
+}
+
+data class DriverCheck(
+    val name: String,
+    /** null if the check couldn't be run */
+    val passed: Boolean?,
+    val detail: String,
+    val hint: String?,
+)
+
+suspend fun getDriverCheck(): List<DriverCheck>? = withContext(Dispatchers.IO) {
+    val shell = getRootShell()
+    val result = shell.newJob()
+        .add("${getKsuDaemonPath()} debug driver-check --json")
+        .to(ArrayList<String>(), null)
+        .exec()
+    if (!result.isSuccess) return@withContext null
+
+    runCatching {
+        val arr = JSONArray(result.out.joinToString("\n"))
+        List(arr.length()) {
+            val obj = arr.getJSONObject(it)
+            DriverCheck(
+                name = obj.getString("name"),
+                passed = if (obj.isNull("passed")) null else obj.getBoolean("passed"),
+                detail = obj.getString("detail"),
+                hint = if (obj.isNull("hint")) null else obj.getString("hint"),
+            )
+        }
+    }.getOrNull()
+}
+
 suspend fun getFeatureStatus(feature: String): String = withContext(Dispatchers.IO) {
     val shell = getRootShell()
     val out = shell.newJob()
@@ -196,6 +309,14 @@ fun listModules(): String {
     return out.joinToString("\n").ifBlank { "[]" }
 }
 
+fun getKernelIntegration(): String {
+    val shell = getRootShell()
+
+    val out = shell.newJob()
+        .add("${getKsuDaemonPath()} debug kernel-config").to(ArrayList(), null).exec().out
+    return out.joinToString("\n")
+}
+
 fun getModuleCount(): Int {
     val result = listModules()
     runCatching {
@@ -666,7 +787,7 @@ fun removeUmountConfigUmountPath(path: String): Boolean {
 
 fun listUmountConfigUmountPaths(): String {
     val shell = getRootShell()
-    val cmd = "${getKsuDaemonPath()} umount-config list"
+    val cmd = "${getKsuDaemonPath()} umount-config list --json"
     return try {
         runCmd(shell, cmd).trim()
     } catch (e: Exception) {
@@ -104,8 +104,13 @@ fun getBugreportFile(context: Context): File {
         pw.println("SafeMode: $safeMode")
         val lkmMode = Natives.isLkmMode
         pw.println("LKM: $lkmMode")
+        val hookType = Natives.getHookType()
+        pw.println("HookType: $hookType")
     }
 
+    val integrationFile = File(bugreportDir, "kernel_integration.txt")
+    integrationFile.writeText(getKernelIntegration())
+
     // modules
     val modulesFile = File(bugreportDir, "modules.json")
     modulesFile.writeText(listModules())
@@ -15,7 +15,8 @@
 #define KSU_FULL_VERSION_STRING 255
 
 // 2: allowlist v4 root profile flags
-static const __u32 KERNEL_SU_UAPI_VERSION = 2;
+// 3: KSU_IOCTL_GET_CAPABILITIES and the calls it gates (108-113), KSU_UMOUNT_GETLIST_NEW
+static const __u32 KERNEL_SU_UAPI_VERSION = 3;
 
 /* Magic numbers for reboot hook to install fd */
 DEFINE_KSU_UAPI_CONST(__u32, KSU_INSTALL_MAGIC1, 0xDEADBEEF)
@@ -28,6 +29,7 @@ struct ksu_become_daemon_cmd {
 DEFINE_KSU_UAPI_CONST(__u32, EVENT_POST_FS_DATA, 1)
 DEFINE_KSU_UAPI_CONST(__u32, EVENT_BOOT_COMPLETED, 2)
 DEFINE_KSU_UAPI_CONST(__u32, EVENT_MODULE_MOUNTED, 3)
+DEFINE_KSU_UAPI_CONST(__u32, EVENT_SERVICES_STARTED, 4)
 
 DEFINE_KSU_UAPI_CONST(__u32, KSU_GET_INFO_FLAG_LKM, (1U << 0))
 DEFINE_KSU_UAPI_CONST(__u32, KSU_GET_INFO_FLAG_MANAGER, (1U << 1))
@@ -149,7 +151,8 @@ struct ksu_get_sulog_fd_cmd {
 struct ksu_manage_try_umount_cmd {
     __aligned_u64 arg; /* char ptr, this is the mountpoint */
     __u32 flags; /* this is the flag we use for it */
-    // downstream: 107,200 = getsize old/new; 108,201 = getlist old/new
+    // downstream: 107,200 = getsize old/new; 108,201 = getlist old/new; 202 = getstats; 203 = add_ext;
+    // 204 = add_batch
     __u8 mode; /* denotes what to do with it 0:wipe_list 1:add_to_list 2:delete_entry */
 };
 
@@ -161,7 +164,37 @@ DEFINE_KSU_UAPI_CONST(__u8, KSU_UMOUNT_DEL, 2) /* delete entry, strcmp */
 DEFINE_KSU_UAPI_CONST(__u8, KSU_UMOUNT_GETSIZE_LEGACY, 107) // get list size (legacy)
 DEFINE_KSU_UAPI_CONST(__u8, KSU_UMOUNT_GETLIST_LEGACY, 108) // get list (legacy)
 DEFINE_KSU_UAPI_CONST(__u8, KSU_UMOUNT_GETSIZE_NEW, 200) // get list size (new (with flags))
+// getlist: flags = buffer size, -ENOSPC when the list no longer fits, 0 = unchecked (old callers)
 DEFINE_KSU_UAPI_CONST(__u8, KSU_UMOUNT_GETLIST_NEW, 201) // get list (new (with flags))
+// get per-entry stats into an array of ksu_umount_stat, flags = array capacity, returns the count
+DEFINE_KSU_UAPI_CONST(__u8, KSU_UMOUNT_GETSTATS, 202)
+
+struct ksu_umount_stat {
+    char path[256];
+    __u32 hits; /* times the entry was unmounted for a process */
+    __s32 last_uid; /* uid of the last such process, -1 if never */
+};
+// add entry with conditions, arg = struct ksu_umount_add_ext ptr
+DEFINE_KSU_UAPI_CONST(__u8, KSU_UMOUNT_ADD_EXT, 203)
+
+DEFINE_KSU_UAPI_CONST(__u32, KSU_UMOUNT_COND_NON_ROOT_ONLY, (1U << 0)) /* skip uids granted root */
+DEFINE_KSU_UAPI_CONST(__u32, KSU_UMOUNT_COND_USER_ID, (1U << 1)) /* only for uids of user_id */
+
+struct ksu_umount_add_ext {
+    __aligned_u64 path; /* char ptr, the mountpoint */
+    __u32 flags; /* umount flags */
+    __u32 cond; /* KSU_UMOUNT_COND_* */
+    __u32 user_id; /* android user, with KSU_UMOUNT_COND_USER_ID */
+};
+// add many entries at once, arg = struct ksu_umount_batch_entry array, flags = count
+// returns how many were added, each entry gets its own status
+DEFINE_KSU_UAPI_CONST(__u8, KSU_UMOUNT_ADD_BATCH, 204)
+DEFINE_KSU_UAPI_CONST(__u32, KSU_UMOUNT_BATCH_MAX, 64)
+
+struct ksu_umount_batch_entry {
+    struct ksu_umount_add_ext add; /* Input */
+    __s32 status; /* Output: 0 or -errno */
+};
 
 // Downstream supercall struct
 struct ksu_get_full_version_cmd {
@@ -179,7 +212,7 @@ DEFINE_KSU_UAPI_CONST(__u8, DYNAMIC_MANAGER_OP_SET_SYNCHRONOUS, 3)
 
 struct ksu_dynamic_manager_cmd {
     __u8 operation;
-    unsigned int size;
+    __u32 size;
     __u8 hash[64];
 };
 
@@ -203,6 +236,65 @@ struct ksu_get_kernel_patch_implement {
     __u8 type; // Output: Current Kernel Patch Implement
 };
 
+DEFINE_KSU_UAPI_CONST(__u64, KSU_CAP_UMOUNT_LIST, (1ULL << 0)) /* kernel umount list */
+DEFINE_KSU_UAPI_CONST(__u64, KSU_CAP_UMOUNT_STATS, (1ULL << 1)) /* KSU_UMOUNT_GETSTATS */
+DEFINE_KSU_UAPI_CONST(__u64, KSU_CAP_UMOUNT_COND, (1ULL << 2)) /* KSU_UMOUNT_ADD_EXT */
+DEFINE_KSU_UAPI_CONST(__u64, KSU_CAP_SUSFS, (1ULL << 3)) /* CONFIG_KSU_SUSFS */
+DEFINE_KSU_UAPI_CONST(__u64, KSU_CAP_ROOT_PROFILE_FLAGS, (1ULL << 4)) /* root_profile flags */
+DEFINE_KSU_UAPI_CONST(__u64, KSU_CAP_DYNAMIC_MANAGER, (1ULL << 5))
+DEFINE_KSU_UAPI_CONST(__u64, KSU_CAP_UMOUNT_BATCH, (1ULL << 6)) /* KSU_UMOUNT_ADD_BATCH */
+DEFINE_KSU_UAPI_CONST(__u64, KSU_CAP_UID_UMOUNT, (1ULL << 7)) /* KSU_IOCTL_{SET,GET}_UID_UMOUNT */
+DEFINE_KSU_UAPI_CONST(__u64, KSU_CAP_RESCAN_UIDS, (1ULL << 8)) /* KSU_IOCTL_RESCAN_UIDS */
+DEFINE_KSU_UAPI_CONST(__u64, KSU_CAP_SUSFS_STATE, (1ULL << 9)) /* KSU_IOCTL_GET_SUSFS_STATE */
+DEFINE_KSU_UAPI_CONST(__u64, KSU_CAP_ENFORCE_MODE, (1ULL << 10)) /* KSU_IOCTL_{GET,SET}_ENFORCE_MODE */
+
+DEFINE_KSU_UAPI_CONST(__u8, KSU_ENFORCE_MODE_ENFORCING, 0) /* App Profiles are applied */
+DEFINE_KSU_UAPI_CONST(__u8, KSU_ENFORCE_MODE_PERMISSIVE, 1) /* App Profiles are logged, granted apps get full root */
+
+DEFINE_KSU_UAPI_CONST(__u8, KSU_UID_UMOUNT_OFF, 0) /* never unmount modules */
+DEFINE_KSU_UAPI_CONST(__u8, KSU_UID_UMOUNT_ON, 1) /* always unmount modules */
+DEFINE_KSU_UAPI_CONST(__u8, KSU_UID_UMOUNT_DEFAULT, 2) /* follow the default non-root profile */
+
+struct ksu_set_uid_umount_cmd {
+    __u32 uid; // Input: app uid
+    __u8 mode; // Input: KSU_UID_UMOUNT_*
+    // Input: package of the uid, starts a new profile when the uid has none yet or its profile
+    // belongs to another package (the uid was reassigned after a reinstall)
+    char key[KSU_MAX_PACKAGE_NAME];
+};
+
+struct ksu_get_uid_umount_cmd {
+    __u32 uid; // Input: app uid
+    __u8 mode; // Output: KSU_UID_UMOUNT_*
+    __u8 allow_su; // Output: granted root, such uids are never unmounted
+    __u8 umount; // Output: whether modules are unmounted for the uid
+};
+
+struct ksu_rescan_uids_cmd {
+    __u32 remapped; // Output: non-root profiles moved to the new uid of their reinstalled package
+    __u32 pruned; // Output: profiles dropped because their package is gone or its root grant went stale
+};
+
+struct ksu_enforce_mode_cmd {
+    __u8 mode; // Input for set, output for get: KSU_ENFORCE_MODE_*
+};
+
+struct ksu_get_susfs_state_cmd {
+    __u32 sus_path; // Output: registered sus_path entries
+    __u32 sus_path_loop; // Output: registered sus_path_loop entries
+    __u32 sus_mount; // Output: mounts currently flagged as sus
+    __u32 sus_kstat; // Output: registered sus_kstat entries
+    __u32 open_redirect; // Output: registered open_redirect entries
+    __u32 sus_map; // Output: registered sus_map entries
+    __u8 log_enabled; // Output: enable_log toggle
+    __u8 hide_sus_mnts_for_non_su_procs; // Output: hide_sus_mnts_for_non_su_procs toggle
+    __u8 avc_log_spoofing; // Output: enable_avc_log_spoofing toggle
+};
+
+struct ksu_get_capabilities_cmd {
+    __u64 caps; // Output: KSU_CAP_* bits, newer kernels may set bits unknown to userspace
+};
+
 /* IOCTL command definitions */
 DEFINE_KSU_UAPI_CONST(__u32, KSU_IOCTL_GRANT_ROOT, _IOC(_IOC_NONE, 'K', 1, 0))
 DEFINE_KSU_UAPI_CONST(__u32, KSU_IOCTL_GET_INFO, _IOR('K', 2, struct ksu_get_info_cmd))
@@ -240,6 +332,13 @@ DEFINE_KSU_UAPI_CONST(__u32, KSU_IOCTL_DYNAMIC_MANAGER, _IOC(_IOC_READ | _IOC_WR
 // 104 = old get_managers, deprecated
 DEFINE_KSU_UAPI_CONST(__u32, KSU_IOCTL_GET_MANAGERS, _IOC(_IOC_READ | _IOC_WRITE, 'K', 105, 0))
 DEFINE_KSU_UAPI_CONST(__u32, KSU_IOCTL_GET_KERNEL_PATCH_IMPLEMENT, _IOC(_IOC_READ, 'K', 106, 0))
+DEFINE_KSU_UAPI_CONST(__u32, KSU_IOCTL_GET_CAPABILITIES, _IOC(_IOC_READ, 'K', 107, 0))
+DEFINE_KSU_UAPI_CONST(__u32, KSU_IOCTL_SET_UID_UMOUNT, _IOC(_IOC_WRITE, 'K', 108, 0))
+DEFINE_KSU_UAPI_CONST(__u32, KSU_IOCTL_GET_UID_UMOUNT, _IOC(_IOC_READ | _IOC_WRITE, 'K', 109, 0))
+DEFINE_KSU_UAPI_CONST(__u32, KSU_IOCTL_RESCAN_UIDS, _IOC(_IOC_READ, 'K', 110, 0))
+DEFINE_KSU_UAPI_CONST(__u32, KSU_IOCTL_GET_SUSFS_STATE, _IOC(_IOC_READ, 'K', 111, 0))
+DEFINE_KSU_UAPI_CONST(__u32, KSU_IOCTL_GET_ENFORCE_MODE, _IOC(_IOC_READ, 'K', 112, 0))
+DEFINE_KSU_UAPI_CONST(__u32, KSU_IOCTL_SET_ENFORCE_MODE, _IOC(_IOC_WRITE, 'K', 113, 0))
 // 200 = MANAGE_KPM,deprecated
 #undef DEFINE_KSU_UAPI_CONST
 #endif
@@ -0,0 +1,136 @@
+//! Compare the kernel allowlist with the copy persisted in /data/adb/ksu/.allowlist
+
+use std::{collections::BTreeMap, ffi::CStr, fs};
+
+use anyhow::{Context, Result, ensure};
+use serde::Serialize;
+
+use crate::{
+    android::{ksucalls, uapi, umount_config},
+    defs,
+};
+
+/// ' KSU', the magic the kernel writes at the start of the allowlist file
+const FILE_MAGIC: u32 = 0x7f4b_5355;
+/// The uid of the default non-root profile, not a real app
+const PRESERVE_UID: i32 = 9999;
+const PER_USER_RANGE: u32 = 100_000;
+
+fn profile_key(profile: &uapi::app_profile) -> String {
+    let mut key = profile.key;
+    key[key.len() - 1] = 0;
+    unsafe { CStr::from_ptr(key.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// The profiles persisted in the allowlist file
+fn read_persisted() -> Result<Vec<uapi::app_profile>> {
+    const PROFILE_SIZE: usize = std::mem::size_of::<uapi::app_profile>();
+
+    let buf = fs::read(defs::ALLOWLIST_PATH)
+        .with_context(|| format!("failed to read {}", defs::ALLOWLIST_PATH))?;
+    ensure!(buf.len() >= 8, "{} is truncated", defs::ALLOWLIST_PATH);
+    let magic = u32::from_ne_bytes([buf[0], buf[1], buf[2], buf[3]]);
+    let version = u32::from_ne_bytes([buf[4], buf[5], buf[6], buf[7]]);
+    ensure!(
+        magic == FILE_MAGIC,
+        "{} is not an allowlist",
+        defs::ALLOWLIST_PATH
+    );
+    // the kernel migrates older files when it loads them
+    ensure!(
+        version == uapi::KSU_APP_PROFILE_VER,
+        "{} has version {version}, expected {}",
+        defs::ALLOWLIST_PATH,
+        uapi::KSU_APP_PROFILE_VER
+    );
+
+    Ok(buf[8..]
+        .chunks_exact(PROFILE_SIZE)
+        .map(|record| unsafe { std::ptr::read_unaligned(record.as_ptr().cast()) })
+        .collect())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Entry {
+    Allow,
+    Deny,
+    Missing,
+}
+
+#[derive(Serialize)]
+struct Drift {
+    uid: u32,
+    package: Option<String>,
+    kernel: Entry,
+    persisted: Entry,
+}
+
+/// Print uids whose allowlist entry differs between the kernel and the persisted file
+pub fn diff(json: bool) -> Result<()> {
+    let persisted = read_persisted()?;
+    let managers = ksucalls::get_managers().unwrap_or_default();
+    let is_manager = |uid: u32| managers.iter().any(|m| m.appid == uid % PER_USER_RANGE);
+
+    let mut kernel = BTreeMap::new();
+    for uid in ksucalls::get_uid_list(false)? {
+        kernel.insert(uid, Entry::Deny);
+    }
+    for uid in ksucalls::get_uid_list(true)? {
+        kernel.insert(uid, Entry::Allow);
+    }
+
+    let mut profiles = BTreeMap::new();
+    for profile in persisted {
+        // the kernel leaves the default profile and managers out of its lists
+        if profile.curr_uid == PRESERVE_UID || is_manager(profile.curr_uid as u32) {
+            continue;
+        }
+        profiles.insert(profile.curr_uid as u32, profile);
+    }
+
+    let mut uids = kernel.keys().chain(profiles.keys()).collect::<Vec<_>>();
+    uids.sort_unstable();
+    uids.dedup();
+    let drift = uids
+        .into_iter()
+        .filter_map(|&uid| {
+            let kernel = kernel.get(&uid).copied().unwrap_or(Entry::Missing);
+            let profile = profiles.get(&uid);
+            let persisted = profile.map_or(Entry::Missing, |p| {
+                if p.allow_su {
+                    Entry::Allow
+                } else {
+                    Entry::Deny
+                }
+            });
+            (kernel != persisted).then(|| Drift {
+                uid,
+                package: profile
+                    .map(profile_key)
+                    .or_else(|| umount_config::package_of_uid(uid).ok().flatten()),
+                kernel,
+                persisted,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if json {
+        println!("{}", serde_json::to_string(&drift)?);
+    } else if drift.is_empty() {
+        println!("kernel and {} agree", defs::ALLOWLIST_PATH);
+    } else {
+        for d in &drift {
+            println!(
+                "uid {} ({}): kernel {:?}, persisted {:?}",
+                d.uid,
+                d.package.as_deref().unwrap_or("unknown package"),
+                d.kernel,
+                d.persisted
+            );
+        }
+    }
+    Ok(())
+}
@@ -2,14 +2,18 @@ use std::path::PathBuf;
 
 use android_logger::Config;
 use anyhow::{Context, Ok, Result};
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 use log::{LevelFilter, error, info};
 
 use crate::{
     android::{
-        debug, dynamic_manager, feature, init_event, ksucalls,
+        allowlist, debug, dynamic_manager, enforce, feature, heartbeat, init_event,
+        ksucalls::{self, UidUmountMode},
+        lkm,
         module::{self, module_config, regenerate_preinit_rc},
-        profile, sepolicy, su, sulog, susfs, uapi, umount_config, utils,
+        profile, sepolicy, su, sulog, susfs, uapi,
+        umount_config::{self, Condition, UmountFlags},
+        utils,
     },
     apk_sign, assets,
     boot_patch::{BootPatchArgs, BootRestoreArgs},
@@ -22,6 +26,10 @@ use crate::{
 struct Args {
     #[command(subcommand)]
     command: Commands,
+
+    /// Talk to kernels whose interface version this ksud doesn't support, for development
+    #[arg(long, global = true)]
+    skip_version_check: bool,
 }
 
 #[derive(clap::Subcommand, Debug)]
@@ -42,6 +50,10 @@ enum Commands {
     #[command(hide = true)]
     Sulogd,
 
+    /// Run the driver heartbeat daemon. Not for user, it is started at boot-completed.
+    #[command(hide = true)]
+    Heartbeatd,
+
     /// Trigger `boot-complete` event
     BootCompleted,
 
@@ -70,6 +82,9 @@ enum Commands {
 
     /// Manage auto apply user custom umount configs
     UmountConfig {
+        /// use this configuration file instead of the default, also settable with KSUD_UMOUNT_CONFIG
+        #[arg(long, global = true)]
+        config: Option<PathBuf>,
         #[command(subcommand)]
         command: UmountConfigOp,
     },
@@ -95,6 +110,12 @@ enum Commands {
     /// Unload KernelSU kernel module (LKM Only)
     Unload,
 
+    /// Inspect, load or unload the KernelSU kernel module
+    Lkm {
+        #[command(subcommand)]
+        command: LkmOp,
+    },
+
     /// Uninstall KernelSU modules and itself(LKM Only)
     Uninstall {
         #[arg(long, default_value_t = String::from("com.resukisu.resukisu"))]
@@ -153,6 +174,30 @@ enum Commands {
         #[command(subcommand)]
         command: Initrc,
     },
+
+    /// Manage the kernel's view of app uids
+    Uid {
+        #[command(subcommand)]
+        command: UidOp,
+    },
+
+    /// Change which manager the kernel trusts
+    Manager {
+        #[command(subcommand)]
+        command: ManagerOp,
+    },
+
+    /// Request safe mode for a later boot
+    Safemode {
+        #[command(subcommand)]
+        command: Safemode,
+    },
+
+    /// Print a shell completion script for ksud
+    Completions {
+        /// Shell to generate the completion script for
+        shell: clap_complete::Shell,
+    },
 }
 
 #[derive(clap::Subcommand, Debug)]
@@ -160,20 +205,183 @@ enum UmountConfigOp {
     /// Add an new umount config to configuration file
     Add {
         /// mount point path
-        mnt: String,
-        /// umount flags (default: 0, MNT_DETACH: 2)
-        #[arg(short, long, default_value = "0")]
-        flags: u32,
+        #[arg(required_unless_present_any = ["from_file", "stdin"])]
+        mnt: Option<String>,
+        /// add every `<path> [flags]` line of this file, registering them with the kernel
+        #[arg(long, conflicts_with_all = ["mnt", "stdin"])]
+        from_file: Option<PathBuf>,
+        /// add every `<path> [flags]` line read from stdin, registering them with the kernel
+        #[arg(long, conflicts_with = "mnt")]
+        stdin: bool,
+        /// umount flags, a number or names like detach|force (force, detach, expire, nofollow);
+        /// chosen from the mount type when omitted
+        #[arg(short, long)]
+        flags: Option<UmountFlags>,
+        /// also hide the mount with SuSFS from processes where it isn't unmounted
+        #[arg(long)]
+        susfs: bool,
+        /// only apply when this holds, e.g. "non_root_only && user_id = 0" (non_root_only, no_susfs, user_id = N)
+        #[arg(long)]
+        condition: Option<Condition>,
+        /// why the entry exists, kept in the configuration file and shown by list
+        #[arg(long)]
+        comment: Option<String>,
     },
     /// Delete an umount config from configuration file
     Del {
         /// mount point path
         mnt: String,
     },
-    /// Clear all auto apply umount config from configuration file
+    /// Move an umount config to another position, entries are applied in order
+    Move {
+        /// mount point path
+        mnt: String,
+        /// new zero-based position
+        index: usize,
+    },
+    /// Drop all configured entries from the kernel umount list and clear the configuration file
     Clear,
+    /// Show whether modules are unmounted for a package, as decided by its App Profile
+    Package {
+        /// package name
+        package: String,
+        /// change the setting for every user the package is installed for
+        #[arg(value_enum)]
+        mode: Option<PackageUmountMode>,
+        /// print the result as a JSON array
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show or change whether modules are unmounted for a uid, as decided by its App Profile
+    Uid {
+        /// app uid
+        uid: u32,
+        /// on, off, default (follow the default profile) or status
+        #[arg(value_enum, default_value = "status")]
+        mode: UidUmountArg,
+        /// print the result as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Add module mounts that no entry covers yet to the configuration
+    SyncModules {
+        /// also sync module mounts automatically at boot
+        #[arg(long, conflicts_with = "disable")]
+        enable: bool,
+        /// stop syncing module mounts at boot
+        #[arg(long)]
+        disable: bool,
+    },
+    /// Show which mounts of a process would be unmounted, without changing anything
+    Simulate {
+        /// process to inspect
+        #[arg(long, required_unless_present = "uid")]
+        pid: Option<u32>,
+        /// inspect any process running as this uid
+        #[arg(long)]
+        uid: Option<u32>,
+    },
+    /// Replay the configured umounts in a throwaway mount namespace and report each result
+    Test,
+    /// Remove entries whose path has been missing for several consecutive boots
+    Prune {
+        /// only list the entries that would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Regenerate the default entries for module mounts, user entries are kept
+    RestoreDefaults,
+    /// Export the umount configuration to a file
+    Export {
+        /// destination file
+        file: PathBuf,
+    },
+    /// Import an exported umount configuration and register its entries
+    Import {
+        /// exported configuration file
+        file: PathBuf,
+        /// keep existing entries that are not in the imported file (default)
+        #[arg(long, conflicts_with = "replace")]
+        merge: bool,
+        /// drop existing entries that are not in the imported file
+        #[arg(long)]
+        replace: bool,
+    },
+    /// Restore the configuration file from a backup and sync the kernel umount list with it
+    RestoreBackup {
+        /// backup to restore, 0 is the most recent
+        #[arg(long, default_value_t = 0)]
+        index: usize,
+    },
+    /// Sync the kernel umount list with the configuration file, reporting the changes
+    Reload {
+        /// clear the whole kernel list and register the configuration from scratch
+        #[arg(long)]
+        rebuild: bool,
+    },
+    /// Register all configured entries with the kernel now, as done at boot
+    Load {
+        /// abort on the first entry the kernel refuses
+        #[arg(long)]
+        strict: bool,
+    },
     /// List all configured auto apply umount configuration
-    List,
+    List {
+        /// list the entries the kernel currently has instead of the configuration file
+        #[arg(long)]
+        kernel: bool,
+        /// print the entries as a JSON array
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum PackageUmountMode {
+    Force,
+    Never,
+    Default,
+}
+
+impl From<PackageUmountMode> for UidUmountMode {
+    fn from(mode: PackageUmountMode) -> Self {
+        match mode {
+            PackageUmountMode::Force => Self::On,
+            PackageUmountMode::Never => Self::Off,
+            PackageUmountMode::Default => Self::Default,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum UidUmountArg {
+    On,
+    Off,
+    Default,
+    Status,
+}
+
+impl UidUmountArg {
+    const fn mode(self) -> Option<UidUmountMode> {
+        match self {
+            Self::On => Some(UidUmountMode::On),
+            Self::Off => Some(UidUmountMode::Off),
+            Self::Default => Some(UidUmountMode::Default),
+            Self::Status => None,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum SkipMountArg {
+    On,
+    Off,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum EnforceArg {
+    Status,
+    On,
 }
 
 #[derive(clap::Subcommand, Debug)]
@@ -246,8 +454,69 @@ enum Debug {
     /// Launch sulogd daemon manually
     Sulogd,
 
+    /// Show whether the driver still answers the heartbeat daemon
+    Heartbeat {
+        /// print the result as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
     /// Get kernel info
     Info,
+
+    /// Get how the kernel is hooked and whether it runs as LKM
+    HookType {
+        /// print the result as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// List the capabilities and features the kernel supports
+    Features {
+        /// print the result as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show whether this boot is in safe mode and why
+    Safemode,
+
+    /// Compare the kernel allowlist with the persisted one
+    AllowlistDiff {
+        /// print the result as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show or switch whether the kernel applies App Profiles.
+    ///
+    /// Off is a diagnostic mode only the manager can enter: granted apps get full root and
+    /// their profile is only logged to dmesg. The next boot always restores on.
+    Enforce {
+        #[arg(value_enum)]
+        state: EnforceArg,
+    },
+
+    /// Show how KernelSU is built into the kernel: CONFIG_KSU, hook path and SuSFS
+    KernelConfig {
+        /// print the result as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Check why the manager can't detect the driver, with hints
+    DriverCheck {
+        /// print the result as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show when each boot stage ran during this boot
+    BootStages {
+        /// print the result as JSON
+        #[arg(long)]
+        json: bool,
+    },
 }
 
 #[derive(clap::Subcommand, Debug)]
@@ -304,6 +573,73 @@ enum Module {
     Install {
         /// module zip file path
         zip: String,
+
+        /// print STEP/PROGRESS/TIMINGS lines for the Manager
+        #[arg(long)]
+        progress: bool,
+
+        /// install even if dependencies declared in module.prop are unmet
+        #[arg(long)]
+        ignore_deps: bool,
+
+        /// extraction threads, one per core by default, 1 extracts serially
+        #[arg(long)]
+        threads: Option<usize>,
+
+        /// refuse to install if the module ships files an enabled module also ships
+        #[arg(long)]
+        strict_conflicts: bool,
+    },
+
+    /// Download a module zip over https and install it
+    ///
+    /// An interrupted download resumes, also when the command is run again with the same URL.
+    /// Progress is printed as `progress: <bytes>/<total>` lines, total is 0 when unknown.
+    InstallUrl {
+        /// https URL of the module zip
+        url: String,
+
+        /// print STEP/PROGRESS/TIMINGS lines for the Manager
+        #[arg(long)]
+        progress: bool,
+
+        /// install even if dependencies declared in module.prop are unmet
+        #[arg(long)]
+        ignore_deps: bool,
+
+        /// extraction threads, one per core by default, 1 extracts serially
+        #[arg(long)]
+        threads: Option<usize>,
+
+        /// refuse to install if the module ships files an enabled module also ships
+        #[arg(long)]
+        strict_conflicts: bool,
+    },
+
+    /// Manage the keys trusted to sign modules
+    Keys {
+        #[command(subcommand)]
+        command: ModuleKeysCmd,
+    },
+
+    /// Restore the version of module <id> saved before its last update, applied on reboot
+    Rollback {
+        /// module id
+        id: String,
+        /// version or versionCode of the snapshot to restore, the newest by default
+        #[arg(long)]
+        to: Option<String>,
+    },
+
+    /// Skip mounting one partition of module <id>, or mount it again, applied on reboot
+    SkipMount {
+        /// module id
+        id: String,
+        /// system, vendor, system_ext, product or odm
+        partition: String,
+        /// on skips the partition, off mounts it again
+        #[arg(value_enum)]
+        state: SkipMountArg,
     },
 
     /// Undo module uninstall mark <id>
@@ -318,6 +654,20 @@ enum Module {
         id: String,
     },
 
+    /// Disable all modules from the next boot, also from a recovery adb shell
+    DisableAll {
+        /// keep these modules enabled
+        #[arg(long, value_name = "ID")]
+        except: Vec<String>,
+    },
+
+    /// Enable all modules again
+    EnableAll {
+        /// only the modules that were enabled before `disable-all` or safe mode disabled them
+        #[arg(long)]
+        previously_enabled: bool,
+    },
+
     /// enable module <id>
     Enable {
         /// module id
@@ -336,8 +686,38 @@ enum Module {
         id: String,
     },
 
+    /// Serve the webroot of module <id> over HTTP, until Ctrl-C
+    Webui {
+        /// module id
+        id: String,
+        /// port to listen on, a free one by default
+        #[arg(long, default_value_t = 0)]
+        port: u16,
+        /// address to listen on
+        #[arg(long, default_value = "127.0.0.1")]
+        bind: std::net::IpAddr,
+        /// allow a non-loopback --bind, anyone reaching it with the token runs the module's action
+        #[arg(long)]
+        allow_remote: bool,
+    },
+
+    /// List files shipped by more than one enabled module, and whose copy is mounted
+    ///
+    /// Modules are assumed to be stacked in module id order, the first on top; the metamodule
+    /// decides the real order.
+    Conflicts {
+        /// print as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
     /// list all modules
-    List,
+    List {
+        /// print typed status of each module: mount status of the last boot, dependencies and
+        /// disk usage
+        #[arg(long)]
+        json: bool,
+    },
 
     /// manage module configuration
     Config {
@@ -349,6 +729,26 @@ enum Module {
     },
 }
 
+#[derive(clap::Subcommand, Debug)]
+enum ModuleKeysCmd {
+    /// Trust an Ed25519 public key: a file (raw or DER) or the key in hex
+    Add {
+        /// key name
+        name: String,
+        /// key file path or hex string
+        key: String,
+    },
+
+    /// List trusted keys and the signature mode
+    List,
+
+    /// Stop trusting a key
+    Remove {
+        /// key name
+        name: String,
+    },
+}
+
 #[derive(clap::Subcommand, Debug)]
 enum ModuleConfigCmd {
     /// Get a config value
@@ -517,9 +917,9 @@ enum UmountOp {
     Add {
         /// mount point path
         mnt: String,
-        /// umount flags (default: 0, MNT_DETACH: 2)
+        /// umount flags, a number or names like detach|force (force, detach, expire, nofollow)
         #[arg(short, long, default_value = "0")]
-        flags: u32,
+        flags: UmountFlags,
     },
     /// Delete mount point from umount list
     Del {
@@ -530,6 +930,12 @@ enum UmountOp {
     Wipe,
     /// List all entries from umount list
     List,
+    /// Show how often each entry was unmounted for a process
+    Stats {
+        /// print the counters as a JSON array
+        #[arg(long)]
+        json: bool,
+    },
 }
 
 #[derive(clap::Subcommand, Debug)]
@@ -538,6 +944,69 @@ enum Initrc {
     Refresh,
 }
 
+#[derive(clap::Subcommand, Debug)]
+enum LkmOp {
+    /// Show whether the module is loaded, its version and taint state
+    Status {
+        /// print the result as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Load the bundled module for this kernel, or the one at path
+    Load {
+        /// kernel module path
+        path: Option<PathBuf>,
+        /// module load parameters (e.g. key=val key2=val2)
+        #[arg(last = true)]
+        params: Vec<String>,
+    },
+    /// Unload the module, restarting Android and killing all root processes
+    Unload {
+        /// confirm the unload
+        #[arg(long)]
+        force_confirm: bool,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum UidOp {
+    /// Re-read packages.list so App Profiles follow reinstalled apps to their new uid.
+    ///
+    /// Root grants never follow, the new package may be signed by someone else. They are
+    /// pruned and have to be granted again.
+    Rescan {
+        /// print the result as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum ManagerOp {
+    /// Trust the manager signed like an installed package, e.g. after switching forks
+    Set {
+        /// package name of the manager
+        package: String,
+        /// confirm replacing the current dynamic manager
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Show the managers the kernel currently trusts
+    Status {
+        /// print the result as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Safemode {
+    /// Boot into safe mode next time only: no modules, umount or susfs configs
+    EnableNextBoot,
+    /// Withdraw a pending safe mode request
+    DisableNextBoot,
+}
+
 #[allow(clippy::similar_names)]
 pub fn run() -> Result<()> {
     android_logger::init_once(
@@ -565,32 +1034,134 @@ pub fn run() -> Result<()> {
     let cli = Args::parse();
 
     log::info!("command: {:?}", cli.command);
+    ksucalls::set_version_check(!cli.skip_version_check);
 
     let result = match cli.command {
         Commands::Susfs(args) => crate::android::susfs::cli::run_main(args),
+        Commands::Completions { shell } => {
+            clap_complete::generate(shell, &mut Args::command(), "ksud", &mut std::io::stdout());
+            Ok(())
+        }
         Commands::PostFsData => init_event::on_post_data_fs(),
         Commands::BootCompleted => {
             init_event::on_boot_completed();
             Ok(())
         }
-        Commands::UmountConfig { command } => match command {
-            UmountConfigOp::Add { mnt, flags } => umount_config::add_umount(&mnt, flags),
-            UmountConfigOp::Del { mnt } => umount_config::del_umount(&mnt),
-            UmountConfigOp::Clear => umount_config::wipe_umount(),
-            UmountConfigOp::List => umount_config::list_umount(),
-        },
+        Commands::UmountConfig { config, command } => {
+            if let Some(config) = config {
+                umount_config::set_config_path(config.to_string_lossy().into_owned());
+            }
+            match command {
+                UmountConfigOp::Add {
+                    mnt: Some(mnt),
+                    flags,
+                    susfs,
+                    condition,
+                    comment,
+                    ..
+                } => umount_config::add_umount(&mnt, flags, susfs, condition, comment),
+                UmountConfigOp::Add {
+                    flags,
+                    susfs,
+                    condition,
+                    comment,
+                    from_file,
+                    ..
+                } => umount_config::add_umount_batch(
+                    from_file.as_deref(),
+                    flags,
+                    susfs,
+                    condition,
+                    comment,
+                ),
+                UmountConfigOp::Del { mnt } => umount_config::del_umount(&mnt),
+                UmountConfigOp::Move { mnt, index } => umount_config::move_umount(&mnt, index),
+                UmountConfigOp::Clear => umount_config::wipe_umount(),
+                UmountConfigOp::Package {
+                    package,
+                    mode,
+                    json,
+                } => umount_config::package_umount(&package, mode.map(Into::into), json),
+                UmountConfigOp::Uid { uid, mode, json } => {
+                    umount_config::uid_umount(uid, mode.mode(), json)
+                }
+                UmountConfigOp::SyncModules { enable, disable } => {
+                    umount_config::sync_modules((enable || disable).then_some(enable))
+                }
+                UmountConfigOp::Simulate { pid, uid } => umount_config::simulate_umount(pid, uid),
+                UmountConfigOp::Test => umount_config::test_umount(),
+                UmountConfigOp::Prune { dry_run } => umount_config::prune_umount(dry_run),
+                UmountConfigOp::RestoreDefaults => umount_config::restore_default_umount(),
+                UmountConfigOp::Export { file } => umount_config::export_umount(&file),
+                UmountConfigOp::Import { file, replace, .. } => {
+                    umount_config::import_umount(&file, replace)
+                }
+                UmountConfigOp::RestoreBackup { index } => umount_config::restore_backup(index),
+                UmountConfigOp::Reload { rebuild: false } => umount_config::reload_umount(),
+                UmountConfigOp::Reload { rebuild: true } => umount_config::rebuild_umount(),
+                UmountConfigOp::Load { strict } => umount_config::load_umount_config(strict),
+                UmountConfigOp::List { kernel, json } => umount_config::list_umount(kernel, json),
+            }
+        }
         Commands::SoftReboot => init_event::soft_reboot(),
         Commands::Insmod { module, params } => debug::insmod(&module, &params),
         Commands::Module { command } => {
             utils::switch_mnt_ns(1)?;
             match command {
-                Module::Install { zip } => module::install_module(&zip),
+                Module::Install {
+                    zip,
+                    progress,
+                    ignore_deps,
+                    threads,
+                    strict_conflicts,
+                } => {
+                    module::progress::set_enabled(progress);
+                    module::dependency::set_ignore_on_install(ignore_deps);
+                    module::extract::set_threads(threads.unwrap_or(0));
+                    module::conflict::set_strict_on_install(strict_conflicts);
+                    module::install_module(&zip)
+                }
+                Module::InstallUrl {
+                    url,
+                    progress,
+                    ignore_deps,
+                    threads,
+                    strict_conflicts,
+                } => {
+                    module::progress::set_enabled(progress);
+                    module::dependency::set_ignore_on_install(ignore_deps);
+                    module::extract::set_threads(threads.unwrap_or(0));
+                    module::conflict::set_strict_on_install(strict_conflicts);
+                    module::install_module_from_url(&url)
+                }
+                Module::Keys { command } => match command {
+                    ModuleKeysCmd::Add { name, key } => module::signature::add_key(&name, &key),
+                    ModuleKeysCmd::List => module::signature::list_keys(),
+                    ModuleKeysCmd::Remove { name } => module::signature::remove_key(&name),
+                },
+                Module::Rollback { id, to } => module::rollback_module(&id, to.as_deref()),
+                Module::SkipMount {
+                    id,
+                    partition,
+                    state,
+                } => module::skip_mount::set(&id, &partition, matches!(state, SkipMountArg::On)),
                 Module::UndoUninstall { id } => module::undo_uninstall_module(&id),
                 Module::Uninstall { id } => module::uninstall_module(&id),
+                Module::DisableAll { except } => module::rescue::disable_all(&except, "command"),
+                Module::EnableAll { previously_enabled } => {
+                    module::rescue::enable_all(previously_enabled)
+                }
                 Module::Enable { id } => module::enable_module(&id),
                 Module::Disable { id } => module::disable_module(&id),
                 Module::Action { id } => module::run_action(&id),
-                Module::List => module::list_modules(),
+                Module::Webui {
+                    id,
+                    port,
+                    bind,
+                    allow_remote,
+                } => module::webui::serve(&id, bind, port, allow_remote),
+                Module::Conflicts { json } => module::conflict::list(json),
+                Module::List { json } => module::list_modules(json),
                 Module::Config { internal, command } => {
                     let module_id = match internal {
                         Some(internal_name) => format!("internal.{internal_name}"),
@@ -685,6 +1256,11 @@ pub fn run() -> Result<()> {
         }
         Commands::Install { libadbroot } => utils::install(libadbroot),
         Commands::Unload => crate::android::unload::unload(),
+        Commands::Lkm { command } => match command {
+            LkmOp::Status { json } => lkm::status(json),
+            LkmOp::Load { path, params } => lkm::load(path.as_deref(), &params),
+            LkmOp::Unload { force_confirm } => lkm::unload(force_confirm),
+        },
         Commands::Uninstall { package_name } => utils::uninstall(&package_name),
         Commands::Sepolicy { command } => match command {
             Sepolicy::Patch { sepolicy } => sepolicy::live_patch(&sepolicy),
@@ -723,6 +1299,7 @@ pub fn run() -> Result<()> {
             Ok(())
         }
         Commands::Sulogd => sulog::run_sulogd(),
+        Commands::Heartbeatd => heartbeat::run(),
         Commands::Profile { command } => match command {
             Profile::GetSepolicy { package } => profile::get_sepolicy(package),
             Profile::SetSepolicy { package, policy } => profile::set_sepolicy(package, policy),
@@ -774,6 +1351,7 @@ pub fn run() -> Result<()> {
                 MarkCommand::Refresh => debug::mark_refresh(),
             },
             Debug::Sulogd => sulog::ensure_sulogd_running(),
+            Debug::Heartbeat { json } => heartbeat::status(json),
             Debug::Info => {
                 let info = ksucalls::get_info();
                 println!("version: {}", info.version);
@@ -784,12 +1362,32 @@ pub fn run() -> Result<()> {
                 println!("lkm: {}", ksucalls::is_lkm());
                 println!("late_load: {}", ksucalls::is_late_load());
                 println!("runtime_mode: {}", ksucalls::runtime_mode());
+                println!(
+                    "hook_type: {}",
+                    ksucalls::get_hook_type()
+                        .as_deref()
+                        .unwrap_or(debug::UNKNOWN_HOOK_TYPE)
+                );
                 println!(
                     "pr_build: {}",
                     (info.flags & uapi::KSU_GET_INFO_FLAG_PR_BUILD) != 0
                 );
                 Ok(())
             }
+            Debug::HookType { json } => debug::hook_type(json),
+            Debug::Features { json } => debug::features(json),
+            Debug::BootStages { json } => debug::boot_stages(json),
+            Debug::DriverCheck { json } => debug::driver_check(json),
+            Debug::Enforce { state } => match state {
+                EnforceArg::Status => enforce::status(),
+                EnforceArg::On => enforce::on(),
+            },
+            Debug::KernelConfig { json } => debug::kernel_config(json),
+            Debug::AllowlistDiff { json } => allowlist::diff(json),
+            Debug::Safemode => {
+                debug::safemode();
+                Ok(())
+            }
         },
 
         Commands::BootPatch(boot_patch) => crate::boot_patch::patch(boot_patch),
@@ -839,14 +1437,15 @@ pub fn run() -> Result<()> {
         Commands::Kernel { command } => match command {
             Kernel::NukeExt4Sysfs { mnt } => ksucalls::nuke_ext4_sysfs(&mnt),
             Kernel::Umount { command } => match command {
-                UmountOp::Add { mnt, flags } => ksucalls::umount_list_add(&mnt, flags),
+                UmountOp::Add { mnt, flags } => ksucalls::umount_list_add(&mnt, flags.0),
                 UmountOp::Del { mnt } => ksucalls::umount_list_del(&mnt),
-                UmountOp::Wipe => ksucalls::umount_list_wipe().map_err(Into::into),
+                UmountOp::Wipe => ksucalls::umount_list_clear(),
                 UmountOp::List => {
                     let list = ksucalls::umount_list_list()?;
                     println!("{}", serde_json::to_string(&list)?);
                     Ok(())
                 }
+                UmountOp::Stats { json } => umount_config::umount_stats(json),
             },
             Kernel::DynamicManager { command } => match command {
                 DynamicManagerOp::Set { size, hash } => dynamic_manager::set(size, hash),
@@ -884,10 +1483,78 @@ pub fn run() -> Result<()> {
         Commands::Initrc { command } => match command {
             Initrc::Refresh => regenerate_preinit_rc(),
         },
+        Commands::Uid { command } => match command {
+            UidOp::Rescan { json } => {
+                let rescan = ksucalls::rescan_uids().map_err(|e| match e.kind() {
+                    ksucalls::KsuCallErrorKind::Unsupported => {
+                        anyhow::anyhow!("kernel cannot rescan uids, reboot to apply uid changes")
+                    }
+                    _ => e.into(),
+                })?;
+                if json {
+                    println!("{}", serde_json::to_string(&rescan)?);
+                } else {
+                    println!(
+                        "{} profile(s) moved to a new uid, {} pruned",
+                        rescan.remapped, rescan.pruned
+                    );
+                }
+                Ok(())
+            }
+        },
+        Commands::Manager { command } => match command {
+            ManagerOp::Set { package, yes } => dynamic_manager::set_package(&package, yes),
+            ManagerOp::Status { json } => dynamic_manager::status(json),
+        },
+        Commands::Safemode { command } => match command {
+            Safemode::EnableNextBoot => {
+                utils::request_safe_mode_next_boot(true)?;
+                println!("safe mode will be enabled on the next boot");
+                Ok(())
+            }
+            Safemode::DisableNextBoot => utils::request_safe_mode_next_boot(false),
+        },
     };
 
     if let Err(e) = &result {
         log::error!("Error: {e:?}");
+        // commands asked for json report their failure as json too
+        if std::env::args().any(|arg| arg == "--json") {
+            let failure = ksucalls::KsuCallError::of(e);
+            println!(
+                "{}",
+                serde_json::json!({
+                    "ok": false,
+                    "call": failure.and_then(|f| f.name()),
+                    "errno": failure.map(|f| f.errno),
+                    "message": format!("{e:#}"),
+                })
+            );
+            std::process::exit(1);
+        }
     }
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use clap_complete::Shell;
+
+    use super::*;
+
+    #[test]
+    fn cli_definition_is_consistent() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn completions_cover_susfs_for_every_shell() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let mut script = Vec::new();
+            clap_complete::generate(shell, &mut Args::command(), "ksud", &mut script);
+            let script = String::from_utf8(script).unwrap();
+            assert!(script.contains("add_sus_kstat_statically"), "{shell}");
+            assert!(script.contains("enabled_features"), "{shell}");
+        }
+    }
+}
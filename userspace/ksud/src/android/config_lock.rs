@@ -0,0 +1,60 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind},
+    os::fd::AsRawFd,
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, bail};
+
+const LOCK_WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Exclusive flock on `<config>.lock`, held while a persisted config is read, modified and written.
+///
+/// The lock is released when dropped.
+pub struct ConfigLock {
+    _file: File,
+}
+
+impl ConfigLock {
+    /// Lock `config_path`, waiting a bounded time for another ksud to finish with it.
+    pub fn acquire(config_path: &str) -> Result<Self> {
+        let lock_path = format!("{config_path}.lock");
+        if let Some(parent) = Path::new(&lock_path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .with_context(|| format!("failed to open {lock_path}"))?;
+
+        let deadline = Instant::now() + LOCK_WAIT_TIMEOUT;
+        while !try_lock(&file).with_context(|| format!("failed to lock {lock_path}"))? {
+            if Instant::now() >= deadline {
+                bail!(
+                    "another ksud operation is in progress on {config_path}, try again later (lock: {lock_path})"
+                );
+            }
+            thread::sleep(LOCK_RETRY_INTERVAL);
+        }
+        Ok(Self { _file: file })
+    }
+}
+
+fn try_lock(file: &File) -> io::Result<bool> {
+    let ret = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    if ret == 0 {
+        return Ok(true);
+    }
+
+    let err = io::Error::last_os_error();
+    if err.kind() == ErrorKind::WouldBlock {
+        return Ok(false);
+    }
+    Err(err)
+}
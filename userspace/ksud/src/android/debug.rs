@@ -1,4 +1,5 @@
 use std::{
+    collections::BTreeMap,
     ffi::CString,
     fs,
     path::{Path, PathBuf},
@@ -6,10 +7,15 @@ use std::{
 };
 
 use anyhow::{Context, Ok, Result, bail, ensure};
+use serde::Serialize;
 
-use crate::android::ksucalls;
+use crate::{
+    android::{feature::FeatureId, init_event, ksucalls, utils},
+    defs,
+};
 
 const KERNEL_PARAM_PATH: &str = "/sys/module/kernelsu";
+const KSU_CONTEXT: &str = "u:r:ksu:s0";
 
 fn read_u32(path: &PathBuf) -> Result<u32> {
     let content = std::fs::read_to_string(path)?;
@@ -108,3 +114,448 @@ pub fn mark_refresh() -> Result<()> {
     println!("Refreshed mark for all running processes");
     Ok(())
 }
+
+/// Shown instead of the hook type when the kernel doesn't report one
+pub const UNKNOWN_HOOK_TYPE: &str = "unknown (kernel too old)";
+
+/// Print how the kernel is hooked and whether it runs as LKM
+pub fn hook_type(json: bool) -> Result<()> {
+    let hook_type = ksucalls::get_hook_type();
+    if json {
+        println!(
+            "{}",
+            serde_json::json!({
+                "hook_type": hook_type,
+                "lkm": ksucalls::is_lkm(),
+                "runtime_mode": ksucalls::runtime_mode(),
+            })
+        );
+    } else {
+        println!(
+            "hook_type: {}",
+            hook_type.as_deref().unwrap_or(UNKNOWN_HOOK_TYPE)
+        );
+        println!("lkm: {}", ksucalls::is_lkm());
+        println!("runtime_mode: {}", ksucalls::runtime_mode());
+    }
+    Ok(())
+}
+
+/// Print the kernel capability bitmap and the features it supports
+///
+/// Bits and feature IDs this ksud has no name for are printed as raw numbers.
+pub fn features(json: bool) -> Result<()> {
+    let caps = ksucalls::get_capabilities();
+    let (known_caps, unknown_caps) = caps.map_or_else(Default::default, |caps| {
+        let known: Vec<&str> = ksucalls::CAPABILITIES
+            .iter()
+            .filter(|(bit, _)| caps & bit != 0)
+            .map(|(_, name)| *name)
+            .collect();
+        let known_mask = ksucalls::CAPABILITIES
+            .iter()
+            .fold(0, |mask, (bit, _)| mask | bit);
+        let unknown: Vec<u32> = (0..u64::BITS)
+            .filter(|i| caps & !known_mask & (1 << i) != 0)
+            .collect();
+        (known, unknown)
+    });
+
+    // Feature IDs are dense, get_info reports how many the kernel knows
+    let feature_count = ksucalls::get_info().features;
+    let (known_features, unknown_features): (Vec<u32>, Vec<u32>) = (0..feature_count)
+        .filter(|&id| ksucalls::get_feature(id).is_ok_and(|(_, supported)| supported))
+        .partition(|&id| FeatureId::from_u32(id).is_some());
+    let known_features: Vec<&str> = known_features
+        .into_iter()
+        .filter_map(FeatureId::from_u32)
+        .map(FeatureId::name)
+        .collect();
+
+    if json {
+        println!(
+            "{}",
+            serde_json::json!({
+                "capabilities": caps,
+                "known_capabilities": known_caps,
+                "unknown_capability_bits": unknown_caps,
+                "features": known_features,
+                "unknown_features": unknown_features,
+            })
+        );
+        return Ok(());
+    }
+
+    match caps {
+        Some(caps) => {
+            println!("capabilities: 0x{caps:x}");
+            for name in &known_caps {
+                println!("  {name}");
+            }
+            for bit in &unknown_caps {
+                println!("  bit {bit} (unknown)");
+            }
+        }
+        None => println!("capabilities: unknown (kernel too old)"),
+    }
+    println!("features:");
+    for name in &known_features {
+        println!("  {name}");
+    }
+    for id in &unknown_features {
+        println!("  {id} (unknown)");
+    }
+    Ok(())
+}
+
+/// Print when post-fs-data, services and boot-completed ran during this boot
+pub fn boot_stages(json: bool) -> Result<()> {
+    let stages = init_event::read_boot_stages();
+    if json {
+        println!("{}", serde_json::to_string(&stages)?);
+        return Ok(());
+    }
+    if stages.is_empty() {
+        println!("no boot stage recorded");
+    }
+    for stage in &stages {
+        match stage.uptime {
+            Some(uptime) => println!("{}: {} (uptime {uptime:.2}s)", stage.stage, stage.time),
+            None => println!("{}: {}", stage.stage, stage.time),
+        }
+    }
+    Ok(())
+}
+
+/// Print the safe mode state and each of its sources
+pub fn safemode() {
+    let kernel = ksucalls::check_kernel_safemode();
+    let system = utils::is_system_safe_mode();
+    let requested = utils::is_requested_safe_mode();
+    println!("safe_mode: {}", kernel || system || requested);
+    println!("kernel: {kernel}");
+    println!("system: {system}");
+    println!("requested: {requested}");
+    println!(
+        "next_boot_requested: {}",
+        Path::new(defs::SAFE_MODE_NEXT_BOOT).exists()
+    );
+}
+
+#[derive(Serialize)]
+struct DriverCheck {
+    name: &'static str,
+    /// None if the check couldn't be run
+    passed: Option<bool>,
+    detail: String,
+    /// What to do when the check fails
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hint: Option<&'static str>,
+}
+
+impl DriverCheck {
+    fn new(name: &'static str, passed: Option<bool>, detail: String, hint: &'static str) -> Self {
+        Self {
+            name,
+            passed,
+            detail,
+            hint: (passed != Some(true)).then_some(hint),
+        }
+    }
+}
+
+/// How KernelSU is integrated into the running kernel
+#[derive(Serialize, Default)]
+struct KernelIntegration {
+    /// CONFIG_KSU from /proc/config.gz: "y", "m" or "n", None without /proc/config.gz
+    config_ksu: Option<String>,
+    /// Whether KernelSU symbols belong to the kernel image rather than a module
+    built_in: Option<bool>,
+    /// tracepoint, manual or inline
+    hook: Option<&'static str>,
+    susfs: Option<bool>,
+    /// Where each finding came from, and what could not be determined
+    notes: Vec<String>,
+}
+
+/// The CONFIG_KSU* options in /proc/config.gz, None if it can't be read
+fn ksu_kernel_config() -> Option<BTreeMap<String, String>> {
+    let output = Command::new("zcat").arg("/proc/config.gz").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let mut options = BTreeMap::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if let Some((key, value)) = line.split_once('=')
+            && key.starts_with("CONFIG_KSU")
+        {
+            options.insert(key.to_string(), value.to_string());
+        } else if let Some(key) = line
+            .strip_prefix("# ")
+            .and_then(|l| l.strip_suffix(" is not set"))
+            && key.starts_with("CONFIG_KSU")
+        {
+            options.insert(key.to_string(), "n".to_string());
+        }
+    }
+    Some(options)
+}
+
+/// Symbols that tell the hook paths and SuSFS apart, see kernel/Kbuild
+const TRACEPOINT_SYMBOL: &str = "ksu_handle_execve_sucompat_tp_internal";
+const MANUAL_OR_INLINE_SYMBOL: &str = "ksu_handle_execveat";
+const INLINE_SYMBOL: &str = "ksu_handle_execveat_sucompat";
+
+fn kernel_integration() -> KernelIntegration {
+    let mut found = KernelIntegration::default();
+
+    if let Some(config) = ksu_kernel_config() {
+        let enabled = |key: &str| config.get(key).is_some_and(|v| v == "y");
+        found.config_ksu = Some(
+            config
+                .get("CONFIG_KSU")
+                .cloned()
+                .unwrap_or_else(|| "n".into()),
+        );
+        found.built_in = Some(enabled("CONFIG_KSU"));
+        found.hook = if enabled("CONFIG_KSU_TRACEPOINT_HOOK") {
+            Some("tracepoint")
+        } else if enabled("CONFIG_KSU_MANUAL_HOOK") {
+            Some("manual")
+        } else if enabled("CONFIG_KSU_SUSFS") {
+            Some("inline")
+        } else {
+            None
+        };
+        found.susfs = Some(enabled("CONFIG_KSU_SUSFS"));
+        found.notes.push("read /proc/config.gz".to_string());
+    } else {
+        found
+            .notes
+            .push("/proc/config.gz is unavailable, guessing from /proc/kallsyms".to_string());
+    }
+
+    // an LKM has no CONFIG_KSU, so kallsyms is consulted even when config.gz exists
+    let std::result::Result::Ok(kallsyms) = fs::read_to_string("/proc/kallsyms") else {
+        found.notes.push("/proc/kallsyms is unreadable".to_string());
+        return found;
+    };
+    let mut ksu_module = None;
+    let (mut tracepoint, mut manual_or_inline, mut inline, mut susfs) =
+        (false, false, false, false);
+    for line in kallsyms.lines() {
+        let mut fields = line.split_whitespace().skip(2);
+        let Some(name) = fields.next() else {
+            continue;
+        };
+        let module = fields.next();
+        if name.starts_with("ksu_") && ksu_module.is_none() {
+            ksu_module = Some(module.is_some());
+        }
+        tracepoint |= name == TRACEPOINT_SYMBOL;
+        manual_or_inline |= name == MANUAL_OR_INLINE_SYMBOL;
+        inline |= name == INLINE_SYMBOL;
+        susfs |= name.starts_with("susfs_");
+    }
+    let Some(in_module) = ksu_module else {
+        found
+            .notes
+            .push("no KernelSU symbols in /proc/kallsyms".to_string());
+        return found;
+    };
+
+    if found.config_ksu.as_deref() != Some("y") {
+        found.built_in = Some(!in_module);
+    }
+    if found.hook.is_none() {
+        found.hook = if tracepoint {
+            Some("tracepoint")
+        } else if inline {
+            Some("inline")
+        } else if manual_or_inline {
+            Some("manual")
+        } else {
+            None
+        };
+    }
+    if found.susfs != Some(true) {
+        found.susfs = Some(susfs || inline);
+        if !found.susfs.unwrap_or_default() {
+            found.notes.push(
+                "no susfs symbols, they may be hidden by CONFIG_KSU_SUSFS_HIDE_KSU_SUSFS_SYMBOLS"
+                    .to_string(),
+            );
+        }
+    }
+    found.notes.push("read /proc/kallsyms".to_string());
+    found
+}
+
+fn describe_integration(found: &KernelIntegration) -> String {
+    let config = match found.config_ksu.as_deref() {
+        Some(value) => format!("CONFIG_KSU={value}"),
+        None => "no /proc/config.gz".to_string(),
+    };
+    let build = match found.built_in {
+        Some(true) => "built-in",
+        Some(false) => "module",
+        None => "unknown build",
+    };
+    let susfs = match found.susfs {
+        Some(true) => "susfs",
+        Some(false) => "no susfs",
+        None => "susfs unknown",
+    };
+    format!(
+        "{config}, {build}, {} hook, {susfs}",
+        found.hook.unwrap_or("unknown")
+    )
+}
+
+/// Print how KernelSU is integrated into the kernel: CONFIG_KSU, hook path and SuSFS
+pub fn kernel_config(json: bool) -> Result<()> {
+    let found = kernel_integration();
+    if json {
+        println!("{}", serde_json::to_string(&found)?);
+        return Ok(());
+    }
+    println!("{}", describe_integration(&found));
+    for note in &found.notes {
+        println!("note: {note}");
+    }
+    Ok(())
+}
+
+fn driver_checks() -> Vec<DriverCheck> {
+    let mut checks = Vec::new();
+
+    let info = ksucalls::get_info();
+    checks.push(DriverCheck::new(
+        "driver",
+        Some(info.version > 0),
+        if info.version > 0 {
+            format!(
+                "version {} ({}), uapi {}, {}",
+                info.version,
+                ksucalls::get_full_version(),
+                info.uapi_version,
+                ksucalls::runtime_mode()
+            )
+        } else {
+            "the kernel doesn't answer ksucalls".to_string()
+        },
+        "the kernel has no KernelSU or it isn't loaded; flash a KernelSU kernel or load the LKM",
+    ));
+    if info.version > 0 {
+        checks.push(DriverCheck::new(
+            "uapi",
+            Some(ksucalls::uapi_supported(info.uapi_version)),
+            format!(
+                "kernel {}, ksud supports {}..={}",
+                info.uapi_version,
+                ksucalls::MIN_SUPPORTED_UAPI,
+                ksucalls::uapi_version()
+            ),
+            "kernel and ksud are from different releases; update both to the same version",
+        ));
+    }
+
+    let uid = unsafe { libc::getuid() };
+    checks.push(DriverCheck::new(
+        "uid",
+        Some(uid == 0),
+        format!("ksud runs as uid {uid}"),
+        "run ksud as root, e.g. through su",
+    ));
+
+    let context = fs::read_to_string("/proc/self/attr/current")
+        .ok()
+        .map(|s| s.trim_end_matches(['\0', '\n']).to_string());
+    checks.push(DriverCheck::new(
+        "selinux_context",
+        context.as_deref().map(|c| c == KSU_CONTEXT),
+        format!(
+            "{}, expected {KSU_CONTEXT}",
+            context.as_deref().unwrap_or("unknown")
+        ),
+        "ksud is not running in the KernelSU domain; run it through KernelSU's su",
+    ));
+
+    let exe = fs::read("/proc/self/exe");
+    let installed = fs::read(defs::DAEMON_PATH);
+    checks.push(match (&exe, &installed) {
+        (std::result::Result::Ok(exe), std::result::Result::Ok(installed)) => DriverCheck::new(
+            "ksud_binary",
+            Some(exe == installed),
+            if exe == installed {
+                format!("{} matches the running binary", defs::DAEMON_PATH)
+            } else {
+                format!("{} differs from the running binary", defs::DAEMON_PATH)
+            },
+            "reinstall from the manager so /data/adb/ksud matches it",
+        ),
+        (_, Err(e)) => DriverCheck::new(
+            "ksud_binary",
+            Some(false),
+            format!("{}: {e}", defs::DAEMON_PATH),
+            "ksud is not installed; open the manager to install it",
+        ),
+        (Err(e), _) => DriverCheck::new(
+            "ksud_binary",
+            None,
+            format!("cannot read the running binary: {e}"),
+            "",
+        ),
+    });
+
+    let modules = fs::read_to_string("/proc/modules");
+    let lkm_loaded = modules
+        .as_ref()
+        .ok()
+        .map(|m| m.lines().any(|line| line.starts_with("kernelsu ")));
+    checks.push(DriverCheck::new(
+        "lkm",
+        // a built-in KernelSU has no module to find
+        Some(lkm_loaded == Some(true) || (info.version > 0 && !ksucalls::is_lkm())),
+        match lkm_loaded {
+            Some(true) => "kernelsu.ko is loaded".to_string(),
+            Some(false) => "kernelsu.ko is not loaded".to_string(),
+            None => "/proc/modules is unreadable".to_string(),
+        },
+        "for LKM mode, patch the boot image from the manager or load kernelsu.ko with `ksud insmod`",
+    ));
+
+    let integration = kernel_integration();
+    checks.push(DriverCheck::new(
+        "kernel_integration",
+        // an LKM needs no CONFIG_KSU, config.gz only describes the kernel image
+        integration
+            .built_in
+            .map(|built_in| built_in || lkm_loaded == Some(true)),
+        describe_integration(&integration),
+        "the kernel wasn't built with KernelSU; use LKM mode or a kernel with CONFIG_KSU",
+    ));
+
+    checks
+}
+
+/// Diagnose why the manager can't find the driver, printing a pass/fail list with hints
+pub fn driver_check(json: bool) -> Result<()> {
+    let checks = driver_checks();
+    if json {
+        println!("{}", serde_json::to_string(&checks)?);
+        return Ok(());
+    }
+    for check in &checks {
+        let status = match check.passed {
+            Some(true) => "PASS",
+            Some(false) => "FAIL",
+            None => "SKIP",
+        };
+        println!("[{status}] {}: {}", check.name, check.detail);
+        if let Some(hint) = check.hint.filter(|hint| !hint.is_empty()) {
+            println!("       hint: {hint}");
+        }
+    }
+    Ok(())
+}
@@ -1,9 +1,13 @@
-use std::fs;
+use std::{fs, process::Command};
 
-use anyhow::Result;
+use anyhow::{Context, Result, bail, ensure};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 
-use crate::{android::ksucalls, defs};
+use crate::{
+    android::{ksucalls, umount_config},
+    apk_sign, defs,
+};
 
 #[derive(Debug, Deserialize, Serialize)]
 struct Config {
@@ -75,3 +79,96 @@ pub fn set(size: u32, hash: [u8; 64]) -> Result<()> {
     ksucalls::dynamic_manager_set_synchronous(size, hash)?;
     Ok(())
 }
+
+/// Path of the installed base APK of `package`
+fn apk_of_package(package: &str) -> Result<String> {
+    let output = Command::new("pm")
+        .args(["path", package])
+        .output()
+        .context("failed to run pm")?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.strip_prefix("package:"))
+        .find(|path| path.ends_with("/base.apk"))
+        .map(str::to_string)
+        .with_context(|| format!("package {package} is not installed"))
+}
+
+/// Make the kernel trust the manager signed like the installed `package`, replacing the
+/// current dynamic manager. Refused unless run by root with `confirmed`.
+pub fn set_package(package: &str, confirmed: bool) -> Result<()> {
+    ensure!(
+        unsafe { libc::getuid() } == 0,
+        "changing the manager identity needs root"
+    );
+    let apk = apk_of_package(package)?;
+    let (size, digest) = apk_sign::get_apk_signature(&apk)
+        .with_context(|| format!("failed to read the signature of {apk}"))?;
+    let hash = parse_hash(&digest).map_err(anyhow::Error::msg)?;
+    if !confirmed {
+        bail!(
+            "{package} (size: {size}, hash: {digest}) would replace the current dynamic manager, \
+             pass --yes to confirm"
+        );
+    }
+
+    set(size, hash)?;
+    println!("Kernel now trusts {package} (size: {size}, hash: {digest})");
+    Ok(())
+}
+
+/// Show the dynamic manager and the managers the kernel currently trusts
+pub fn status(json: bool) -> Result<()> {
+    let dynamic = ksucalls::dynamic_manager_get().ok();
+    let managers = ksucalls::get_managers()?;
+    let packages = managers
+        .iter()
+        .map(|m| umount_config::package_of_uid(m.appid).ok().flatten())
+        .collect::<Vec<_>>();
+
+    if json {
+        let managers = managers
+            .iter()
+            .zip(&packages)
+            .map(|(m, package)| {
+                json!({
+                    "appid": m.appid,
+                    "signature_index": m.signature_index,
+                    "dynamic": m.signature_index == ksucalls::DYNAMIC_MANAGER_SIGNATURE_INDEX,
+                    "package": package,
+                })
+            })
+            .collect::<Vec<_>>();
+        let dynamic = dynamic
+            .map(|(size, hash)| json!({ "size": size, "hash": String::from_utf8_lossy(&hash) }));
+        println!(
+            "{}",
+            json!({ "dynamic_manager": dynamic, "managers": managers })
+        );
+        return Ok(());
+    }
+
+    match dynamic {
+        Some((size, hash)) => println!(
+            "dynamic manager: size: {size}, hash: {}",
+            String::from_utf8_lossy(&hash)
+        ),
+        None => println!("dynamic manager: not set"),
+    }
+    if managers.is_empty() {
+        println!("no manager is registered");
+    }
+    for (m, package) in managers.iter().zip(&packages) {
+        let kind = if m.signature_index == ksucalls::DYNAMIC_MANAGER_SIGNATURE_INDEX {
+            "dynamic".to_string()
+        } else {
+            format!("signature #{}", m.signature_index)
+        };
+        println!(
+            "appid {} ({}): {kind}",
+            m.appid,
+            package.as_deref().unwrap_or("unknown package")
+        );
+    }
+    Ok(())
+}
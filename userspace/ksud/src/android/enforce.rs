@@ -0,0 +1,85 @@
+//! Switch the kernel between applying App Profiles and the permissive diagnostic mode
+
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+};
+
+use anyhow::Result;
+use chrono::Local;
+use const_format::concatcp;
+
+use crate::{
+    android::ksucalls::{self, EnforceMode, KsuCallErrorKind},
+    defs,
+};
+
+/// Every mode change ksud makes, appended and never rotated, changes are rare
+pub const AUDIT_LOG_PATH: &str = concatcp!(defs::LOG_DIR, "enforce.log");
+
+/// Record a mode change. Failing to write the log never fails the change itself.
+fn record(from: EnforceMode, to: EnforceMode, reason: &str) {
+    let line = format!(
+        "{} pid={} uid={} {} -> {} ({reason})",
+        Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+        std::process::id(),
+        unsafe { libc::getuid() },
+        from.name(),
+        to.name()
+    );
+    let result = fs::create_dir_all(defs::LOG_DIR).and_then(|()| {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(AUDIT_LOG_PATH)?;
+        writeln!(file, "{line}")
+    });
+    if let Err(e) = result {
+        log::warn!("failed to write enforce audit log: {e}");
+    }
+}
+
+/// Print the kernel's enforcement mode
+pub fn status() -> Result<()> {
+    let mode = ksucalls::get_enforce_mode()?;
+    println!("{}", mode.name());
+    if mode == EnforceMode::Permissive {
+        println!(
+            "App Profiles are logged to dmesg, not applied, until `ksud debug enforce on` or reboot"
+        );
+    }
+    Ok(())
+}
+
+/// Switch App Profiles back on, `ksud debug enforce on`
+///
+/// Only the manager may switch to permissive, the kernel refuses it from any root
+/// process, ksud included, so this is the only direction ksud offers.
+pub fn on() -> Result<()> {
+    let current = ksucalls::get_enforce_mode()?;
+    if current == EnforceMode::Enforcing {
+        println!("already {}", current.name());
+        return Ok(());
+    }
+    ksucalls::set_enforce_mode(EnforceMode::Enforcing)?;
+    record(current, EnforceMode::Enforcing, "ksud debug enforce");
+    log::warn!("kernel is now {}", EnforceMode::Enforcing.name());
+    println!("{}", EnforceMode::Enforcing.name());
+    Ok(())
+}
+
+/// Make sure a debugging session never outlives a reboot
+pub fn restore_on_boot() {
+    match ksucalls::get_enforce_mode() {
+        Ok(EnforceMode::Enforcing) => {}
+        Err(e) if e.kind() == KsuCallErrorKind::Unsupported => {}
+        Ok(EnforceMode::Permissive) => match ksucalls::set_enforce_mode(EnforceMode::Enforcing) {
+            Ok(()) => {
+                log::warn!("kernel was permissive at boot, restored enforcing");
+                record(EnforceMode::Permissive, EnforceMode::Enforcing, "boot");
+            }
+            Err(e) => log::error!("failed to restore enforcing: {e}"),
+        },
+        Err(e) => log::warn!("failed to query the enforcement mode: {e}"),
+    }
+}
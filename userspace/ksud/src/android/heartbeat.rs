@@ -0,0 +1,279 @@
+//! Periodically check that the kernel driver still answers ksud
+
+use std::{
+    fs::{self, File, OpenOptions},
+    os::{fd::AsRawFd, unix::process::CommandExt},
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    android::{ksucalls, module::module_config, utils},
+    defs,
+};
+
+pub const HEARTBEAT_CONFIG_MODULE_ID: &str = "internal.ksud.heartbeat";
+/// Seconds between heartbeats, 0 disables the daemon
+const INTERVAL_CONFIG_KEY: &str = "heartbeat.interval";
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+/// Consecutive failed heartbeats before the driver is reported dead
+const FAILURES_BEFORE_ALERT: u32 = 3;
+/// How far the interval stretches while the device sleeps
+const MAX_IDLE_BACKOFF: u32 = 16;
+/// Rewrite an unchanged status this often, so the Manager can tell the daemon is alive
+const STATUS_REFRESH: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DriverState {
+    Ok,
+    /// The driver stopped answering
+    Failing,
+    /// The driver answers with another version or mode than at daemon start
+    Replaced,
+}
+
+impl DriverState {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Failing => "failing",
+            Self::Replaced => "replaced",
+        }
+    }
+}
+
+/// What the daemon last saw, persisted in `defs::HEARTBEAT_STATUS_PATH`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HeartbeatStatus {
+    pub state: DriverState,
+    pub pid: u32,
+    /// Local time of the last heartbeat, RFC 3339
+    pub checked: String,
+    /// Local time of the last answered heartbeat, RFC 3339
+    pub last_ok: Option<String>,
+    pub failures: u32,
+    pub errno: Option<i32>,
+    pub version: i32,
+}
+
+fn interval() -> Duration {
+    let Ok(config) = module_config::merge_configs(HEARTBEAT_CONFIG_MODULE_ID) else {
+        return DEFAULT_INTERVAL;
+    };
+    match config
+        .get(INTERVAL_CONFIG_KEY)
+        .map(|v| v.trim().parse::<u64>())
+    {
+        Some(Ok(secs)) => Duration::from_secs(secs),
+        Some(Err(_)) => {
+            log::warn!("invalid {INTERVAL_CONFIG_KEY}, using {DEFAULT_INTERVAL:?}");
+            DEFAULT_INTERVAL
+        }
+        None => DEFAULT_INTERVAL,
+    }
+}
+
+/// Time the device spent suspended since boot, sleep() doesn't count it
+fn suspended_time() -> Duration {
+    let read = |clock| {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        unsafe { libc::clock_gettime(clock, &raw mut ts) };
+        Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+    };
+    read(libc::CLOCK_BOOTTIME).saturating_sub(read(libc::CLOCK_MONOTONIC))
+}
+
+fn write_status(status: &HeartbeatStatus) {
+    let result = serde_json::to_string(status)
+        .map_err(anyhow::Error::from)
+        .and_then(|content| Ok(fs::write(defs::HEARTBEAT_STATUS_PATH, content)?));
+    if let Err(e) = result {
+        log::warn!("failed to write heartbeat status: {e}");
+    }
+}
+
+/// Run the user's hook script, if any, without waiting for it
+fn run_hook(status: &HeartbeatStatus) {
+    if !Path::new(defs::HEARTBEAT_HOOK_PATH).exists() {
+        return;
+    }
+    let spawned = Command::new("/system/bin/sh")
+        .arg(defs::HEARTBEAT_HOOK_PATH)
+        .env("KSU_DRIVER_STATE", status.state.name())
+        .env("KSU_DRIVER_FAILURES", status.failures.to_string())
+        .env(
+            "KSU_DRIVER_ERRNO",
+            status.errno.map(|e| e.to_string()).unwrap_or_default(),
+        )
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    match spawned {
+        Ok(mut child) => {
+            thread::spawn(move || child.wait());
+        }
+        Err(e) => log::warn!("failed to run {}: {e}", defs::HEARTBEAT_HOOK_PATH),
+    }
+}
+
+fn lock() -> Result<Option<File>> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(defs::HEARTBEAT_LOCK_PATH)
+        .with_context(|| format!("failed to open {}", defs::HEARTBEAT_LOCK_PATH))?;
+    let ret = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    Ok((ret == 0).then_some(file))
+}
+
+/// The heartbeat loop, `ksud heartbeatd`
+pub fn run() -> Result<()> {
+    let Some(_lock) = lock()? else {
+        log::info!("heartbeat lock is held, skipping start");
+        return Ok(());
+    };
+
+    let base = interval();
+    if base.is_zero() {
+        log::info!("heartbeat is disabled");
+        return Ok(());
+    }
+
+    let baseline = ksucalls::heartbeat().ok();
+    let now = || chrono::Local::now().to_rfc3339();
+    let mut status = HeartbeatStatus {
+        state: DriverState::Ok,
+        pid: std::process::id(),
+        checked: now(),
+        last_ok: baseline.map(|_| now()),
+        failures: 0,
+        errno: None,
+        version: baseline.map_or(0, |info| info.version as i32),
+    };
+    write_status(&status);
+    let mut written = Instant::now();
+    let mut backoff = 1;
+
+    loop {
+        let suspended = suspended_time();
+        thread::sleep(base * backoff);
+        // a device that slept through most of the wait is idle, stretch the interval
+        if suspended_time().saturating_sub(suspended) >= base * backoff / 2 {
+            backoff = (backoff * 2).min(MAX_IDLE_BACKOFF);
+        } else {
+            backoff = 1;
+        }
+
+        let previous = status.state;
+        status.checked = now();
+        match ksucalls::heartbeat() {
+            Ok(info) => {
+                status.failures = 0;
+                status.errno = None;
+                status.last_ok = Some(status.checked.clone());
+                status.version = info.version as i32;
+                status.state = match baseline {
+                    Some(b) if b.version != info.version || b.flags != info.flags => {
+                        DriverState::Replaced
+                    }
+                    _ => DriverState::Ok,
+                };
+            }
+            Err(e) => {
+                status.failures = status.failures.saturating_add(1);
+                status.errno = Some(e.errno);
+                if status.failures >= FAILURES_BEFORE_ALERT {
+                    status.state = DriverState::Failing;
+                }
+                backoff = 1;
+            }
+        }
+
+        if status.state != previous {
+            match status.state {
+                DriverState::Ok => log::warn!("kernel driver answers again"),
+                DriverState::Failing => log::error!(
+                    "kernel driver stopped answering: {} failed heartbeats, errno {:?}; root will stop working, reboot to recover",
+                    status.failures,
+                    status.errno
+                ),
+                DriverState::Replaced => log::error!(
+                    "kernel driver was replaced, version {} now; reboot to resync ksud",
+                    status.version
+                ),
+            }
+            write_status(&status);
+            written = Instant::now();
+            if status.state != DriverState::Ok {
+                run_hook(&status);
+            }
+        } else if written.elapsed() >= STATUS_REFRESH {
+            write_status(&status);
+            written = Instant::now();
+        }
+    }
+}
+
+/// Start the heartbeat daemon in the background
+pub fn spawn() -> Result<()> {
+    if utils::create_daemon(true)? {
+        let current_exe = std::env::current_exe().context("failed to resolve current ksud path")?;
+        let mut command = Command::new(current_exe);
+        command
+            .arg("heartbeatd")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .current_dir("/");
+
+        Err(command.exec()).context("failed to exec heartbeatd")
+    } else {
+        Ok(())
+    }
+}
+
+/// Print the last status written by the daemon
+pub fn status(json: bool) -> Result<()> {
+    let content = fs::read_to_string(defs::HEARTBEAT_STATUS_PATH).with_context(|| {
+        format!(
+            "heartbeat daemon has not run, no {}",
+            defs::HEARTBEAT_STATUS_PATH
+        )
+    })?;
+    if json {
+        println!("{}", content.trim());
+        return Ok(());
+    }
+    let status: HeartbeatStatus = serde_json::from_str(&content)?;
+    println!(
+        "driver: {}, version {}",
+        status.state.name(),
+        status.version
+    );
+    println!("checked: {} by pid {}", status.checked, status.pid);
+    println!(
+        "last answered: {}",
+        status.last_ok.as_deref().unwrap_or("never")
+    );
+    if status.failures > 0 {
+        println!(
+            "failed heartbeats: {}, errno {}",
+            status.failures,
+            status
+                .errno
+                .map_or_else(|| "unknown".to_string(), |e| e.to_string())
+        );
+    }
+    Ok(())
+}
@@ -1,14 +1,15 @@
-use std::{path::Path, process::Command};
+use std::{fs, path::Path, process::Command};
 
 use anyhow::{Context, Result};
 use libc::_exit;
 use log::{error, info, warn};
 use prop_rs_android::{resetprop::ResetProp, sys_prop};
 use rustix::process::chdir;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     android::{
-        dynamic_manager, ksucalls,
+        dynamic_manager, enforce, heartbeat, ksucalls,
         module::{self, handle_updated_modules, metamodule, prune_modules},
         restorecon,
         utils::{self, is_safe_mode, switch_mnt_ns},
@@ -23,6 +24,9 @@ pub fn on_post_data_fs() -> Result<()> {
     }
 
     ksucalls::report_post_fs_data();
+    record_boot_stage("post-fs-data");
+    enforce::restore_on_boot();
+    utils::apply_safe_mode_request();
 
     utils::umask(0);
 
@@ -64,10 +68,13 @@ pub fn on_post_data_fs() -> Result<()> {
 
     // if we are in safe mode, we should disable all modules
     if safe_mode {
-        warn!("safe mode, skip post-fs-data scripts and disable all modules!");
-        if let Err(e) = module::disable_all_modules() {
+        warn!(
+            "safe mode, skip post-fs-data scripts, module mounts, umount and susfs configs and disable all modules!"
+        );
+        if let Err(e) = module::rescue::disable_all(&[], module::rescue::safe_mode_trigger()) {
             warn!("disable all modules failed: {e}");
         }
+        module::status::record_safe_mode();
         return Ok(());
     }
 
@@ -115,7 +122,6 @@ pub fn on_post_data_fs() -> Result<()> {
     }
 
     // exec modules post-fs-data scripts
-    // TODO: Add timeout
     if let Err(e) = module::exec_stage_script("post-fs-data", true) {
         warn!("exec post-fs-data scripts failed: {e}");
     }
@@ -125,15 +131,28 @@ pub fn on_post_data_fs() -> Result<()> {
         warn!("load system.prop failed: {e}");
     }
 
+    crate::android::susfs::init_event::on_pre_mount();
+
+    module::dependency::apply_skip_mount(&module::dependency::blocked_modules());
+    module::skip_mount::log_skipped();
+
     // execute metamodule mount script
-    if let Err(e) = metamodule::exec_mount_script(module_dir) {
+    let mount_result = metamodule::exec_mount_script(module_dir);
+    if let Err(ref e) = mount_result {
         warn!("execute metamodule mount failed: {e}");
     }
+    module::status::record_mount(&mount_result);
+
+    crate::android::susfs::init_event::on_post_mount();
 
     // Load umount config and apply to kernel
-    if let Err(e) = crate::android::umount_config::load_umount_config() {
+    if let Err(e) = crate::android::umount_config::load_umount_config(false) {
         warn!("load umount config failed: {e}");
     }
+    if let Err(e) = crate::android::umount_config::sync_module_mounts_on_boot() {
+        warn!("sync module mounts to umount config failed: {e}");
+    }
+    crate::android::umount_config::record_missing_on_boot();
 
     run_stage("post-mount", true);
 
@@ -176,6 +195,8 @@ pub fn on_services() {
         return;
     }
 
+    ksucalls::report_services_started();
+    record_boot_stage("services");
     info!("on_services triggered!");
     run_stage("service", false);
 }
@@ -187,14 +208,62 @@ pub fn on_boot_completed() {
     }
 
     ksucalls::report_boot_complete();
+    record_boot_stage("boot-completed");
     info!("on_boot_completed triggered!");
     run_stage("boot-completed", false);
+    if let Err(e) = heartbeat::spawn() {
+        warn!("failed to start heartbeat daemon: {e}");
+    }
     // Load susfs boot-completed
     if !is_safe_mode() {
         crate::android::susfs::init_event::on_boot_completed();
     }
 }
 
+/// When a boot stage ran, as recorded in `defs::BOOT_STAGES_PATH`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BootStage {
+    pub stage: String,
+    /// Local time, RFC 3339
+    pub time: String,
+    /// Seconds since the kernel booted
+    pub uptime: Option<f64>,
+}
+
+pub fn read_boot_stages() -> Vec<BootStage> {
+    fs::read_to_string(defs::BOOT_STAGES_PATH)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+/// Record that `stage` ran now, post-fs-data starts a new boot and drops older records
+fn record_boot_stage(stage: &str) {
+    let mut stages = if stage == "post-fs-data" {
+        Vec::new()
+    } else {
+        read_boot_stages()
+    };
+    let uptime = fs::read_to_string("/proc/uptime").ok().and_then(|content| {
+        content
+            .split_whitespace()
+            .next()
+            .and_then(|secs| secs.parse().ok())
+    });
+    stages.retain(|s| s.stage != stage);
+    stages.push(BootStage {
+        stage: stage.to_string(),
+        time: chrono::Local::now().to_rfc3339(),
+        uptime,
+    });
+    let result = serde_json::to_string(&stages)
+        .map_err(anyhow::Error::from)
+        .and_then(|content| Ok(fs::write(defs::BOOT_STAGES_PATH, content)?));
+    if let Err(e) = result {
+        warn!("failed to record boot stage {stage}: {e}");
+    }
+}
+
 const fn resetprop() -> ResetProp {
     ResetProp {
         skip_svc: true,
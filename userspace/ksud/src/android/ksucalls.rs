@@ -1,9 +1,24 @@
 #![allow(clippy::unreadable_literal)]
 use anyhow::bail;
 
-use std::{fs, os::fd::RawFd, sync::OnceLock};
-
-use crate::{android::uapi, defs::MountInfo};
+use std::{
+    fmt, fs,
+    os::fd::RawFd,
+    sync::{
+        OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+#[cfg(test)]
+use std::sync::Mutex;
+
+use crate::{
+    android::uapi,
+    defs::{self, MountInfo, UmountStat},
+};
 
 // Global driver fd cache
 static DRIVER_FD: OnceLock<RawFd> = OnceLock::new();
@@ -47,21 +62,455 @@ fn init_driver_fd() -> Option<RawFd> {
     }
 }
 
-// ioctl wrapper using libc
-pub fn ksuctl<T>(request: u32, arg: *mut T) -> std::io::Result<i32> {
-    use std::io;
+/// Transport for ksucalls, the KernelSU driver in production
+pub trait KernelChannel: Send + Sync {
+    /// Issue `request` with `arg` pointing at its `size` byte payload, returning the ioctl result
+    fn call(&self, request: u32, arg: *mut libc::c_void, size: usize) -> std::io::Result<i32>;
+}
 
-    let fd = *DRIVER_FD.get_or_init(|| init_driver_fd().unwrap_or(-1));
-    unsafe {
-        let ret = libc::ioctl(fd as libc::c_int, request as i32, arg);
+/// ioctl on the KernelSU driver fd
+struct DriverChannel;
+
+impl KernelChannel for DriverChannel {
+    fn call(&self, request: u32, arg: *mut libc::c_void, _size: usize) -> std::io::Result<i32> {
+        let fd = *DRIVER_FD.get_or_init(|| init_driver_fd().unwrap_or(-1));
+        let ret = unsafe { libc::ioctl(fd as libc::c_int, request as i32, arg) };
         if ret < 0 {
-            Err(io::Error::last_os_error())
+            Err(std::io::Error::last_os_error())
         } else {
             Ok(ret)
         }
     }
 }
 
+/// In-memory channel that records every call and answers from a queue, for exercising
+/// the wrappers without a kernel
+#[cfg(test)]
+pub struct MockChannel {
+    /// Request and payload bytes of each call, in order
+    pub calls: Mutex<Vec<(u32, Vec<u8>)>>,
+    /// Results to return, with bytes to copy over the payload; empty answers Ok(0)
+    pub replies: Mutex<std::collections::VecDeque<(std::io::Result<i32>, Vec<u8>)>>,
+    /// Sees each request and payload during the call, while the pointers it carries are valid
+    pub inspect: Mutex<Option<Inspector>>,
+}
+
+#[cfg(test)]
+pub type Inspector = Box<dyn FnMut(u32, &[u8]) + Send>;
+
+#[cfg(test)]
+impl MockChannel {
+    pub const fn new() -> Self {
+        Self {
+            calls: Mutex::new(Vec::new()),
+            replies: Mutex::new(std::collections::VecDeque::new()),
+            inspect: Mutex::new(None),
+        }
+    }
+
+    /// Forget recorded calls, queued replies and the inspector
+    pub fn reset(&self) {
+        lock(&self.calls).clear();
+        lock(&self.replies).clear();
+        *lock(&self.inspect) = None;
+    }
+
+    /// Answer the next call with `result`, copying `bytes` over its payload
+    pub fn reply(&self, result: std::io::Result<i32>, bytes: Vec<u8>) {
+        lock(&self.replies).push_back((result, bytes));
+    }
+
+    pub fn calls(&self) -> Vec<(u32, Vec<u8>)> {
+        lock(&self.calls).clone()
+    }
+}
+
+#[cfg(test)]
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+#[cfg(test)]
+impl KernelChannel for &'static MockChannel {
+    fn call(&self, request: u32, arg: *mut libc::c_void, size: usize) -> std::io::Result<i32> {
+        let payload: &mut [u8] = if arg.is_null() {
+            &mut []
+        } else {
+            unsafe { std::slice::from_raw_parts_mut(arg.cast::<u8>(), size) }
+        };
+        lock(&self.calls).push((request, payload.to_vec()));
+        if let Some(inspect) = lock(&self.inspect).as_mut() {
+            inspect(request, payload);
+        }
+        let Some((result, bytes)) = lock(&self.replies).pop_front() else {
+            return Ok(0);
+        };
+        let len = bytes.len().min(payload.len());
+        payload[..len].copy_from_slice(&bytes[..len]);
+        result
+    }
+}
+
+static CHANNEL: OnceLock<Box<dyn KernelChannel>> = OnceLock::new();
+
+/// Send the ksucalls of this process through `channel`, must be called before the first one
+#[cfg(test)]
+pub fn set_channel(channel: Box<dyn KernelChannel>) {
+    if CHANNEL.set(channel).is_err() {
+        log::warn!("ksucall channel is already set, ignoring");
+    }
+}
+
+/// The channel every test's ksucalls go to
+#[cfg(test)]
+pub static MOCK: MockChannel = MockChannel::new();
+
+/// Route the ksucalls of this process to a freshly reset `MOCK`.
+///
+/// Hold the returned guard for the whole test, tests running in parallel would see each
+/// other's calls otherwise.
+#[cfg(test)]
+pub fn mock_kernel() -> std::sync::MutexGuard<'static, ()> {
+    static SERIAL: Mutex<()> = Mutex::new(());
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+    let guard = lock(&SERIAL);
+    INSTALL.call_once(|| {
+        set_version_check(false);
+        set_channel(Box::new(&MOCK));
+    });
+    MOCK.reset();
+    guard
+}
+
+/// Oldest kernel interface ksud still speaks, its own is `uapi::KERNEL_SU_UAPI_VERSION`
+pub const MIN_SUPPORTED_UAPI: u32 = 2;
+/// What kernels from before the interface version was reported leave in GET_INFO
+const UAPI_UNVERSIONED: u32 = 0;
+
+static VERSION_CHECK: AtomicBool = AtomicBool::new(true);
+/// Whether the kernel interface is compatible, decided before the first gated call
+static HANDSHAKE: OnceLock<bool> = OnceLock::new();
+/// The kernel's KSU_CAP_* bits, read once by the handshake
+static KERNEL_CAPS: OnceLock<Option<u64>> = OnceLock::new();
+
+/// Calls whose layout never changes, the handshake itself relies on them
+const UNGATED: &[u32] = &[
+    uapi::KSU_IOCTL_GET_INFO_RUST,
+    uapi::KSU_IOCTL_GET_INFO_LEGACY_RUST,
+    uapi::KSU_IOCTL_GET_FULL_VERSION_RUST,
+    uapi::KSU_IOCTL_GET_CAPABILITIES_RUST,
+];
+
+/// Calls a kernel only has when it reports the capability, refused with ENOTTY elsewhere
+const CAPABILITY_GATED: &[(u32, u64)] = &[
+    (
+        uapi::KSU_IOCTL_SET_UID_UMOUNT_RUST,
+        uapi::KSU_CAP_UID_UMOUNT_RUST,
+    ),
+    (
+        uapi::KSU_IOCTL_GET_UID_UMOUNT_RUST,
+        uapi::KSU_CAP_UID_UMOUNT_RUST,
+    ),
+    (
+        uapi::KSU_IOCTL_RESCAN_UIDS_RUST,
+        uapi::KSU_CAP_RESCAN_UIDS_RUST,
+    ),
+    (
+        uapi::KSU_IOCTL_GET_SUSFS_STATE_RUST,
+        uapi::KSU_CAP_SUSFS_STATE_RUST,
+    ),
+    (
+        uapi::KSU_IOCTL_GET_ENFORCE_MODE_RUST,
+        uapi::KSU_CAP_ENFORCE_MODE_RUST,
+    ),
+    (
+        uapi::KSU_IOCTL_SET_ENFORCE_MODE_RUST,
+        uapi::KSU_CAP_ENFORCE_MODE_RUST,
+    ),
+];
+
+/// Whether a kernel reporting `caps` has `request`, calls without a capability always pass.
+/// A kernel that can't report them predates every capability gated call.
+fn capability_present(caps: Option<u64>, request: u32) -> bool {
+    let Some(&(_, bit)) = CAPABILITY_GATED.iter().find(|(gated, _)| *gated == request) else {
+        return true;
+    };
+    caps.is_some_and(|caps| caps & bit != 0)
+}
+
+/// Allow calls into a kernel whose interface version ksud doesn't support, for development
+pub fn set_version_check(enabled: bool) {
+    VERSION_CHECK.store(enabled, Ordering::Relaxed);
+}
+
+/// Compare the kernel interface version with the range ksud was built for, once per process.
+///
+/// A mismatch is printed and logged with both versions, and every later gated call fails
+/// with EPROTO instead of passing a payload the kernel would misread. The kernel's capabilities
+/// are read here too, a call it doesn't report fails with ENOTTY without reaching it.
+fn handshake() -> bool {
+    *HANDSHAKE.get_or_init(|| {
+        if let Err(e) = check_uapi_version() {
+            eprintln!("ERROR: {e:#}");
+            log::error!("{e:#}");
+            return false;
+        }
+        if let Some(caps) = *KERNEL_CAPS.get_or_init(get_capabilities) {
+            let missing = CAPABILITIES
+                .iter()
+                .filter(|(bit, _)| caps & bit == 0)
+                .map(|(_, name)| *name)
+                .collect::<Vec<_>>();
+            if !missing.is_empty() {
+                log::info!(
+                    "kernel lacks {}, those features are disabled",
+                    missing.join(", ")
+                );
+            }
+        }
+        true
+    })
+}
+
+/// Issue a ksucall, `arg` points at its payload
+pub fn ksuctl<T>(request: u32, arg: *mut T) -> Result<i32, KsuCallError> {
+    let gated = VERSION_CHECK.load(Ordering::Relaxed) && !UNGATED.contains(&request);
+    let result = if gated && !handshake() {
+        Err(std::io::Error::from_raw_os_error(libc::EPROTO))
+    } else if gated && !capability_present(KERNEL_CAPS.get().copied().flatten(), request) {
+        Err(std::io::Error::from_raw_os_error(libc::ENOTTY))
+    } else {
+        CHANNEL.get_or_init(|| Box::new(DriverChannel)).call(
+            request,
+            arg.cast(),
+            std::mem::size_of::<T>(),
+        )
+    };
+    result.map_err(|e| KsuCallError {
+        request,
+        errno: e.raw_os_error().unwrap_or(libc::EIO),
+    })
+}
+
+/// Name of each ksucall and the kernel feature it needs, for error messages
+const CALLS: &[(u32, &str, &str)] = &[
+    (uapi::KSU_IOCTL_GRANT_ROOT_RUST, "grant_root", "root access"),
+    (uapi::KSU_IOCTL_GET_INFO_RUST, "get_info", "version queries"),
+    (
+        uapi::KSU_IOCTL_REPORT_EVENT_RUST,
+        "report_event",
+        "boot events",
+    ),
+    (
+        uapi::KSU_IOCTL_SET_SEPOLICY_RUST,
+        "set_sepolicy",
+        "live sepolicy patching",
+    ),
+    (
+        uapi::KSU_IOCTL_CHECK_SAFEMODE_RUST,
+        "check_safemode",
+        "safe mode",
+    ),
+    (
+        uapi::KSU_IOCTL_NEW_GET_ALLOW_LIST_RUST,
+        "get_allow_list",
+        "allowlist queries",
+    ),
+    (
+        uapi::KSU_IOCTL_NEW_GET_DENY_LIST_RUST,
+        "get_deny_list",
+        "allowlist queries",
+    ),
+    (
+        uapi::KSU_IOCTL_UID_GRANTED_ROOT_RUST,
+        "uid_granted_root",
+        "allowlist queries",
+    ),
+    (
+        uapi::KSU_IOCTL_UID_SHOULD_UMOUNT_RUST,
+        "uid_should_umount",
+        "umount queries",
+    ),
+    (
+        uapi::KSU_IOCTL_GET_APP_PROFILE_RUST,
+        "get_app_profile",
+        "App Profiles",
+    ),
+    (
+        uapi::KSU_IOCTL_SET_APP_PROFILE_RUST,
+        "set_app_profile",
+        "App Profiles",
+    ),
+    (
+        uapi::KSU_IOCTL_GET_FEATURE_RUST,
+        "get_feature",
+        "feature switches",
+    ),
+    (
+        uapi::KSU_IOCTL_SET_FEATURE_RUST,
+        "set_feature",
+        "feature switches",
+    ),
+    (
+        uapi::KSU_IOCTL_GET_WRAPPER_FD_RUST,
+        "get_wrapper_fd",
+        "fd wrapping",
+    ),
+    (
+        uapi::KSU_IOCTL_MANAGE_MARK_RUST,
+        "manage_mark",
+        "process marking",
+    ),
+    (
+        uapi::KSU_IOCTL_NUKE_EXT4_SYSFS_RUST,
+        "nuke_ext4_sysfs",
+        "ext4 sysfs nuking",
+    ),
+    (
+        uapi::KSU_IOCTL_MANAGE_TRY_UMOUNT_RUST,
+        "manage_try_umount",
+        "the umount list",
+    ),
+    (uapi::KSU_IOCTL_GET_SULOG_FD_RUST, "get_sulog_fd", "sulog"),
+    (
+        uapi::KSU_IOCTL_GET_FULL_VERSION_RUST,
+        "get_full_version",
+        "version queries",
+    ),
+    (
+        uapi::KSU_IOCTL_HOOK_TYPE_RUST,
+        "hook_type",
+        "hook type queries",
+    ),
+    (
+        uapi::KSU_IOCTL_DYNAMIC_MANAGER_RUST,
+        "dynamic_manager",
+        "the dynamic manager",
+    ),
+    (
+        uapi::KSU_IOCTL_GET_MANAGERS_RUST,
+        "get_managers",
+        "manager queries",
+    ),
+    (
+        uapi::KSU_IOCTL_GET_CAPABILITIES_RUST,
+        "get_capabilities",
+        "capability queries",
+    ),
+    (
+        uapi::KSU_IOCTL_SET_UID_UMOUNT_RUST,
+        "set_uid_umount",
+        "per-uid umount",
+    ),
+    (
+        uapi::KSU_IOCTL_GET_UID_UMOUNT_RUST,
+        "get_uid_umount",
+        "per-uid umount",
+    ),
+    (
+        uapi::KSU_IOCTL_RESCAN_UIDS_RUST,
+        "rescan_uids",
+        "uid rescans",
+    ),
+    (
+        uapi::KSU_IOCTL_GET_SUSFS_STATE_RUST,
+        "get_susfs_state",
+        "SuSFS state queries",
+    ),
+    (
+        uapi::KSU_IOCTL_GET_ENFORCE_MODE_RUST,
+        "get_enforce_mode",
+        "the enforcement mode",
+    ),
+    (
+        uapi::KSU_IOCTL_SET_ENFORCE_MODE_RUST,
+        "set_enforce_mode",
+        "the enforcement mode",
+    ),
+];
+
+/// A ksucall the kernel rejected, rendered as actionable text that keeps the raw errno
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KsuCallError {
+    pub request: u32,
+    pub errno: i32,
+}
+
+impl KsuCallError {
+    pub const fn kind(&self) -> KsuCallErrorKind {
+        KsuCallErrorKind::from_errno(self.errno)
+    }
+
+    /// The failed ksucall behind `error`, if it came from the kernel
+    pub fn of(error: &anyhow::Error) -> Option<Self> {
+        error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<Self>().copied())
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        CALLS
+            .iter()
+            .find(|(request, _, _)| *request == self.request)
+            .map(|(_, name, _)| *name)
+    }
+
+    fn feature(&self) -> &'static str {
+        CALLS
+            .iter()
+            .find(|(request, _, _)| *request == self.request)
+            .map_or("this call", |(_, _, feature)| *feature)
+    }
+}
+
+impl fmt::Display for KsuCallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let feature = self.feature();
+        match self.errno {
+            libc::EBADF => write!(
+                f,
+                "KernelSU driver is unavailable, the kernel lacks KernelSU or ksud isn't run through su"
+            ),
+            libc::ENOTTY | libc::ENOSYS | libc::EOPNOTSUPP => write!(
+                f,
+                "kernel lacks {feature}, update to a ReSukiSU kernel that supports it"
+            ),
+            libc::EPERM | libc::EACCES => write!(
+                f,
+                "permission denied, ksud is not running as root or the manager"
+            ),
+            libc::EAGAIN | libc::EBUSY | libc::EINTR => {
+                write!(f, "kernel is busy with {feature}, try again")
+            }
+            // older kernels also reject unknown umount modes this way
+            libc::EINVAL => write!(
+                f,
+                "kernel rejected the arguments for {feature}, it may be too old for this request"
+            ),
+            libc::ENOENT | libc::ENODATA => write!(f, "kernel has no such entry for {feature}"),
+            libc::EEXIST => write!(f, "the entry is already registered for {feature}"),
+            libc::ENOSPC | libc::E2BIG | libc::ENOMEM => {
+                write!(f, "kernel is out of room for {feature}")
+            }
+            libc::EFAULT => write!(f, "kernel could not access the request, this is a ksud bug"),
+            libc::EPROTO => write!(
+                f,
+                "ksud and the kernel speak different interface versions, update the older one"
+            ),
+            _ => write!(f, "kernel rejected {feature}"),
+        }?;
+        write!(
+            f,
+            " ({}: errno {}, {})",
+            self.name().unwrap_or("unknown ksucall"),
+            self.errno,
+            std::io::Error::from_raw_os_error(self.errno)
+        )
+    }
+}
+
 // API implementations
 pub fn get_info() -> uapi::ksu_get_info_cmd {
     *INFO_CACHE.get_or_init(|| {
@@ -79,6 +528,18 @@ pub fn get_info() -> uapi::ksu_get_info_cmd {
     })
 }
 
+/// An uncached GET_INFO, the cheapest call that proves the driver still answers
+pub fn heartbeat() -> Result<uapi::ksu_get_info_cmd, KsuCallError> {
+    let mut cmd = uapi::ksu_get_info_cmd {
+        version: 0,
+        flags: 0,
+        features: 0,
+        uapi_version: 0,
+    };
+    ksuctl(uapi::KSU_IOCTL_GET_INFO, &raw mut cmd)?;
+    Ok(cmd)
+}
+
 pub fn get_version() -> i32 {
     get_info().version as i32
 }
@@ -95,6 +556,14 @@ pub const fn uapi_version() -> u32 {
     uapi::KERNEL_SU_UAPI_VERSION
 }
 
+/// Whether ksud can talk to a kernel speaking interface `kernel_uapi`.
+///
+/// Kernels from before the version was reported are accepted, the calls they lack are kept
+/// away by the capability gate rather than the version.
+pub fn uapi_supported(kernel_uapi: u32) -> bool {
+    kernel_uapi == UAPI_UNVERSIONED || (MIN_SUPPORTED_UAPI..=uapi_version()).contains(&kernel_uapi)
+}
+
 pub fn runtime_mode() -> &'static str {
     if is_late_load() {
         "late-load"
@@ -105,17 +574,55 @@ pub fn runtime_mode() -> &'static str {
     }
 }
 
+fn check_uapi_version() -> anyhow::Result<()> {
+    let info = get_info();
+    if info.version == 0 {
+        // no driver, the calls fail on their own
+        return Ok(());
+    }
+    let kernel_uapi = info.uapi_version;
+    if uapi_supported(kernel_uapi) {
+        return Ok(());
+    }
+    let supported = MIN_SUPPORTED_UAPI..=uapi_version();
+    let update = if kernel_uapi < MIN_SUPPORTED_UAPI {
+        "update the kernel"
+    } else {
+        "update ksud"
+    };
+    bail!(
+        "UAPI version mismatch: kernel {} speaks interface {kernel_uapi}, ksud {} speaks {}..={}. \
+         All kernel calls except version queries are disabled, {update} or pass --skip-version-check",
+        info.version,
+        defs::VERSION_NAME.trim(),
+        supported.start(),
+        supported.end()
+    )
+}
+
+/// Fail unless the kernel interface version is one ksud supports
 pub fn ensure_uapi_version_matched() -> anyhow::Result<()> {
-    let kernel_uapi = get_info().uapi_version;
-    let userspace_uapi = uapi_version();
-    if kernel_uapi != userspace_uapi {
-        bail!(
-            "UAPI version mismatch: kernel={kernel_uapi}, ksud={userspace_uapi}. Please update KernelSU!"
-        );
+    if !VERSION_CHECK.load(Ordering::Relaxed) {
+        return Ok(());
     }
-    Ok(())
+    check_uapi_version()
 }
 
+/// Capability bits known to this ksud, newer kernels may report more
+pub const CAPABILITIES: &[(u64, &str)] = &[
+    (uapi::KSU_CAP_UMOUNT_LIST_RUST, "umount_list"),
+    (uapi::KSU_CAP_UMOUNT_STATS_RUST, "umount_stats"),
+    (uapi::KSU_CAP_UMOUNT_COND_RUST, "umount_cond"),
+    (uapi::KSU_CAP_SUSFS_RUST, "susfs"),
+    (uapi::KSU_CAP_ROOT_PROFILE_FLAGS_RUST, "root_profile_flags"),
+    (uapi::KSU_CAP_DYNAMIC_MANAGER_RUST, "dynamic_manager"),
+    (uapi::KSU_CAP_UMOUNT_BATCH_RUST, "umount_batch"),
+    (uapi::KSU_CAP_UID_UMOUNT_RUST, "uid_umount"),
+    (uapi::KSU_CAP_RESCAN_UIDS_RUST, "rescan_uids"),
+    (uapi::KSU_CAP_SUSFS_STATE_RUST, "susfs_state"),
+    (uapi::KSU_CAP_ENFORCE_MODE_RUST, "enforce_mode"),
+];
+
 pub fn get_full_version() -> String {
     let mut cmd = uapi::ksu_get_full_version_cmd {
         version_full: [0; 255],
@@ -140,7 +647,28 @@ pub fn get_full_version() -> String {
     }
 }
 
-pub fn grant_root() -> std::io::Result<()> {
+/// How the kernel is hooked (e.g. "Manual", "Tracepoint Syscall Redirect"), None if the kernel
+/// doesn't report it
+pub fn get_hook_type() -> Option<String> {
+    let mut cmd = uapi::ksu_hook_type_cmd { hook_type: [0; 32] };
+
+    ksuctl(uapi::KSU_IOCTL_HOOK_TYPE_RUST, &raw mut cmd).ok()?;
+
+    cmd.hook_type[cmd.hook_type.len() - 1] = 0;
+    let hook_type = unsafe { std::ffi::CStr::from_ptr(cmd.hook_type.as_ptr()) }
+        .to_string_lossy()
+        .into_owned();
+    (!hook_type.is_empty()).then_some(hook_type)
+}
+
+/// The KSU_CAP_* bits of the running kernel, None if the kernel predates the query
+pub fn get_capabilities() -> Option<u64> {
+    let mut cmd = uapi::ksu_get_capabilities_cmd { caps: 0 };
+    ksuctl(uapi::KSU_IOCTL_GET_CAPABILITIES_RUST, &raw mut cmd).ok()?;
+    Some(cmd.caps)
+}
+
+pub fn grant_root() -> Result<(), KsuCallError> {
     ksuctl(uapi::KSU_IOCTL_GRANT_ROOT_RUST, std::ptr::null_mut::<u8>())?;
     Ok(())
 }
@@ -154,6 +682,10 @@ pub fn report_post_fs_data() {
     report_event(uapi::EVENT_POST_FS_DATA_RUST);
 }
 
+pub fn report_services_started() {
+    report_event(uapi::EVENT_SERVICES_STARTED_RUST);
+}
+
 pub fn report_boot_complete() {
     report_event(uapi::EVENT_BOOT_COMPLETED_RUST);
 }
@@ -168,7 +700,7 @@ pub fn check_kernel_safemode() -> bool {
     cmd.in_safe_mode != 0
 }
 
-pub fn set_sepolicy(payload: *const u8, payload_len: u64) -> std::io::Result<i32> {
+pub fn set_sepolicy(payload: *const u8, payload_len: u64) -> Result<i32, KsuCallError> {
     let mut ioctl_cmd = uapi::ksu_set_sepolicy_cmd {
         data_len: payload_len,
         data: payload as u64,
@@ -179,7 +711,7 @@ pub fn set_sepolicy(payload: *const u8, payload_len: u64) -> std::io::Result<i32
 
 /// Get feature value and support status from kernel
 /// Returns (value, supported)
-pub fn get_feature(feature_id: u32) -> std::io::Result<(u64, bool)> {
+pub fn get_feature(feature_id: u32) -> Result<(u64, bool), KsuCallError> {
     let mut cmd = uapi::ksu_get_feature_cmd {
         feature_id,
         value: 0,
@@ -189,14 +721,230 @@ pub fn get_feature(feature_id: u32) -> std::io::Result<(u64, bool)> {
     Ok((cmd.value, cmd.supported != 0))
 }
 
+/// Whether the kernel unmounts modules for `uid`, following its App Profile
+pub fn uid_should_umount(uid: u32) -> Result<bool, KsuCallError> {
+    let mut cmd = uapi::ksu_uid_should_umount_cmd {
+        uid,
+        should_umount: 0,
+    };
+    ksuctl(uapi::KSU_IOCTL_UID_SHOULD_UMOUNT_RUST, &raw mut cmd)?;
+    Ok(cmd.should_umount != 0)
+}
+
+/// Whether modules are unmounted for a uid, as set in its App Profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UidUmountMode {
+    Off,
+    On,
+    /// Follow the default non-root profile
+    Default,
+}
+
+impl UidUmountMode {
+    const fn to_raw(self) -> u8 {
+        match self {
+            Self::Off => uapi::KSU_UID_UMOUNT_OFF_RUST,
+            Self::On => uapi::KSU_UID_UMOUNT_ON_RUST,
+            Self::Default => uapi::KSU_UID_UMOUNT_DEFAULT_RUST,
+        }
+    }
+
+    const fn from_raw(mode: u8) -> Self {
+        match mode {
+            uapi::KSU_UID_UMOUNT_OFF_RUST => Self::Off,
+            uapi::KSU_UID_UMOUNT_ON_RUST => Self::On,
+            _ => Self::Default,
+        }
+    }
+}
+
+pub struct UidUmountState {
+    pub mode: UidUmountMode,
+    /// Root apps are never unmounted, whatever the mode
+    pub allow_su: bool,
+    /// Whether modules are unmounted for the uid
+    pub umount: bool,
+}
+
+/// Change only the umount part of `uid`'s App Profile.
+///
+/// `package` owns the uid, a profile left by another package that had the uid before is
+/// replaced instead of being inherited. Root apps are rejected with Invalid.
+pub fn set_uid_umount(uid: u32, package: &str, mode: UidUmountMode) -> anyhow::Result<()> {
+    let mut cmd = uapi::ksu_set_uid_umount_cmd {
+        uid,
+        mode: mode.to_raw(),
+        key: [0; 256],
+    };
+    if package.len() >= cmd.key.len() {
+        bail!("package name {package} is too long");
+    }
+    // the rest of key stays NUL
+    unsafe {
+        std::ptr::copy_nonoverlapping(
+            package.as_ptr(),
+            cmd.key.as_mut_ptr().cast::<u8>(),
+            package.len(),
+        );
+    }
+    ksuctl_retry(uapi::KSU_IOCTL_SET_UID_UMOUNT_RUST, &raw mut cmd)?;
+    Ok(())
+}
+
+pub fn get_uid_umount(uid: u32) -> Result<UidUmountState, KsuCallError> {
+    let mut cmd = uapi::ksu_get_uid_umount_cmd {
+        uid,
+        mode: 0,
+        allow_su: 0,
+        umount: 0,
+    };
+    ksuctl_retry(uapi::KSU_IOCTL_GET_UID_UMOUNT_RUST, &raw mut cmd)?;
+    Ok(UidUmountState {
+        mode: UidUmountMode::from_raw(cmd.mode),
+        allow_su: cmd.allow_su != 0,
+        umount: cmd.umount != 0,
+    })
+}
+
+/// Whether the kernel applies App Profiles, or only logs them while granting full root
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnforceMode {
+    Enforcing,
+    Permissive,
+}
+
+impl EnforceMode {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Enforcing => "enforcing",
+            Self::Permissive => "permissive",
+        }
+    }
+}
+
+pub fn get_enforce_mode() -> Result<EnforceMode, KsuCallError> {
+    let mut cmd = uapi::ksu_enforce_mode_cmd { mode: 0 };
+    ksuctl_retry(uapi::KSU_IOCTL_GET_ENFORCE_MODE_RUST, &raw mut cmd)?;
+    Ok(if cmd.mode == uapi::KSU_ENFORCE_MODE_PERMISSIVE_RUST {
+        EnforceMode::Permissive
+    } else {
+        EnforceMode::Enforcing
+    })
+}
+
+pub fn set_enforce_mode(mode: EnforceMode) -> Result<(), KsuCallError> {
+    let mut cmd = uapi::ksu_enforce_mode_cmd {
+        mode: match mode {
+            EnforceMode::Enforcing => uapi::KSU_ENFORCE_MODE_ENFORCING_RUST,
+            EnforceMode::Permissive => uapi::KSU_ENFORCE_MODE_PERMISSIVE_RUST,
+        },
+    };
+    ksuctl_retry(uapi::KSU_IOCTL_SET_ENFORCE_MODE_RUST, &raw mut cmd)?;
+    Ok(())
+}
+
+/// The uids the kernel grants root (`allow`) or keeps a non-root profile for, managers excluded
+pub fn get_uid_list(allow: bool) -> Result<Vec<u32>, KsuCallError> {
+    const HEADER_SIZE: usize = std::mem::size_of::<uapi::ksu_new_get_allow_list_cmd>();
+    const UID_SIZE: usize = std::mem::size_of::<u32>();
+    let request = if allow {
+        uapi::KSU_IOCTL_NEW_GET_ALLOW_LIST_RUST
+    } else {
+        uapi::KSU_IOCTL_NEW_GET_DENY_LIST_RUST
+    };
+
+    let mut count = 0u16;
+    loop {
+        let mut buffer = vec![0u8; HEADER_SIZE + usize::from(count) * UID_SIZE];
+        buffer[..2].copy_from_slice(&count.to_ne_bytes());
+        ksuctl_retry(request, buffer.as_mut_ptr())?;
+        let returned = u16::from_ne_bytes([buffer[0], buffer[1]]);
+        let total = u16::from_ne_bytes([buffer[2], buffer[3]]);
+        // the list grew in between, query again with room for it
+        if total > count {
+            count = total;
+            continue;
+        }
+
+        return Ok(buffer[HEADER_SIZE..]
+            .chunks_exact(UID_SIZE)
+            .take(usize::from(returned))
+            .map(|uid| u32::from_ne_bytes([uid[0], uid[1], uid[2], uid[3]]))
+            .collect());
+    }
+}
+
+/// What a uid rescan changed in the kernel's App Profiles
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+pub struct UidRescan {
+    /// Non-root profiles moved to the new uid of their reinstalled package
+    pub remapped: u32,
+    /// Profiles dropped because their package is gone, root grants whose appid changed included
+    pub pruned: u32,
+}
+
+/// Make the kernel re-read packages.list and follow packages whose uid changed
+pub fn rescan_uids() -> Result<UidRescan, KsuCallError> {
+    let mut cmd = uapi::ksu_rescan_uids_cmd {
+        remapped: 0,
+        pruned: 0,
+    };
+    ksuctl_retry(uapi::KSU_IOCTL_RESCAN_UIDS_RUST, &raw mut cmd)?;
+    Ok(UidRescan {
+        remapped: cmd.remapped,
+        pruned: cmd.pruned,
+    })
+}
+
+/// What SuSFS has registered in the kernel
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+pub struct SusfsState {
+    pub sus_path: u32,
+    pub sus_path_loop: u32,
+    pub sus_mount: u32,
+    pub sus_kstat: u32,
+    pub open_redirect: u32,
+    pub sus_map: u32,
+    pub log_enabled: bool,
+    pub hide_sus_mnts_for_non_su_procs: bool,
+    pub avc_log_spoofing: bool,
+}
+
+/// Ask the kernel for the SuSFS entry counts and toggles, Unsupported if its SuSFS can't report them
+pub fn get_susfs_state() -> Result<SusfsState, KsuCallError> {
+    let mut cmd = uapi::ksu_get_susfs_state_cmd {
+        sus_path: 0,
+        sus_path_loop: 0,
+        sus_mount: 0,
+        sus_kstat: 0,
+        open_redirect: 0,
+        sus_map: 0,
+        log_enabled: 0,
+        hide_sus_mnts_for_non_su_procs: 0,
+        avc_log_spoofing: 0,
+    };
+    ksuctl_retry(uapi::KSU_IOCTL_GET_SUSFS_STATE_RUST, &raw mut cmd)?;
+    Ok(SusfsState {
+        sus_path: cmd.sus_path,
+        sus_path_loop: cmd.sus_path_loop,
+        sus_mount: cmd.sus_mount,
+        sus_kstat: cmd.sus_kstat,
+        open_redirect: cmd.open_redirect,
+        sus_map: cmd.sus_map,
+        log_enabled: cmd.log_enabled != 0,
+        hide_sus_mnts_for_non_su_procs: cmd.hide_sus_mnts_for_non_su_procs != 0,
+        avc_log_spoofing: cmd.avc_log_spoofing != 0,
+    })
+}
+
 /// Set feature value in kernel
-pub fn set_feature(feature_id: u32, value: u64) -> std::io::Result<()> {
+pub fn set_feature(feature_id: u32, value: u64) -> Result<(), KsuCallError> {
     let mut cmd = uapi::ksu_set_feature_cmd { feature_id, value };
     ksuctl(uapi::KSU_IOCTL_SET_FEATURE_RUST, &raw mut cmd)?;
     Ok(())
 }
 
-pub fn get_wrapped_fd(fd: RawFd) -> std::io::Result<RawFd> {
+pub fn get_wrapped_fd(fd: RawFd) -> Result<RawFd, KsuCallError> {
     let mut cmd = uapi::ksu_get_wrapper_fd_cmd {
         fd: fd as u32,
         flags: 0,
@@ -205,14 +953,14 @@ pub fn get_wrapped_fd(fd: RawFd) -> std::io::Result<RawFd> {
     Ok(result)
 }
 
-pub fn get_sulog_fd() -> std::io::Result<RawFd> {
+pub fn get_sulog_fd() -> Result<RawFd, KsuCallError> {
     let mut cmd = uapi::ksu_get_sulog_fd_cmd { flags: 0 };
     let result = ksuctl(uapi::KSU_IOCTL_GET_SULOG_FD, &raw mut cmd)?;
     Ok(result)
 }
 
 /// Get mark status for a process (pid=0 returns total marked count)
-pub fn mark_get(pid: i32) -> std::io::Result<u32> {
+pub fn mark_get(pid: i32) -> Result<u32, KsuCallError> {
     let mut cmd = uapi::ksu_manage_mark_cmd {
         operation: uapi::KSU_MARK_GET_RUST,
         pid,
@@ -223,7 +971,7 @@ pub fn mark_get(pid: i32) -> std::io::Result<u32> {
 }
 
 /// Mark a process (pid=0 marks all processes)
-pub fn mark_set(pid: i32) -> std::io::Result<()> {
+pub fn mark_set(pid: i32) -> Result<(), KsuCallError> {
     let mut cmd = uapi::ksu_manage_mark_cmd {
         operation: uapi::KSU_MARK_MARK_RUST,
         pid,
@@ -234,7 +982,7 @@ pub fn mark_set(pid: i32) -> std::io::Result<()> {
 }
 
 /// Unmark a process (pid=0 unmarks all processes)
-pub fn mark_unset(pid: i32) -> std::io::Result<()> {
+pub fn mark_unset(pid: i32) -> Result<(), KsuCallError> {
     let mut cmd = uapi::ksu_manage_mark_cmd {
         operation: uapi::KSU_MARK_UNMARK_RUST,
         pid,
@@ -245,7 +993,7 @@ pub fn mark_unset(pid: i32) -> std::io::Result<()> {
 }
 
 /// Refresh mark for all running processes
-pub fn mark_refresh() -> std::io::Result<()> {
+pub fn mark_refresh() -> Result<(), KsuCallError> {
     let mut cmd = uapi::ksu_manage_mark_cmd {
         operation: uapi::KSU_MARK_REFRESH_RUST,
         pid: 0,
@@ -264,17 +1012,94 @@ pub fn nuke_ext4_sysfs(mnt: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
+impl std::error::Error for KsuCallError {}
+
+impl From<KsuCallError> for std::io::Error {
+    fn from(e: KsuCallError) -> Self {
+        Self::from_raw_os_error(e.errno)
+    }
+}
+
+/// Why the kernel rejected a ksucall
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KsuCallErrorKind {
+    /// The kernel doesn't know the command (ENOTTY, ENOSYS, EOPNOTSUPP)
+    Unsupported,
+    /// The kernel can't serve the command right now, e.g. early in boot (EAGAIN, EBUSY, EINTR)
+    Transient,
+    /// The caller isn't allowed to issue the command (EPERM, EACCES)
+    Permission,
+    /// The kernel rejected the arguments (EINVAL), also how older kernels reject unknown modes
+    Invalid,
+    Other(i32),
+}
+
+impl KsuCallErrorKind {
+    pub const fn from_errno(errno: i32) -> Self {
+        match errno {
+            libc::ENOTTY | libc::ENOSYS | libc::EOPNOTSUPP => Self::Unsupported,
+            libc::EAGAIN | libc::EBUSY | libc::EINTR => Self::Transient,
+            libc::EPERM | libc::EACCES => Self::Permission,
+            libc::EINVAL => Self::Invalid,
+            errno => Self::Other(errno),
+        }
+    }
+}
+
+/// Attempts for a ksucall that keeps failing with a transient error, the delay doubles each time
+const TRANSIENT_ATTEMPTS: u32 = 4;
+const TRANSIENT_DELAY: Duration = Duration::from_millis(10);
+
+/// Issue a ksucall, retrying transient failures with a bounded backoff
+fn ksuctl_retry<T>(request: u32, arg: *mut T) -> Result<i32, KsuCallError> {
+    let mut attempt = 1;
+    let mut delay = TRANSIENT_DELAY;
+    loop {
+        match ksuctl(request, arg) {
+            Err(e) if e.kind() == KsuCallErrorKind::Transient && attempt < TRANSIENT_ATTEMPTS => {
+                log::debug!("ksucall 0x{request:x} is busy, retrying in {delay:?}");
+                thread::sleep(delay);
+                attempt += 1;
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Issue an umount list command
+fn umount_ctl(cmd: &mut uapi::ksu_manage_try_umount_cmd) -> Result<i32, KsuCallError> {
+    ksuctl_retry(
+        uapi::KSU_IOCTL_MANAGE_TRY_UMOUNT_RUST,
+        std::ptr::from_mut(cmd),
+    )
+}
+
 /// Wipe all entries from umount list
-pub fn umount_list_wipe() -> std::io::Result<()> {
+pub fn umount_list_wipe() -> Result<(), KsuCallError> {
     let mut cmd = uapi::ksu_manage_try_umount_cmd {
         arg: 0,
         flags: 0,
         mode: uapi::KSU_UMOUNT_WIPE_RUST,
     };
-    ksuctl(uapi::KSU_IOCTL_MANAGE_TRY_UMOUNT_RUST, &raw mut cmd)?;
+    umount_ctl(&mut cmd)?;
     Ok(())
 }
 
+/// Empty the umount list, deleting the entries one by one on kernels that reject the wipe
+pub fn umount_list_clear() -> anyhow::Result<()> {
+    match umount_list_wipe() {
+        Err(e) if e.kind() == KsuCallErrorKind::Invalid => {
+            log::warn!("kernel rejected the umount list wipe, deleting entries one by one");
+            for info in umount_list_list()? {
+                umount_list_del(&info.path)?;
+            }
+            Ok(())
+        }
+        result => result.map_err(Into::into),
+    }
+}
+
 /// Add mount point to umount list
 pub fn umount_list_add(path: &str, flags: u32) -> anyhow::Result<()> {
     let c_path = std::ffi::CString::new(path)?;
@@ -283,10 +1108,78 @@ pub fn umount_list_add(path: &str, flags: u32) -> anyhow::Result<()> {
         flags,
         mode: uapi::KSU_UMOUNT_ADD_RUST,
     };
-    ksuctl(uapi::KSU_IOCTL_MANAGE_TRY_UMOUNT_RUST, &raw mut cmd)?;
+    umount_ctl(&mut cmd)?;
     Ok(())
 }
 
+/// Add mount point to umount list, applied only to processes matching `cond` (`KSU_UMOUNT_COND_*`)
+///
+/// Kernels without conditional entries reject this with EINVAL.
+pub fn umount_list_add_ext(path: &str, flags: u32, cond: u32, user_id: u32) -> anyhow::Result<()> {
+    let c_path = std::ffi::CString::new(path)?;
+    let mut ext = uapi::ksu_umount_add_ext {
+        path: c_path.as_ptr() as u64,
+        flags,
+        cond,
+        user_id,
+    };
+    let mut cmd = uapi::ksu_manage_try_umount_cmd {
+        arg: &raw mut ext as u64,
+        flags: 0,
+        mode: uapi::KSU_UMOUNT_ADD_EXT_RUST,
+    };
+    umount_ctl(&mut cmd)?;
+    Ok(())
+}
+
+/// One entry of a batched umount list add
+pub struct UmountAdd<'a> {
+    pub path: &'a str,
+    pub flags: u32,
+    pub cond: u32,
+    pub user_id: u32,
+}
+
+/// Whether the kernel can add umount entries in batches
+pub fn umount_list_can_batch() -> bool {
+    get_capabilities().is_some_and(|caps| caps & uapi::KSU_CAP_UMOUNT_BATCH_RUST != 0)
+}
+
+/// Add entries to the umount list, `KSU_UMOUNT_BATCH_MAX` per kernel call.
+///
+/// Returns each entry's status, 0 or a negative errno, in order. Callers should check
+/// `umount_list_can_batch` first, older kernels reject this with EINVAL.
+pub fn umount_list_add_batch(entries: &[UmountAdd]) -> anyhow::Result<Vec<i32>> {
+    let mut statuses = Vec::with_capacity(entries.len());
+    for chunk in entries.chunks(uapi::KSU_UMOUNT_BATCH_MAX_RUST as usize) {
+        let c_paths = chunk
+            .iter()
+            .map(|entry| std::ffi::CString::new(entry.path))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut batch: Vec<uapi::ksu_umount_batch_entry> = chunk
+            .iter()
+            .zip(&c_paths)
+            .map(|(entry, c_path)| uapi::ksu_umount_batch_entry {
+                add: uapi::ksu_umount_add_ext {
+                    path: c_path.as_ptr() as u64,
+                    flags: entry.flags,
+                    cond: entry.cond,
+                    user_id: entry.user_id,
+                },
+                status: 0,
+            })
+            .collect();
+        let mut cmd = uapi::ksu_manage_try_umount_cmd {
+            arg: batch.as_mut_ptr() as u64,
+            flags: batch.len() as u32,
+            mode: uapi::KSU_UMOUNT_ADD_BATCH_RUST,
+        };
+        umount_ctl(&mut cmd)?;
+        statuses.extend(batch.iter().map(|entry| entry.status));
+    }
+    Ok(statuses)
+}
+
 /// Delete mount point from umount list
 pub fn umount_list_del(path: &str) -> anyhow::Result<()> {
     let c_path = std::ffi::CString::new(path)?;
@@ -295,12 +1188,12 @@ pub fn umount_list_del(path: &str) -> anyhow::Result<()> {
         flags: 0,
         mode: uapi::KSU_UMOUNT_DEL_RUST,
     };
-    ksuctl(uapi::KSU_IOCTL_MANAGE_TRY_UMOUNT_RUST, &raw mut cmd)?;
+    umount_ctl(&mut cmd)?;
     Ok(())
 }
 
 /// Set current process's process group to init_group (pgid = 0)
-pub fn set_init_pgrp() -> std::io::Result<()> {
+pub fn set_init_pgrp() -> Result<(), KsuCallError> {
     ksuctl(
         uapi::KSU_IOCTL_SET_INIT_PGRP_RUST,
         std::ptr::null_mut::<u8>(),
@@ -361,47 +1254,131 @@ pub fn dynamic_manager_clear() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Signature index the kernel uses for the dynamic manager
+pub const DYNAMIC_MANAGER_SIGNATURE_INDEX: u8 = 255;
+
+/// A manager the kernel currently trusts
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+pub struct ManagerEntry {
+    pub appid: u32,
+    pub signature_index: u8,
+}
+
+/// The managers the kernel currently trusts
+pub fn get_managers() -> anyhow::Result<Vec<ManagerEntry>> {
+    const HEADER_SIZE: usize = std::mem::size_of::<uapi::ksu_get_managers_cmd>();
+    const ENTRY_SIZE: usize = std::mem::size_of::<uapi::ksu_manager_entry>();
+
+    let mut count = 0u16;
+    loop {
+        let mut buffer = vec![0u8; HEADER_SIZE + usize::from(count) * ENTRY_SIZE];
+        buffer[..2].copy_from_slice(&count.to_ne_bytes());
+        ksuctl(uapi::KSU_IOCTL_GET_MANAGERS_RUST, buffer.as_mut_ptr())?;
+        let total = u16::from_ne_bytes([buffer[2], buffer[3]]);
+        // a manager registered in between, query again with room for it
+        if total > count {
+            count = total;
+            continue;
+        }
+
+        return Ok(buffer[HEADER_SIZE..]
+            .chunks_exact(ENTRY_SIZE)
+            .take(usize::from(total))
+            .map(|entry| ManagerEntry {
+                appid: u32::from_ne_bytes([entry[0], entry[1], entry[2], entry[3]]),
+                signature_index: entry[4],
+            })
+            .collect());
+    }
+}
+
+/// Times to re-query the umount list when it grows between the size and list queries
+const UMOUNT_LIST_ATTEMPTS: usize = 3;
+
 /// List all mount points in umount list
 pub fn umount_list_list() -> anyhow::Result<Vec<MountInfo>> {
-    const FLAGS_SIZE: usize = std::mem::size_of::<u32>();
-    let mut total_size: usize = 0;
-    let mut size_cmd = uapi::ksu_manage_try_umount_cmd {
-        arg: &raw mut total_size as u64,
-        flags: 0,
-        mode: uapi::KSU_UMOUNT_GETSIZE_NEW_RUST,
-    };
-    ksuctl(uapi::KSU_IOCTL_MANAGE_TRY_UMOUNT_RUST, &raw mut size_cmd)?;
+    umount_list_get()?.ok_or_else(|| anyhow::anyhow!("kernel cannot report its umount list"))
+}
+
+/// The kernel's umount list with flags, None if the kernel lacks the query
+///
+/// The size is queried first, then the list is read into a buffer of that size. The buffer
+/// size is passed along so kernels that check it fail with ENOSPC instead of overrunning it
+/// when an entry was added in between, which is retried.
+pub fn umount_list_get() -> anyhow::Result<Option<Vec<MountInfo>>> {
+    for _ in 0..UMOUNT_LIST_ATTEMPTS {
+        // a size_t of the 64-bit kernel, wider than usize in a 32-bit ksud
+        let mut total_size: u64 = 0;
+        let mut size_cmd = uapi::ksu_manage_try_umount_cmd {
+            arg: &raw mut total_size as u64,
+            flags: 0,
+            mode: uapi::KSU_UMOUNT_GETSIZE_NEW_RUST,
+        };
+        match umount_ctl(&mut size_cmd) {
+            Ok(_) => {}
+            // older kernels reject unknown modes, kernels without the umount list the command
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    KsuCallErrorKind::Invalid | KsuCallErrorKind::Unsupported
+                ) =>
+            {
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        }
 
-    if total_size == 0 {
-        return Ok(vec![]);
+        if total_size == 0 {
+            return Ok(Some(vec![]));
+        }
+
+        let mut buffer = vec![0u8; usize::try_from(total_size)?];
+        let mut list_cmd = uapi::ksu_manage_try_umount_cmd {
+            arg: buffer.as_mut_ptr() as u64,
+            flags: u32::try_from(total_size)?,
+            mode: uapi::KSU_UMOUNT_GETLIST_NEW_RUST,
+        };
+        match umount_ctl(&mut list_cmd) {
+            Ok(_) => return parse_umount_list(&buffer).map(Some),
+            Err(KsuCallError {
+                errno: libc::ENOSPC,
+                ..
+            }) => {
+                log::debug!("umount list grew while reading it, retrying");
+            }
+            Err(e) => return Err(e.into()),
+        }
     }
+    bail!("umount list kept changing while reading it")
+}
 
-    let mut buffer = vec![0u8; total_size];
-    let mut list_cmd = uapi::ksu_manage_try_umount_cmd {
-        arg: buffer.as_mut_ptr() as u64,
-        flags: 0,
-        mode: uapi::KSU_UMOUNT_GETLIST_NEW_RUST,
-    };
-    ksuctl(uapi::KSU_IOCTL_MANAGE_TRY_UMOUNT_RUST, &raw mut list_cmd)?;
+/// Parse the `path\0flags` records of GETLIST_NEW
+fn parse_umount_list(buffer: &[u8]) -> anyhow::Result<Vec<MountInfo>> {
+    use anyhow::Context;
+    const FLAGS_SIZE: usize = std::mem::size_of::<u32>();
 
     let mut list = Vec::new();
     let mut cursor = 0;
     let len = buffer.len();
 
     while cursor < len {
-        use anyhow::Context;
         let null_pos = buffer[cursor..]
             .iter()
             .position(|&b| b == 0)
             .context("Malformed buffer: missing null terminator")?;
 
+        // the list shrank since the size query, the rest of the buffer is still zeroed
+        if null_pos == 0 {
+            break;
+        }
+
         let end_str = cursor + null_pos;
 
         let path = String::from_utf8_lossy(&buffer[cursor..end_str]).into_owned();
 
         cursor = end_str + 1;
 
-        if cursor + 4 > len {
+        if cursor + FLAGS_SIZE > len {
             break;
         }
 
@@ -418,3 +1395,432 @@ pub fn umount_list_list() -> anyhow::Result<Vec<MountInfo>> {
 
     Ok(list)
 }
+
+/// Per-entry hit counters of the umount list, None if the kernel doesn't keep them
+pub fn umount_list_stats() -> anyhow::Result<Option<Vec<UmountStat>>> {
+    // a kernel that can't report its list predates the counters too
+    let Some(list) = umount_list_get()? else {
+        return Ok(None);
+    };
+    let capacity = list.len();
+    if capacity == 0 {
+        return Ok(Some(vec![]));
+    }
+
+    // entries added in between are left out, the count returned by the kernel is authoritative
+    let mut buffer = vec![
+        uapi::ksu_umount_stat {
+            path: [0; 256],
+            hits: 0,
+            last_uid: -1,
+        };
+        capacity
+    ];
+    let mut cmd = uapi::ksu_manage_try_umount_cmd {
+        arg: buffer.as_mut_ptr() as u64,
+        flags: capacity as u32,
+        mode: uapi::KSU_UMOUNT_GETSTATS_RUST,
+    };
+    let count = match umount_ctl(&mut cmd) {
+        Ok(count) => count as usize,
+        // older kernels reject unknown modes
+        Err(e)
+            if matches!(
+                e.kind(),
+                KsuCallErrorKind::Invalid | KsuCallErrorKind::Unsupported
+            ) =>
+        {
+            return Ok(None);
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let stats = buffer
+        .iter()
+        .take(count.min(capacity))
+        .map(|stat| UmountStat {
+            // the kernel always leaves the last byte as NUL
+            path: unsafe { std::ffi::CStr::from_ptr(stat.path.as_ptr()) }
+                .to_string_lossy()
+                .into_owned(),
+            hits: stat.hits,
+            last_uid: u32::try_from(stat.last_uid).ok(),
+        })
+        .collect();
+    Ok(Some(stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    fn u32_at(payload: &[u8], offset: usize) -> u32 {
+        u32::from_ne_bytes(payload[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u64_at(payload: &[u8], offset: usize) -> u64 {
+        u64::from_ne_bytes(payload[offset..offset + 8].try_into().unwrap())
+    }
+
+    /// The NUL-terminated string at `addr`, only valid while the call is in flight
+    fn c_str_at(addr: u64) -> String {
+        unsafe { std::ffi::CStr::from_ptr(addr as *const libc::c_char) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Collect what `read` pulls out of each payload during the call
+    fn follow(read: fn(&[u8]) -> String) -> Arc<Mutex<Vec<String>>> {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&seen);
+        *MOCK.inspect.lock().unwrap() = Some(Box::new(move |_: u32, payload: &[u8]| {
+            sink.lock().unwrap().push(read(payload));
+        }));
+        seen
+    }
+
+    #[test]
+    fn command_numbers_match_the_kernel() {
+        // _IOC(dir, 'K', nr, 0)
+        assert_eq!(uapi::KSU_IOCTL_MANAGE_TRY_UMOUNT_RUST, 0x4000_4b12);
+        assert_eq!(uapi::KSU_IOCTL_GET_FEATURE_RUST, 0xc000_4b0d);
+        assert_eq!(uapi::KSU_IOCTL_MANAGE_MARK_RUST, 0xc000_4b10);
+        assert_eq!(uapi::KSU_IOCTL_SET_UID_UMOUNT_RUST, 0x4000_4b6c);
+    }
+
+    #[test]
+    fn umount_list_add_encodes_path_flags_and_mode() {
+        let _kernel = mock_kernel();
+        let paths = follow(|payload| c_str_at(u64_at(payload, 0)));
+
+        umount_list_add("/system/app/Foo", libc::MNT_DETACH as u32).unwrap();
+
+        let calls = MOCK.calls();
+        assert_eq!(calls.len(), 1);
+        let (request, payload) = &calls[0];
+        assert_eq!(*request, uapi::KSU_IOCTL_MANAGE_TRY_UMOUNT_RUST);
+        assert_eq!(payload.len(), 16);
+        assert_eq!(u32_at(payload, 8), libc::MNT_DETACH as u32);
+        assert_eq!(payload[12], uapi::KSU_UMOUNT_ADD_RUST);
+        assert_eq!(*paths.lock().unwrap(), ["/system/app/Foo"]);
+    }
+
+    #[test]
+    fn umount_list_del_and_wipe_encode_their_modes() {
+        let _kernel = mock_kernel();
+        let paths = follow(|payload| {
+            let addr = u64_at(payload, 0);
+            if addr == 0 {
+                String::new()
+            } else {
+                c_str_at(addr)
+            }
+        });
+
+        umount_list_del("/vendor").unwrap();
+        umount_list_wipe().unwrap();
+
+        let calls = MOCK.calls();
+        assert_eq!(calls[0].1[12], uapi::KSU_UMOUNT_DEL_RUST);
+        assert_eq!(u32_at(&calls[0].1, 8), 0);
+        assert_eq!(calls[1].1[12], uapi::KSU_UMOUNT_WIPE_RUST);
+        assert_eq!(u64_at(&calls[1].1, 0), 0);
+        assert_eq!(*paths.lock().unwrap(), ["/vendor", ""]);
+    }
+
+    #[test]
+    fn umount_list_add_ext_points_at_the_extended_entry() {
+        let _kernel = mock_kernel();
+        let entries = follow(|payload| {
+            assert_eq!(payload[12], uapi::KSU_UMOUNT_ADD_EXT_RUST);
+            let ext = unsafe {
+                std::slice::from_raw_parts(
+                    u64_at(payload, 0) as *const u8,
+                    size_of::<uapi::ksu_umount_add_ext>(),
+                )
+            };
+            format!(
+                "{} {} {} {}",
+                c_str_at(u64_at(ext, 0)),
+                u32_at(ext, 8),
+                u32_at(ext, 12),
+                u32_at(ext, 16)
+            )
+        });
+
+        umount_list_add_ext(
+            "/data/adb/modules",
+            2,
+            uapi::KSU_UMOUNT_COND_USER_ID_RUST,
+            10,
+        )
+        .unwrap();
+
+        assert_eq!(
+            *entries.lock().unwrap(),
+            [format!(
+                "/data/adb/modules 2 {} 10",
+                uapi::KSU_UMOUNT_COND_USER_ID_RUST
+            )]
+        );
+    }
+
+    #[test]
+    fn umount_list_get_takes_a_64_bit_size() {
+        let _kernel = mock_kernel();
+        let mut record = b"/vendor\0".to_vec();
+        record.extend_from_slice(&2u32.to_ne_bytes());
+        *MOCK.inspect.lock().unwrap() = Some(Box::new(move |_: u32, payload: &[u8]| {
+            let arg = u64_at(payload, 0) as *mut u8;
+            match payload[12] {
+                uapi::KSU_UMOUNT_GETSIZE_NEW_RUST => unsafe {
+                    arg.cast::<u64>().write_unaligned(record.len() as u64);
+                },
+                uapi::KSU_UMOUNT_GETLIST_NEW_RUST => {
+                    assert_eq!(u32_at(payload, 8) as usize, record.len());
+                    unsafe { std::ptr::copy_nonoverlapping(record.as_ptr(), arg, record.len()) };
+                }
+                mode => panic!("unexpected umount mode {mode}"),
+            }
+        }));
+
+        let list = umount_list_get().unwrap().unwrap();
+
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].path, "/vendor");
+        assert_eq!(list[0].flags, 2);
+    }
+
+    #[test]
+    fn umount_stats_are_unsupported_without_the_list_queries() {
+        let _kernel = mock_kernel();
+        for errno in [libc::EINVAL, libc::ENOTTY] {
+            MOCK.reply(Err(std::io::Error::from_raw_os_error(errno)), vec![]);
+            assert!(umount_list_stats().unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn get_feature_sends_the_id_and_decodes_the_reply() {
+        let _kernel = mock_kernel();
+        let mut reply = vec![0u8; size_of::<uapi::ksu_get_feature_cmd>()];
+        reply[..4].copy_from_slice(&7u32.to_ne_bytes());
+        reply[8..16].copy_from_slice(&42u64.to_ne_bytes());
+        reply[16] = 1;
+        MOCK.reply(Ok(0), reply);
+
+        assert_eq!(get_feature(7).unwrap(), (42, true));
+
+        let calls = MOCK.calls();
+        assert_eq!(calls[0].0, uapi::KSU_IOCTL_GET_FEATURE_RUST);
+        assert_eq!(calls[0].1.len(), 24);
+        assert_eq!(u32_at(&calls[0].1, 0), 7);
+    }
+
+    #[test]
+    fn set_uid_umount_encodes_uid_mode_and_package() {
+        let _kernel = mock_kernel();
+
+        set_uid_umount(10123, "com.example.app", UidUmountMode::Off).unwrap();
+
+        let calls = MOCK.calls();
+        let (request, payload) = &calls[0];
+        assert_eq!(*request, uapi::KSU_IOCTL_SET_UID_UMOUNT_RUST);
+        assert_eq!(payload.len(), 264);
+        assert_eq!(u32_at(payload, 0), 10123);
+        assert_eq!(payload[4], uapi::KSU_UID_UMOUNT_OFF_RUST);
+        assert_eq!(&payload[5..20], b"com.example.app");
+        assert!(payload[20..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn mark_calls_encode_operation_and_pid() {
+        let _kernel = mock_kernel();
+        let mut reply = vec![0u8; 12];
+        reply[8..].copy_from_slice(&3u32.to_ne_bytes());
+        MOCK.reply(Ok(0), vec![]);
+        MOCK.reply(Ok(0), reply);
+
+        mark_set(1234).unwrap();
+        assert_eq!(mark_get(0).unwrap(), 3);
+
+        let calls = MOCK.calls();
+        assert_eq!(calls[0].0, uapi::KSU_IOCTL_MANAGE_MARK_RUST);
+        assert_eq!(u32_at(&calls[0].1, 0), uapi::KSU_MARK_MARK_RUST);
+        assert_eq!(u32_at(&calls[0].1, 4), 1234);
+        assert_eq!(u32_at(&calls[1].1, 0), uapi::KSU_MARK_GET_RUST);
+        assert_eq!(u32_at(&calls[1].1, 4), 0);
+    }
+
+    #[test]
+    fn transient_failures_are_retried() {
+        let _kernel = mock_kernel();
+        MOCK.reply(Err(std::io::Error::from_raw_os_error(libc::EBUSY)), vec![]);
+
+        umount_list_del("/vendor").unwrap();
+
+        assert_eq!(MOCK.calls().len(), 2);
+    }
+
+    #[test]
+    fn failures_carry_their_call_and_errno() {
+        let _kernel = mock_kernel();
+        MOCK.reply(Err(std::io::Error::from_raw_os_error(libc::ENOTTY)), vec![]);
+
+        let error = umount_list_wipe().unwrap_err();
+
+        assert_eq!(
+            error,
+            KsuCallError {
+                request: uapi::KSU_IOCTL_MANAGE_TRY_UMOUNT_RUST,
+                errno: libc::ENOTTY,
+            }
+        );
+        assert_eq!(error.kind(), KsuCallErrorKind::Unsupported);
+        assert_eq!(error.name(), Some("manage_try_umount"));
+        assert!(error.to_string().contains("errno 25"));
+    }
+
+    #[test]
+    fn unrelated_errors_are_not_taken_for_ksucall_failures() {
+        let _kernel = mock_kernel();
+        MOCK.reply(Err(std::io::Error::from_raw_os_error(libc::ENOENT)), vec![]);
+        assert!(umount_list_del("/vendor").is_err());
+
+        let error = anyhow::Error::from(std::io::Error::from_raw_os_error(libc::ENOENT))
+            .context("failed to read /data/adb/ksu/.umount");
+
+        assert_eq!(KsuCallError::of(&error), None);
+    }
+
+    /// Serve `paths` as the kernel umount list and collect the paths of DEL calls
+    fn serve_umount_list(paths: &[&str]) -> Arc<Mutex<Vec<String>>> {
+        let mut records = Vec::new();
+        for path in paths {
+            records.extend_from_slice(path.as_bytes());
+            records.push(0);
+            records.extend_from_slice(&(libc::MNT_DETACH as u32).to_ne_bytes());
+        }
+        let deleted = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&deleted);
+        *MOCK.inspect.lock().unwrap() = Some(Box::new(move |_: u32, payload: &[u8]| {
+            let arg = u64_at(payload, 0) as *mut u8;
+            match payload[12] {
+                uapi::KSU_UMOUNT_WIPE_RUST => {}
+                uapi::KSU_UMOUNT_GETSIZE_NEW_RUST => unsafe {
+                    arg.cast::<u64>().write_unaligned(records.len() as u64);
+                },
+                uapi::KSU_UMOUNT_GETLIST_NEW_RUST => unsafe {
+                    std::ptr::copy_nonoverlapping(records.as_ptr(), arg, records.len());
+                },
+                uapi::KSU_UMOUNT_DEL_RUST => sink.lock().unwrap().push(c_str_at(arg as u64)),
+                mode => panic!("unexpected umount mode {mode}"),
+            }
+        }));
+        deleted
+    }
+
+    #[test]
+    fn umount_list_clear_wipes_in_one_call() {
+        let _kernel = mock_kernel();
+        let deleted = serve_umount_list(&["/vendor", "/system/app/Foo"]);
+
+        umount_list_clear().unwrap();
+
+        assert_eq!(MOCK.calls().len(), 1);
+        assert!(deleted.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn umount_list_clear_deletes_one_by_one_when_the_wipe_is_rejected() {
+        let _kernel = mock_kernel();
+        let deleted = serve_umount_list(&["/vendor", "/system/app/Foo"]);
+        MOCK.reply(Err(std::io::Error::from_raw_os_error(libc::EINVAL)), vec![]);
+
+        umount_list_clear().unwrap();
+
+        assert_eq!(*deleted.lock().unwrap(), ["/vendor", "/system/app/Foo"]);
+    }
+
+    #[test]
+    fn umount_list_clear_does_not_fall_back_without_the_list() {
+        let _kernel = mock_kernel();
+        let deleted = serve_umount_list(&["/vendor"]);
+        MOCK.reply(Err(std::io::Error::from_raw_os_error(libc::ENOTTY)), vec![]);
+
+        assert!(umount_list_clear().is_err());
+
+        assert_eq!(MOCK.calls().len(), 1);
+        assert!(deleted.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn unversioned_kernels_are_supported() {
+        assert!(uapi_supported(0));
+        assert!(!uapi_supported(1));
+        assert!(uapi_supported(MIN_SUPPORTED_UAPI));
+        assert!(uapi_supported(uapi_version()));
+        assert!(!uapi_supported(uapi_version() + 1));
+    }
+
+    #[test]
+    fn gated_calls_need_their_capability() {
+        let enforce = uapi::KSU_IOCTL_SET_ENFORCE_MODE_RUST;
+        let umount = uapi::KSU_IOCTL_MANAGE_TRY_UMOUNT_RUST;
+        let caps = Some(uapi::KSU_CAP_ENFORCE_MODE_RUST);
+
+        assert!(capability_present(caps, enforce));
+        assert!(!capability_present(Some(0), enforce));
+        assert!(!capability_present(None, enforce));
+        // calls older than the capability query never need one
+        assert!(capability_present(None, umount));
+    }
+
+    #[test]
+    fn errnos_are_classified() {
+        use KsuCallErrorKind::*;
+
+        for (errno, kind) in [
+            (libc::ENOTTY, Unsupported),
+            (libc::ENOSYS, Unsupported),
+            (libc::EOPNOTSUPP, Unsupported),
+            (libc::EAGAIN, Transient),
+            (libc::EBUSY, Transient),
+            (libc::EINTR, Transient),
+            (libc::EPERM, Permission),
+            (libc::EACCES, Permission),
+            (libc::EINVAL, Invalid),
+            (libc::ENOENT, Other(libc::ENOENT)),
+            (libc::ENOSPC, Other(libc::ENOSPC)),
+        ] {
+            assert_eq!(KsuCallErrorKind::from_errno(errno), kind, "errno {errno}");
+        }
+    }
+
+    #[test]
+    fn transient_failures_give_up_after_the_last_attempt() {
+        let _kernel = mock_kernel();
+        for _ in 0..TRANSIENT_ATTEMPTS {
+            MOCK.reply(Err(std::io::Error::from_raw_os_error(libc::EAGAIN)), vec![]);
+        }
+
+        let error = umount_list_wipe().unwrap_err();
+
+        assert_eq!(error.kind(), KsuCallErrorKind::Transient);
+        assert_eq!(MOCK.calls().len(), TRANSIENT_ATTEMPTS as usize);
+    }
+
+    #[test]
+    fn other_failures_are_not_retried() {
+        let _kernel = mock_kernel();
+        for errno in [libc::EINVAL, libc::EPERM, libc::ENOTTY] {
+            MOCK.reset();
+            MOCK.reply(Err(std::io::Error::from_raw_os_error(errno)), vec![]);
+
+            assert!(umount_list_wipe().is_err());
+            assert_eq!(MOCK.calls().len(), 1, "errno {errno}");
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Inspect, load and unload the KernelSU LKM
+
+use std::{ffi::CString, fs, path::Path};
+
+use anyhow::{Context, Result, bail, ensure};
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::{
+    android::{ksucalls, unload},
+    assets,
+    boot_patch::{get_current_kmi, parse_kmi},
+};
+
+const MODULE_NAME: &str = "kernelsu";
+
+#[derive(Serialize)]
+struct LkmStatus {
+    loaded: bool,
+    size: Option<u64>,
+    refcount: Option<u32>,
+    state: Option<String>,
+    /// Taint flags of the module, e.g. "OE"
+    taint: Option<String>,
+    /// /proc/sys/kernel/tainted, the taint mask of the whole kernel
+    kernel_taint: Option<u64>,
+    /// Version reported by the driver, 0 if it doesn't answer
+    version: i32,
+    mode: &'static str,
+}
+
+/// The /proc/modules line of the KernelSU LKM, split into fields
+fn proc_modules_entry() -> Option<Vec<String>> {
+    fs::read_to_string("/proc/modules")
+        .ok()?
+        .lines()
+        .find(|line| line.split_whitespace().next() == Some(MODULE_NAME))
+        .map(|line| line.split_whitespace().map(str::to_string).collect())
+}
+
+/// Show whether the LKM is loaded, its version and taint state
+pub fn status(json: bool) -> Result<()> {
+    // name size refcount deps state address [(taint)]
+    let entry = proc_modules_entry();
+    let field = |i: usize| entry.as_ref().and_then(|e| e.get(i));
+    let status = LkmStatus {
+        loaded: entry.is_some(),
+        size: field(1).and_then(|s| s.parse().ok()),
+        refcount: field(2).and_then(|s| s.parse().ok()),
+        state: field(4).cloned(),
+        taint: field(6).map(|t| t.trim_matches(['(', ')']).to_string()),
+        kernel_taint: fs::read_to_string("/proc/sys/kernel/tainted")
+            .ok()
+            .and_then(|s| s.trim().parse().ok()),
+        version: ksucalls::get_version(),
+        mode: ksucalls::runtime_mode(),
+    };
+
+    if json {
+        println!("{}", serde_json::to_string(&status)?);
+        return Ok(());
+    }
+    if status.loaded {
+        println!(
+            "{MODULE_NAME}: loaded, {} bytes, {} references, {}",
+            status.size.unwrap_or_default(),
+            status.refcount.unwrap_or_default(),
+            status.state.as_deref().unwrap_or("unknown state")
+        );
+        println!("taint: {}", status.taint.as_deref().unwrap_or("none"));
+    } else {
+        println!("{MODULE_NAME}: not loaded");
+    }
+    if let Some(mask) = status.kernel_taint {
+        println!("kernel taint mask: {mask}");
+    }
+    if status.version > 0 {
+        println!("driver: version {} ({})", status.version, status.mode);
+    } else {
+        println!("driver: not answering");
+    }
+    Ok(())
+}
+
+/// The vermagic string in the module's .modinfo
+fn module_vermagic(data: &[u8]) -> Option<String> {
+    const KEY: &[u8] = b"vermagic=";
+    let start = data.windows(KEY.len()).position(|w| w == KEY)? + KEY.len();
+    let len = data[start..].iter().position(|&b| b == 0)?;
+    Some(String::from_utf8_lossy(&data[start..start + len]).into_owned())
+}
+
+/// Refuse modules built for another KMI, the loader only patches the release in vermagic
+fn check_vermagic(data: &[u8]) -> Result<()> {
+    let Some(vermagic) = module_vermagic(data) else {
+        warn!("module has no vermagic, loading anyway");
+        return Ok(());
+    };
+    let uname = rustix::system::uname();
+    let release = uname.release().to_string_lossy();
+    let module_release = vermagic.split_whitespace().next().unwrap_or_default();
+    if module_release == release {
+        return Ok(());
+    }
+
+    match (parse_kmi(module_release), parse_kmi(&release)) {
+        (Ok(module_kmi), Ok(kernel_kmi)) if module_kmi != kernel_kmi => bail!(
+            "module is built for KMI {module_kmi} ({module_release}), the kernel is {kernel_kmi} ({release})"
+        ),
+        (Ok(_), Ok(_)) => {
+            info!("module vermagic {module_release} differs from {release}, same KMI, patching");
+            Ok(())
+        }
+        _ => {
+            warn!("cannot compare module vermagic {module_release} with {release}, loading anyway");
+            Ok(())
+        }
+    }
+}
+
+/// Explain why init_module refused the module
+fn describe_load_error(error: &anyhow::Error) -> Option<&'static str> {
+    let errno = error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<rustix::io::Errno>())?
+        .raw_os_error();
+    Some(match errno {
+        libc::EEXIST => "a module with this name is already loaded",
+        libc::ENOEXEC => "not a valid module for this kernel, check the architecture",
+        libc::EKEYREJECTED | libc::EBADMSG => "the kernel enforces module signatures",
+        libc::EPERM => {
+            "not permitted: modules may be disabled (kernel.modules_disabled) or SELinux denied it"
+        }
+        libc::ENOENT => "the module needs kernel symbols this kernel lacks, check dmesg",
+        libc::EINVAL => {
+            "the kernel rejected the module, usually a symbol CRC or parameter mismatch, check dmesg"
+        }
+        libc::ENOMEM => "out of memory",
+        libc::EBUSY => "the kernel is busy loading another module, try again",
+        _ => return None,
+    })
+}
+
+/// Load `path`, or the bundled module for the running KMI
+pub fn load(path: Option<&Path>, params: &[String]) -> Result<()> {
+    ensure!(
+        proc_modules_entry().is_none(),
+        "{MODULE_NAME} is already loaded, unload it first"
+    );
+
+    let (name, data) = match path {
+        Some(path) => (
+            path.display().to_string(),
+            fs::read(path).with_context(|| format!("failed to read {}", path.display()))?,
+        ),
+        None => {
+            let kmi = get_current_kmi().context("failed to detect the current KMI")?;
+            let name = format!("{kmi}_kernelsu.ko");
+            let data = assets::get_asset(&name)
+                .with_context(|| format!("no bundled module for KMI {kmi}"))?
+                .into_owned();
+            (name, data)
+        }
+    };
+    check_vermagic(&data)?;
+
+    let cparams = CString::new(params.join(" "))?;
+    if let Err(e) = ksuinit::load_module(&data, &cparams) {
+        return Err(match describe_load_error(&e) {
+            Some(reason) => e.context(format!("failed to load {name}: {reason}")),
+            None => e.context(format!("failed to load {name}")),
+        });
+    }
+    println!("Loaded {name}");
+    Ok(())
+}
+
+/// Unload the LKM, this stops Android services and kills every root process on the way
+pub fn unload(confirmed: bool) -> Result<()> {
+    ensure!(
+        proc_modules_entry().is_some(),
+        "{MODULE_NAME} is not loaded as a module"
+    );
+    ensure!(
+        confirmed,
+        "unloading restarts Android and kills all root processes, pass --force-confirm"
+    );
+    unload::unload()
+}
@@ -1,10 +1,15 @@
+mod allowlist;
 pub mod cli;
+mod config_lock;
 mod debug;
 mod dynamic_manager;
+mod enforce;
 mod feature;
+mod heartbeat;
 mod init_event;
 mod ksucalls;
 mod late_load;
+mod lkm;
 mod module;
 mod profile;
 mod resetprop;
@@ -0,0 +1,208 @@
+//! Files shipped by more than one module, `ksud module conflicts`
+//!
+//! Only one copy of such a file is visible once mounted. Modules are taken to be stacked in
+//! module id order with the first one on top, the way overlayfs orders lower dirs; the metamodule
+//! has the final say.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use anyhow::{Result, bail};
+use jwalk::{Parallelism::Serial, WalkDir};
+use log::warn;
+use serde::Serialize;
+
+use crate::android::module::{MODULE_PARTITIONS, ModuleType::Active, foreach_module, skip_mount};
+
+/// Paths listed per module in an install warning
+const SAMPLE_PATHS: usize = 5;
+/// Marks a directory the module replaces as a whole, not a file it ships
+const REPLACE_FILE: &str = ".replace";
+
+static STRICT_ON_INSTALL: AtomicBool = AtomicBool::new(false);
+
+/// Refuse installs that conflict with an installed module, `--strict-conflicts`
+pub fn set_strict_on_install(strict: bool) {
+    STRICT_ON_INSTALL.store(strict, Ordering::Relaxed);
+}
+
+#[derive(Debug, Serialize)]
+pub struct Conflict {
+    pub path: String,
+    /// The module whose copy is visible
+    pub winner: String,
+    pub losers: Vec<String>,
+}
+
+/// Where the files of the module in `module_dir` end up, `/system/...` or `/<partition>/...`.
+///
+/// `system/<partition>` counts as `/<partition>`, both layouts reach the same files.
+pub fn mounted_files(module_dir: &Path) -> HashSet<String> {
+    let mut files = HashSet::new();
+    if module_dir.join("skip_mount").exists() {
+        return files;
+    }
+    let skipped: Vec<&str> = skip_mount::skipped(module_dir)
+        .into_iter()
+        .map(|(partition, _)| partition)
+        .collect();
+    for partition in std::iter::once("system").chain(MODULE_PARTITIONS) {
+        let root = module_dir.join(partition);
+        if !root.is_dir() {
+            continue;
+        }
+        for entry in WalkDir::new(&root)
+            .parallelism(Serial)
+            .skip_hidden(false)
+            .into_iter()
+            .flatten()
+        {
+            if entry.file_type().is_dir() || entry.file_name() == REPLACE_FILE {
+                continue;
+            }
+            let Ok(relative) = entry.path().strip_prefix(&root).map(Path::to_path_buf) else {
+                continue;
+            };
+            let relative = relative.to_string_lossy().into_owned();
+            let (target, rest) = match relative.split_once('/') {
+                Some((first, rest))
+                    if partition == "system" && MODULE_PARTITIONS.contains(&first) =>
+                {
+                    (first.to_string(), rest.to_string())
+                }
+                // system/<partition> symlinked to the moved partition is not a file of its own
+                None if partition == "system"
+                    && MODULE_PARTITIONS.contains(&relative.as_str())
+                    && entry.file_type().is_symlink() =>
+                {
+                    continue;
+                }
+                _ => (partition.to_string(), relative.clone()),
+            };
+            if skipped.contains(&target.as_str()) {
+                continue;
+            }
+            files.insert(format!("/{target}/{rest}"));
+        }
+    }
+    files
+}
+
+/// Active modules with their files, in mount order
+fn active_modules() -> Result<Vec<(String, HashSet<String>)>> {
+    let mut modules: Vec<(String, PathBuf)> = Vec::new();
+    foreach_module(Active, |module_dir| {
+        if let Some(id) = module_dir.file_name().and_then(|n| n.to_str()) {
+            modules.push((id.to_string(), module_dir.to_path_buf()));
+        }
+        Ok(())
+    })?;
+    modules.sort();
+    Ok(modules
+        .into_iter()
+        .map(|(id, dir)| {
+            let files = mounted_files(&dir);
+            (id, files)
+        })
+        .collect())
+}
+
+/// Every file shipped by more than one of `modules`, which are in mount order
+fn find_conflicts(modules: &[(String, HashSet<String>)]) -> Vec<Conflict> {
+    let mut owners: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (id, files) in modules {
+        for file in files {
+            owners.entry(file).or_default().push(id);
+        }
+    }
+    owners
+        .into_iter()
+        .filter(|(_, ids)| ids.len() > 1)
+        .map(|(path, ids)| Conflict {
+            path: path.to_string(),
+            winner: ids[0].to_string(),
+            losers: ids[1..].iter().map(ToString::to_string).collect(),
+        })
+        .collect()
+}
+
+/// Print the files enabled modules fight over, and whose copy wins
+pub fn list(json: bool) -> Result<()> {
+    let conflicts = find_conflicts(&active_modules()?);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&conflicts)?);
+        return Ok(());
+    }
+    if conflicts.is_empty() {
+        println!("No conflicts between enabled modules");
+        return Ok(());
+    }
+    for conflict in &conflicts {
+        println!(
+            "{}: {} wins over {}",
+            conflict.path,
+            conflict.winner,
+            conflict.losers.join(", ")
+        );
+    }
+    println!("{} files shipped by more than one module", conflicts.len());
+    Ok(())
+}
+
+/// Warn about files the module being installed to `module_dir` shares with enabled modules, or
+/// refuse the install with `--strict-conflicts`
+pub fn check_install(id: &str, module_dir: &Path) -> Result<()> {
+    let files = mounted_files(module_dir);
+    if files.is_empty() {
+        return Ok(());
+    }
+    let mut modules: Vec<(String, HashSet<String>)> = active_modules()?
+        .into_iter()
+        .filter(|(other, _)| other != id)
+        .collect();
+    modules.push((id.to_string(), files));
+    modules.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut by_module: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for conflict in find_conflicts(&modules) {
+        let others = std::iter::once(&conflict.winner).chain(&conflict.losers);
+        if !others.clone().any(|module| module == id) {
+            continue;
+        }
+        for other in others.filter(|module| *module != id) {
+            by_module
+                .entry(other.clone())
+                .or_default()
+                .push(conflict.path.clone());
+        }
+    }
+    if by_module.is_empty() {
+        return Ok(());
+    }
+
+    for (other, paths) in &by_module {
+        let wins = id.min(other.as_str());
+        println!(
+            "- Warning: {} files also shipped by {other}, {wins}'s copy wins:",
+            paths.len()
+        );
+        for path in paths.iter().take(SAMPLE_PATHS) {
+            println!("-   {path}");
+        }
+        if paths.len() > SAMPLE_PATHS {
+            println!("-   ... see `ksud module conflicts` after reboot");
+        }
+    }
+    let others: Vec<&str> = by_module.keys().map(String::as_str).collect();
+    if STRICT_ON_INSTALL.load(Ordering::Relaxed) {
+        bail!(
+            "module {id} conflicts with {}, refusing to install (--strict-conflicts)",
+            others.join(", ")
+        );
+    }
+    warn!("module {id} conflicts with {}", others.join(", "));
+    Ok(())
+}
@@ -0,0 +1,349 @@
+//! Module dependencies, declared in module.prop as `dependencies=<id>[>=<versionCode>],...`
+
+use std::{
+    collections::HashMap,
+    fmt,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use anyhow::{Result, bail};
+use log::{info, warn};
+
+use crate::{
+    android::module::{read_module_prop, validate_module_id},
+    defs,
+};
+
+const DEPENDENCIES_KEY: &str = "dependencies";
+/// Marks a skip_mount file created by ksud for a module with unmet dependencies
+const SKIP_MOUNT_MARKER: &str = ".deps_skip_mount";
+const SKIP_MOUNT_FILE: &str = "skip_mount";
+
+static IGNORE_ON_INSTALL: AtomicBool = AtomicBool::new(false);
+
+/// Install modules with unmet dependencies with a warning, `--ignore-deps`
+pub fn set_ignore_on_install(ignore: bool) {
+    IGNORE_ON_INSTALL.store(ignore, Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone)]
+pub struct Dependency {
+    pub id: String,
+    pub min_version_code: Option<i64>,
+}
+
+impl fmt::Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.min_version_code {
+            Some(min) => write!(f, "{}>={min}", self.id),
+            None => write!(f, "{}", self.id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Met,
+    Missing,
+    /// Installed but disabled, being removed, or itself missing a dependency
+    Inactive,
+    TooOld {
+        installed: i64,
+    },
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Met => write!(f, "met"),
+            Self::Missing => write!(f, "missing"),
+            Self::Inactive => write!(f, "inactive"),
+            Self::TooOld { installed } => write!(f, "too old, versionCode {installed}"),
+        }
+    }
+}
+
+/// Parse the `dependencies` value of a module.prop
+pub fn parse(value: &str) -> Result<Vec<Dependency>> {
+    let mut dependencies = Vec::new();
+    for item in value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+    {
+        let (id, min_version_code) = match item.split_once(">=") {
+            Some((id, min)) => {
+                let Ok(min) = min.trim().parse() else {
+                    bail!("invalid versionCode in dependency '{item}'");
+                };
+                (id.trim(), Some(min))
+            }
+            None => (item, None),
+        };
+        validate_module_id(id)?;
+        dependencies.push(Dependency {
+            id: id.to_string(),
+            min_version_code,
+        });
+    }
+    Ok(dependencies)
+}
+
+fn declared(module_prop: &HashMap<String, String>) -> Result<Vec<Dependency>> {
+    match module_prop.get(DEPENDENCIES_KEY) {
+        Some(value) => parse(value),
+        None => Ok(Vec::new()),
+    }
+}
+
+struct Installed {
+    version_code: i64,
+    active: bool,
+    dependencies: Vec<Dependency>,
+}
+
+fn version_code(module_prop: &HashMap<String, String>) -> i64 {
+    module_prop
+        .get("versionCode")
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+fn installed(id: &str, path: &Path, module_prop: &HashMap<String, String>) -> Installed {
+    let dependencies = declared(module_prop).unwrap_or_else(|e| {
+        warn!("module {id} declares invalid dependencies: {e}");
+        Vec::new()
+    });
+    let active =
+        !path.join(defs::DISABLE_FILE_NAME).exists() && !path.join(defs::REMOVE_FILE_NAME).exists();
+    Installed {
+        version_code: version_code(module_prop),
+        active,
+        dependencies,
+    }
+}
+
+/// The modules in `dir` by id
+fn scan(dir: &str) -> HashMap<String, Installed> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return HashMap::new();
+    };
+    let mut modules = HashMap::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(id) = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map(str::to_string)
+        else {
+            continue;
+        };
+        let Ok(module_prop) = read_module_prop(&path) else {
+            continue;
+        };
+        let module = installed(&id, &path, &module_prop);
+        modules.insert(id, module);
+    }
+    modules
+}
+
+fn status(dependency: &Dependency, modules: &HashMap<String, Installed>) -> Status {
+    match modules.get(&dependency.id) {
+        None => Status::Missing,
+        Some(module) if !module.active => Status::Inactive,
+        Some(module) => match dependency.min_version_code {
+            Some(min) if module.version_code < min => Status::TooOld {
+                installed: module.version_code,
+            },
+            _ => Status::Met,
+        },
+    }
+}
+
+/// Deactivate modules with unmet dependencies until none is left, so chains resolve too
+fn resolve(modules: &mut HashMap<String, Installed>) {
+    loop {
+        let blocked: Vec<String> = modules
+            .iter()
+            .filter(|(_, module)| module.active)
+            .filter(|(_, module)| {
+                module
+                    .dependencies
+                    .iter()
+                    .any(|dep| status(dep, modules) != Status::Met)
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+        if blocked.is_empty() {
+            return;
+        }
+        for id in blocked {
+            if let Some(module) = modules.get_mut(&id) {
+                module.active = false;
+            }
+        }
+    }
+}
+
+/// The installed modules, with dependency chains resolved
+pub struct Resolved(HashMap<String, Installed>);
+
+pub fn resolved() -> Resolved {
+    let mut modules = scan(defs::MODULE_DIR);
+    resolve(&mut modules);
+    Resolved(modules)
+}
+
+/// Resolve modules whose module.prop was already read, by id and module dir
+pub fn resolved_from<'a>(
+    modules: impl IntoIterator<Item = (&'a str, &'a Path, &'a HashMap<String, String>)>,
+) -> Resolved {
+    let mut modules = modules
+        .into_iter()
+        .map(|(id, path, module_prop)| (id.to_string(), installed(id, path, module_prop)))
+        .collect();
+    resolve(&mut modules);
+    Resolved(modules)
+}
+
+impl Resolved {
+    /// Whether module `id` runs, false if it is disabled, removed or blocked by a dependency
+    pub fn is_active(&self, id: &str) -> bool {
+        self.0.get(id).is_some_and(|module| module.active)
+    }
+}
+
+/// The declared dependencies of a module with the status of each, empty if they don't parse
+pub fn statuses(
+    module_prop: &HashMap<String, String>,
+    resolved: &Resolved,
+) -> Vec<(Dependency, Status)> {
+    declared(module_prop)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|dep| {
+            let status = status(&dep, &resolved.0);
+            (dep, status)
+        })
+        .collect()
+}
+
+/// Whether all dependencies of a module are met, and the status of each, for `ksud module list`
+pub fn describe(
+    module_prop: &HashMap<String, String>,
+    resolved: &Resolved,
+) -> Option<(bool, String)> {
+    let statuses: Vec<(String, Status)> = statuses(module_prop, resolved)
+        .into_iter()
+        .map(|(dep, status)| (dep.to_string(), status))
+        .collect();
+    if statuses.is_empty() {
+        return None;
+    }
+    let met = statuses.iter().all(|(_, status)| *status == Status::Met);
+    let text = statuses
+        .iter()
+        .map(|(dep, status)| format!("{dep}: {status}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    Some((met, text))
+}
+
+/// Modules that are enabled but can't run because a dependency is unmet, with the reason
+pub fn blocked_modules() -> HashMap<String, String> {
+    let mut modules = scan(defs::MODULE_DIR);
+    let enabled: Vec<String> = modules
+        .iter()
+        .filter(|(_, module)| module.active)
+        .map(|(id, _)| id.clone())
+        .collect();
+    resolve(&mut modules);
+    enabled
+        .into_iter()
+        .filter(|id| !modules[id].active)
+        .map(|id| {
+            let reason = modules[&id]
+                .dependencies
+                .iter()
+                .map(|dep| (dep, status(dep, &modules)))
+                .filter(|(_, status)| *status != Status::Met)
+                .map(|(dep, status)| format!("{dep} is {status}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            (id, reason)
+        })
+        .collect()
+}
+
+/// Check that everything a module about to be installed needs is there.
+///
+/// Modules waiting in modules_update count as installed, they are active after the reboot.
+pub fn check_install(module_prop: &HashMap<String, String>) -> Result<()> {
+    let dependencies = declared(module_prop)?;
+    if dependencies.is_empty() {
+        return Ok(());
+    }
+    let mut modules = scan(defs::MODULE_DIR);
+    modules.extend(scan(defs::MODULE_UPDATE_DIR));
+    resolve(&mut modules);
+
+    let unmet: Vec<String> = dependencies
+        .iter()
+        .map(|dep| (dep, status(dep, &modules)))
+        .filter(|(_, status)| *status != Status::Met)
+        .map(|(dep, status)| format!("{dep} ({status})"))
+        .collect();
+    if unmet.is_empty() {
+        println!("- Dependencies met");
+        return Ok(());
+    }
+    let unmet = unmet.join(", ");
+    if IGNORE_ON_INSTALL.load(Ordering::Relaxed) {
+        println!("- Warning: unmet dependencies: {unmet}");
+        warn!("installing despite unmet dependencies: {unmet}");
+        return Ok(());
+    }
+    bail!("unmet dependencies: {unmet}, install them first or pass --ignore-deps");
+}
+
+/// Keep the metamodule from mounting modules with unmet dependencies.
+///
+/// Metamodules honor skip_mount, so one is created for every blocked module and removed again
+/// once its dependencies are met. A skip_mount the module shipped itself is never touched.
+pub fn apply_skip_mount(blocked: &HashMap<String, String>) {
+    let Ok(entries) = std::fs::read_dir(defs::MODULE_DIR) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(id) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let marker = path.join(SKIP_MOUNT_MARKER);
+        let skip_mount = path.join(SKIP_MOUNT_FILE);
+        let result = if blocked.contains_key(id) {
+            if skip_mount.exists() {
+                continue;
+            }
+            std::fs::write(&marker, "")
+                .and_then(|()| std::fs::write(&skip_mount, ""))
+                .map(|()| info!("not mounting {id}: {}", blocked[id]))
+        } else if marker.exists() {
+            remove_skip_mount(&path).map(|()| info!("dependencies of {id} are met, mounting again"))
+        } else {
+            continue;
+        };
+        if let Err(e) = result {
+            warn!("failed to update skip_mount of {id}: {e}");
+        }
+    }
+}
+
+fn remove_skip_mount(module: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(module.join(SKIP_MOUNT_FILE)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    std::fs::remove_file(module.join(SKIP_MOUNT_MARKER))
+}
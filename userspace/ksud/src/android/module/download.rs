@@ -0,0 +1,348 @@
+//! Download a module zip over https, resuming interrupted transfers
+
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, bail, ensure};
+use log::{info, warn};
+use ureq::{
+    Agent,
+    http::{HeaderMap, Response},
+};
+
+use crate::{android::utils::ensure_dir_exists, defs};
+
+/// Attempts in a row that made no progress before giving up
+const MAX_ATTEMPTS: u32 = 5;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+/// ureq has no idle timeout, a stalled body is cut off after this and resumed
+const BODY_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+/// A detached signature is 64 bytes, or 128 in hex
+const SIGNATURE_LIMIT: u64 = 4096;
+
+/// A transfer that broke off and can be resumed from where it stopped
+#[derive(Debug)]
+struct Interrupted(String);
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Print `progress: <bytes>/<total>` for the Manager, total is 0 if the server sent no length
+fn progress(bytes: u64, total: Option<u64>) {
+    println!("progress: {bytes}/{}", total.unwrap_or(0));
+}
+
+/// The rustls error behind a failed request, if the TLS handshake failed
+fn tls_error(error: &ureq::Error) -> Option<&rustls::Error> {
+    match error {
+        ureq::Error::Rustls(e) => Some(e),
+        ureq::Error::Io(e) => e.get_ref()?.downcast_ref::<rustls::Error>(),
+        _ => None,
+    }
+}
+
+fn request_error(error: ureq::Error, url: &str) -> anyhow::Error {
+    if let Some(tls) = tls_error(&error) {
+        return match tls {
+            rustls::Error::InvalidCertificate(cert) => anyhow::anyhow!(
+                "TLS certificate of {url} is not trusted ({cert:?}), refusing to download; check the URL and the device date"
+            ),
+            other => anyhow::anyhow!("TLS handshake with {url} failed: {other}"),
+        };
+    }
+    match error {
+        ureq::Error::Io(_)
+        | ureq::Error::Timeout(_)
+        | ureq::Error::ConnectionFailed
+        | ureq::Error::HostNotFound => Interrupted(error.to_string()).into(),
+        ureq::Error::RequireHttpsOnly(_) => {
+            anyhow::anyhow!("{url} redirected to a plain http URL, refusing to follow")
+        }
+        error => anyhow::Error::new(error).context(format!("failed to request {url}")),
+    }
+}
+
+fn header<'a>(response: &'a Response<ureq::Body>, name: &str) -> Option<&'a str> {
+    response.headers().get(name)?.to_str().ok()
+}
+
+/// Where the validator of the response a part file was started from is kept
+fn validator_path(part: &Path) -> PathBuf {
+    part.with_extension("part.validator")
+}
+
+/// The strong ETag, else the Last-Modified date, the server identifies the file with.
+///
+/// Sent as If-Range on resume, so a file changed on the server is fetched whole instead of
+/// appended to the old bytes. Weak ETags are not allowed there.
+fn validator(headers: &HeaderMap) -> Option<&str> {
+    let get = |name: &str| headers.get(name)?.to_str().ok();
+    get("etag")
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| get("last-modified"))
+}
+
+/// Parse `bytes <start>-<end>/<total>` or `bytes */<total>`, total is None if unknown
+fn parse_content_range(value: &str) -> Option<(Option<u64>, Option<u64>)> {
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let start = match range {
+        "*" => None,
+        range => Some(range.split_once('-')?.0.parse().ok()?),
+    };
+    Some((start, total.parse().ok()))
+}
+
+/// Fetch `url` into `part`, continuing after the bytes it already holds
+fn fetch(agent: &Agent, url: &str, part: &Path) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(part)
+        .with_context(|| format!("failed to open {}", part.display()))?;
+    let mut offset = file.metadata()?.len();
+
+    let mut request = agent.get(url);
+    if offset > 0 {
+        match fs::read_to_string(validator_path(part)) {
+            Ok(validator) => {
+                println!("- Resuming download at {offset} bytes");
+                request = request
+                    .header("Range", format!("bytes={offset}-"))
+                    .header("If-Range", validator.trim());
+            }
+            Err(_) => {
+                info!("nothing identifies the partial download, restarting it");
+                file.set_len(0)?;
+                offset = 0;
+            }
+        }
+    }
+    let mut response = request.call().map_err(|e| request_error(e, url))?;
+
+    let content_length = header(&response, "content-length").and_then(|v| v.parse::<u64>().ok());
+    let content_range = header(&response, "content-range").and_then(parse_content_range);
+    let status = response.status().as_u16();
+    let (mut written, total) = match status {
+        200 => {
+            if offset > 0 {
+                info!(
+                    "server ignored the range request or the file changed, restarting the download"
+                );
+                file.set_len(0)?;
+            }
+            let saved = match validator(response.headers()) {
+                Some(validator) => fs::write(validator_path(part), validator),
+                None => fs::remove_file(validator_path(part)).or_else(|e| match e.kind() {
+                    ErrorKind::NotFound => Ok(()),
+                    _ => Err(e),
+                }),
+            };
+            if let Err(e) = saved {
+                warn!(
+                    "failed to record the download validator, an interrupted download restarts: {e}"
+                );
+            }
+            (0, content_length)
+        }
+        206 => {
+            let Some((Some(start), total)) = content_range else {
+                bail!("server answered the range request without a valid Content-Range");
+            };
+            ensure!(
+                start == offset,
+                "server resumed at byte {start}, expected {offset}"
+            );
+            (offset, total.or(content_length.map(|len| offset + len)))
+        }
+        416 if offset > 0 => {
+            if content_range.is_some_and(|(_, total)| total == Some(offset)) {
+                progress(offset, Some(offset));
+                return Ok(());
+            }
+            file.set_len(0)?;
+            return Err(Interrupted("server rejected the resume offset, restarting".into()).into());
+        }
+        500..=599 => return Err(Interrupted(format!("server error, HTTP {status}")).into()),
+        _ => bail!("failed to download {url}: HTTP {status}"),
+    };
+
+    let mut reader = response.body_mut().as_reader();
+    let mut buffer = vec![0; 64 * 1024];
+    let mut reported = Instant::now();
+    progress(written, total);
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => {
+                file.sync_all()?;
+                return Err(Interrupted(e.to_string()).into());
+            }
+        };
+        file.write_all(&buffer[..read])
+            .with_context(|| format!("failed to write {}", part.display()))?;
+        written += read as u64;
+        if reported.elapsed() >= PROGRESS_INTERVAL {
+            progress(written, total);
+            reported = Instant::now();
+        }
+    }
+    file.sync_all()?;
+    progress(written, total);
+
+    if let Some(total) = total
+        && written < total
+    {
+        return Err(Interrupted(format!("connection closed at {written} of {total} bytes")).into());
+    }
+    Ok(())
+}
+
+fn agent() -> Agent {
+    Agent::config_builder()
+        .https_only(true)
+        .http_status_as_error(false)
+        .timeout_connect(Some(CONNECT_TIMEOUT))
+        .timeout_recv_body(Some(BODY_TIMEOUT))
+        .build()
+        .into()
+}
+
+/// Download the detached signature published at `<url>.sig` to `to`, if there is one
+pub fn download_signature(url: &str, to: &Path) -> Result<()> {
+    let sig_url = format!("{url}.sig");
+    // never verify against a signature left over from an earlier run
+    let _ = fs::remove_file(to);
+    let mut response = agent()
+        .get(&sig_url)
+        .call()
+        .map_err(|e| request_error(e, &sig_url))?;
+    match response.status().as_u16() {
+        200 => {
+            let data = response
+                .body_mut()
+                .with_config()
+                .limit(SIGNATURE_LIMIT)
+                .read_to_vec()
+                .with_context(|| format!("failed to download {sig_url}"))?;
+            fs::write(to, data).with_context(|| format!("failed to write {}", to.display()))?;
+            println!("- Downloaded signature {sig_url}");
+        }
+        404 => info!("no signature at {sig_url}"),
+        status => bail!("failed to download {sig_url}: HTTP {status}"),
+    }
+    Ok(())
+}
+
+/// Download `url` to the ksu temp dir and return the path of the zip.
+///
+/// The partial file is named after the URL, so running the same download again resumes it.
+pub fn download(url: &str) -> Result<PathBuf> {
+    ensure!(
+        url.starts_with("https://"),
+        "only https:// URLs are supported"
+    );
+    ensure_dir_exists(defs::TEMP_DIR).with_context(|| "Failed to create temp dir")?;
+    let name = &sha256::digest(url)[..16];
+    let part = Path::new(defs::TEMP_DIR).join(format!("{name}.zip.part"));
+
+    let agent = agent();
+    println!("- Downloading {url}");
+    let mut attempt = 1;
+    loop {
+        let before = fs::metadata(&part).map_or(0, |m| m.len());
+        match fetch(&agent, url, &part) {
+            Ok(()) => break,
+            Err(e) if e.is::<Interrupted>() => {
+                // a resume that got further is a new start, only stalls count
+                if fs::metadata(&part).map_or(0, |m| m.len()) > before {
+                    attempt = 1;
+                }
+                if attempt >= MAX_ATTEMPTS {
+                    return Err(e.context("download failed, run the command again to resume"));
+                }
+                warn!("download of {url} interrupted: {e}");
+                println!("- Download interrupted: {e}, retrying ({attempt}/{MAX_ATTEMPTS})");
+                thread::sleep(RETRY_DELAY * attempt);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    let _ = fs::remove_file(validator_path(&part));
+    let zip = part.with_extension("");
+    fs::rename(&part, &zip)?;
+    if let Err(e) = File::open(&zip)
+        .map_err(anyhow::Error::from)
+        .and_then(|file| Ok(zip::ZipArchive::new(file)?))
+    {
+        let _ = fs::remove_file(&zip);
+        bail!("downloaded file is not a valid zip: {e}");
+    }
+    Ok(zip)
+}
+
+#[cfg(test)]
+mod tests {
+    use ureq::http::HeaderValue;
+
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn strong_etag_is_preferred_as_validator() {
+        let date = "Wed, 14 Oct 2026 08:00:00 GMT";
+        let both = headers(&[("etag", "\"v2\""), ("last-modified", date)]);
+        assert_eq!(validator(&both), Some("\"v2\""));
+
+        let weak = headers(&[("etag", "W/\"v2\""), ("last-modified", date)]);
+        assert_eq!(validator(&weak), Some(date));
+
+        assert_eq!(validator(&headers(&[("etag", "W/\"v2\"")])), None);
+        assert_eq!(validator(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn validator_is_kept_next_to_the_part_file() {
+        assert_eq!(
+            validator_path(Path::new("/data/adb/ksu/tmp/0123456789abcdef.zip.part")),
+            Path::new("/data/adb/ksu/tmp/0123456789abcdef.zip.part.validator")
+        );
+    }
+
+    #[test]
+    fn content_ranges_are_parsed() {
+        assert_eq!(
+            parse_content_range("bytes 100-199/200"),
+            Some((Some(100), Some(200)))
+        );
+        assert_eq!(
+            parse_content_range("bytes 100-199/*"),
+            Some((Some(100), None))
+        );
+        assert_eq!(parse_content_range("bytes */200"), Some((None, Some(200))));
+        assert_eq!(parse_content_range("items 0-1/2"), None);
+    }
+}
@@ -0,0 +1,295 @@
+//! Extract module zips, decompressing entries on a worker pool
+
+use std::{
+    collections::HashSet,
+    fs::{self, File, Permissions},
+    io::{self, BufWriter, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    thread,
+};
+
+use anyhow::{Context, Result};
+use log::info;
+use zip::ZipArchive;
+
+use crate::android::module::progress::{self, ProgressReader};
+
+/// Upper bound of the pool, storage stops scaling long before the cores do
+const MAX_THREADS: usize = 8;
+/// Below this the pool costs more than it saves
+const MIN_PARALLEL_ENTRIES: usize = 16;
+const WRITE_BUFFER: usize = 1024 * 1024;
+const PROGRESS_STEP: &str = "extract";
+
+/// Worker threads, 0 picks one per core
+static THREADS: AtomicUsize = AtomicUsize::new(0);
+
+/// Override the size of the worker pool, `--threads`
+pub fn set_threads(threads: usize) {
+    THREADS.store(threads, Ordering::Relaxed);
+}
+
+fn threads() -> usize {
+    match THREADS.load(Ordering::Relaxed) {
+        0 => thread::available_parallelism()
+            .map_or(1, usize::from)
+            .min(MAX_THREADS),
+        threads => threads,
+    }
+}
+
+struct Entry {
+    index: usize,
+    path: PathBuf,
+    mode: Option<u32>,
+    compressed: u64,
+}
+
+/// Plan the parallel extraction, None if the zip needs the serial path.
+///
+/// Symlinks, encryption, names escaping the target and duplicate names are left to zip's own
+/// extract(), which handles them the way module installs always did.
+fn plan(zip: &Path, dest: &Path) -> Result<Option<(Vec<Entry>, Vec<Entry>)>> {
+    let mut archive = ZipArchive::new(File::open(zip)?)?;
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    let mut seen = HashSet::new();
+    for index in 0..archive.len() {
+        let entry = archive.by_index_raw(index)?;
+        let Some(name) = entry.enclosed_name() else {
+            return Ok(None);
+        };
+        if entry.encrypted() || entry.is_symlink() || !seen.insert(name.clone()) {
+            return Ok(None);
+        }
+        let planned = Entry {
+            index,
+            path: dest.join(name),
+            mode: entry.unix_mode(),
+            compressed: entry.compressed_size(),
+        };
+        if entry.is_dir() {
+            dirs.push(planned);
+        } else {
+            files.push(planned);
+        }
+    }
+    if files.len() < MIN_PARALLEL_ENTRIES {
+        return Ok(None);
+    }
+    Ok(Some((dirs, files)))
+}
+
+fn extract_serial(zip: &Path, dest: &Path) -> Result<()> {
+    let file = File::open(zip)?;
+    let len = file.metadata()?.len();
+    let mut archive = ZipArchive::new(ProgressReader::new(file, PROGRESS_STEP, len))?;
+    archive.extract(dest)?;
+    Ok(())
+}
+
+fn extract_entry(archive: &mut ZipArchive<File>, entry: &Entry) -> Result<()> {
+    let mut source = archive.by_index(entry.index)?;
+    let out = File::create(&entry.path)
+        .with_context(|| format!("failed to create {}", entry.path.display()))?;
+    let mut writer = BufWriter::with_capacity(WRITE_BUFFER, out);
+    io::copy(&mut source, &mut writer)
+        .with_context(|| format!("failed to extract {}", entry.path.display()))?;
+    writer.flush()?;
+    if let Some(mode) = entry.mode {
+        fs::set_permissions(&entry.path, Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+fn extract_parallel(
+    zip: &Path,
+    dirs: &[Entry],
+    mut files: Vec<Entry>,
+    threads: usize,
+) -> Result<()> {
+    for dir in dirs {
+        fs::create_dir_all(&dir.path)?;
+    }
+    for file in &files {
+        if let Some(parent) = file.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+
+    // biggest first, so one large entry doesn't run alone at the end
+    files.sort_by(|a, b| b.compressed.cmp(&a.compressed));
+    let total: u64 = files.iter().map(|f| f.compressed).sum::<u64>().max(1);
+    let next = AtomicUsize::new(0);
+    let done = AtomicU64::new(0);
+    let reported = AtomicU64::new(0);
+    let failed = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| -> Result<()> {
+                    let mut archive = ZipArchive::new(File::open(zip)?)?;
+                    while !failed.load(Ordering::Relaxed) {
+                        let Some(entry) = files.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                            break;
+                        };
+                        if let Err(e) = extract_entry(&mut archive, entry) {
+                            failed.store(true, Ordering::Relaxed);
+                            return Err(e);
+                        }
+                        let done =
+                            done.fetch_add(entry.compressed, Ordering::Relaxed) + entry.compressed;
+                        let percent = (done * 100 / total).min(99);
+                        if reported.fetch_max(percent, Ordering::Relaxed) < percent {
+                            progress::percent(PROGRESS_STEP, percent);
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|_| Err(anyhow::anyhow!("extract worker panicked")))
+            })
+            .collect::<Result<()>>()
+    })?;
+
+    // like zip's extract(), directory modes last, a read-only one would block its files
+    for dir in dirs.iter().rev() {
+        if let Some(mode) = dir.mode {
+            fs::set_permissions(&dir.path, Permissions::from_mode(mode))?;
+        }
+    }
+    Ok(())
+}
+
+/// Extract `zip` into `dest`, in parallel unless the zip needs the serial path
+pub fn extract(zip: &Path, dest: &Path) -> Result<()> {
+    let threads = threads();
+    let plan = if threads > 1 { plan(zip, dest)? } else { None };
+    match plan {
+        Some((dirs, files)) => {
+            info!("extracting {} entries on {threads} threads", files.len());
+            extract_parallel(zip, &dirs, files, threads)
+        }
+        None => {
+            info!("extracting serially");
+            extract_serial(zip, dest)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+    use super::*;
+
+    const FILES: usize = 40;
+
+    /// A zip big enough for the pool, with nested directories and mixed sizes, compression and
+    /// modes
+    fn synthetic_zip(dir: &Path) -> PathBuf {
+        let path = dir.join("module.zip");
+        let mut writer = ZipWriter::new(File::create(&path).unwrap());
+        writer
+            .add_directory(
+                "system/bin/",
+                SimpleFileOptions::default().unix_permissions(0o750),
+            )
+            .unwrap();
+        for i in 0..FILES {
+            let (name, mode) = if i % 4 == 0 {
+                (format!("system/bin/tool{i}"), 0o755)
+            } else {
+                (format!("system/fonts/{}/font{i}.ttf", i % 3), 0o644)
+            };
+            let method = if i % 2 == 0 {
+                CompressionMethod::Deflated
+            } else {
+                CompressionMethod::Stored
+            };
+            let options = SimpleFileOptions::default()
+                .compression_method(method)
+                .unix_permissions(mode);
+            writer.start_file(name, options).unwrap();
+            let content: Vec<u8> = (0..i * 997).map(|n| (n * 31 + i) as u8).collect();
+            writer.write_all(&content).unwrap();
+        }
+        writer.finish().unwrap();
+        path
+    }
+
+    /// Every path under `root` with its mode, and the hash of its content for files
+    fn snapshot(root: &Path) -> BTreeMap<PathBuf, (u32, Option<String>)> {
+        fn walk(root: &Path, dir: &Path, out: &mut BTreeMap<PathBuf, (u32, Option<String>)>) {
+            for entry in fs::read_dir(dir).unwrap().flatten() {
+                let path = entry.path();
+                let metadata = fs::symlink_metadata(&path).unwrap();
+                let hash = metadata
+                    .is_file()
+                    .then(|| sha256::digest(&fs::read(&path).unwrap()));
+                let relative = path.strip_prefix(root).unwrap().to_path_buf();
+                out.insert(relative, (metadata.permissions().mode() & 0o7777, hash));
+                if metadata.is_dir() {
+                    walk(root, &path, out);
+                }
+            }
+        }
+
+        let mut out = BTreeMap::new();
+        walk(root, root, &mut out);
+        out
+    }
+
+    #[test]
+    fn parallel_extraction_matches_serial() {
+        let dir = tempfile::tempdir().unwrap();
+        let zip = synthetic_zip(dir.path());
+        let serial = dir.path().join("serial");
+        let parallel = dir.path().join("parallel");
+
+        extract_serial(&zip, &serial).unwrap();
+        let (dirs, files) = plan(&zip, &parallel)
+            .unwrap()
+            .expect("the synthetic zip takes the parallel path");
+        extract_parallel(&zip, &dirs, files, 4).unwrap();
+
+        let serial = snapshot(&serial);
+        let files = serial.values().filter(|(_, hash)| hash.is_some()).count();
+        assert_eq!(files, FILES);
+        assert_eq!(serial[Path::new("system/bin")].0, 0o750);
+        assert_eq!(serial[Path::new("system/bin/tool4")].0, 0o755);
+        assert_eq!(serial, snapshot(&parallel));
+    }
+
+    #[test]
+    fn symlinks_take_the_serial_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let zip = synthetic_zip(dir.path());
+        let linked = dir.path().join("linked.zip");
+        fs::copy(&zip, &linked).unwrap();
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .open(&linked)
+            .unwrap();
+        let mut writer = ZipWriter::new_append(file).unwrap();
+        writer
+            .add_symlink("system/bin/sh", "tool0", SimpleFileOptions::default())
+            .unwrap();
+        writer.finish().unwrap();
+
+        assert!(plan(&zip, dir.path()).unwrap().is_some());
+        assert!(plan(&linked, dir.path()).unwrap().is_none());
+    }
+}
@@ -1,5 +1,17 @@
+pub mod conflict;
+pub mod dependency;
+mod download;
+pub mod extract;
 pub mod metamodule;
 pub mod module_config;
+pub mod progress;
+pub mod rescue;
+mod script;
+pub mod signature;
+pub mod skip_mount;
+pub mod snapshot;
+pub mod status;
+pub mod webui;
 
 #[cfg(unix)]
 use std::os::unix::{prelude::PermissionsExt, process::CommandExt};
@@ -26,7 +38,7 @@ use crate::{
         ksucalls,
         module::ModuleType::{Active, All},
         restorecon::{restore_syscon, setsyscon},
-        sepolicy,
+        sepolicy, umount_config,
         utils::{
             detach_process_group, ensure_clean_dir, ensure_dir_exists, ensure_file_exists,
             get_zip_uncompressed_size, getprop, switch_cgroups,
@@ -142,6 +154,11 @@ pub fn foreach_module(
         ModuleType::Updated => MODULE_UPDATE_DIR,
         _ => defs::MODULE_DIR,
     });
+    let blocked = if module_type == Active {
+        dependency::blocked_modules()
+    } else {
+        HashMap::new()
+    };
     let dir = std::fs::read_dir(modules_dir)?;
     for entry in dir.flatten() {
         let path = entry.path();
@@ -158,6 +175,14 @@ pub fn foreach_module(
             warn!("{} is removed, skip", path.display());
             continue;
         }
+        if let Some(reason) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|id| blocked.get(id))
+        {
+            warn!("{} has unmet dependencies: {reason}, skip", path.display());
+            continue;
+        }
 
         f(&path)?;
     }
@@ -187,13 +212,22 @@ pub fn load_sepolicy_rule() -> Result<()> {
 }
 
 pub fn exec_script<T: AsRef<Path>>(path: T, wait: bool) -> Result<()> {
-    info!("exec {}", path.as_ref().display());
+    let mode = if wait {
+        script::Mode::Wait
+    } else {
+        script::Mode::Spawn
+    };
+    exec_script_with(path.as_ref(), mode).map(|_| ())
+}
+
+/// Run a script, module scripts get a timeout and their output logged, see [`script`]
+fn exec_script_with(path: &Path, mode: script::Mode) -> Result<Option<script::Outcome>> {
+    info!("exec {}", path.display());
 
-    let is_module_script = path.as_ref().starts_with(defs::MODULE_DIR);
+    let is_module_script = path.starts_with(defs::MODULE_DIR);
     // Extract module_id from path if it matches /data/adb/modules/{id}/...
     let module_id = if is_module_script {
-        path.as_ref()
-            .strip_prefix(defs::MODULE_DIR)
+        path.strip_prefix(defs::MODULE_DIR)
             .ok()
             .and_then(|p| p.components().next())
             .and_then(|c| c.as_os_str().to_str())
@@ -213,7 +247,7 @@ pub fn exec_script<T: AsRef<Path>>(path: T, wait: bool) -> Result<()> {
             Err(e) => {
                 warn!(
                     "Invalid module ID '{id}' extracted from script path '{}': {e}",
-                    path.as_ref().display(),
+                    path.display(),
                 );
                 None
             }
@@ -222,34 +256,44 @@ pub fn exec_script<T: AsRef<Path>>(path: T, wait: bool) -> Result<()> {
     if is_module_script && module_id.is_none() {
         debug!(
             "Failed to extract module_id from script path '{}'. Script will run without KSU_MODULE environment variable.",
-            path.as_ref().display()
+            path.display()
         );
     }
 
-    let mut command = &mut Command::new(assets::BUSYBOX_PATH);
+    // a module script gets a process group of its own, so a timeout can kill all of it
+    let own_group = validated_module_id.is_some();
+    let mut command = Command::new(assets::BUSYBOX_PATH);
     #[cfg(unix)]
-    {
-        command = unsafe {
-            command.pre_exec(|| {
-                detach_process_group(true);
-                // ignore the error?
-                switch_cgroups();
-                Ok(())
-            })
-        };
+    unsafe {
+        command.pre_exec(move || {
+            detach_process_group(!own_group);
+            // ignore the error?
+            switch_cgroups();
+            Ok(())
+        });
     }
-    command = command
-        .current_dir(path.as_ref().parent().unwrap())
+    command
+        .current_dir(path.parent().unwrap())
         .arg("sh")
-        .arg(path.as_ref())
+        .arg(path)
         .envs(get_common_script_envs(validated_module_id));
 
-    let result = if wait {
-        command.status().map(|_| ())
-    } else {
+    if let Some(id) = validated_module_id {
+        let outcome = script::run(command, id, path, mode)
+            .map_err(|e| anyhow!("Failed to exec {}: {e}", path.display()))?;
+        if !outcome.success() {
+            warn!("{} {}", path.display(), outcome.describe());
+        }
+        return Ok(Some(outcome));
+    }
+
+    let result = if mode == script::Mode::Spawn {
         command.spawn().map(|_| ())
+    } else {
+        command.status().map(|_| ())
     };
-    result.map_err(|e| anyhow!("Failed to exec {}: {e}", path.as_ref().display()))
+    result.map_err(|e| anyhow!("Failed to exec {}: {e}", path.display()))?;
+    Ok(None)
 }
 
 pub fn exec_stage_script(stage: &str, block: bool) -> Result<()> {
@@ -348,6 +392,14 @@ pub fn prune_modules() -> Result<()> {
             warn!("Failed to clear configs for {module_id}: {e}");
         }
 
+        if let Err(e) = umount_config::remove_module_entries(module_id) {
+            warn!("Failed to remove umount entries for {module_id}: {e}");
+        }
+
+        if let Err(e) = snapshot::remove_all(module_id) {
+            warn!("Failed to remove snapshots of {module_id}: {e}");
+        }
+
         // Finally remove the module directory
         if let Err(e) = remove_dir_all(module) {
             warn!("Failed to remove {}: {e}", module.display());
@@ -537,6 +589,10 @@ fn install_module_to_system(zip: &str) -> Result<()> {
     ensure_dir_exists(defs::WORKING_DIR).with_context(|| "Failed to create working dir")?;
     ensure_dir_exists(defs::BINARY_DIR).with_context(|| "Failed to create bin dir")?;
 
+    let scan = progress::Step::start("scan-zip");
+    // refuse untrusted modules before anything is read from or extracted out of the zip
+    signature::verify(Path::new(zip))?;
+
     // read the module_id from zip, if failed it will return early.
     let mut buffer: Vec<u8> = Vec::new();
     let entry_path = PathBuf::from_str("module.prop")?;
@@ -561,6 +617,8 @@ fn install_module_to_system(zip: &str) -> Result<()> {
     validate_module_id(module_id)
         .with_context(|| format!("Invalid module ID in module.prop: '{module_id}'"))?;
 
+    dependency::check_install(&module_prop)?;
+
     // Check if this module is a metamodule
     let is_metamodule = metamodule::is_metamodule(&module_prop);
 
@@ -621,6 +679,12 @@ fn install_module_to_system(zip: &str) -> Result<()> {
         "- Module size: {}",
         humansize::format_size(zip_uncompressed_size, humansize::DECIMAL)
     );
+    drop(scan);
+
+    if let Err(e) = snapshot::take(module_id) {
+        warn!("Failed to snapshot {module_id} before the update: {e}");
+        println!("- Warning: no snapshot of the installed version, rollback won't be possible");
+    }
 
     // Ensure module directory exists and set SELinux context
     ensure_dir_exists(defs::MODULE_UPDATE_DIR)?;
@@ -633,9 +697,10 @@ fn install_module_to_system(zip: &str) -> Result<()> {
 
     // Extract zip to target directory
     println!("- Extracting module files");
-    let file = File::open(zip)?;
-    let mut archive = zip::ZipArchive::new(file)?;
-    archive.extract(&updated_dir)?;
+    let step = progress::Step::start("extract");
+    extract::extract(Path::new(zip), &updated_dir)?;
+    progress::percent("extract", 100);
+    drop(step);
 
     // Set permission and selinux context for $MOD/system
     let module_system_dir = updated_dir.join("system");
@@ -647,7 +712,19 @@ fn install_module_to_system(zip: &str) -> Result<()> {
 
     // Execute install script
     println!("- Running module installer");
+    let script = progress::Step::start("running-script");
     exec_install_script(zip, is_metamodule, module_id)?;
+    drop(script);
+    let _finalize = progress::Step::start("finalize");
+
+    if !is_metamodule {
+        if let Err(e) = conflict::check_install(module_id, &updated_dir) {
+            // a staged module is installed by the next boot, a refused one must not be
+            let _ = remove_dir_all(&updated_dir);
+            return Err(e);
+        }
+        add_module_umount_entries(&module_prop, &updated_dir, module_id);
+    }
 
     let module_dir = Path::new(MODULE_DIR).join(module_id);
     ensure_dir_exists(&module_dir)?;
@@ -669,6 +746,60 @@ fn install_module_to_system(zip: &str) -> Result<()> {
     Ok(())
 }
 
+/// Partitions a module may carry at its top level after the installer moved them out of system/
+const MODULE_PARTITIONS: [&str; 4] = ["vendor", "system_ext", "product", "odm"];
+
+/// Where the files of an installed module get mounted: `/system/<dir>` and `/<partition>/<dir>`
+/// for every directory the module ships.
+fn module_mount_targets(module_dir: &Path) -> Vec<String> {
+    let mut targets = Vec::new();
+    for partition in std::iter::once("system").chain(MODULE_PARTITIONS) {
+        let Ok(dir) = std::fs::read_dir(module_dir.join(partition)) else {
+            continue;
+        };
+        for entry in dir.flatten() {
+            // system/<partition> is a symlink to the moved partition, or a real part of /system
+            if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+                continue;
+            }
+            let name = entry.file_name();
+            targets.push(format!("/{partition}/{}", name.to_string_lossy()));
+        }
+    }
+    targets.sort();
+    targets
+}
+
+/// Add the module's mount targets to the umount config, unless it opts out with `no_auto_umount=true`.
+///
+/// Targets come from the `umount_paths` module.prop key (comma separated) when present, or are
+/// computed from the module files otherwise.
+fn add_module_umount_entries(module_prop: &HashMap<String, String>, module_dir: &Path, id: &str) {
+    if module_prop
+        .get("no_auto_umount")
+        .is_some_and(|v| v.trim() == "true")
+    {
+        info!("module {id} opted out of automatic umount entries");
+        return;
+    }
+    let targets: Vec<String> = match module_prop.get("umount_paths") {
+        Some(paths) => paths
+            .split(',')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .map(str::to_string)
+            .collect(),
+        None => module_mount_targets(module_dir),
+    };
+    if targets.is_empty() {
+        return;
+    }
+    match umount_config::add_module_entries(id, &targets) {
+        Ok(added) => println!("- Added {added} umount entries"),
+        Err(e) => warn!("Failed to add umount entries for {id}: {e}"),
+    }
+}
+
 pub fn install_module(zip: &str) -> Result<()> {
     ksucalls::ensure_uapi_version_matched()?;
 
@@ -678,9 +809,39 @@ pub fn install_module(zip: &str) -> Result<()> {
     } else if let Err(e) = regenerate_preinit_rc() {
         warn!("regenerate preinit rc failed: {e}");
     }
+    progress::summary();
     result
 }
 
+/// Download a module zip over https and install it, `ksud module install-url`
+pub fn install_module_from_url(url: &str) -> Result<()> {
+    let step = progress::Step::start("download");
+    let zip = download::download(url)?;
+    let sig = signature::signature_path(&zip);
+    if signature::mode() != signature::Mode::Off {
+        download::download_signature(url, &sig)?;
+    }
+    drop(step);
+    let result = install_module(&zip.to_string_lossy());
+    for file in [&zip, &sig] {
+        if let Err(e) = std::fs::remove_file(file)
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            warn!("failed to remove {}: {e}", file.display());
+        }
+    }
+    result
+}
+
+/// Restore the last or a given snapshot of a module, `ksud module rollback`
+pub fn rollback_module(id: &str, to: Option<&str>) -> Result<()> {
+    snapshot::rollback(id, to)?;
+    if let Err(e) = regenerate_preinit_rc() {
+        warn!("regenerate preinit rc failed: {e}");
+    }
+    Ok(())
+}
+
 pub fn undo_uninstall_module(id: &str) -> Result<()> {
     validate_module_id(id)?;
 
@@ -727,7 +888,20 @@ pub fn run_action(id: &str) -> Result<()> {
 
     let action_script_path = format!("/data/adb/modules/{id}/action.sh");
 
-    exec_script(&action_script_path, true)
+    let outcome = exec_script_with(Path::new(&action_script_path), script::Mode::Stream)?;
+    if let Some(outcome) = outcome {
+        println!(
+            "- action.sh {}, log: {}",
+            outcome.describe(),
+            outcome.log.display()
+        );
+        ensure!(
+            outcome.success(),
+            "action.sh of {id} {}",
+            outcome.describe()
+        );
+    }
+    Ok(())
 }
 
 pub fn enable_module(id: &str) -> Result<()> {
@@ -767,14 +941,6 @@ pub fn disable_module(id: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn disable_all_modules() -> Result<()> {
-    mark_all_modules(defs::DISABLE_FILE_NAME)?;
-    if let Err(e) = regenerate_preinit_rc() {
-        warn!("regenerate preinit rc failed: {e}");
-    }
-    Ok(())
-}
-
 pub fn uninstall_all_modules() -> Result<()> {
     info!("Uninstalling all modules");
     mark_all_modules(defs::REMOVE_FILE_NAME)?;
@@ -886,6 +1052,7 @@ fn list_module(path: &str) -> Vec<HashMap<String, String>> {
     };
 
     let mut modules: Vec<HashMap<String, String>> = Vec::new();
+    let dependencies = dependency::resolved();
 
     for entry in dir.flatten() {
         let path = entry.path();
@@ -920,7 +1087,10 @@ fn list_module(path: &str) -> Vec<HashMap<String, String>> {
         let remove = path.join(defs::REMOVE_FILE_NAME).exists();
         let web = path.join(defs::MODULE_WEB_DIR).exists();
         let action = path.join(defs::MODULE_ACTION_SH).exists();
-        let need_mount = path.join("system").exists() && !path.join("skip_mount").exists();
+        let skipped = skip_mount::skipped_in(&path, &module_prop_map);
+        let need_mount = path.join("system").exists()
+            && !path.join("skip_mount").exists()
+            && !skipped.iter().any(|(partition, _)| *partition == "system");
 
         module_prop_map.insert("enabled".to_owned(), enabled.to_string());
         module_prop_map.insert("update".to_owned(), update.to_string());
@@ -928,6 +1098,17 @@ fn list_module(path: &str) -> Vec<HashMap<String, String>> {
         module_prop_map.insert("web".to_owned(), web.to_string());
         module_prop_map.insert("action".to_owned(), action.to_string());
         module_prop_map.insert("mount".to_owned(), need_mount.to_string());
+        let skipped: Vec<&str> = skipped
+            .into_iter()
+            .map(|(partition, _)| partition)
+            .collect();
+        if !skipped.is_empty() {
+            module_prop_map.insert("skippedPartitions".to_owned(), skipped.join(","));
+        }
+        if let Some((met, status)) = dependency::describe(&module_prop_map, &dependencies) {
+            module_prop_map.insert("dependenciesMet".to_owned(), met.to_string());
+            module_prop_map.insert("dependencyStatus".to_owned(), status);
+        }
 
         resolve_module_icon_path(&mut module_prop_map, "actionIcon", &path);
         resolve_module_icon_path(&mut module_prop_map, "webuiIcon", &path);
@@ -965,7 +1146,11 @@ fn list_module(path: &str) -> Vec<HashMap<String, String>> {
     modules
 }
 
-pub fn list_modules() -> Result<()> {
+pub fn list_modules(json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status::modules())?);
+        return Ok(());
+    }
     let modules = list_module(defs::MODULE_DIR);
     println!("{}", serde_json::to_string_pretty(&modules)?);
     Ok(())
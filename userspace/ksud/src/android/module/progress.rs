@@ -0,0 +1,119 @@
+//! Machine readable progress of a module install, `ksud module install --progress`
+//!
+//! Progress mode adds `STEP <name>` when a step starts, `PROGRESS <step> <n>%` while it runs
+//! and a final `TIMINGS <step>=<seconds>s ...` line. Step timings are logged either way.
+
+use std::{
+    io::{self, Read, Seek, SeekFrom},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use log::info;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TIMINGS: Mutex<Vec<(&'static str, Duration)>> = Mutex::new(Vec::new());
+
+/// Enable the progress lines on stdout, disabled by default
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// A timed install step, it ends when dropped
+pub struct Step {
+    name: &'static str,
+    start: Instant,
+}
+
+impl Step {
+    pub fn start(name: &'static str) -> Self {
+        if enabled() {
+            println!("STEP {name}");
+        }
+        Self {
+            name,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Step {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        info!("install step {} took {elapsed:?}", self.name);
+        if let Ok(mut timings) = TIMINGS.lock() {
+            timings.push((self.name, elapsed));
+        }
+    }
+}
+
+pub fn percent(step: &str, percent: u64) {
+    if enabled() {
+        println!("PROGRESS {step} {percent}%");
+    }
+}
+
+/// Print the timings of the steps so far, in the order they ran
+pub fn summary() {
+    if !enabled() {
+        return;
+    }
+    let Ok(timings) = TIMINGS.lock() else {
+        return;
+    };
+    let line: Vec<String> = timings
+        .iter()
+        .map(|(name, elapsed)| format!("{name}={:.3}s", elapsed.as_secs_f64()))
+        .collect();
+    println!("TIMINGS {}", line.join(" "));
+}
+
+/// Reports `step` progress by the share of `len` bytes read so far.
+///
+/// Extraction reads every entry once, so the bytes read track the compressed data extracted.
+pub struct ProgressReader<R> {
+    inner: R,
+    step: &'static str,
+    len: u64,
+    read: u64,
+    reported: u64,
+}
+
+impl<R> ProgressReader<R> {
+    pub fn new(inner: R, step: &'static str, len: u64) -> Self {
+        Self {
+            inner,
+            step,
+            len,
+            read: 0,
+            reported: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read += read as u64;
+        // 100% is reported by the caller once the step is really done
+        let current = (self.read * 100 / self.len.max(1)).min(99);
+        if current > self.reported {
+            self.reported = current;
+            percent(self.step, current);
+        }
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for ProgressReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
@@ -0,0 +1,169 @@
+//! Disable every module at once and bring them back, `ksud module disable-all` and `enable-all`
+//!
+//! The modules that were enabled are saved to `defs::MODULES_DISABLED_ALL_PATH` before any of
+//! them is disabled, so `enable-all --previously-enabled` restores exactly that set.
+
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::{ErrorKind, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+
+use crate::{
+    android::{
+        module::{regenerate_preinit_rc, validate_module_id},
+        utils::{self, ensure_dir_exists, ensure_file_exists},
+    },
+    defs,
+};
+
+/// Why all modules were disabled, and which of them were enabled before
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DisabledAll {
+    /// Local time, RFC 3339
+    time: String,
+    trigger: String,
+    previously_enabled: BTreeSet<String>,
+}
+
+fn read_state() -> Option<DisabledAll> {
+    fs::read_to_string(defs::MODULES_DISABLED_ALL_PATH)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+}
+
+fn write_state(state: &DisabledAll) -> Result<()> {
+    ensure_dir_exists(defs::WORKING_DIR)?;
+    let mut temp_file = NamedTempFile::new_in(defs::WORKING_DIR)?;
+    temp_file.write_all(serde_json::to_string_pretty(state)?.as_bytes())?;
+    temp_file.as_file().sync_all()?;
+    temp_file
+        .persist(defs::MODULES_DISABLED_ALL_PATH)
+        .with_context(|| format!("failed to write {}", defs::MODULES_DISABLED_ALL_PATH))?;
+    Ok(())
+}
+
+fn remove_state() -> Result<()> {
+    match fs::remove_file(defs::MODULES_DISABLED_ALL_PATH) {
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("failed to remove {}", defs::MODULES_DISABLED_ALL_PATH))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Ids of the installed modules, with whether each is enabled
+fn installed_modules() -> Result<Vec<(String, bool)>> {
+    let mut modules: Vec<(String, bool)> = fs::read_dir(defs::MODULE_DIR)?
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| {
+            let id = entry.file_name().to_str()?.to_string();
+            let enabled = !entry.path().join(defs::DISABLE_FILE_NAME).exists();
+            Some((id, enabled))
+        })
+        .collect();
+    modules.sort();
+    Ok(modules)
+}
+
+/// What put this boot into safe mode, recorded when safe mode disables all modules
+pub fn safe_mode_trigger() -> &'static str {
+    if utils::is_requested_safe_mode() {
+        "safe mode requested by `ksud safemode enable-next-boot`"
+    } else if utils::is_system_safe_mode() {
+        "Android safe mode"
+    } else {
+        "safe mode, volume down held during boot"
+    }
+}
+
+/// Disable every enabled module but `except` from the next boot, saving which were enabled.
+///
+/// Only files in the module dirs are touched, so this also works from a recovery adb shell.
+/// Running it again keeps the modules saved by the earlier run.
+pub fn disable_all(except: &[String], trigger: &str) -> Result<()> {
+    for id in except {
+        validate_module_id(id)?;
+    }
+    let to_disable: Vec<String> = installed_modules()?
+        .into_iter()
+        .filter(|(id, enabled)| *enabled && !except.contains(id))
+        .map(|(id, _)| id)
+        .collect();
+
+    // saved before anything is disabled, an interrupted run must not lose the list
+    let mut state = read_state().unwrap_or_default();
+    state.time = chrono::Local::now().to_rfc3339();
+    state.trigger = trigger.to_string();
+    state.previously_enabled.extend(to_disable.iter().cloned());
+    write_state(&state)?;
+
+    let mut disabled = 0;
+    for id in &to_disable {
+        let flag = Path::new(defs::MODULE_DIR)
+            .join(id)
+            .join(defs::DISABLE_FILE_NAME);
+        match ensure_file_exists(&flag) {
+            Ok(()) => disabled += 1,
+            Err(e) => warn!("failed to disable {id}: {e}"),
+        }
+    }
+    warn!("disabled {disabled} modules: {trigger}");
+    println!("- Disabled {disabled} modules, effective on the next boot");
+    println!("- Restore them with `ksud module enable-all --previously-enabled`");
+
+    if let Err(e) = regenerate_preinit_rc() {
+        warn!("regenerate preinit rc failed: {e}");
+    }
+    Ok(())
+}
+
+/// Enable modules again, all of them or only those `disable_all` saved
+pub fn enable_all(previously_enabled: bool) -> Result<()> {
+    let state = read_state();
+    if let Some(state) = &state {
+        println!(
+            "- Modules were disabled at {}: {}",
+            state.time, state.trigger
+        );
+    }
+    let to_enable: Vec<String> = if previously_enabled {
+        let state = state.with_context(|| {
+            format!(
+                "no saved module list in {}, modules were not disabled by `ksud module disable-all` or safe mode",
+                defs::MODULES_DISABLED_ALL_PATH
+            )
+        })?;
+        state.previously_enabled.into_iter().collect()
+    } else {
+        installed_modules()?.into_iter().map(|(id, _)| id).collect()
+    };
+
+    let mut enabled = 0;
+    for id in &to_enable {
+        let module_dir = Path::new(defs::MODULE_DIR).join(id);
+        if !module_dir.exists() {
+            info!("module {id} is gone, not enabling it");
+            continue;
+        }
+        match fs::remove_file(module_dir.join(defs::DISABLE_FILE_NAME)) {
+            Ok(()) => enabled += 1,
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => warn!("failed to enable {id}: {e}"),
+        }
+    }
+    remove_state()?;
+    println!("- Enabled {enabled} modules, effective on the next boot");
+
+    if let Err(e) = regenerate_preinit_rc() {
+        warn!("regenerate preinit rc failed: {e}");
+    }
+    Ok(())
+}
@@ -0,0 +1,250 @@
+//! Run module scripts with a timeout, their output captured to `defs::MODULE_LOG_DIR`
+//!
+//! Each run of `<id>/<script>.sh` writes `<id>/<script>.log`, the previous run is kept as
+//! `<script>.old.log`. A script ksud waits for is killed with its whole process group once the
+//! timeout expires: `script.timeout` of internal.ksud.module_script, or `<script>Timeout` in
+//! module.prop (`actionTimeout`, `postFsDataTimeout`, ...), in seconds, 0 for none.
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+
+use crate::{
+    android::{
+        module::{module_config, read_module_prop},
+        utils::ensure_dir_exists,
+    },
+    defs,
+};
+
+pub const SCRIPT_CONFIG_MODULE_ID: &str = "internal.ksud.module_script";
+const TIMEOUT_CONFIG_KEY: &str = "script.timeout";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long to wait for output still buffered after a streamed script exited, background
+/// children holding the pipe open must not keep `ksud module action` alive
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Start it and return, boot stages that don't block
+    Spawn,
+    /// Wait for it, output only goes to the log
+    Wait,
+    /// Wait for it and print the output as it comes, `ksud module action`
+    Stream,
+}
+
+#[derive(Debug)]
+pub struct Outcome {
+    /// None for a spawned script, or one killed by the timeout
+    pub status: Option<ExitStatus>,
+    pub timed_out: Option<Duration>,
+    pub log: PathBuf,
+}
+
+impl Outcome {
+    pub fn success(&self) -> bool {
+        self.timed_out.is_none() && self.status.is_none_or(|status| status.success())
+    }
+
+    /// `exited with 0`, `timed out after 600s`, for logs and the Manager
+    pub fn describe(&self) -> String {
+        if let Some(timeout) = self.timed_out {
+            return format!("timed out after {}s, killed", timeout.as_secs());
+        }
+        match self.status {
+            Some(status) => match status.code() {
+                Some(code) => format!("exited with {code}"),
+                None => format!("terminated by {status}"),
+            },
+            None => "started".to_string(),
+        }
+    }
+}
+
+/// `post-fs-data` to `postFsDataTimeout`
+fn timeout_key(script: &str) -> String {
+    let mut key = String::new();
+    let mut upper = false;
+    for c in script.chars() {
+        if c == '-' || c == '_' {
+            upper = true;
+        } else if upper {
+            key.push(c.to_ascii_uppercase());
+            upper = false;
+        } else {
+            key.push(c);
+        }
+    }
+    key.push_str("Timeout");
+    key
+}
+
+fn parse_timeout(value: &str) -> Option<Option<Duration>> {
+    match value.trim().parse::<u64>() {
+        Ok(0) => Some(None),
+        Ok(secs) => Some(Some(Duration::from_secs(secs))),
+        Err(_) => None,
+    }
+}
+
+/// The timeout of `script` in `module_dir`, None if it may run forever
+fn timeout(module_dir: &Path, script: &str) -> Option<Duration> {
+    let key = timeout_key(script);
+    if let Some(value) = read_module_prop(module_dir)
+        .ok()
+        .and_then(|prop| prop.get(&key).cloned())
+    {
+        match parse_timeout(&value) {
+            Some(timeout) => return timeout,
+            None => warn!("invalid {key} in {}, ignored", module_dir.display()),
+        }
+    }
+    let Ok(config) = module_config::merge_configs(SCRIPT_CONFIG_MODULE_ID) else {
+        return Some(DEFAULT_TIMEOUT);
+    };
+    match config.get(TIMEOUT_CONFIG_KEY).map(|v| parse_timeout(v)) {
+        Some(Some(timeout)) => timeout,
+        Some(None) => {
+            warn!(
+                "invalid {TIMEOUT_CONFIG_KEY}, using {}s",
+                DEFAULT_TIMEOUT.as_secs()
+            );
+            Some(DEFAULT_TIMEOUT)
+        }
+        None => Some(DEFAULT_TIMEOUT),
+    }
+}
+
+/// Create the log of this run of `script`, keeping the previous one
+fn open_log(id: &str, script: &str) -> Result<(PathBuf, File)> {
+    let dir = Path::new(defs::MODULE_LOG_DIR).join(id);
+    ensure_dir_exists(&dir)?;
+    let log = dir.join(format!("{script}.log"));
+    if log.exists() {
+        fs::rename(&log, dir.join(format!("{script}.old.log")))?;
+    }
+    let mut file =
+        File::create(&log).with_context(|| format!("failed to create {}", log.display()))?;
+    writeln!(
+        file,
+        "# {script}.sh of {id}, {}",
+        chrono::Local::now().to_rfc3339()
+    )?;
+    Ok((log, file))
+}
+
+/// Wait for `child`, killing its process group once `timeout` passed
+fn wait(child: &mut Child, timeout: Option<Duration>) -> io::Result<(ExitStatus, bool)> {
+    let Some(timeout) = timeout else {
+        return Ok((child.wait()?, false));
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok((status, false));
+        }
+        if Instant::now() >= deadline {
+            let pgid = i32::try_from(child.id()).unwrap_or(i32::MAX);
+            // the script runs in a process group of its own, see exec_script
+            unsafe {
+                libc::kill(-pgid, libc::SIGKILL);
+            }
+            return Ok((child.wait()?, true));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Copy `from` to `to` and the log as it comes
+fn tee(
+    mut from: impl Read + Send + 'static,
+    mut to: impl Write + Send + 'static,
+    log: Arc<Mutex<File>>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        while let Ok(read) = from.read(&mut buffer) {
+            if read == 0 {
+                break;
+            }
+            let _ = to.write_all(&buffer[..read]);
+            let _ = to.flush();
+            if let Ok(mut log) = log.lock() {
+                let _ = log.write_all(&buffer[..read]);
+            }
+        }
+    })
+}
+
+/// Run the module script `path` of module `id` with `command`, set up by the caller
+pub fn run(mut command: Command, id: &str, path: &Path, mode: Mode) -> Result<Outcome> {
+    let script = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("script")
+        .to_string();
+    let module_dir = path.parent().unwrap_or(path);
+    let (log, mut file) = open_log(id, &script)?;
+    command.stdin(Stdio::null());
+
+    if mode != Mode::Stream {
+        command.stdout(file.try_clone()?).stderr(file.try_clone()?);
+        let mut child = command.spawn()?;
+        if mode == Mode::Spawn {
+            return Ok(Outcome {
+                status: None,
+                timed_out: None,
+                log,
+            });
+        }
+        let timeout = timeout(module_dir, &script);
+        let (status, timed_out) = wait(&mut child, timeout)?;
+        let outcome = Outcome {
+            status: (!timed_out).then_some(status),
+            timed_out: timeout.filter(|_| timed_out),
+            log,
+        };
+        writeln!(file, "# {}", outcome.describe())?;
+        info!("{script}.sh of {id} {}", outcome.describe());
+        return Ok(outcome);
+    }
+
+    command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    let mut child = command.spawn()?;
+    let file = Arc::new(Mutex::new(file));
+    let mut readers = Vec::new();
+    if let Some(stdout) = child.stdout.take() {
+        readers.push(tee(stdout, io::stdout(), file.clone()));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        readers.push(tee(stderr, io::stderr(), file.clone()));
+    }
+    let timeout = timeout(module_dir, &script);
+    let (status, timed_out) = wait(&mut child, timeout)?;
+
+    let drain_deadline = Instant::now() + DRAIN_TIMEOUT;
+    while readers.iter().any(|reader| !reader.is_finished()) && Instant::now() < drain_deadline {
+        thread::sleep(POLL_INTERVAL);
+    }
+    let outcome = Outcome {
+        status: (!timed_out).then_some(status),
+        timed_out: timeout.filter(|_| timed_out),
+        log,
+    };
+    if let Ok(mut file) = file.lock() {
+        writeln!(file, "# {}", outcome.describe())?;
+    }
+    info!("{script}.sh of {id} {}", outcome.describe());
+    Ok(outcome)
+}
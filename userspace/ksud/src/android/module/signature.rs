@@ -0,0 +1,237 @@
+//! Verify module zips against trusted keys, `ksud module keys`
+//!
+//! A module is signed by a detached `<zip>.sig` next to it: an Ed25519 signature over the
+//! SHA-256 digest of the zip, raw 64 bytes or hex. Trusted public keys live in
+//! `defs::MODULE_KEYS_DIR` as `<name>.pub`, the raw 32 byte key in hex.
+
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail, ensure};
+use log::{info, warn};
+use regex_lite::Regex;
+use ring::signature::{ED25519, UnparsedPublicKey};
+
+use crate::{
+    android::{module::module_config, utils::ensure_dir_exists},
+    defs,
+};
+
+pub const SIGNATURE_CONFIG_MODULE_ID: &str = "internal.ksud.module_signature";
+/// `off`, `warn` or `enforce`
+const MODE_CONFIG_KEY: &str = "signature.mode";
+const KEY_SUFFIX: &str = ".pub";
+const ED25519_KEY_LEN: usize = 32;
+const ED25519_SIGNATURE_LEN: usize = 64;
+/// DER SubjectPublicKeyInfo header of an Ed25519 key, `openssl pkey -pubin -outform DER`
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Off,
+    /// Install anyway and print a warning
+    Warn,
+    /// Refuse the install before anything is extracted
+    Enforce,
+}
+
+impl Mode {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Warn => "warn",
+            Self::Enforce => "enforce",
+        }
+    }
+}
+
+/// The configured mode, off unless set. An unknown value enforces, a typo must not open the door.
+pub fn mode() -> Mode {
+    let Ok(config) = module_config::merge_configs(SIGNATURE_CONFIG_MODULE_ID) else {
+        return Mode::Off;
+    };
+    match config.get(MODE_CONFIG_KEY).map(|v| v.trim()) {
+        None | Some("off") => Mode::Off,
+        Some("warn") => Mode::Warn,
+        Some("enforce") => Mode::Enforce,
+        Some(other) => {
+            warn!("invalid {MODE_CONFIG_KEY} '{other}', enforcing module signatures");
+            Mode::Enforce
+        }
+    }
+}
+
+/// Where the detached signature of `zip` is expected
+pub fn signature_path(zip: &Path) -> PathBuf {
+    let mut path = OsString::from(zip.as_os_str());
+    path.push(".sig");
+    PathBuf::from(path)
+}
+
+/// Short, stable id of a key for listings and logs
+fn fingerprint(key: &[u8]) -> String {
+    sha256::digest(key)[..16].to_string()
+}
+
+fn decode_hex(text: &str) -> Result<Vec<u8>> {
+    base16ct::mixed::decode_vec(text.trim()).map_err(|e| anyhow::anyhow!("invalid hex: {e}"))
+}
+
+/// Accept a raw key, a DER SubjectPublicKeyInfo or hex of either
+fn parse_key(data: &[u8]) -> Result<Vec<u8>> {
+    let raw = |data: &[u8]| match data.len() {
+        ED25519_KEY_LEN => Some(data.to_vec()),
+        len if len == ED25519_SPKI_PREFIX.len() + ED25519_KEY_LEN
+            && data.starts_with(&ED25519_SPKI_PREFIX) =>
+        {
+            Some(data[ED25519_SPKI_PREFIX.len()..].to_vec())
+        }
+        _ => None,
+    };
+    if let Some(key) = raw(data) {
+        return Ok(key);
+    }
+    let text = std::str::from_utf8(data).context("not an Ed25519 public key")?;
+    raw(&decode_hex(text)?).context("not an Ed25519 public key")
+}
+
+fn validate_key_name(name: &str) -> Result<()> {
+    let re = Regex::new(r"^[a-zA-Z0-9][a-zA-Z0-9._-]*$")?;
+    ensure!(
+        re.is_match(name),
+        "invalid key name '{name}', use letters, digits, '.', '_' and '-'"
+    );
+    Ok(())
+}
+
+fn key_path(name: &str) -> PathBuf {
+    Path::new(defs::MODULE_KEYS_DIR).join(format!("{name}{KEY_SUFFIX}"))
+}
+
+/// Trusted keys by name, unreadable key files are skipped
+fn trusted_keys() -> Vec<(String, Vec<u8>)> {
+    let Ok(dir) = fs::read_dir(defs::MODULE_KEYS_DIR) else {
+        return Vec::new();
+    };
+    let mut keys: Vec<(String, Vec<u8>)> = dir
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name();
+            let name = file_name.to_str()?.strip_suffix(KEY_SUFFIX)?.to_string();
+            match fs::read(entry.path())
+                .map_err(anyhow::Error::from)
+                .and_then(|data| parse_key(&data))
+            {
+                Ok(key) => Some((name, key)),
+                Err(e) => {
+                    warn!("skipping trusted key {name}: {e}");
+                    None
+                }
+            }
+        })
+        .collect();
+    keys.sort();
+    keys
+}
+
+/// Name of the trusted key that signed `zip`, or why none did
+fn signer(zip: &Path) -> Result<Result<String, String>> {
+    let sig_path = signature_path(zip);
+    let Ok(data) = fs::read(&sig_path) else {
+        return Ok(Err(format!(
+            "module is unsigned, no {}",
+            sig_path.display()
+        )));
+    };
+    let signature = if data.len() == ED25519_SIGNATURE_LEN {
+        data
+    } else {
+        match std::str::from_utf8(&data).map(decode_hex) {
+            Ok(Ok(decoded)) if decoded.len() == ED25519_SIGNATURE_LEN => decoded,
+            _ => {
+                return Ok(Err(format!(
+                    "{} is not an Ed25519 signature",
+                    sig_path.display()
+                )));
+            }
+        }
+    };
+
+    let keys = trusted_keys();
+    if keys.is_empty() {
+        return Ok(Err(format!(
+            "no trusted keys in {}, add one with `ksud module keys add`",
+            defs::MODULE_KEYS_DIR
+        )));
+    }
+    let digest = decode_hex(
+        &sha256::try_digest(zip).with_context(|| format!("failed to read {}", zip.display()))?,
+    )?;
+    Ok(keys
+        .into_iter()
+        .find(|(_, key)| {
+            UnparsedPublicKey::new(&ED25519, key)
+                .verify(&digest, &signature)
+                .is_ok()
+        })
+        .map(|(name, _)| name)
+        .ok_or_else(|| "signature does not match any trusted key".to_string()))
+}
+
+/// Check the signature of `zip` according to the configured mode
+pub fn verify(zip: &Path) -> Result<()> {
+    let mode = mode();
+    if mode == Mode::Off {
+        return Ok(());
+    }
+    match signer(zip)? {
+        Ok(name) => {
+            println!("- Signed by trusted key {name}");
+            info!("module {} is signed by {name}", zip.display());
+        }
+        Err(reason) if mode == Mode::Warn => {
+            println!("- Warning: {reason}");
+            warn!("module {}: {reason}, installing anyway", zip.display());
+        }
+        Err(reason) => bail!(
+            "{reason}, refusing to install (module signature mode is {})",
+            mode.name()
+        ),
+    }
+    Ok(())
+}
+
+/// Trust `key`, a file or a hex string, under `name`
+pub fn add_key(name: &str, key: &str) -> Result<()> {
+    validate_key_name(name)?;
+    let data = fs::read(key).unwrap_or_else(|_| key.as_bytes().to_vec());
+    let key = parse_key(&data)?;
+    let path = key_path(name);
+    ensure!(!path.exists(), "key {name} already exists, remove it first");
+    ensure_dir_exists(defs::MODULE_KEYS_DIR)?;
+    fs::write(&path, base16ct::lower::encode_string(&key))
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    println!("{name} {}", fingerprint(&key));
+    Ok(())
+}
+
+/// Print every trusted key with its fingerprint
+pub fn list_keys() -> Result<()> {
+    println!("mode: {}", mode().name());
+    for (name, key) in trusted_keys() {
+        println!("{name} {}", fingerprint(&key));
+    }
+    Ok(())
+}
+
+pub fn remove_key(name: &str) -> Result<()> {
+    validate_key_name(name)?;
+    let path = key_path(name);
+    ensure!(path.exists(), "key {name} not found");
+    fs::remove_file(&path).with_context(|| format!("failed to remove {}", path.display()))
+}
@@ -0,0 +1,144 @@
+//! Per-partition skip_mount, `ksud module skip-mount`
+//!
+//! A module keeps one of its partitions from being mounted with a `skip_mount_<partition>` file,
+//! or lists partitions in module.prop as `skip_mount=product,vendor`. Metamodules honor these the
+//! way they honor a whole-module `skip_mount`.
+
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{Context, Result, ensure};
+use log::{info, warn};
+
+use crate::{
+    android::module::{
+        MODULE_PARTITIONS, ModuleType::Active, foreach_module, read_module_prop, validate_module_id,
+    },
+    defs,
+};
+
+const SKIP_MOUNT_FILE: &str = "skip_mount";
+const SKIP_MOUNT_KEY: &str = "skip_mount";
+const MARKER_PREFIX: &str = "skip_mount_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// `skip_mount_<partition>` in the module dir
+    Marker,
+    /// Listed in the `skip_mount` key of module.prop
+    ModuleProp,
+}
+
+impl Reason {
+    pub fn describe(self, partition: &str) -> String {
+        match self {
+            Self::Marker => marker(partition),
+            Self::ModuleProp => format!("{SKIP_MOUNT_KEY}={partition} in module.prop"),
+        }
+    }
+}
+
+fn partitions() -> impl Iterator<Item = &'static str> {
+    std::iter::once("system").chain(MODULE_PARTITIONS)
+}
+
+fn validate_partition(partition: &str) -> Result<&'static str> {
+    partitions().find(|p| *p == partition).with_context(|| {
+        let known: Vec<&str> = partitions().collect();
+        format!(
+            "unknown partition '{partition}', use one of {}",
+            known.join(", ")
+        )
+    })
+}
+
+fn marker(partition: &str) -> String {
+    format!("{MARKER_PREFIX}{partition}")
+}
+
+fn listed(module_prop: &HashMap<String, String>, partition: &str) -> bool {
+    module_prop
+        .get(SKIP_MOUNT_KEY)
+        .is_some_and(|list| list.split(',').map(str::trim).any(|p| p == partition))
+}
+
+fn listed_in_prop(module_dir: &Path, partition: &str) -> bool {
+    read_module_prop(module_dir).is_ok_and(|prop| listed(&prop, partition))
+}
+
+/// The partitions of the module in `module_dir` that are not mounted, with the reason
+pub fn skipped(module_dir: &Path) -> Vec<(&'static str, Reason)> {
+    skipped_in(
+        module_dir,
+        &read_module_prop(module_dir).unwrap_or_default(),
+    )
+}
+
+/// Like [`skipped`], with the module.prop already read
+pub fn skipped_in(
+    module_dir: &Path,
+    module_prop: &HashMap<String, String>,
+) -> Vec<(&'static str, Reason)> {
+    partitions()
+        .filter_map(|partition| {
+            if module_dir.join(marker(partition)).exists() {
+                Some((partition, Reason::Marker))
+            } else if listed(module_prop, partition) {
+                Some((partition, Reason::ModuleProp))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Skip or mount `partition` of module `id` again from the next boot, without a reinstall
+pub fn set(id: &str, partition: &str, skip: bool) -> Result<()> {
+    validate_module_id(id)?;
+    let partition = validate_partition(partition)?;
+    let module_dir = Path::new(defs::MODULE_DIR).join(id);
+    ensure!(module_dir.exists(), "module {id} not found");
+
+    let path = module_dir.join(marker(partition));
+    if skip {
+        fs::write(&path, "").with_context(|| format!("failed to create {}", path.display()))?;
+        println!("- /{partition} of {id} is not mounted from the next boot");
+        return Ok(());
+    }
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(e).with_context(|| format!("failed to remove {}", path.display()));
+        }
+        _ => {}
+    }
+    if listed_in_prop(&module_dir, partition) {
+        println!("- Warning: module.prop of {id} still skips /{partition}");
+    } else if module_dir.join(SKIP_MOUNT_FILE).exists() {
+        println!("- Warning: {id} has skip_mount, none of its partitions are mounted");
+    } else {
+        println!("- /{partition} of {id} is mounted again from the next boot");
+    }
+    Ok(())
+}
+
+/// Log what the metamodule is about to leave unmounted, before its mount script runs
+pub fn log_skipped() {
+    let result = foreach_module(Active, |module_dir| {
+        let id = module_dir
+            .file_name()
+            .map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+        if module_dir.join(SKIP_MOUNT_FILE).exists() {
+            info!("module {id}: not mounting any partition (skip_mount)");
+            return Ok(());
+        }
+        for (partition, reason) in skipped(module_dir) {
+            info!(
+                "module {id}: not mounting /{partition} ({})",
+                reason.describe(partition)
+            );
+        }
+        Ok(())
+    });
+    if let Err(e) = result {
+        warn!("failed to list skipped partitions: {e}");
+    }
+}
@@ -0,0 +1,201 @@
+//! Snapshots of installed modules taken before an update, `ksud module rollback`
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{Context, Result, ensure};
+use log::{info, warn};
+
+use crate::{
+    android::{
+        module::{add_module_umount_entries, module_config, read_module_prop, validate_module_id},
+        restorecon::{restore_syscon, setsyscon},
+        utils::{ensure_clean_dir, ensure_dir_exists, ensure_file_exists},
+    },
+    assets, defs,
+};
+
+pub const SNAPSHOT_CONFIG_MODULE_ID: &str = "internal.ksud.module_snapshot";
+/// Snapshots kept per module, 0 disables them
+const KEEP_CONFIG_KEY: &str = "snapshot.keep";
+const DEFAULT_KEEP: usize = 3;
+
+struct Snapshot {
+    path: PathBuf,
+    version: String,
+    version_code: String,
+}
+
+impl Snapshot {
+    fn describe(&self) -> String {
+        format!("{} ({})", self.version, self.version_code)
+    }
+}
+
+fn keep() -> usize {
+    let Ok(config) = module_config::merge_configs(SNAPSHOT_CONFIG_MODULE_ID) else {
+        return DEFAULT_KEEP;
+    };
+    match config.get(KEEP_CONFIG_KEY).map(|v| v.trim().parse()) {
+        Some(Ok(keep)) => keep,
+        Some(Err(_)) => {
+            warn!("invalid {KEEP_CONFIG_KEY}, keeping {DEFAULT_KEEP} snapshots");
+            DEFAULT_KEEP
+        }
+        None => DEFAULT_KEEP,
+    }
+}
+
+fn snapshot_dir(id: &str) -> PathBuf {
+    Path::new(defs::MODULE_SNAPSHOT_DIR).join(id)
+}
+
+/// Snapshots of `id`, oldest first
+fn snapshots(id: &str) -> Vec<Snapshot> {
+    let Ok(dir) = fs::read_dir(snapshot_dir(id)) else {
+        return Vec::new();
+    };
+    let mut snapshots: Vec<Snapshot> = dir
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            let prop = read_module_prop(&path).ok()?;
+            let field = |key: &str| {
+                prop.get(key)
+                    .map_or_else(String::new, |v| v.trim().to_string())
+            };
+            Some(Snapshot {
+                version: field("version"),
+                version_code: field("versionCode"),
+                path,
+            })
+        })
+        .collect();
+    // named by the time they were taken
+    snapshots.sort_by(|a, b| a.path.cmp(&b.path));
+    snapshots
+}
+
+/// Copy the contents of `src` into a fresh `dst`, keeping modes, owners and symlinks
+fn copy_dir(src: &Path, dst: &Path) -> Result<()> {
+    ensure_clean_dir(dst)?;
+    let status = Command::new(assets::BUSYBOX_PATH)
+        .arg("cp")
+        .arg("-a")
+        .arg(src.join("."))
+        .arg(dst)
+        .status()?;
+    ensure!(
+        status.success(),
+        "failed to copy {} to {}",
+        src.display(),
+        dst.display()
+    );
+    Ok(())
+}
+
+fn prune(id: &str, keep: usize) {
+    let snapshots = snapshots(id);
+    let excess = snapshots.len().saturating_sub(keep);
+    for snapshot in &snapshots[..excess] {
+        info!("prune snapshot {}", snapshot.path.display());
+        if let Err(e) = fs::remove_dir_all(&snapshot.path) {
+            warn!("failed to remove {}: {e}", snapshot.path.display());
+        }
+    }
+}
+
+/// Drop every snapshot of `id`, once the module itself is gone
+pub fn remove_all(id: &str) -> Result<()> {
+    let dir = snapshot_dir(id);
+    if dir.exists() {
+        fs::remove_dir_all(&dir)?;
+    }
+    Ok(())
+}
+
+/// Save the installed `id` before an update replaces it, nothing to do for a fresh install
+pub fn take(id: &str) -> Result<()> {
+    let keep = keep();
+    let module_dir = Path::new(defs::MODULE_DIR).join(id);
+    if keep == 0 || !module_dir.join("module.prop").exists() {
+        return Ok(());
+    }
+
+    let name = chrono::Local::now().format("%Y%m%d-%H%M%S%.3f").to_string();
+    let path = snapshot_dir(id).join(name);
+    copy_dir(&module_dir, &path)?;
+    // pending state of the old copy must not come back with a rollback
+    for flag in [defs::UPDATE_FILE_NAME, defs::REMOVE_FILE_NAME] {
+        let _ = fs::remove_file(path.join(flag));
+    }
+
+    let version = read_module_prop(&path)
+        .ok()
+        .and_then(|prop| prop.get("version").cloned())
+        .unwrap_or_default();
+    println!("- Saved snapshot of {id} {}", version.trim());
+    prune(id, keep);
+    Ok(())
+}
+
+/// Put a snapshot of `id` back in place on the next boot, the newest one unless `to` names a
+/// version or versionCode
+pub fn rollback(id: &str, to: Option<&str>) -> Result<()> {
+    validate_module_id(id)?;
+    let mut snapshots = snapshots(id);
+    ensure!(
+        !snapshots.is_empty(),
+        "module {id} has no snapshot to roll back to, snapshots are taken when an installed module is updated"
+    );
+    let index = match to {
+        None => snapshots.len() - 1,
+        Some(to) => snapshots
+            .iter()
+            .rposition(|s| s.version == to || s.version_code == to)
+            .with_context(|| {
+                let available: Vec<String> = snapshots.iter().map(Snapshot::describe).collect();
+                format!(
+                    "module {id} has no snapshot of version {to}, available: {}",
+                    available.join(", ")
+                )
+            })?,
+    };
+    let snapshot = snapshots.swap_remove(index);
+
+    // staged like an update, the next boot moves it in place and runs its scripts
+    ensure_dir_exists(defs::MODULE_UPDATE_DIR)?;
+    setsyscon(defs::MODULE_UPDATE_DIR)?;
+    let updated_dir = Path::new(defs::MODULE_UPDATE_DIR).join(id);
+    copy_dir(&snapshot.path, &updated_dir)?;
+    let system_dir = updated_dir.join("system");
+    if system_dir.exists() {
+        restore_syscon(&system_dir)?;
+    }
+    let module_prop = read_module_prop(&updated_dir)?;
+    add_module_umount_entries(&module_prop, &updated_dir, id);
+
+    // the version being replaced becomes a snapshot as well, so the rollback can be undone
+    if let Err(e) = take(id) {
+        warn!("failed to snapshot {id} before rollback: {e}");
+    }
+
+    let module_dir = Path::new(defs::MODULE_DIR).join(id);
+    ensure_dir_exists(&module_dir)?;
+    fs::copy(
+        updated_dir.join("module.prop"),
+        module_dir.join("module.prop"),
+    )?;
+    ensure_file_exists(module_dir.join(defs::UPDATE_FILE_NAME))?;
+    let _ = fs::remove_file(module_dir.join(defs::REMOVE_FILE_NAME));
+
+    println!(
+        "- Rolled back {id} to {}, reboot to apply",
+        snapshot.describe()
+    );
+    info!("module {id} rolled back to {}", snapshot.path.display());
+    Ok(())
+}
@@ -0,0 +1,281 @@
+//! Rich module status, `ksud module list --json`, and the mount status recorded at boot
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use jwalk::{Parallelism::Serial, WalkDir};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    android::module::{
+        MODULE_PARTITIONS, dependency, metamodule, read_module_prop, skip_mount, skip_mount::Reason,
+    },
+    defs,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MountState {
+    Mounted,
+    Failed,
+    Skipped,
+}
+
+/// How a module fared in the last boot's mount, as recorded in `defs::MODULE_MOUNT_STATUS_PATH`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MountStatus {
+    pub status: MountState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl MountStatus {
+    fn new(status: MountState, reason: impl Into<String>) -> Self {
+        Self {
+            status,
+            reason: Some(reason.into()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DependencyStatus {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_version_code: Option<i64>,
+    pub status: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModuleStatus {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub version_code: i64,
+    pub author: String,
+    pub description: String,
+    pub enabled: bool,
+    pub update_pending: bool,
+    pub remove_pending: bool,
+    /// None if the module was not there at the last boot
+    pub mount: Option<MountStatus>,
+    pub skipped_partitions: Vec<String>,
+    pub dependencies: Vec<DependencyStatus>,
+    /// Bytes the module dir takes on disk
+    pub size: u64,
+}
+
+/// A module dir with its module.prop, read once and shared by every field
+struct Scanned {
+    id: String,
+    path: PathBuf,
+    prop: HashMap<String, String>,
+}
+
+fn scan() -> Vec<Scanned> {
+    let Ok(dir) = fs::read_dir(defs::MODULE_DIR) else {
+        return Vec::new();
+    };
+    let mut modules: Vec<Scanned> = dir
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            let mut prop = read_module_prop(&path).ok()?;
+            let id = match prop.get("id") {
+                Some(id) if !id.trim().is_empty() => id.trim().to_string(),
+                _ => entry.file_name().to_str()?.to_string(),
+            };
+            prop.insert("id".to_owned(), id.clone());
+            Some(Scanned { id, path, prop })
+        })
+        .collect();
+    modules.sort_by(|a, b| a.id.cmp(&b.id));
+    modules
+}
+
+fn has_mount_files(module: &Path) -> bool {
+    std::iter::once("system")
+        .chain(MODULE_PARTITIONS)
+        .any(|partition| module.join(partition).is_dir())
+}
+
+/// Disk usage of `path`, from allocated blocks so sparse files count what they really take
+fn disk_usage(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .parallelism(Serial)
+        .skip_hidden(false)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.blocks() * 512)
+        .sum()
+}
+
+/// Why a module that exists is not mounted, None if it is handed to the metamodule
+fn skip_reason(
+    module: &Scanned,
+    resolved: &dependency::Resolved,
+    skipped: &[(&str, Reason)],
+) -> Option<String> {
+    let path = &module.path;
+    if path.join(defs::DISABLE_FILE_NAME).exists() {
+        return Some("disabled".into());
+    }
+    if path.join(defs::REMOVE_FILE_NAME).exists() {
+        return Some("removed".into());
+    }
+    if !resolved.is_active(&module.id) {
+        let unmet: Vec<String> = dependency::statuses(&module.prop, resolved)
+            .into_iter()
+            .filter(|(_, status)| *status != dependency::Status::Met)
+            .map(|(dep, status)| format!("{dep} is {status}"))
+            .collect();
+        return Some(format!("unmet dependencies: {}", unmet.join(", ")));
+    }
+    if path.join("skip_mount").exists() {
+        return Some("skip_mount".into());
+    }
+    if !has_mount_files(path) {
+        return Some("no files to mount".into());
+    }
+    let all_skipped = std::iter::once("system")
+        .chain(MODULE_PARTITIONS)
+        .filter(|partition| path.join(partition).is_dir())
+        .all(|partition| skipped.iter().any(|(p, _)| *p == partition));
+    all_skipped.then(|| "all partitions skipped".into())
+}
+
+fn write_mount_status(statuses: &BTreeMap<String, MountStatus>) {
+    let result = serde_json::to_string(statuses)
+        .map_err(anyhow::Error::from)
+        .and_then(|content| Ok(fs::write(defs::MODULE_MOUNT_STATUS_PATH, content)?));
+    if let Err(e) = result {
+        warn!("failed to record module mount status: {e}");
+    }
+}
+
+/// Record how each module fared once the metamodule mount script returned `result`.
+///
+/// The mount script covers all modules at once, so a failure is recorded for every module that
+/// was handed to it.
+pub fn record_mount(result: &Result<()>) {
+    let modules = scan();
+    let resolved = dependency::resolved_from(
+        modules
+            .iter()
+            .map(|m| (m.id.as_str(), m.path.as_path(), &m.prop)),
+    );
+    let has_metamodule = metamodule::has_metamodule();
+    let statuses: BTreeMap<String, MountStatus> = modules
+        .iter()
+        .map(|module| {
+            let skipped = skip_mount::skipped_in(&module.path, &module.prop);
+            let status = match skip_reason(module, &resolved, &skipped) {
+                Some(reason) => MountStatus::new(MountState::Skipped, reason),
+                None if !has_metamodule => {
+                    MountStatus::new(MountState::Skipped, "no metamodule installed")
+                }
+                None => match result {
+                    Err(e) => MountStatus::new(MountState::Failed, format!("{e:#}")),
+                    Ok(()) if skipped.is_empty() => MountStatus {
+                        status: MountState::Mounted,
+                        reason: None,
+                    },
+                    Ok(()) => {
+                        let partitions: Vec<String> = skipped
+                            .iter()
+                            .map(|(partition, reason)| {
+                                format!("/{partition} ({})", reason.describe(partition))
+                            })
+                            .collect();
+                        MountStatus::new(
+                            MountState::Mounted,
+                            format!("skipped {}", partitions.join(", ")),
+                        )
+                    }
+                },
+            };
+            (module.id.clone(), status)
+        })
+        .collect();
+    write_mount_status(&statuses);
+}
+
+/// Record that safe mode kept every module from being mounted
+pub fn record_safe_mode() {
+    let statuses: BTreeMap<String, MountStatus> = scan()
+        .into_iter()
+        .map(|module| {
+            (
+                module.id,
+                MountStatus::new(MountState::Skipped, "safe mode"),
+            )
+        })
+        .collect();
+    write_mount_status(&statuses);
+}
+
+fn read_mount_status() -> BTreeMap<String, MountStatus> {
+    fs::read_to_string(defs::MODULE_MOUNT_STATUS_PATH)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+/// Every installed module with its full status, in one pass over the module dirs
+pub fn modules() -> Vec<ModuleStatus> {
+    let modules = scan();
+    let resolved = dependency::resolved_from(
+        modules
+            .iter()
+            .map(|m| (m.id.as_str(), m.path.as_path(), &m.prop)),
+    );
+    let mut mount_status = read_mount_status();
+    info!("listing {} modules", modules.len());
+    modules
+        .into_iter()
+        .map(|module| {
+            let field = |key: &str| {
+                module
+                    .prop
+                    .get(key)
+                    .map_or_else(String::new, |v| v.trim().to_string())
+            };
+            let dependencies = dependency::statuses(&module.prop, &resolved)
+                .into_iter()
+                .map(|(dep, status)| DependencyStatus {
+                    id: dep.id,
+                    min_version_code: dep.min_version_code,
+                    status: status.to_string(),
+                })
+                .collect();
+            let skipped_partitions = skip_mount::skipped_in(&module.path, &module.prop)
+                .into_iter()
+                .map(|(partition, _)| partition.to_string())
+                .collect();
+            ModuleStatus {
+                name: field("name"),
+                version: field("version"),
+                version_code: field("versionCode").parse().unwrap_or(0),
+                author: field("author"),
+                description: field("description"),
+                enabled: !module.path.join(defs::DISABLE_FILE_NAME).exists(),
+                update_pending: module.path.join(defs::UPDATE_FILE_NAME).exists(),
+                remove_pending: module.path.join(defs::REMOVE_FILE_NAME).exists(),
+                mount: mount_status.remove(&module.id),
+                skipped_partitions,
+                dependencies,
+                size: disk_usage(&module.path),
+                id: module.id,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,456 @@
+//! Serve the webroot of a module over HTTP, `ksud module webui`
+//!
+//! Files come from `<module>/webroot`, nothing outside it is reachable. The bridge under `/ksu/`
+//! needs the token printed at start, as `Authorization: Bearer <token>` or through the cookie set
+//! when the printed URL is opened:
+//!
+//! - `GET /ksu/info`: module.prop of the module as JSON
+//! - `POST /ksu/action`: run the module's action.sh, `{"exitCode":0,"timedOut":false,"output":""}`
+//! - `GET /ksu/bridge.js`: `ksu.moduleInfo()` and `ksu.action()` for the page
+
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    path::{Component, Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail, ensure};
+use log::{info, warn};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::{
+    android::module::{exec_script_with, read_module_prop, script, validate_module_id},
+    defs,
+};
+
+const TOKEN_COOKIE: &str = "ksu_webui_token";
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+const MAX_BODY_SIZE: u64 = 64 * 1024;
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+
+const BRIDGE_JS: &str = r#"window.ksu = {
+  moduleInfo: () => fetch("/ksu/info").then((r) => r.json()),
+  action: () => fetch("/ksu/action", { method: "POST" }).then((r) => r.json()),
+};
+"#;
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(_: libc::c_int) {
+    STOP.store(true, Ordering::Relaxed);
+}
+
+struct Server {
+    id: String,
+    module_dir: PathBuf,
+    /// Canonical, so resolved request paths can be checked against it
+    webroot: PathBuf,
+    token: String,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    headers: Vec<String>,
+    body: Body,
+}
+
+enum Body {
+    Bytes(Vec<u8>),
+    File(File, u64),
+}
+
+impl Response {
+    fn text(status: &'static str, text: &str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            headers: Vec::new(),
+            body: Body::Bytes(text.as_bytes().to_vec()),
+        }
+    }
+
+    fn json(value: &serde_json::Value) -> Self {
+        Self {
+            status: "200 OK",
+            content_type: "application/json",
+            headers: Vec::new(),
+            body: Body::Bytes(value.to_string().into_bytes()),
+        }
+    }
+
+    fn write(self, stream: &mut TcpStream, head_only: bool) -> io::Result<()> {
+        let len = match &self.body {
+            Body::Bytes(bytes) => bytes.len() as u64,
+            Body::File(_, len) => *len,
+        };
+        let mut head = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {len}\r\nCache-Control: no-store\r\nX-Content-Type-Options: nosniff\r\nConnection: close\r\n",
+            self.status, self.content_type
+        );
+        for header in &self.headers {
+            head.push_str(header);
+            head.push_str("\r\n");
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes())?;
+        if head_only {
+            return stream.flush();
+        }
+        match self.body {
+            Body::Bytes(bytes) => stream.write_all(&bytes)?,
+            Body::File(mut file, _) => {
+                io::copy(&mut file, stream)?;
+            }
+        }
+        stream.flush()
+    }
+}
+
+/// Content type by extension, the ones a webroot ships
+fn mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "xml" => "text/xml",
+        "svg" | "svgz" => "image/svg+xml",
+        "png" => "image/png",
+        "apng" => "image/apng",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "bmp" => "image/bmp",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "wasm" => "application/wasm",
+        "mp3" => "audio/mpeg",
+        "ogg" | "oga" | "opus" => "audio/ogg",
+        "wav" => "audio/wav",
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+fn percent_decode(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn generate_token() -> Result<String> {
+    let mut token = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut token)
+        .map_err(|_| anyhow::anyhow!("failed to generate the webui token"))?;
+    Ok(base16ct::lower::encode_string(&token))
+}
+
+fn read_request(stream: &mut TcpStream) -> Result<Request> {
+    let limit = MAX_HEADER_SIZE as u64 + MAX_BODY_SIZE;
+    let mut reader = BufReader::new(stream.try_clone()?.take(limit));
+    let mut size = 0;
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        bail!("malformed request line");
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.to_string(),
+        headers: Vec::new(),
+    };
+    loop {
+        line.clear();
+        size += reader.read_line(&mut line)?;
+        ensure!(size <= MAX_HEADER_SIZE, "request headers too large");
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((key, value)) = header.split_once(':') {
+            request
+                .headers
+                .push((key.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    // the bridge takes no body, drain it so the response isn't cut off by a reset
+    let body_len = request
+        .header("Content-Length")
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(0);
+    ensure!(body_len <= MAX_BODY_SIZE, "request body too large");
+    io::copy(&mut reader.take(body_len), &mut io::sink())?;
+    Ok(request)
+}
+
+impl Server {
+    fn authorized(&self, request: &Request) -> bool {
+        let bearer = request
+            .header("Authorization")
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .is_some_and(|token| token.trim() == self.token);
+        let cookie = request.header("Cookie").is_some_and(|cookies| {
+            cookies
+                .split(';')
+                .filter_map(|c| c.trim().split_once('='))
+                .any(|(name, value)| name == TOKEN_COOKIE && value == self.token)
+        });
+        bearer || cookie
+    }
+
+    /// The file under the webroot a request path names, None if it names anything else
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let decoded = percent_decode(path)?;
+        if decoded.contains('\0') {
+            return None;
+        }
+        let mut file = self.webroot.clone();
+        for component in Path::new(decoded.trim_start_matches('/')).components() {
+            match component {
+                Component::Normal(part) => file.push(part),
+                Component::CurDir => {}
+                _ => return None,
+            }
+        }
+        if file.is_dir() {
+            file.push("index.html");
+        }
+        // symlinks may point anywhere, only what ends up under the webroot is served
+        let file = fs::canonicalize(file).ok()?;
+        (file.starts_with(&self.webroot) && file.is_file()).then_some(file)
+    }
+
+    fn serve_file(&self, path: &str) -> Response {
+        let Some(file) = self.resolve(path) else {
+            return Response::text("404 Not Found", "not found");
+        };
+        match File::open(&file).and_then(|f| Ok((f.metadata()?.len(), f))) {
+            Ok((len, f)) => Response {
+                status: "200 OK",
+                content_type: mime_type(&file),
+                headers: Vec::new(),
+                body: Body::File(f, len),
+            },
+            Err(e) => {
+                warn!("webui: failed to open {}: {e}", file.display());
+                Response::text("500 Internal Server Error", "failed to read file")
+            }
+        }
+    }
+
+    fn info(&self) -> Response {
+        match read_module_prop(&self.module_dir) {
+            Ok(prop) => {
+                let mut info: serde_json::Map<String, serde_json::Value> = prop
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into()))
+                    .collect();
+                info.insert(
+                    "moduleDir".into(),
+                    self.module_dir.to_string_lossy().into_owned().into(),
+                );
+                Response::json(&info.into())
+            }
+            Err(e) => Response::text("500 Internal Server Error", &e.to_string()),
+        }
+    }
+
+    fn action(&self) -> Response {
+        let action = self.module_dir.join(defs::MODULE_ACTION_SH);
+        if !action.exists() {
+            return Response::text("404 Not Found", "module has no action.sh");
+        }
+        info!("webui: running action of {}", self.id);
+        match exec_script_with(&action, script::Mode::Wait) {
+            Ok(Some(outcome)) => {
+                let output = fs::read_to_string(&outcome.log).unwrap_or_default();
+                Response::json(&serde_json::json!({
+                    "exitCode": outcome.status.and_then(|status| status.code()),
+                    "timedOut": outcome.timed_out.is_some(),
+                    "output": output,
+                }))
+            }
+            Ok(None) => Response::text("500 Internal Server Error", "action did not run"),
+            Err(e) => Response::text("500 Internal Server Error", &e.to_string()),
+        }
+    }
+
+    fn respond(&self, request: &Request) -> Response {
+        let get = matches!(request.method.as_str(), "GET" | "HEAD");
+        if let Some(endpoint) = request.path.strip_prefix("/ksu/") {
+            if endpoint == "bridge.js" && get {
+                return Response {
+                    status: "200 OK",
+                    content_type: "text/javascript; charset=utf-8",
+                    headers: Vec::new(),
+                    body: Body::Bytes(BRIDGE_JS.as_bytes().to_vec()),
+                };
+            }
+            if !self.authorized(request) {
+                return Response::text("401 Unauthorized", "missing or wrong token");
+            }
+            return match (endpoint, request.method.as_str()) {
+                ("info", "GET" | "HEAD") => self.info(),
+                ("action", "POST") => self.action(),
+                _ => Response::text("404 Not Found", "no such endpoint"),
+            };
+        }
+        if !get {
+            return Response::text("405 Method Not Allowed", "method not allowed");
+        }
+        let mut response = self.serve_file(&request.path);
+        // opening the printed URL hands the token to the page, the bridge then works by cookie
+        let token = request
+            .query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("token="));
+        if token == Some(self.token.as_str()) {
+            response.headers.push(format!(
+                "Set-Cookie: {TOKEN_COOKIE}={}; Path=/; HttpOnly; SameSite=Strict",
+                self.token
+            ));
+        }
+        response
+    }
+
+    fn handle(&self, mut stream: TcpStream) {
+        let _ = stream.set_read_timeout(Some(IO_TIMEOUT));
+        let _ = stream.set_write_timeout(Some(IO_TIMEOUT));
+        let request = match read_request(&mut stream) {
+            Ok(request) => request,
+            Err(e) => {
+                let _ = Response::text("400 Bad Request", &e.to_string()).write(&mut stream, false);
+                return;
+            }
+        };
+        let response = self.respond(&request);
+        info!(
+            "webui: {} {} {}",
+            request.method, request.path, response.status
+        );
+        if let Err(e) = response.write(&mut stream, request.method == "HEAD") {
+            warn!("webui: failed to answer {}: {e}", request.path);
+        }
+    }
+}
+
+/// Serve the webroot of module `id` until SIGINT or SIGTERM.
+///
+/// Only loopback addresses are accepted for `bind` unless `allow_remote`, the bridge runs
+/// scripts as root.
+pub fn serve(id: &str, bind: IpAddr, port: u16, allow_remote: bool) -> Result<()> {
+    validate_module_id(id)?;
+    ensure!(
+        bind.is_loopback() || allow_remote,
+        "refusing to listen on {bind}, not a loopback address; pass --allow-remote to force it"
+    );
+    let module_dir = Path::new(defs::MODULE_DIR).join(id);
+    ensure!(module_dir.exists(), "module {id} not found");
+    let webroot = module_dir.join(defs::MODULE_WEB_DIR);
+    ensure!(
+        webroot.is_dir(),
+        "module {id} has no {}",
+        defs::MODULE_WEB_DIR
+    );
+
+    let server = Arc::new(Server {
+        id: id.to_string(),
+        webroot: fs::canonicalize(&webroot)
+            .with_context(|| format!("failed to resolve {}", webroot.display()))?,
+        module_dir,
+        token: generate_token()?,
+    });
+
+    let listener = TcpListener::bind(SocketAddr::new(bind, port))
+        .with_context(|| format!("failed to listen on {bind}:{port}"))?;
+    listener.set_nonblocking(true)?;
+    let addr = listener.local_addr()?;
+    let host = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        ip => ip,
+    };
+    let host = match host {
+        IpAddr::V6(ip) => format!("[{ip}]"),
+        IpAddr::V4(ip) => ip.to_string(),
+    };
+    if !bind.is_loopback() {
+        println!("- Warning: listening on {bind}, reachable from other hosts");
+    }
+    println!("- Serving {} on {addr}", server.webroot.display());
+    println!(
+        "- Open http://{host}:{}/?token={}",
+        addr.port(),
+        server.token
+    );
+    println!("- Press Ctrl-C to stop");
+
+    unsafe {
+        libc::signal(libc::SIGINT, on_signal as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, on_signal as libc::sighandler_t);
+    }
+    while !STOP.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let _ = stream.set_nonblocking(false);
+                let server = server.clone();
+                thread::spawn(move || server.handle(stream));
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_INTERVAL),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e).context("failed to accept a connection"),
+        }
+    }
+    println!("- Stopped");
+    Ok(())
+}
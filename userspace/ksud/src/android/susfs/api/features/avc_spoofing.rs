@@ -1,8 +1,10 @@
+use std::mem::{offset_of, size_of};
+
 use anyhow::Result;
 
 use crate::android::susfs::api::{
     magic::{CMD_SUSFS_ENABLE_AVC_LOG_SPOOFING, ERR_CMD_NOT_SUPPORTED},
-    susfsctl::{parse_err, susfsctl},
+    susfsctl::susfsctl_checked,
 };
 
 #[repr(C)]
@@ -11,12 +13,21 @@ struct SusfsAvcLogSpoofing {
     err: i32,
 }
 
+const _: () = {
+    assert!(size_of::<SusfsAvcLogSpoofing>() == 8);
+    assert!(offset_of!(SusfsAvcLogSpoofing, err) == 4);
+};
+
 pub fn enable_avc_log_spoofing(enabled: bool) -> Result<()> {
     let mut arg = SusfsAvcLogSpoofing {
         enabled,
         err: ERR_CMD_NOT_SUPPORTED,
     };
 
-    susfsctl(CMD_SUSFS_ENABLE_AVC_LOG_SPOOFING, &mut arg);
-    parse_err(CMD_SUSFS_ENABLE_AVC_LOG_SPOOFING, arg.err)
+    susfsctl_checked(
+        CMD_SUSFS_ENABLE_AVC_LOG_SPOOFING,
+        &format!("enabled={enabled}"),
+        &mut arg,
+        |arg| &mut arg.err,
+    )
 }
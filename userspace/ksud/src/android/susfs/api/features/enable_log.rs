@@ -1,8 +1,10 @@
+use std::mem::{offset_of, size_of};
+
 use anyhow::Result;
 
 use crate::android::susfs::api::{
     magic::{CMD_SUSFS_ENABLE_LOG, ERR_CMD_NOT_SUPPORTED},
-    susfsctl::{parse_err, susfsctl},
+    susfsctl::susfsctl_checked,
 };
 
 #[repr(C)]
@@ -11,12 +13,21 @@ struct SusfsLog {
     err: i32,
 }
 
+const _: () = {
+    assert!(size_of::<SusfsLog>() == 8);
+    assert!(offset_of!(SusfsLog, err) == 4);
+};
+
 pub fn enable_log(enabled: bool) -> Result<()> {
     let mut info = SusfsLog {
         enabled,
         err: ERR_CMD_NOT_SUPPORTED,
     };
 
-    susfsctl(CMD_SUSFS_ENABLE_LOG, &mut info);
-    parse_err(CMD_SUSFS_ENABLE_LOG, info.err)
+    susfsctl_checked(
+        CMD_SUSFS_ENABLE_LOG,
+        &format!("enabled={enabled}"),
+        &mut info,
+        |arg| &mut arg.err,
+    )
 }
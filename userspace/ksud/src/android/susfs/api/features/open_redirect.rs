@@ -1,11 +1,14 @@
-use std::fs;
+use std::{
+    fs,
+    mem::{offset_of, size_of},
+};
 
 use anyhow::{Result, anyhow};
 
 use crate::android::susfs::{
     api::{
         magic::{CMD_SUSFS_ADD_OPEN_REDIRECT, ERR_CMD_NOT_SUPPORTED, SUSFS_MAX_LEN_PATHNAME},
-        susfsctl::{parse_err, susfsctl},
+        susfsctl::susfsctl_checked,
     },
     enums::UidScheme,
     utils::str_to_c_array,
@@ -19,6 +22,13 @@ struct SusfsOpenRedirect {
     err: i32,
 }
 
+const _: () = {
+    assert!(size_of::<SusfsOpenRedirect>() == 520);
+    assert!(offset_of!(SusfsOpenRedirect, redirected_pathname) == 256);
+    assert!(offset_of!(SusfsOpenRedirect, uid_scheme) == 512);
+    assert!(offset_of!(SusfsOpenRedirect, err) == 516);
+};
+
 impl Default for SusfsOpenRedirect {
     fn default() -> Self {
         Self {
@@ -53,7 +63,16 @@ pub fn add_open_redirect(
     info.uid_scheme = (*uid_scheme) as i32;
     info.err = ERR_CMD_NOT_SUPPORTED;
 
-    susfsctl(CMD_SUSFS_ADD_OPEN_REDIRECT, &mut info);
-    parse_err(CMD_SUSFS_ADD_OPEN_REDIRECT, info.err)?;
+    susfsctl_checked(
+        CMD_SUSFS_ADD_OPEN_REDIRECT,
+        &format!(
+            "target={} redirect={} uid_scheme={}",
+            abs_target.display(),
+            abs_redirect.display(),
+            info.uid_scheme
+        ),
+        &mut info,
+        |arg| &mut arg.err,
+    )?;
     Ok(())
 }
@@ -1,3 +1,5 @@
+use std::mem::{offset_of, size_of};
+
 use anyhow::Result;
 
 use crate::android::susfs::{
@@ -7,7 +9,7 @@ use crate::android::susfs::{
             ERR_CMD_NOT_SUPPORTED, SUSFS_ENABLED_FEATURES_SIZE, SUSFS_MAX_VARIANT_BUFSIZE,
             SUSFS_MAX_VERSION_BUFSIZE,
         },
-        susfsctl::{parse_err, susfsctl},
+        susfsctl::susfsctl_checked,
     },
     utils::c_array_to_string,
 };
@@ -18,33 +20,53 @@ struct SusfsEnabledFeatures {
     err: i32,
 }
 
+const _: () = {
+    assert!(size_of::<SusfsEnabledFeatures>() == 8196);
+    assert!(offset_of!(SusfsEnabledFeatures, err) == 8192);
+};
+
 #[repr(C)]
 struct SusfsVariant {
     susfs_variant: [u8; SUSFS_MAX_VARIANT_BUFSIZE],
     err: i32,
 }
 
+const _: () = {
+    assert!(size_of::<SusfsVariant>() == 20);
+    assert!(offset_of!(SusfsVariant, err) == 16);
+};
+
 #[repr(C)]
 struct SusfsVersion {
     susfs_version: [u8; SUSFS_MAX_VERSION_BUFSIZE],
     err: i32,
 }
 
-pub fn version() -> Result<String> {
+const _: () = {
+    assert!(size_of::<SusfsVersion>() == 20);
+    assert!(offset_of!(SusfsVersion, err) == 16);
+};
+
+/// Get the version bytes exactly as returned by the kernel, up to the first NUL.
+///
+/// The whole buffer is returned if the kernel filled it without a NUL terminator.
+pub fn version_raw() -> Result<Vec<u8>> {
     let mut info = SusfsVersion {
         susfs_version: [0; SUSFS_MAX_VERSION_BUFSIZE],
         err: ERR_CMD_NOT_SUPPORTED,
     };
-    susfsctl(CMD_SUSFS_SHOW_VERSION, &mut info);
-    parse_err(CMD_SUSFS_SHOW_VERSION, info.err)?;
+    susfsctl_checked(CMD_SUSFS_SHOW_VERSION, "", &mut info, |arg| &mut arg.err)?;
 
-    let ver = c_array_to_string(&info.susfs_version);
+    let len = info
+        .susfs_version
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(SUSFS_MAX_VERSION_BUFSIZE);
+    Ok(info.susfs_version[..len].to_vec())
+}
 
-    if ver.starts_with('v') {
-        Ok(ver)
-    } else {
-        Ok("Unsupported".to_string())
-    }
+pub fn version() -> Result<String> {
+    Ok(String::from_utf8_lossy(&version_raw()?).into_owned())
 }
 
 pub fn variant() -> Result<String> {
@@ -52,8 +74,7 @@ pub fn variant() -> Result<String> {
         susfs_variant: [0; SUSFS_MAX_VARIANT_BUFSIZE],
         err: ERR_CMD_NOT_SUPPORTED,
     };
-    susfsctl(CMD_SUSFS_SHOW_VARIANT, &mut info);
-    parse_err(CMD_SUSFS_SHOW_VARIANT, info.err)?;
+    susfsctl_checked(CMD_SUSFS_SHOW_VARIANT, "", &mut info, |arg| &mut arg.err)?;
 
     let variant = c_array_to_string(&info.susfs_variant);
     Ok(variant)
@@ -64,10 +85,31 @@ pub fn enabled_features() -> Result<String> {
         enabled_features: [0; SUSFS_ENABLED_FEATURES_SIZE],
         err: ERR_CMD_NOT_SUPPORTED,
     });
-    susfsctl(CMD_SUSFS_SHOW_ENABLED_FEATURES, &mut *info);
-    parse_err(CMD_SUSFS_SHOW_ENABLED_FEATURES, info.err)?;
+    susfsctl_checked(CMD_SUSFS_SHOW_ENABLED_FEATURES, "", &mut *info, |arg| {
+        &mut arg.err
+    })?;
 
     let features = c_array_to_string(&info.enabled_features);
 
     Ok(features)
 }
+
+/// Check whether `name` is listed in the enabled features.
+///
+/// The comparison is case-insensitive and the `CONFIG_KSU_SUSFS_` prefix is optional.
+pub fn is_feature_enabled(name: &str) -> Result<bool> {
+    const PREFIX: &str = "CONFIG_KSU_SUSFS_";
+
+    let strip = |token: &str| {
+        let token = token.to_ascii_uppercase();
+        token
+            .strip_prefix(PREFIX)
+            .map(str::to_string)
+            .unwrap_or(token)
+    };
+    let wanted = strip(name);
+
+    Ok(enabled_features()?
+        .split_whitespace()
+        .any(|token| strip(token) == wanted))
+}
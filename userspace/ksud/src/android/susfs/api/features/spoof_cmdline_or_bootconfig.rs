@@ -1,21 +1,93 @@
-use std::fs;
+use std::{
+    fmt, fs,
+    mem::{offset_of, size_of},
+};
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 
 use crate::android::susfs::api::{
+    features::show::variant,
     magic::{
         CMD_SUSFS_SET_CMDLINE_OR_BOOTCONFIG, ERR_CMD_NOT_SUPPORTED,
         SUSFS_FAKE_CMDLINE_OR_BOOTCONFIG_SIZE,
     },
-    susfsctl::{parse_err, susfsctl},
+    susfsctl::susfsctl_checked,
 };
 
+/// The file SuSFS spoofs with the content of `set_cmdline_or_bootconfig`.
+///
+/// The kernel picks exactly one at build time, GKI kernels spoof /proc/bootconfig and
+/// non-GKI kernels /proc/cmdline, there is no way to set both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpoofTarget {
+    Cmdline,
+    Bootconfig,
+}
+
+impl fmt::Display for SpoofTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cmdline => write!(f, "/proc/cmdline"),
+            Self::Bootconfig => write!(f, "/proc/bootconfig"),
+        }
+    }
+}
+
+/// Which file the running kernel spoofs, judged by the SuSFS variant.
+///
+/// Returns `None` for unknown variants.
+pub fn spoof_target() -> Result<Option<SpoofTarget>> {
+    let variant = variant()?;
+    Ok(match variant.trim().to_ascii_uppercase().as_str() {
+        "GKI" => Some(SpoofTarget::Bootconfig),
+        "NON-GKI" => Some(SpoofTarget::Cmdline),
+        _ => None,
+    })
+}
+
+/// Check that `content` is a plausible /proc/cmdline: a single line.
+pub fn validate_cmdline(content: &str) -> Result<()> {
+    let content = content.strip_suffix('\n').unwrap_or(content);
+    if content.trim().is_empty() {
+        bail!("cmdline is empty");
+    }
+    if content.contains('\n') {
+        bail!("cmdline must be a single line");
+    }
+    Ok(())
+}
+
+/// Check that `content` uses bootconfig `key = value` syntax, line by line.
+pub fn validate_bootconfig(content: &str) -> Result<()> {
+    let is_key_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-');
+
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, _)) = line.split_once('=') else {
+            bail!("bootconfig line {}: missing '=': {line}", index + 1);
+        };
+        let key = key.trim();
+        if key.is_empty() || !key.chars().all(is_key_char) {
+            bail!("bootconfig line {}: invalid key '{key}'", index + 1);
+        }
+    }
+    Ok(())
+}
+
 #[repr(C)]
 struct SusfsSpoofCmdlineOrBootconfig {
     fake_cmdline_or_bootconfig: [u8; SUSFS_FAKE_CMDLINE_OR_BOOTCONFIG_SIZE],
     err: i32,
 }
 
+const _: () = {
+    assert!(size_of::<SusfsSpoofCmdlineOrBootconfig>() == 8196);
+    assert!(offset_of!(SusfsSpoofCmdlineOrBootconfig, err) == 8192);
+};
+
 pub fn set_cmdline_or_bootconfig(path: &str) -> Result<()> {
     if path.is_empty() {
         return Ok(());
@@ -36,6 +108,10 @@ pub fn set_cmdline_or_bootconfig(path: &str) -> Result<()> {
         info.fake_cmdline_or_bootconfig[i] = b;
     }
 
-    susfsctl(CMD_SUSFS_SET_CMDLINE_OR_BOOTCONFIG, &mut *info);
-    parse_err(CMD_SUSFS_SET_CMDLINE_OR_BOOTCONFIG, info.err)
+    susfsctl_checked(
+        CMD_SUSFS_SET_CMDLINE_OR_BOOTCONFIG,
+        &format!("path={} size={}", abs_path.display(), content.len()),
+        &mut *info,
+        |arg| &mut arg.err,
+    )
 }
@@ -1,13 +1,18 @@
+use std::mem::{offset_of, size_of};
+
 use anyhow::Result;
 
 use crate::android::susfs::{
     api::{
         magic::{CMD_SUSFS_SET_UNAME, ERR_CMD_NOT_SUPPORTED, NEW_UTS_LEN},
-        susfsctl::{parse_err, susfsctl},
+        susfsctl::susfsctl_checked,
     },
     utils::str_to_c_array,
 };
 
+/// Mirror of the kernel's struct st_susfs_uname.
+///
+/// SuSFS only spoofs release and version, nodename and machine are always the real ones.
 #[repr(C)]
 struct SusfsUname {
     release: [u8; NEW_UTS_LEN + 1],
@@ -15,6 +20,12 @@ struct SusfsUname {
     err: i32,
 }
 
+const _: () = {
+    assert!(size_of::<SusfsUname>() == 136);
+    assert!(offset_of!(SusfsUname, version) == 65);
+    assert!(offset_of!(SusfsUname, err) == 132);
+};
+
 impl Default for SusfsUname {
     fn default() -> Self {
         Self {
@@ -35,6 +46,10 @@ pub fn set_uname(release: &str, version: &str) -> Result<()> {
     str_to_c_array(version, &mut info.version);
     info.err = ERR_CMD_NOT_SUPPORTED;
 
-    susfsctl(CMD_SUSFS_SET_UNAME, &mut info);
-    parse_err(CMD_SUSFS_SET_UNAME, info.err)
+    susfsctl_checked(
+        CMD_SUSFS_SET_UNAME,
+        &format!("release={release} version={version}"),
+        &mut info,
+        |arg| &mut arg.err,
+    )
 }
@@ -1,13 +1,14 @@
 #![allow(clippy::similar_names)]
 
 use std::{
-    ffi::{c_long, c_ulong},
     fs,
+    mem::{offset_of, size_of},
     os::unix::fs::MetadataExt,
 };
 
 use anyhow::Result;
 use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
 
 use crate::android::susfs::{
     api::{
@@ -15,9 +16,9 @@ use crate::android::susfs::{
             CMD_SUSFS_ADD_SUS_KSTAT, CMD_SUSFS_ADD_SUS_KSTAT_STATICALLY,
             CMD_SUSFS_UPDATE_SUS_KSTAT, ERR_CMD_NOT_SUPPORTED, SUSFS_MAX_LEN_PATHNAME,
         },
-        susfsctl::{parse_err, susfsctl},
+        susfsctl::susfsctl_checked,
     },
-    utils::str_to_c_array,
+    utils::{fetch_metadata, str_to_c_array},
 };
 
 bitflags! {
@@ -53,24 +54,43 @@ bitflags! {
 #[repr(C)]
 struct SusfsSusKstat {
     is_statically: bool,
-    target_ino: c_ulong,
+    target_ino: u64,
     target_pathname: [u8; SUSFS_MAX_LEN_PATHNAME],
-    spoofed_ino: c_ulong,
-    spoofed_dev: c_ulong,
+    spoofed_ino: u64,
+    spoofed_dev: u64,
     spoofed_nlink: u32,
     spoofed_size: i64,
-    spoofed_atime_tv_sec: c_long,
-    spoofed_atime_tv_nsec: c_ulong,
-    spoofed_mtime_tv_sec: c_long,
-    spoofed_mtime_tv_nsec: c_ulong,
-    spoofed_ctime_tv_sec: c_long,
-    spoofed_ctime_tv_nsec: c_ulong,
+    spoofed_atime_tv_sec: i64,
+    spoofed_atime_tv_nsec: u64,
+    spoofed_mtime_tv_sec: i64,
+    spoofed_mtime_tv_nsec: u64,
+    spoofed_ctime_tv_sec: i64,
+    spoofed_ctime_tv_nsec: u64,
     spoofed_blocks: i64,
-    spoofed_blksize: c_long,
+    spoofed_blksize: i64,
     flags: i32,
     err: i32,
 }
 
+// Layout of the 64-bit kernel's struct st_susfs_sus_kstat, fixed-width so that
+// 32-bit userspace matches it as well.
+// There are no btime fields: no SuSFS release spoofs the statx birth time, and appending
+// fields here would move `err` past what the kernel writes.
+const _: () = {
+    assert!(size_of::<SusfsSusKstat>() == 376);
+    assert!(offset_of!(SusfsSusKstat, target_ino) == 8);
+    assert!(offset_of!(SusfsSusKstat, target_pathname) == 16);
+    assert!(offset_of!(SusfsSusKstat, spoofed_ino) == 272);
+    assert!(offset_of!(SusfsSusKstat, spoofed_nlink) == 288);
+    assert!(offset_of!(SusfsSusKstat, spoofed_size) == 296);
+    assert!(offset_of!(SusfsSusKstat, spoofed_atime_tv_sec) == 304);
+    assert!(offset_of!(SusfsSusKstat, spoofed_ctime_tv_nsec) == 344);
+    assert!(offset_of!(SusfsSusKstat, spoofed_blocks) == 352);
+    assert!(offset_of!(SusfsSusKstat, spoofed_blksize) == 360);
+    assert!(offset_of!(SusfsSusKstat, flags) == 368);
+    assert!(offset_of!(SusfsSusKstat, err) == 372);
+};
+
 impl Default for SusfsSusKstat {
     fn default() -> Self {
         Self {
@@ -95,23 +115,63 @@ impl Default for SusfsSusKstat {
     }
 }
 
+/// Stat values of a file, used as the spoofed values of a sus_kstat.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct KstatValues {
+    pub ino: u64,
+    pub dev: u64,
+    pub nlink: u64,
+    pub size: u64,
+    pub atime: i64,
+    pub atime_nsec: i64,
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+    pub ctime: i64,
+    pub ctime_nsec: i64,
+    pub blocks: u64,
+    pub blksize: u64,
+}
+
+impl KstatValues {
+    pub fn from_metadata(md: &fs::Metadata) -> Self {
+        Self {
+            ino: md.ino(),
+            dev: md.dev(),
+            nlink: md.nlink(),
+            size: md.size(),
+            atime: md.atime(),
+            atime_nsec: md.atime_nsec(),
+            mtime: md.mtime(),
+            mtime_nsec: md.mtime_nsec(),
+            ctime: md.ctime(),
+            ctime_nsec: md.ctime_nsec(),
+            blocks: md.blocks(),
+            blksize: md.blksize(),
+        }
+    }
+}
+
+fn copy_values_to_sus_kstat(info: &mut SusfsSusKstat, values: &KstatValues) {
+    info.spoofed_ino = values.ino;
+    info.spoofed_dev = values.dev;
+    info.spoofed_nlink = values.nlink as u32;
+    info.spoofed_size = values.size as i64;
+    info.spoofed_atime_tv_sec = values.atime;
+    info.spoofed_mtime_tv_sec = values.mtime;
+    info.spoofed_ctime_tv_sec = values.ctime;
+    info.spoofed_atime_tv_nsec = values.atime_nsec as u64;
+    info.spoofed_mtime_tv_nsec = values.mtime_nsec as u64;
+    info.spoofed_ctime_tv_nsec = values.ctime_nsec as u64;
+    info.spoofed_blksize = values.blksize as i64;
+    info.spoofed_blocks = values.blocks as i64;
+}
+
 fn copy_metadata_to_sus_kstat(info: &mut SusfsSusKstat, md: &fs::Metadata) {
-    info.spoofed_ino = md.ino() as c_ulong;
-    info.spoofed_dev = md.dev() as c_ulong;
-    info.spoofed_nlink = md.nlink() as u32;
-    info.spoofed_size = md.size() as i64;
-    info.spoofed_atime_tv_sec = md.atime() as c_long;
-    info.spoofed_mtime_tv_sec = md.mtime() as c_long;
-    info.spoofed_ctime_tv_sec = md.ctime() as c_long;
-    info.spoofed_atime_tv_nsec = md.atime_nsec() as c_ulong;
-    info.spoofed_mtime_tv_nsec = md.mtime_nsec() as c_ulong;
-    info.spoofed_ctime_tv_nsec = md.ctime_nsec() as c_ulong;
-    info.spoofed_blksize = md.blksize() as c_long;
-    info.spoofed_blocks = md.blocks() as i64;
+    copy_values_to_sus_kstat(info, &KstatValues::from_metadata(md));
 }
 
-pub fn update_sus_kstat(path: &str, full_clone: bool) -> Result<()> {
-    let md = fs::metadata(path)?;
+pub fn update_sus_kstat(path: &str, full_clone: bool, follow_symlink: bool) -> Result<()> {
+    let md = fetch_metadata(path, follow_symlink)?;
     let mut info = SusfsSusKstat::default();
     let spoof_flag = match full_clone {
         true => SusKstatSpoofFlags::AUTO_FULL_CLONE,
@@ -121,30 +181,61 @@ pub fn update_sus_kstat(path: &str, full_clone: bool) -> Result<()> {
     str_to_c_array(path, &mut info.target_pathname);
 
     info.is_statically = false;
-    info.target_ino = md.ino() as c_ulong;
+    info.target_ino = md.ino();
     copy_metadata_to_sus_kstat(&mut info, &md);
     info.flags |= spoof_flag.bits();
     info.err = ERR_CMD_NOT_SUPPORTED;
 
-    susfsctl(CMD_SUSFS_UPDATE_SUS_KSTAT, &mut info);
-    parse_err(CMD_SUSFS_UPDATE_SUS_KSTAT, info.err)?;
+    susfsctl_checked(
+        CMD_SUSFS_UPDATE_SUS_KSTAT,
+        &format!("path={path} ino={}", info.target_ino),
+        &mut info,
+        |arg| &mut arg.err,
+    )?;
     Ok(())
 }
 
-pub fn add_sus_kstat(path: &str) -> Result<()> {
-    let md = fs::metadata(path)?;
+pub fn add_sus_kstat(path: &str, follow_symlink: bool) -> Result<()> {
+    let md = fetch_metadata(path, follow_symlink)?;
     let mut info = SusfsSusKstat::default();
 
     str_to_c_array(path, &mut info.target_pathname);
     copy_metadata_to_sus_kstat(&mut info, &md);
 
     info.is_statically = false;
-    info.target_ino = md.ino() as c_ulong;
+    info.target_ino = md.ino();
+    info.flags |= SusKstatSpoofFlags::AUTO.bits();
+    info.err = ERR_CMD_NOT_SUPPORTED;
+
+    susfsctl_checked(
+        CMD_SUSFS_ADD_SUS_KSTAT,
+        &format!("path={path} ino={}", info.target_ino),
+        &mut info,
+        |arg| &mut arg.err,
+    )?;
+    Ok(())
+}
+
+/// Add a sus_kstat for an already mounted `path`, spoofing it with `original` values
+/// captured before the mount.
+pub fn add_sus_kstat_from_snapshot(path: &str, original: &KstatValues) -> Result<()> {
+    let md = fs::metadata(path)?;
+    let mut info = SusfsSusKstat::default();
+
+    str_to_c_array(path, &mut info.target_pathname);
+    copy_values_to_sus_kstat(&mut info, original);
+
+    info.is_statically = false;
+    info.target_ino = md.ino();
     info.flags |= SusKstatSpoofFlags::AUTO.bits();
     info.err = ERR_CMD_NOT_SUPPORTED;
 
-    susfsctl(CMD_SUSFS_ADD_SUS_KSTAT, &mut info);
-    parse_err(CMD_SUSFS_ADD_SUS_KSTAT, info.err)?;
+    susfsctl_checked(
+        CMD_SUSFS_ADD_SUS_KSTAT,
+        &format!("path={path} ino={}", info.target_ino),
+        &mut info,
+        |arg| &mut arg.err,
+    )?;
     Ok(())
 }
 
@@ -167,7 +258,7 @@ pub fn add_sus_kstat_statically(
     let md = fs::metadata(path)?;
 
     let mut info = SusfsSusKstat {
-        target_ino: md.ino() as c_ulong,
+        target_ino: md.ino(),
         is_statically: true,
         ..Default::default()
     };
@@ -183,22 +274,29 @@ pub fn add_sus_kstat_statically(
         };
     }
 
-    set_spoof_field!(spoofed_ino, ino, c_ulong, INO);
-    set_spoof_field!(spoofed_dev, dev, c_ulong, DEV);
+    set_spoof_field!(spoofed_ino, ino, u64, INO);
+    set_spoof_field!(spoofed_dev, dev, u64, DEV);
     set_spoof_field!(spoofed_nlink, nlink, u32, NLINK);
     set_spoof_field!(spoofed_size, size, i64, SIZE);
-    set_spoof_field!(spoofed_atime_tv_sec, atime, c_long, ATIME_TV_SEC);
-    set_spoof_field!(spoofed_atime_tv_nsec, atime_nsec, c_ulong, ATIME_TV_NSEC);
-    set_spoof_field!(spoofed_mtime_tv_sec, mtime, c_long, MTIME_TV_SEC);
-    set_spoof_field!(spoofed_mtime_tv_nsec, mtime_nsec, c_ulong, MTIME_TV_NSEC);
-    set_spoof_field!(spoofed_ctime_tv_sec, ctime, c_long, CTIME_TV_SEC);
-    set_spoof_field!(spoofed_ctime_tv_nsec, ctime_nsec, c_ulong, CTIME_TV_NSEC);
+    set_spoof_field!(spoofed_atime_tv_sec, atime, i64, ATIME_TV_SEC);
+    set_spoof_field!(spoofed_atime_tv_nsec, atime_nsec, u64, ATIME_TV_NSEC);
+    set_spoof_field!(spoofed_mtime_tv_sec, mtime, i64, MTIME_TV_SEC);
+    set_spoof_field!(spoofed_mtime_tv_nsec, mtime_nsec, u64, MTIME_TV_NSEC);
+    set_spoof_field!(spoofed_ctime_tv_sec, ctime, i64, CTIME_TV_SEC);
+    set_spoof_field!(spoofed_ctime_tv_nsec, ctime_nsec, u64, CTIME_TV_NSEC);
     set_spoof_field!(spoofed_blocks, blocks, i64, BLOCKS);
-    set_spoof_field!(spoofed_blksize, blksize, c_long, BLKSIZE);
+    set_spoof_field!(spoofed_blksize, blksize, i64, BLKSIZE);
 
     info.err = ERR_CMD_NOT_SUPPORTED;
 
-    susfsctl(CMD_SUSFS_ADD_SUS_KSTAT_STATICALLY, &mut info);
-    parse_err(CMD_SUSFS_ADD_SUS_KSTAT_STATICALLY, info.err)?;
+    susfsctl_checked(
+        CMD_SUSFS_ADD_SUS_KSTAT_STATICALLY,
+        &format!(
+            "path={path} ino={} flags=0x{:x}",
+            info.target_ino, info.flags
+        ),
+        &mut info,
+        |arg| &mut arg.err,
+    )?;
     Ok(())
 }
@@ -1,9 +1,11 @@
+use std::mem::{offset_of, size_of};
+
 use anyhow::Result;
 
 use crate::android::susfs::{
     api::{
         magic::{CMD_SUSFS_ADD_SUS_MAP, ERR_CMD_NOT_SUPPORTED, SUSFS_MAX_LEN_PATHNAME},
-        susfsctl::{parse_err, susfsctl},
+        susfsctl::susfsctl_checked,
     },
     macros::ensure_path_exists,
     utils::str_to_c_array,
@@ -15,6 +17,11 @@ struct SusfsSusMap {
     err: i32,
 }
 
+const _: () = {
+    assert!(size_of::<SusfsSusMap>() == 260);
+    assert!(offset_of!(SusfsSusMap, err) == 256);
+};
+
 impl Default for SusfsSusMap {
     fn default() -> Self {
         Self {
@@ -30,8 +37,12 @@ pub fn add_sus_map(path: &str) -> Result<()> {
     str_to_c_array(path, &mut info.target_pathname);
     info.err = ERR_CMD_NOT_SUPPORTED;
 
-    susfsctl(CMD_SUSFS_ADD_SUS_MAP, &mut info);
-    parse_err(CMD_SUSFS_ADD_SUS_MAP, info.err)?;
+    susfsctl_checked(
+        CMD_SUSFS_ADD_SUS_MAP,
+        &format!("path={path}"),
+        &mut info,
+        |arg| &mut arg.err,
+    )?;
 
     Ok(())
 }
@@ -1,8 +1,13 @@
+use std::mem::{offset_of, size_of};
+
 use anyhow::Result;
 
-use crate::android::susfs::api::{
-    magic::{CMD_SUSFS_HIDE_SUS_MNTS_FOR_NON_SU_PROCS, ERR_CMD_NOT_SUPPORTED},
-    susfsctl::{parse_err, susfsctl},
+use crate::android::{
+    ksucalls::{self, KsuCallErrorKind},
+    susfs::api::{
+        magic::{CMD_SUSFS_HIDE_SUS_MNTS_FOR_NON_SU_PROCS, ERR_CMD_NOT_SUPPORTED},
+        susfsctl::susfsctl_checked,
+    },
 };
 
 #[repr(C)]
@@ -11,13 +16,34 @@ struct SusfsHideSusMntsForNonSuProcs {
     err: i32,
 }
 
+const _: () = {
+    assert!(size_of::<SusfsHideSusMntsForNonSuProcs>() == 8);
+    assert!(offset_of!(SusfsHideSusMntsForNonSuProcs, err) == 4);
+};
+
 pub fn hide_sus_mnts_for_non_su_procs(enabled: bool) -> Result<()> {
     let mut info = SusfsHideSusMntsForNonSuProcs {
         enabled,
         err: ERR_CMD_NOT_SUPPORTED,
     };
 
-    susfsctl(CMD_SUSFS_HIDE_SUS_MNTS_FOR_NON_SU_PROCS, &mut info);
-    parse_err(CMD_SUSFS_HIDE_SUS_MNTS_FOR_NON_SU_PROCS, info.err)?;
+    susfsctl_checked(
+        CMD_SUSFS_HIDE_SUS_MNTS_FOR_NON_SU_PROCS,
+        &format!("enabled={enabled}"),
+        &mut info,
+        |arg| &mut arg.err,
+    )?;
     Ok(())
 }
+
+/// Read back whether sus mounts are hidden for non-su processes.
+///
+/// SuSFS has no command for this, the KernelSU side reports the toggle along with the rest of
+/// the SuSFS state. Returns `None` if the kernel is too old to have that query.
+pub fn hide_sus_mnts_for_non_su_procs_status() -> Result<Option<bool>> {
+    match ksucalls::get_susfs_state() {
+        Ok(state) => Ok(Some(state.hide_sus_mnts_for_non_su_procs)),
+        Err(e) if e.kind() == KsuCallErrorKind::Unsupported => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -1,4 +1,6 @@
-use anyhow::Result;
+use std::mem::{offset_of, size_of};
+
+use anyhow::{Context, Result};
 
 use crate::android::susfs::{
     api::{
@@ -6,18 +8,24 @@ use crate::android::susfs::{
             CMD_SUSFS_ADD_SUS_PATH, CMD_SUSFS_ADD_SUS_PATH_LOOP, ERR_CMD_NOT_SUPPORTED,
             SUSFS_MAX_LEN_PATHNAME,
         },
-        susfsctl::{parse_err, susfsctl},
+        susfsctl::susfsctl_checked,
     },
-    macros::ensure_path_exists,
-    utils::str_to_c_array,
+    utils::{fetch_metadata, str_to_c_array},
 };
 
+/// The kernel resolves the inode from `target_pathname` itself, following symlinks, there is no
+/// inode/uid field to override, so the path must still exist when registered.
 #[repr(C)]
 struct SusfsSusPath {
     target_pathname: [u8; SUSFS_MAX_LEN_PATHNAME],
     err: i32,
 }
 
+const _: () = {
+    assert!(size_of::<SusfsSusPath>() == 260);
+    assert!(offset_of!(SusfsSusPath, err) == 256);
+};
+
 impl Default for SusfsSusPath {
     fn default() -> Self {
         Self {
@@ -28,7 +36,9 @@ impl Default for SusfsSusPath {
 }
 
 pub fn add_sus_path(path: &str, is_loop: bool) -> Result<()> {
-    ensure_path_exists!(path);
+    // checked the way the kernel resolves it, a dangling symlink would be refused there
+    fetch_metadata(path, true)
+        .with_context(|| format!("Path does not exist: {path} (it must exist until registered)"))?;
 
     let mut info = SusfsSusPath::default();
     let magic = match is_loop {
@@ -38,7 +48,8 @@ pub fn add_sus_path(path: &str, is_loop: bool) -> Result<()> {
     str_to_c_array(path, &mut info.target_pathname);
     info.err = ERR_CMD_NOT_SUPPORTED;
 
-    susfsctl(magic, &mut info);
-    parse_err(magic, info.err)?;
+    susfsctl_checked(magic, &format!("path={path}"), &mut info, |arg| {
+        &mut arg.err
+    })?;
     Ok(())
 }
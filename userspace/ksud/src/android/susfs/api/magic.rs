@@ -1,6 +1,8 @@
 pub(super) const KSU_INSTALL_MAGIC1: u64 = 0xDEAD_BEEF;
 pub(super) const SUSFS_MAGIC: u64 = 0xFAFA_FAFA;
 
+// SuSFS 2.x no longer has the set_android_data_root_path and set_sdcard_root_path commands,
+// the kernel resolves the data and sdcard roots itself, so there is nothing to configure here.
 pub(super) const CMD_SUSFS_ADD_SUS_PATH: u64 = 0x55550;
 pub(super) const CMD_SUSFS_ADD_SUS_PATH_LOOP: u64 = 0x55553;
 pub(super) const CMD_SUSFS_HIDE_SUS_MNTS_FOR_NON_SU_PROCS: u64 = 0x55561;
@@ -24,3 +26,25 @@ pub(super) const SUSFS_MAX_VERSION_BUFSIZE: usize = 16;
 pub(super) const SUSFS_MAX_VARIANT_BUFSIZE: usize = 16;
 pub(super) const NEW_UTS_LEN: usize = 64;
 pub(super) const ERR_CMD_NOT_SUPPORTED: i32 = 126;
+
+/// Name of `cmd` as used by the ksud susfs cli, for logs.
+pub(super) const fn command_name(cmd: u64) -> &'static str {
+    match cmd {
+        CMD_SUSFS_ADD_SUS_PATH => "add_sus_path",
+        CMD_SUSFS_ADD_SUS_PATH_LOOP => "add_sus_path_loop",
+        CMD_SUSFS_HIDE_SUS_MNTS_FOR_NON_SU_PROCS => "hide_sus_mnts_for_non_su_procs",
+        CMD_SUSFS_ADD_SUS_KSTAT => "add_sus_kstat",
+        CMD_SUSFS_UPDATE_SUS_KSTAT => "update_sus_kstat",
+        CMD_SUSFS_ADD_SUS_KSTAT_STATICALLY => "add_sus_kstat_statically",
+        CMD_SUSFS_SET_UNAME => "set_uname",
+        CMD_SUSFS_ENABLE_LOG => "enable_log",
+        CMD_SUSFS_SET_CMDLINE_OR_BOOTCONFIG => "set_cmdline_or_bootconfig",
+        CMD_SUSFS_ADD_OPEN_REDIRECT => "add_open_redirect",
+        CMD_SUSFS_SHOW_VERSION => "show_version",
+        CMD_SUSFS_SHOW_ENABLED_FEATURES => "show_enabled_features",
+        CMD_SUSFS_SHOW_VARIANT => "show_variant",
+        CMD_SUSFS_ENABLE_AVC_LOG_SPOOFING => "enable_avc_log_spoofing",
+        CMD_SUSFS_ADD_SUS_MAP => "add_sus_map",
+        _ => "unknown",
+    }
+}
@@ -2,11 +2,20 @@ pub use crate::android::susfs::api::features::{
     avc_spoofing::enable_avc_log_spoofing,
     enable_log::enable_log,
     open_redirect::add_open_redirect,
-    show::{enabled_features, variant, version},
-    spoof_cmdline_or_bootconfig::set_cmdline_or_bootconfig,
+    show::{enabled_features, is_feature_enabled, variant, version, version_raw},
+    spoof_cmdline_or_bootconfig::{
+        SpoofTarget, set_cmdline_or_bootconfig, spoof_target, validate_bootconfig, validate_cmdline,
+    },
     spoof_uname::set_uname,
-    sus_kstat::{add_sus_kstat, add_sus_kstat_statically, update_sus_kstat},
+    sus_kstat::{
+        KstatValues, add_sus_kstat, add_sus_kstat_from_snapshot, add_sus_kstat_statically,
+        update_sus_kstat,
+    },
     sus_map::add_sus_map,
-    sus_mount::hide_sus_mnts_for_non_su_procs,
+    sus_mount::{hide_sus_mnts_for_non_su_procs, hide_sus_mnts_for_non_su_procs_status},
     sus_path::add_sus_path,
 };
+pub use crate::android::susfs::api::susfsctl::{
+    NotPresent, RetryPolicy, command_failure, detect, ignore_already_exists, is_present,
+    set_presence_check, set_retry_policy,
+};
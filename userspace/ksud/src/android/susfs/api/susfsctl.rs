@@ -1,30 +1,257 @@
 //! Functions used to communicate with SuSFS
 
+use std::{
+    fmt,
+    mem::{offset_of, size_of},
+    sync::{
+        Once, OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
 use anyhow::Result;
-use libc::{SYS_reboot, syscall};
+use libc::{EAGAIN, EBUSY, EEXIST, SYS_reboot, syscall};
 
-use crate::android::susfs::api::magic::{ERR_CMD_NOT_SUPPORTED, KSU_INSTALL_MAGIC1, SUSFS_MAGIC};
+use crate::android::susfs::{
+    api::magic::{
+        CMD_SUSFS_SHOW_VERSION, ERR_CMD_NOT_SUPPORTED, KSU_INSTALL_MAGIC1, SUSFS_MAGIC,
+        SUSFS_MAX_VERSION_BUFSIZE, command_name,
+    },
+    audit, selinux,
+};
 
-/// Communicate with SuSFS
-pub(super) fn susfsctl<T>(cmd: u64, arg: &mut T) {
+/// Communicate with SuSFS, returning the raw syscall result
+///
+/// syscall() reads its arguments as longs, a u64 would take two registers on 32-bit ARM.
+pub(super) fn susfsctl<T>(cmd: u64, arg: &mut T) -> libc::c_long {
     unsafe {
         syscall(
             SYS_reboot,
-            KSU_INSTALL_MAGIC1,
-            SUSFS_MAGIC,
-            cmd,
+            KSU_INSTALL_MAGIC1 as libc::c_long,
+            SUSFS_MAGIC as libc::c_long,
+            cmd as libc::c_long,
             std::ptr::from_mut::<T>(arg),
-        );
+        )
+    }
+}
+
+/// The kernel does not handle SuSFS commands at all
+#[derive(Debug)]
+pub struct NotPresent;
+
+impl fmt::Display for NotPresent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "kernel lacks SuSFS support, check CONFIG_KSU_SUSFS and that the SuSFS patch set is applied"
+        )
+    }
+}
+
+impl std::error::Error for NotPresent {}
+
+/// Outcome of the show_version probe, with the syscall error when the kernel rejected it
+struct Probe {
+    present: bool,
+    rejected: Option<std::io::Error>,
+}
+
+static PROBE: OnceLock<Probe> = OnceLock::new();
+static PRESENCE_CHECK: AtomicBool = AtomicBool::new(true);
+
+/// Probe for SuSFS once per process with show_version.
+///
+/// show_version exists in every SuSFS release, so if nothing fills in its error code the
+/// kernel has no SuSFS.
+fn probe() -> &'static Probe {
+    #[repr(C)]
+    struct ShowVersion {
+        version: [u8; SUSFS_MAX_VERSION_BUFSIZE],
+        err: i32,
+    }
+
+    const _: () = {
+        assert!(size_of::<ShowVersion>() == 20);
+        assert!(offset_of!(ShowVersion, err) == 16);
+    };
+
+    PROBE.get_or_init(|| {
+        let mut info = ShowVersion {
+            version: [0; SUSFS_MAX_VERSION_BUFSIZE],
+            err: ERR_CMD_NOT_SUPPORTED,
+        };
+        let ret = susfsctl(CMD_SUSFS_SHOW_VERSION, &mut info);
+        let errno = std::io::Error::last_os_error();
+        audit::record(command_name(CMD_SUSFS_SHOW_VERSION), "probe", ret, info.err);
+        Probe {
+            present: info.err != ERR_CMD_NOT_SUPPORTED,
+            rejected: (ret < 0).then_some(errno),
+        }
+    })
+}
+
+/// Whether the kernel handles SuSFS at all, for callers that skip SuSFS work quietly without it
+pub fn detect() -> bool {
+    probe().present
+}
+
+/// Whether the kernel handles SuSFS at all, the diagnosis is printed the first time it doesn't
+pub fn is_present() -> bool {
+    static REPORTED: Once = Once::new();
+
+    let probe = probe();
+    if !probe.present {
+        REPORTED.call_once(|| {
+            let detail = match &probe.rejected {
+                Some(errno) => {
+                    if selinux::is_denial(errno) {
+                        selinux::explain_denial();
+                    }
+                    format!(" (syscall rejected: {errno})")
+                }
+                None => String::new(),
+            };
+            eprintln!("ERROR: {NotPresent}{detail}");
+            log::error!("{NotPresent}{detail}");
+        });
+    }
+    probe.present
+}
+
+/// Enable or disable failing fast when the kernel has no SuSFS, enabled by default
+pub fn set_presence_check(enabled: bool) {
+    PRESENCE_CHECK.store(enabled, Ordering::Relaxed);
+}
+
+/// How often to reissue a command that failed with a transient EAGAIN/EBUSY
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Total attempts, including the first one
+    pub attempts: u32,
+    pub delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            delay: Duration::from_millis(50),
+        }
     }
 }
 
+static RETRY_POLICY: OnceLock<RetryPolicy> = OnceLock::new();
+
+/// Set the retry policy for this process, must be called before the first command
+pub fn set_retry_policy(policy: RetryPolicy) {
+    if RETRY_POLICY.set(policy).is_err() {
+        log::warn!("SuSFS retry policy is already set, ignoring {policy:?}");
+    }
+}
+
+/// Communicate with SuSFS and parse the error code written to `err(arg)`
+///
+/// EAGAIN and EBUSY are retried according to the retry policy, other errors fail immediately.
+/// Every attempt is recorded in the audit log along with `payload`.
+pub(super) fn susfsctl_checked<T>(
+    cmd: u64,
+    payload: &str,
+    arg: &mut T,
+    err: fn(&mut T) -> &mut i32,
+) -> Result<()> {
+    if PRESENCE_CHECK.load(Ordering::Relaxed) && !is_present() {
+        return Err(NotPresent.into());
+    }
+
+    let policy = *RETRY_POLICY.get_or_init(RetryPolicy::default);
+    let mut attempt = 1;
+    loop {
+        *err(arg) = ERR_CMD_NOT_SUPPORTED;
+        let ret = susfsctl(cmd, arg);
+        if ret < 0 && selinux::is_denial(&std::io::Error::last_os_error()) {
+            selinux::explain_denial();
+        }
+        let error = *err(arg);
+        audit::record(command_name(cmd), payload, ret, error);
+        if (error == -EAGAIN || error == -EBUSY) && attempt < policy.attempts {
+            log::warn!(
+                "SuSFS command 0x{cmd:x} returned {error}, retrying ({attempt}/{})",
+                policy.attempts - 1
+            );
+            thread::sleep(policy.delay);
+            attempt += 1;
+            continue;
+        }
+        return parse_err(cmd, error);
+    }
+}
+
+/// SuSFS rejected a registration because the entry already exists
+#[derive(Debug)]
+pub struct AlreadyExists {
+    cmd: u64,
+}
+
+impl fmt::Display for AlreadyExists {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SuSFS entry already exists: 0x{:x}", self.cmd)
+    }
+}
+
+impl std::error::Error for AlreadyExists {}
+
+/// SuSFS rejected a command or doesn't know it
+#[derive(Debug)]
+pub struct CommandFailed {
+    cmd: u64,
+    err: i32,
+}
+
+impl fmt::Display for CommandFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.err == ERR_CMD_NOT_SUPPORTED {
+            write!(f, "Unsupported SuSFS command: 0x{:x}", self.cmd)
+        } else {
+            write!(f, "SuSFS error: {}", self.err)
+        }
+    }
+}
+
+impl std::error::Error for CommandFailed {}
+
 /// Parse error code to Err
 pub(super) fn parse_err(cmd: u64, error: i32) -> Result<()> {
-    if error == ERR_CMD_NOT_SUPPORTED {
-        return Err(anyhow::format_err!("Unsupported SuSFS command: 0x{cmd:x}"));
+    if error == -EEXIST {
+        return Err(AlreadyExists { cmd }.into());
     }
     if error != 0 {
-        return Err(anyhow::format_err!("SuSFS error: {error}"));
+        return Err(CommandFailed { cmd, err: error }.into());
     }
     Ok(())
 }
+
+/// The SuSFS command and error code behind `error`, if it came from the kernel
+pub fn command_failure(error: &anyhow::Error) -> Option<(u64, i32)> {
+    error.chain().find_map(|cause| {
+        if let Some(e) = cause.downcast_ref::<CommandFailed>() {
+            Some((e.cmd, e.err))
+        } else {
+            cause
+                .downcast_ref::<AlreadyExists>()
+                .map(|e| (e.cmd, -EEXIST))
+        }
+    })
+}
+
+/// Treat an already registered entry as success, so that registrations are idempotent
+pub fn ignore_already_exists(result: Result<()>, what: &str) -> Result<()> {
+    match result {
+        Err(e) if e.is::<AlreadyExists>() => {
+            log::info!("{what} is already registered, skip");
+            Ok(())
+        }
+        result => result,
+    }
+}
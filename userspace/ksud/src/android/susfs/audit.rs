@@ -0,0 +1,61 @@
+//! Append-only record of every SuSFS command ksud issued
+
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+use anyhow::Result;
+use chrono::Local;
+use const_format::concatcp;
+
+use crate::defs;
+
+pub const AUDIT_LOG_PATH: &str = concatcp!(defs::LOG_DIR, "susfs.log");
+const AUDIT_LOG_ROTATED_PATH: &str = concatcp!(AUDIT_LOG_PATH, ".1");
+const AUDIT_LOG_MAX_SIZE: u64 = 512 * 1024;
+
+fn rotate_if_needed() -> std::io::Result<()> {
+    if fs::metadata(AUDIT_LOG_PATH).is_ok_and(|md| md.len() >= AUDIT_LOG_MAX_SIZE) {
+        fs::rename(AUDIT_LOG_PATH, AUDIT_LOG_ROTATED_PATH)?;
+    }
+    Ok(())
+}
+
+fn append(line: &str) -> std::io::Result<()> {
+    fs::create_dir_all(defs::LOG_DIR)?;
+    rotate_if_needed()?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(AUDIT_LOG_PATH)?;
+    writeln!(file, "{line}")
+}
+
+/// Record one issued command. Failing to write the log never fails the command itself.
+pub fn record(cmd: &str, payload: &str, ret: libc::c_long, err: i32) {
+    let line = format!(
+        "{} pid={} cmd={cmd} {payload} ret={ret} err={err}",
+        Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+        std::process::id()
+    );
+    if let Err(e) = append(&line) {
+        log::debug!("failed to write SuSFS audit log: {e}");
+    }
+}
+
+/// Print the audit log, oldest entries first.
+pub fn dump() -> Result<()> {
+    let mut found = false;
+    for path in [AUDIT_LOG_ROTATED_PATH, AUDIT_LOG_PATH] {
+        if Path::new(path).exists() {
+            print!("{}", fs::read_to_string(path)?);
+            found = true;
+        }
+    }
+    if !found {
+        println!("No SuSFS commands recorded in {AUDIT_LOG_PATH}");
+    }
+    Ok(())
+}
@@ -0,0 +1,58 @@
+//! Hide KernelSU's own footprint with SuSFS at boot
+//!
+//! SuSFS v2 has no per-path sus_mount registration, mounts are covered by
+//! `hide_sus_mnts_for_non_su_procs` instead.
+
+use std::{fs, path::Path};
+
+use anyhow::{Context, Result};
+
+use crate::{android::susfs::api::prelude as api, defs};
+
+/// Paths always registered as sus_path unless auto hide is opted out.
+const DEFAULT_SUS_PATHS: &[&str] = &[defs::WORKING_DIR, defs::MODULE_DIR, defs::DAEMON_PATH];
+
+/// Extra paths listed as a JSON string array in [`defs::SUSFS_AUTO_HIDE_EXTRA`].
+fn read_extra_paths() -> Result<Vec<String>> {
+    let path = Path::new(defs::SUSFS_AUTO_HIDE_EXTRA);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(path)?;
+    serde_json::from_str(&content)
+        .with_context(|| format!("invalid {}", defs::SUSFS_AUTO_HIDE_EXTRA))
+}
+
+/// Register the built-in default sus paths for KernelSU's own files.
+///
+/// Skipped when [`defs::SUSFS_NO_AUTO_HIDE`] exists or the kernel has no SuSFS.
+pub fn register_default_sus_paths() {
+    if Path::new(defs::SUSFS_NO_AUTO_HIDE).exists() {
+        log::info!("{} exists, skip auto hide", defs::SUSFS_NO_AUTO_HIDE);
+        return;
+    }
+    if !api::detect() {
+        log::info!("SuSFS not detected, skip auto hide");
+        return;
+    }
+
+    let extra = read_extra_paths().unwrap_or_else(|e| {
+        log::warn!("read auto hide extra paths failed: {e:#}");
+        Vec::new()
+    });
+
+    for path in DEFAULT_SUS_PATHS
+        .iter()
+        .map(|p| p.trim_end_matches('/'))
+        .chain(extra.iter().map(String::as_str))
+    {
+        if !Path::new(path).exists() {
+            log::info!("auto hide: {path} does not exist, skip");
+            continue;
+        }
+        match api::add_sus_path(path, false) {
+            Ok(()) => log::info!("auto hide: registered sus_path {path}"),
+            Err(e) => log::warn!("auto hide: add sus_path {path} failed: {e}"),
+        }
+    }
+}
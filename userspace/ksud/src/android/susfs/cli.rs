@@ -1,22 +1,81 @@
 // TODO: Add get_x commands
 
-use std::{fmt::Display, str::FromStr};
+use std::{
+    fmt::Display,
+    fs,
+    io::Write,
+    os::fd::FromRawFd,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use anyhow::Result;
-use clap::{ArgAction, Args, Parser, Subcommand, error::ErrorKind};
+use anyhow::{Context, Result, bail};
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum, error::ErrorKind};
 use num_enum::TryFromPrimitive;
+use serde_json::{Value, json};
 
-use crate::android::susfs::{
-    api::prelude as api,
-    config::cli::{ConfigCommand, run as run_config},
-    enums::UidScheme,
-    slot_info,
+use crate::{
+    android::{
+        susfs::{
+            api::prelude as api,
+            audit,
+            config::{
+                cli::{ConfigCommand, run as run_config},
+                model::Config,
+                verify_ops,
+            },
+            enums::UidScheme,
+            init_event, kmsg, kstat_snapshot, selinux, slot_info, utils, version,
+        },
+        umount_config,
+    },
+    defs,
 };
 
 #[derive(Debug, Args)]
 pub struct SusfsArgs {
     #[command(subcommand)]
     pub command: SuSFSSubCommands,
+
+    /// Do not warn when the kernel SuSFS version is outside the supported range.
+    #[arg(long, global = true)]
+    pub no_version_check: bool,
+
+    /// Fail when add_sus_path, add_sus_map or add_open_redirect target is already registered,
+    /// instead of treating it as success.
+    #[arg(long, global = true)]
+    pub strict: bool,
+
+    /// Maximum attempts for a SuSFS command failing with a transient EAGAIN/EBUSY.
+    #[arg(
+        long,
+        global = true,
+        default_value_t = 3,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub retries: u32,
+
+    /// Delay between attempts of a transiently failing SuSFS command, in milliseconds.
+    #[arg(long, global = true, default_value_t = 50)]
+    pub retry_delay_ms: u64,
+
+    /// Issue commands even if the kernel has no SuSFS. Without it, a kernel lacking SuSFS
+    /// exits with code 3.
+    #[arg(long, global = true)]
+    pub skip_presence_check: bool,
+
+    /// Output format. With json, a single JSON object describing the result is the only thing
+    /// printed on stdout, other output goes to stderr. Failures and non-zero exit codes are
+    /// reported in it with `"ok": false`.
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
 }
 
 #[derive(Debug, Subcommand)]
@@ -37,7 +96,7 @@ pub enum SuSFSSubCommands {
     /// - Only effective for umounted process with uid >= 10000.
     #[command(name = "add_sus_path")]
     AddSusPath {
-        /// Path of file or directory
+        /// Path of file or directory, a symlink hides its target
         path: String,
     },
 
@@ -53,8 +112,12 @@ pub enum SuSFSSubCommands {
     /// - Only effective for umounted process with uid >= 10000.
     #[command(name = "add_sus_path_loop")]
     AddSusPathLoop {
-        /// Path of file or directory
-        path: String,
+        /// Path of file or directory, a symlink hides its target
+        #[arg(required_unless_present = "packages")]
+        path: Option<String>,
+        /// Also add the data directories of this package, can be repeated
+        #[arg(long = "package", value_name = "NAME")]
+        packages: Vec<String>,
     },
 
     /// Hide SUS mounts for non-SU processes.
@@ -68,8 +131,17 @@ pub enum SuSFSSubCommands {
     HideSusMntsForNonSuProcs {
         /// 0: DO NOT hide sus mounts for non-su processes
         /// 1: hide all sus mounts for non-su processes
-        #[arg(action = ArgAction::Set, value_parser = treat_int_as_boolean)]
-        enabled: bool,
+        #[arg(
+            action = ArgAction::Set,
+            value_parser = treat_int_as_boolean,
+            required_unless_present = "status"
+        )]
+        enabled: Option<bool>,
+        /// Print the current state instead of setting it.
+        /// Exits with 0 if enabled, 1 if disabled, 2 if the query failed, 4 if the kernel is too
+        /// old to report it.
+        #[arg(long, conflicts_with = "enabled")]
+        status: bool,
     },
 
     /// Add the desired path BEFORE it gets bind mounted or overlayed, this is used for storing original stat info in kernel
@@ -83,6 +155,20 @@ pub enum SuSFSSubCommands {
     AddSusKstat {
         /// Path of file or directory
         path: String,
+        /// Take the stat of a symlink itself rather than of its target
+        #[arg(long)]
+        no_follow: bool,
+        /// Wait for MOUNTPOINT to be mounted, then complete with <update_sus_kstat> automatically
+        #[arg(long, value_name = "MOUNTPOINT")]
+        wait_for_mount: Option<String>,
+        /// How long to wait for the mount, e.g. 30s or 2m
+        #[arg(
+            long,
+            default_value = "30s",
+            requires = "wait_for_mount",
+            value_parser = parse_timeout
+        )]
+        timeout: Duration,
     },
 
     /// Add the desired path you have added before via <add_sus_kstat> to complete the kstat spoofing procedure.
@@ -95,6 +181,9 @@ pub enum SuSFSSubCommands {
     UpdateSusKstat {
         /// Path of file or directory
         path: String,
+        /// Take the stat of a symlink itself rather than of its target
+        #[arg(long)]
+        no_follow: bool,
     },
 
     /// Add the desired path you have added before via <add_sus_kstat> to complete the kstat spoofing procedure.
@@ -107,10 +196,35 @@ pub enum SuSFSSubCommands {
     UpdateSusKstatFullClone {
         /// Path of file or directory
         path: String,
+        /// Take the stat of a symlink itself rather than of its target
+        #[arg(long)]
+        no_follow: bool,
     },
 
+    /// Record the original stat of files BEFORE they get bind mounted or overlayed.
+    ///
+    /// The snapshot is stored under /data/adb/ksu and applied later with <kstat_apply>.
+    #[command(name = "kstat_snapshot")]
+    KstatSnapshot {
+        /// Paths of files or directories
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+
+    /// Spoof all snapshotted paths with their recorded stat, AFTER they are mounted.
+    ///
+    /// * Important Notes *
+    /// - Only effective for umounted process with uid >= 10000.
+    #[command(name = "kstat_apply")]
+    KstatApply,
+
     /// Spoof the kstat of a file or directory by static fields.
     ///
+    /// atime/mtime/ctime also accept `now`, `boot` and `now-<duration>` (e.g. `now-30d`), resolved at
+    /// invocation time. The matching nsec field is zeroed unless explicitly given.
+    ///
+    /// Every field can also be given as an option instead, e.g. `--size 4096 --mtime now-30d`.
+    ///
     /// * Important Notes *
     /// - Only effective for umounted process with uid >= 10000.
     #[command(name = "add_sus_kstat_statically")]
@@ -125,22 +239,24 @@ pub enum SuSFSSubCommands {
         nlink: std::option::Option<i64>,
         #[arg(default_value = "default", value_parser = treat_default_as_none::<i64>)]
         size: std::option::Option<i64>,
-        #[arg(default_value = "default", value_parser = treat_default_as_none::<i64>)]
-        atime: std::option::Option<i64>,
+        #[arg(default_value = "default", value_parser = treat_time_keyword)]
+        atime: std::option::Option<TimeField>,
         #[arg(default_value = "default", value_parser = treat_default_as_none::<i64>)]
         atime_nsec: std::option::Option<i64>,
-        #[arg(default_value = "default", value_parser = treat_default_as_none::<i64>)]
-        mtime: std::option::Option<i64>,
+        #[arg(default_value = "default", value_parser = treat_time_keyword)]
+        mtime: std::option::Option<TimeField>,
         #[arg(default_value = "default", value_parser = treat_default_as_none::<i64>)]
         mtime_nsec: std::option::Option<i64>,
-        #[arg(default_value = "default", value_parser = treat_default_as_none::<i64>)]
-        ctime: std::option::Option<i64>,
+        #[arg(default_value = "default", value_parser = treat_time_keyword)]
+        ctime: std::option::Option<TimeField>,
         #[arg(default_value = "default", value_parser = treat_default_as_none::<i64>)]
         ctime_nsec: std::option::Option<i64>,
         #[arg(default_value = "default", value_parser = treat_default_as_none::<i64>)]
         blocks: std::option::Option<i64>,
         #[arg(default_value = "default", value_parser = treat_default_as_none::<i64>)]
         blksize: std::option::Option<i64>,
+        #[command(flatten)]
+        named: KstatStaticOptions,
     },
 
     /// Spoof uname for all processes.
@@ -163,6 +279,20 @@ pub enum SuSFSSubCommands {
         path: String,
     },
 
+    /// Spoof the output of /proc/cmdline from a single-line text file (non-gki kernels only).
+    #[command(name = "set_cmdline")]
+    SetCmdline {
+        /// Path to fake cmdline file
+        path: String,
+    },
+
+    /// Spoof the output of /proc/bootconfig from a `key = value` text file (gki kernels only).
+    #[command(name = "set_bootconfig")]
+    SetBootconfig {
+        /// Path to fake bootconfig file
+        path: String,
+    },
+
     /// Redirect the target path to be opened with user defined path and pre-defined uid scheme.
     ///
     /// * Important Notes *
@@ -179,6 +309,9 @@ pub enum SuSFSSubCommands {
         /// 3: Effective for processes that are marked umounted with uid >= 10000 (Use it carefully!)
         /// 4: Effective for processes that are marked umounted (include most of the init spawned process, use it carefully!)
         uid_scheme: i32,
+        /// Register the redirect even if apps look unable to read redirected_path
+        #[arg(long)]
+        force: bool,
     },
 
     /// Added real file path which gets mmapped will be hidden from /proc/self/[maps|smaps|smaps_rollup|map_files|mem|pagemap].
@@ -190,8 +323,18 @@ pub enum SuSFSSubCommands {
     /// - Only effective for umounted process with uid >= 10000.
     #[command(name = "add_sus_map")]
     AddSusMap {
-        /// Path to actual library
-        path: String,
+        /// Paths to actual libraries
+        #[arg(required_unless_present_any = ["library", "from_file"])]
+        paths: Vec<String>,
+        /// Read additional paths from a file, one per line ('#' starts a comment)
+        #[arg(long)]
+        from_file: Option<PathBuf>,
+        /// Resolve the library paths by basename from /proc/<pid>/maps instead
+        #[arg(long, conflicts_with_all = ["paths", "from_file"])]
+        library: Option<String>,
+        /// Process whose maps are scanned when using --library
+        #[arg(long, default_value = "1", requires = "library")]
+        pid: i32,
     },
 
     /// Spoofing the sus tcontext 'su' shown in avc log in kernel.
@@ -214,6 +357,59 @@ pub enum SuSFSSubCommands {
         info_type: ShowType,
     },
 
+    /// Show the kernel SuSFS version, variant and compatibility with ksud.
+    Status,
+
+    /// Compare the entries and toggles registered in the kernel with the persisted configuration.
+    ///
+    /// Fails when the kernel lacks entries or has different toggles, json output lists them in
+    /// `discrepancies` instead. Needs a kernel whose SuSFS can report its state.
+    Verify,
+
+    /// Apply a complete SuSFS configuration file at once.
+    ///
+    /// The file uses the same JSON format as `config backup`, missing sections keep their defaults.
+    Apply {
+        /// Path to the configuration file
+        path: PathBuf,
+        /// Only parse and check the file, without touching the kernel
+        #[arg(long)]
+        validate: bool,
+    },
+
+    /// Re-apply the persisted per-user configuration (sus_path_loop) for an Android user.
+    ///
+    /// Meant to be called by the manager or an init trigger after switching users.
+    #[command(name = "on_user_switch")]
+    OnUserSwitch {
+        /// Android user id, e.g. 10 for a work profile
+        user_id: u32,
+    },
+
+    /// Print SuSFS messages from the kernel log (requires `enable_log 1`).
+    Log {
+        /// Keep streaming new messages
+        #[arg(short, long)]
+        follow: bool,
+        /// Only print the last N existing messages
+        #[arg(short = 'n', long, value_name = "N")]
+        lines: Option<usize>,
+    },
+
+    /// Clear the persisted SuSFS configuration and turn the kernel toggles off.
+    ///
+    /// SuSFS has no commands to remove registered entries, those stay until reboot. Without
+    /// SuSFS in the kernel only the files are cleaned up.
+    Reset {
+        /// Also clear the umount configuration
+        #[arg(long)]
+        include_umount: bool,
+    },
+
+    /// Print the log of SuSFS commands issued by ksud, with payloads and results.
+    #[command(name = "log_dump")]
+    LogDump,
+
     /// Read boot slot kernel uname and build-time
     #[command(name = "slot_info")]
     SlotInfo {
@@ -223,11 +419,169 @@ pub enum SuSFSSubCommands {
     },
 }
 
+impl SuSFSSubCommands {
+    /// Name of the subcommand as typed on the command line, e.g. `add_sus_path`
+    const fn name(&self) -> &'static str {
+        match self {
+            Self::Config { .. } => "config",
+            Self::AddSusPath { .. } => "add_sus_path",
+            Self::AddSusPathLoop { .. } => "add_sus_path_loop",
+            Self::HideSusMntsForNonSuProcs { .. } => "hide_sus_mnts_for_non_su_procs",
+            Self::AddSusKstat { .. } => "add_sus_kstat",
+            Self::UpdateSusKstat { .. } => "update_sus_kstat",
+            Self::UpdateSusKstatFullClone { .. } => "update_sus_kstat_full_clone",
+            Self::KstatSnapshot { .. } => "kstat_snapshot",
+            Self::KstatApply => "kstat_apply",
+            Self::AddSusKstatStatically { .. } => "add_sus_kstat_statically",
+            Self::SetUname { .. } => "set_uname",
+            Self::EnableLog { .. } => "enable_log",
+            Self::SetCmdlineOrBootconfig { .. } => "set_cmdline_or_bootconfig",
+            Self::SetCmdline { .. } => "set_cmdline",
+            Self::SetBootconfig { .. } => "set_bootconfig",
+            Self::AddOpenRedirect { .. } => "add_open_redirect",
+            Self::AddSusMap { .. } => "add_sus_map",
+            Self::EnableAvcLogSpoofing { .. } => "enable_avc_log_spoofing",
+            Self::Show { .. } => "show",
+            Self::Status => "status",
+            Self::Verify => "verify",
+            Self::Apply { .. } => "apply",
+            Self::OnUserSwitch { .. } => "on_user_switch",
+            Self::Log { .. } => "log",
+            Self::Reset { .. } => "reset",
+            Self::LogDump => "log_dump",
+            Self::SlotInfo { .. } => "slot_info",
+        }
+    }
+}
+
+/// Named forms of the positional add_sus_kstat_statically fields, so single fields can be
+/// set without spelling out all the ones before it.
+#[derive(Debug, Args)]
+pub struct KstatStaticOptions {
+    #[arg(
+        long = "ino",
+        id = "ino_opt",
+        conflicts_with = "ino",
+        default_value = "default",
+        hide_default_value = true,
+        value_parser = treat_default_as_none::<i64>
+    )]
+    ino: std::option::Option<i64>,
+    #[arg(
+        long = "dev",
+        id = "dev_opt",
+        conflicts_with = "dev",
+        default_value = "default",
+        hide_default_value = true,
+        value_parser = treat_default_as_none::<i64>
+    )]
+    dev: std::option::Option<i64>,
+    #[arg(
+        long = "nlink",
+        id = "nlink_opt",
+        conflicts_with = "nlink",
+        default_value = "default",
+        hide_default_value = true,
+        value_parser = treat_default_as_none::<i64>
+    )]
+    nlink: std::option::Option<i64>,
+    #[arg(
+        long = "size",
+        id = "size_opt",
+        conflicts_with = "size",
+        default_value = "default",
+        hide_default_value = true,
+        value_parser = treat_default_as_none::<i64>
+    )]
+    size: std::option::Option<i64>,
+    #[arg(
+        long = "atime",
+        id = "atime_opt",
+        conflicts_with = "atime",
+        default_value = "default",
+        hide_default_value = true,
+        value_parser = treat_time_keyword
+    )]
+    atime: std::option::Option<TimeField>,
+    #[arg(
+        long = "atime-nsec",
+        id = "atime_nsec_opt",
+        conflicts_with = "atime_nsec",
+        default_value = "default",
+        hide_default_value = true,
+        value_parser = treat_default_as_none::<i64>
+    )]
+    atime_nsec: std::option::Option<i64>,
+    #[arg(
+        long = "mtime",
+        id = "mtime_opt",
+        conflicts_with = "mtime",
+        default_value = "default",
+        hide_default_value = true,
+        value_parser = treat_time_keyword
+    )]
+    mtime: std::option::Option<TimeField>,
+    #[arg(
+        long = "mtime-nsec",
+        id = "mtime_nsec_opt",
+        conflicts_with = "mtime_nsec",
+        default_value = "default",
+        hide_default_value = true,
+        value_parser = treat_default_as_none::<i64>
+    )]
+    mtime_nsec: std::option::Option<i64>,
+    #[arg(
+        long = "ctime",
+        id = "ctime_opt",
+        conflicts_with = "ctime",
+        default_value = "default",
+        hide_default_value = true,
+        value_parser = treat_time_keyword
+    )]
+    ctime: std::option::Option<TimeField>,
+    #[arg(
+        long = "ctime-nsec",
+        id = "ctime_nsec_opt",
+        conflicts_with = "ctime_nsec",
+        default_value = "default",
+        hide_default_value = true,
+        value_parser = treat_default_as_none::<i64>
+    )]
+    ctime_nsec: std::option::Option<i64>,
+    #[arg(
+        long = "blocks",
+        id = "blocks_opt",
+        conflicts_with = "blocks",
+        default_value = "default",
+        hide_default_value = true,
+        value_parser = treat_default_as_none::<i64>
+    )]
+    blocks: std::option::Option<i64>,
+    #[arg(
+        long = "blksize",
+        id = "blksize_opt",
+        conflicts_with = "blksize",
+        default_value = "default",
+        hide_default_value = true,
+        value_parser = treat_default_as_none::<i64>
+    )]
+    blksize: std::option::Option<i64>,
+}
+
 #[derive(Subcommand, Debug)]
 pub enum ShowType {
-    Version,
+    Version {
+        /// Print the bytes returned by the kernel as-is
+        #[arg(long)]
+        raw: bool,
+    },
     #[command(name = "enabled_features")]
-    EnabledFeatures,
+    EnabledFeatures {
+        /// Only check whether FEATURE is enabled, printing nothing.
+        /// Exits with 0 if present, 1 if absent, 2 if the kernel query failed.
+        #[arg(long, value_name = "FEATURE")]
+        check: Option<String>,
+    },
     Variant,
 }
 
@@ -249,6 +603,104 @@ where
     }
 }
 
+/// Seconds value of a kstat time field, remembering whether it was resolved from a keyword.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeField {
+    secs: i64,
+    from_keyword: bool,
+}
+
+const DURATION_UNITS: &[(char, i64)] = &[
+    ('s', 1),
+    ('m', 60),
+    ('h', 3600),
+    ('d', 86400),
+    ('w', 604_800),
+];
+
+fn now_secs() -> Result<i64, String> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .map_err(|e| format!("{e}"))
+}
+
+fn boot_secs() -> Result<i64, String> {
+    let stat = fs::read_to_string("/proc/stat").map_err(|e| format!("read /proc/stat: {e}"))?;
+    stat.lines()
+        .find_map(|line| line.strip_prefix("btime "))
+        .ok_or_else(|| "btime not found in /proc/stat".to_string())?
+        .trim()
+        .parse()
+        .map_err(|e| format!("invalid btime: {e}"))
+}
+
+fn parse_duration_secs(value: &str) -> Result<i64, String> {
+    let accepted = || {
+        DURATION_UNITS
+            .iter()
+            .map(|(unit, _)| unit.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let Some(unit) = value.chars().last() else {
+        return Err(format!("empty duration, accepted units: {}", accepted()));
+    };
+    let Some((_, scale)) = DURATION_UNITS.iter().find(|(u, _)| *u == unit) else {
+        return Err(format!(
+            "invalid duration unit in '{value}', accepted units: {}",
+            accepted()
+        ));
+    };
+    let amount: i64 = value[..value.len() - unit.len_utf8()]
+        .parse()
+        .map_err(|e| format!("invalid duration '{value}': {e}"))?;
+    amount
+        .checked_mul(*scale)
+        .ok_or_else(|| format!("duration '{value}' is too large"))
+}
+
+fn parse_timeout(value: &str) -> Result<Duration, String> {
+    let secs = parse_duration_secs(value)?;
+    u64::try_from(secs)
+        .map(Duration::from_secs)
+        .map_err(|_| format!("timeout '{value}' must not be negative"))
+}
+
+fn treat_time_keyword(value: &str) -> Result<Option<TimeField>, String> {
+    let keyword = |secs| {
+        Ok(Some(TimeField {
+            secs,
+            from_keyword: true,
+        }))
+    };
+    match value {
+        "default" => Ok(None),
+        "now" => keyword(now_secs()?),
+        "boot" => keyword(boot_secs()?),
+        _ => {
+            if let Some(duration) = value.strip_prefix("now-") {
+                keyword(now_secs()? - parse_duration_secs(duration)?)
+            } else {
+                value
+                    .parse()
+                    .map(|secs| {
+                        Some(TimeField {
+                            secs,
+                            from_keyword: false,
+                        })
+                    })
+                    .map_err(|e| format!("{e}"))
+            }
+        }
+    }
+}
+
+/// Zero the nsec field of a keyword-resolved time unless it was explicitly given.
+fn keyword_nsec(time: Option<TimeField>, nsec: Option<i64>) -> Option<i64> {
+    nsec.or_else(|| time.filter(|t| t.from_keyword).map(|_| 0))
+}
+
 fn treat_int_as_boolean(s: &str) -> Result<bool, String> {
     match s {
         "0" => Ok(false),
@@ -257,6 +709,199 @@ fn treat_int_as_boolean(s: &str) -> Result<bool, String> {
     }
 }
 
+/// Apply [`api::ignore_already_exists`] unless `strict` is set.
+fn tolerate_duplicate(result: Result<()>, what: &str, strict: bool) -> Result<()> {
+    if strict {
+        result
+    } else {
+        api::ignore_already_exists(result, what)
+    }
+}
+
+fn add_sus_map_batch(paths: &[String], strict: bool) -> Result<()> {
+    if paths.is_empty() {
+        bail!("No sus_map paths given");
+    }
+
+    let mut failed = 0;
+    for path in paths {
+        match tolerate_duplicate(api::add_sus_map(path), path, strict) {
+            Ok(()) => println!("Registered sus_map: {path}"),
+            Err(e) => {
+                eprintln!("Failed to add sus_map {path}: {e}");
+                failed += 1;
+            }
+        }
+    }
+
+    println!("sus_map: {} added, {failed} failed", paths.len() - failed);
+    if failed == paths.len() {
+        bail!("All sus_map entries failed");
+    }
+    Ok(())
+}
+
+/// Entries the kernel can only forget on reboot, SuSFS has no removal commands.
+const IRREVERSIBLE_ENTRIES: &[&str] = &[
+    "sus_path",
+    "sus_path_loop",
+    "sus_kstat",
+    "sus_map",
+    "open_redirect",
+    "uname",
+    "cmdline_or_bootconfig",
+];
+
+/// Undo what ksud set up for SuSFS, the kernel toggles only when `kernel` has SuSFS
+fn reset(include_umount: bool, kernel: bool) -> Result<()> {
+    let mut failed = 0;
+    let mut step = |what: &str, result: Result<()>| match result {
+        Ok(()) => println!("[ok] {what}"),
+        Err(e) => {
+            println!("[failed] {what}: {e}");
+            failed += 1;
+        }
+    };
+
+    step(
+        "restore default persisted configuration",
+        run_config(ConfigCommand::Restore { path: None }),
+    );
+    step(
+        "remove kstat snapshot",
+        match fs::remove_file(defs::SUSFS_KSTAT_SNAPSHOT) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        },
+    );
+    if kernel {
+        step("disable log", api::enable_log(false));
+        step(
+            "disable avc log spoofing",
+            api::enable_avc_log_spoofing(false),
+        );
+        step(
+            "disable hide_sus_mnts_for_non_su_procs",
+            api::hide_sus_mnts_for_non_su_procs(false),
+        );
+    } else {
+        println!("[skipped] kernel toggles: SuSFS is not present");
+    }
+    if include_umount {
+        step("clear umount configuration", umount_config::wipe_umount());
+    }
+
+    println!(
+        "Not undone, reboot to clear: {}",
+        IRREVERSIBLE_ENTRIES.join(", ")
+    );
+    if failed > 0 {
+        bail!("{failed} reset step(s) failed");
+    }
+    Ok(())
+}
+
+fn set_spoof_target(path: &str, wanted: api::SpoofTarget) -> Result<()> {
+    let content = fs::read_to_string(path)?;
+    match wanted {
+        api::SpoofTarget::Cmdline => api::validate_cmdline(&content)?,
+        api::SpoofTarget::Bootconfig => api::validate_bootconfig(&content)?,
+    }
+
+    match api::spoof_target()? {
+        Some(target) if target != wanted => {
+            bail!("This kernel's SuSFS spoofs {target} only, it can't spoof {wanted} separately")
+        }
+        Some(_) => {}
+        None => eprintln!(
+            "WARNING: unknown SuSFS variant, the kernel may spoof the other of /proc/cmdline and /proc/bootconfig"
+        ),
+    }
+
+    api::set_cmdline_or_bootconfig(path)?;
+    println!("Kernel spoofs {wanted} with {path}");
+    Ok(())
+}
+
+fn add_sus_path_loop_packages(packages: &[String], strict: bool) -> Result<()> {
+    let packages_list = utils::read_packages_list().unwrap_or_else(|e| {
+        eprintln!("WARNING: {e}, falling back to /data/data");
+        Default::default()
+    });
+
+    let mut failed = 0;
+    for package in packages {
+        let dirs = utils::package_data_dirs(&packages_list, package);
+        if dirs.is_empty() {
+            eprintln!("Unknown package or no data directories: {package}");
+            failed += 1;
+            continue;
+        }
+        let mut package_failed = false;
+        for dir in &dirs {
+            match tolerate_duplicate(api::add_sus_path(dir, true), dir, strict) {
+                Ok(()) => println!("Registered sus_path_loop: {dir}"),
+                Err(e) => {
+                    eprintln!("Failed to add sus_path_loop {dir}: {e}");
+                    package_failed = true;
+                }
+            }
+        }
+        if package_failed {
+            failed += 1;
+        }
+    }
+
+    println!(
+        "sus_path_loop packages: {} added, {failed} failed",
+        packages.len() - failed
+    );
+    if failed == packages.len() {
+        bail!("All packages failed");
+    }
+    Ok(())
+}
+
+fn apply_file(path: &Path, validate_only: bool) -> Result<()> {
+    let config = Config::read_from(path)?;
+
+    let problems = config.validate();
+    for problem in &problems {
+        eprintln!("{problem}");
+    }
+    if validate_only {
+        if !problems.is_empty() {
+            bail!("{} problem(s) found in {}", problems.len(), path.display());
+        }
+        println!("{}: OK", path.display());
+        return Ok(());
+    }
+
+    let results = config.apply_all();
+    let mut failed = 0;
+    for (name, result) in &results {
+        match result {
+            Ok(()) => println!("[ok] {name}"),
+            Err(e) => {
+                failed += 1;
+                println!("[failed] {name}");
+                for line in e.to_string().lines() {
+                    println!("    {line}");
+                }
+            }
+        }
+    }
+
+    println!(
+        "susfs apply: {} steps succeeded, {failed} failed",
+        results.len() - failed
+    );
+    if failed > 0 {
+        bail!("{failed} step(s) failed");
+    }
+    Ok(())
+}
+
 pub fn run_from_args(args: &[String]) -> Result<()> {
     let parser = match SusfsParser::try_parse_from(args) {
         Ok(cli) => cli,
@@ -276,23 +921,168 @@ pub fn run_from_args(args: &[String]) -> Result<()> {
     run_main(parser.arg)
 }
 
+/// Exit code when the kernel has no SuSFS at all.
+const EXIT_NO_SUSFS: i32 = 3;
+/// Exit code when the kernel query behind a yes/no answer failed.
+const EXIT_QUERY_FAILED: i32 = 2;
+/// Exit code when the kernel is too old to answer a yes/no query at all.
+const EXIT_UNSUPPORTED: i32 = 4;
+
+/// Marks the failure of a kernel query whose answer is an exit code, it exits with
+/// [`EXIT_QUERY_FAILED`] instead of 1
+#[derive(Debug)]
+struct QueryFailed;
+
+impl Display for QueryFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SuSFS query failed")
+    }
+}
+
+fn exit_code(error: &anyhow::Error) -> i32 {
+    if error.is::<api::NotPresent>() {
+        EXIT_NO_SUSFS
+    } else if error.is::<QueryFailed>() {
+        EXIT_QUERY_FAILED
+    } else {
+        1
+    }
+}
+
 pub fn run_main(args: SusfsArgs) -> Result<()> {
+    if args.format == OutputFormat::Text {
+        return match run(args, &mut None) {
+            Ok(0) => Ok(()),
+            Ok(code) => std::process::exit(code),
+            // is_present already printed the diagnosis
+            Err(e) if e.is::<api::NotPresent>() => std::process::exit(EXIT_NO_SUSFS),
+            Err(e) if e.is::<QueryFailed>() => {
+                eprintln!("{e:#}");
+                std::process::exit(EXIT_QUERY_FAILED);
+            }
+            Err(e) => Err(e),
+        };
+    }
+
+    let cmd = args.command.name();
+    let mut stdout = divert_stdout()?;
+    let mut data = None;
+    let (report, code) = match run(args, &mut data) {
+        Ok(code) => (json!({ "ok": code == 0, "cmd": cmd, "data": data }), code),
+        Err(e) => (error_json(cmd, &e), exit_code(&e)),
+    };
+    std::io::stdout().flush()?;
+    writeln!(stdout, "{report}")?;
+    if code != 0 {
+        std::process::exit(code);
+    }
+    Ok(())
+}
+
+/// Point stdout at stderr for the rest of the process and return the original stdout, so in
+/// json mode nothing but the final object reaches it, whatever the subcommand prints.
+fn divert_stdout() -> Result<fs::File> {
+    std::io::stdout().flush()?;
+    let saved = unsafe { libc::dup(libc::STDOUT_FILENO) };
+    if saved < 0 {
+        return Err(std::io::Error::last_os_error()).context("failed to duplicate stdout");
+    }
+    let saved = unsafe { fs::File::from_raw_fd(saved) };
+    if unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } < 0 {
+        return Err(std::io::Error::last_os_error()).context("failed to redirect stdout");
+    }
+    Ok(saved)
+}
+
+fn error_json(cmd: &str, error: &anyhow::Error) -> Value {
+    let (cmd_id, errno) = match api::command_failure(error) {
+        // negative values are errnos, positive ones are SuSFS' own codes
+        Some((id, err)) => (Some(format!("0x{id:x}")), (err < 0).then_some(-err)),
+        None => (
+            None,
+            error
+                .chain()
+                .find_map(|cause| cause.downcast_ref::<std::io::Error>())
+                .and_then(std::io::Error::raw_os_error),
+        ),
+    };
+    json!({
+        "ok": false,
+        "cmd": cmd,
+        "cmd_id": cmd_id,
+        "errno": errno,
+        "message": format!("{error:#}"),
+    })
+}
+
+/// Run a susfs subcommand, in json mode returned data is stored in `data` instead of printed.
+///
+/// Returns the exit code, non-zero for answers given by the exit code alone.
+fn run(args: SusfsArgs, data: &mut Option<Value>) -> Result<i32> {
+    let json_output = args.format == OutputFormat::Json;
+    // config, slot_info, log and log_dump never issue SuSFS commands, status prints the warning
+    // itself, reset skips its kernel steps
+    let talks_to_kernel = !matches!(
+        args.command,
+        SuSFSSubCommands::Config { .. }
+            | SuSFSSubCommands::SlotInfo { .. }
+            | SuSFSSubCommands::Apply { validate: true, .. }
+            | SuSFSSubCommands::Status
+            | SuSFSSubCommands::Log { .. }
+            | SuSFSSubCommands::LogDump
+            | SuSFSSubCommands::Reset { .. }
+    );
+    api::set_retry_policy(api::RetryPolicy {
+        attempts: args.retries,
+        delay: Duration::from_millis(args.retry_delay_ms),
+    });
+    api::set_presence_check(!args.skip_presence_check);
+    if talks_to_kernel && !args.skip_presence_check && !api::is_present() {
+        return Err(api::NotPresent.into());
+    }
+    if talks_to_kernel && !args.no_version_check {
+        version::warn_on_mismatch();
+    }
+
     match args.command {
         SuSFSSubCommands::Config { command } => run_config(command)?,
         SuSFSSubCommands::AddSusPath { path } => {
-            api::add_sus_path(&path, false)?;
+            tolerate_duplicate(api::add_sus_path(&path, false), &path, args.strict)?;
         }
-        SuSFSSubCommands::AddSusPathLoop { path } => {
-            api::add_sus_path(&path, true)?;
+        SuSFSSubCommands::AddSusPathLoop { path, packages } => {
+            if let Some(path) = path {
+                tolerate_duplicate(api::add_sus_path(&path, true), &path, args.strict)?;
+            }
+            if !packages.is_empty() {
+                add_sus_path_loop_packages(&packages, args.strict)?;
+            }
         }
-        SuSFSSubCommands::AddSusKstat { path } => {
-            api::add_sus_kstat(&path)?;
+        SuSFSSubCommands::AddSusKstat {
+            path,
+            no_follow,
+            wait_for_mount,
+            timeout,
+        } => {
+            api::add_sus_kstat(&path, !no_follow)?;
+            if let Some(mountpoint) = wait_for_mount {
+                println!(
+                    "Recorded original stat of {path}, waiting for {mountpoint} to be mounted"
+                );
+                if !utils::wait_for_mount(&mountpoint, timeout)? {
+                    bail!(
+                        "Timed out after {}s waiting for {mountpoint}: the original stat of {path} was recorded, but update_sus_kstat was not issued",
+                        timeout.as_secs()
+                    );
+                }
+                api::update_sus_kstat(&path, false, !no_follow)?;
+                println!("Updated sus_kstat of {path} after {mountpoint} was mounted");
+            }
         }
-        SuSFSSubCommands::UpdateSusKstat { path } => {
-            api::update_sus_kstat(&path, false)?;
+        SuSFSSubCommands::UpdateSusKstat { path, no_follow } => {
+            api::update_sus_kstat(&path, false, !no_follow)?;
         }
-        SuSFSSubCommands::UpdateSusKstatFullClone { path } => {
-            api::update_sus_kstat(&path, true)?;
+        SuSFSSubCommands::UpdateSusKstatFullClone { path, no_follow } => {
+            api::update_sus_kstat(&path, true, !no_follow)?;
         }
         SuSFSSubCommands::AddSusKstatStatically {
             path,
@@ -308,52 +1098,237 @@ pub fn run_main(args: SusfsArgs) -> Result<()> {
             ctime_nsec,
             blocks,
             blksize,
+            named,
         } => {
+            let ino = named.ino.or(ino);
+            let dev = named.dev.or(dev);
+            let nlink = named.nlink.or(nlink);
+            let size = named.size.or(size);
+            let atime = named.atime.or(atime);
+            let atime_nsec = named.atime_nsec.or(atime_nsec);
+            let mtime = named.mtime.or(mtime);
+            let mtime_nsec = named.mtime_nsec.or(mtime_nsec);
+            let ctime = named.ctime.or(ctime);
+            let ctime_nsec = named.ctime_nsec.or(ctime_nsec);
+            let blocks = named.blocks.or(blocks);
+            let blksize = named.blksize.or(blksize);
             api::add_sus_kstat_statically(
-                &path, ino, dev, nlink, size, atime, atime_nsec, mtime, mtime_nsec, ctime,
-                ctime_nsec, blocks, blksize,
+                &path,
+                ino,
+                dev,
+                nlink,
+                size,
+                atime.map(|t| t.secs),
+                keyword_nsec(atime, atime_nsec),
+                mtime.map(|t| t.secs),
+                keyword_nsec(mtime, mtime_nsec),
+                ctime.map(|t| t.secs),
+                keyword_nsec(ctime, ctime_nsec),
+                blocks,
+                blksize,
             )?;
         }
+        SuSFSSubCommands::KstatSnapshot { paths } => kstat_snapshot::snapshot(&paths)?,
+        SuSFSSubCommands::KstatApply => kstat_snapshot::apply()?,
         SuSFSSubCommands::SetUname { release, version } => {
             api::set_uname(&release, &version)?;
         }
-        SuSFSSubCommands::HideSusMntsForNonSuProcs { enabled } => {
-            api::hide_sus_mnts_for_non_su_procs(enabled)?;
+        SuSFSSubCommands::HideSusMntsForNonSuProcs { enabled, status } => {
+            if status {
+                let state = api::hide_sus_mnts_for_non_su_procs_status().context(QueryFailed)?;
+                let (name, code) = match state {
+                    Some(true) => ("enabled", 0),
+                    Some(false) => ("disabled", 1),
+                    None => ("unsupported", EXIT_UNSUPPORTED),
+                };
+                if json_output {
+                    *data = Some(json!({ "status": name, "enabled": state }));
+                } else {
+                    println!("{name}");
+                }
+                if state.is_none() {
+                    eprintln!(
+                        "the kernel predates the SuSFS state query, the toggle can be set but not read back"
+                    );
+                }
+                return Ok(code);
+            } else if let Some(enabled) = enabled {
+                api::hide_sus_mnts_for_non_su_procs(enabled)?;
+            }
         }
         SuSFSSubCommands::EnableLog { enabled } => {
             api::enable_log(enabled)?;
         }
         SuSFSSubCommands::SetCmdlineOrBootconfig { path } => {
             api::set_cmdline_or_bootconfig(&path)?;
+            if let Ok(Some(target)) = api::spoof_target()
+                && !path.is_empty()
+            {
+                println!("Kernel spoofs {target} with {path}");
+            }
+        }
+        SuSFSSubCommands::SetCmdline { path } => {
+            set_spoof_target(&path, api::SpoofTarget::Cmdline)?;
+        }
+        SuSFSSubCommands::SetBootconfig { path } => {
+            set_spoof_target(&path, api::SpoofTarget::Bootconfig)?;
         }
         SuSFSSubCommands::AddOpenRedirect {
             target_path,
             redirected_path,
             uid_scheme,
+            force,
         } => {
             let uid_scheme = UidScheme::try_from_primitive(uid_scheme)?;
-            api::add_open_redirect(&target_path, &redirected_path, &uid_scheme)?;
+            let issues = utils::app_readability_issues(&redirected_path)?;
+            for issue in &issues {
+                eprintln!("WARNING: {issue}");
+            }
+            if !issues.is_empty() && !force {
+                bail!(
+                    "apps may be unable to open {redirected_path} and crash instead of reading it, fix the file or pass --force"
+                );
+            }
+            tolerate_duplicate(
+                api::add_open_redirect(&target_path, &redirected_path, &uid_scheme),
+                &target_path,
+                args.strict,
+            )?;
         }
-        SuSFSSubCommands::AddSusMap { path } => {
-            api::add_sus_map(&path)?;
+        SuSFSSubCommands::AddSusMap {
+            mut paths,
+            from_file,
+            library,
+            pid,
+        } => {
+            if let Some(library) = library {
+                paths = utils::find_mapped_library(&library, pid)?;
+                if paths.is_empty() {
+                    bail!("No mapping of {library} found in /proc/{pid}/maps");
+                }
+            }
+            if let Some(from_file) = from_file {
+                paths.extend(utils::read_path_list(&from_file)?);
+            }
+            add_sus_map_batch(&paths, args.strict)?;
         }
         SuSFSSubCommands::EnableAvcLogSpoofing { enabled } => {
             api::enable_avc_log_spoofing(enabled)?;
         }
         SuSFSSubCommands::Show { info_type } => match info_type {
-            ShowType::Version => {
+            ShowType::Version { raw: true } if json_output => {
+                *data = Some(String::from_utf8_lossy(&api::version_raw()?).into());
+            }
+            ShowType::Version { raw: true } => {
+                let mut stdout = std::io::stdout();
+                stdout.write_all(&api::version_raw()?)?;
+                stdout.write_all(b"\n")?;
+            }
+            ShowType::Version { raw: false } if json_output => {
+                *data = Some(api::version()?.into());
+            }
+            ShowType::Version { raw: false } => {
                 let version = api::version()?;
+                if !version.starts_with('v') {
+                    eprintln!("WARNING: unusual SuSFS version string: {version}");
+                }
                 println!("{version}");
             }
-            ShowType::EnabledFeatures => {
+            ShowType::EnabledFeatures {
+                check: Some(feature),
+            } => {
+                let enabled = api::is_feature_enabled(&feature).context(QueryFailed)?;
+                if json_output {
+                    *data = Some(json!({ "feature": feature, "enabled": enabled }));
+                }
+                if !enabled {
+                    return Ok(1);
+                }
+            }
+            ShowType::EnabledFeatures { check: None } if json_output => {
+                let features = api::enabled_features()?;
+                *data = Some(
+                    features
+                        .lines()
+                        .map(str::trim)
+                        .filter(|l| !l.is_empty())
+                        .collect(),
+                );
+            }
+            ShowType::EnabledFeatures { check: None } => {
                 let features = api::enabled_features()?;
                 println!("{features}");
             }
+            ShowType::Variant if json_output => {
+                *data = Some(api::variant()?.into());
+            }
             ShowType::Variant => {
                 let variant = api::variant()?;
                 println!("{variant}");
             }
         },
+        SuSFSSubCommands::Status if json_output => {
+            *data = Some(json!({
+                "version": api::version()?,
+                "variant": api::variant()?,
+                "min_supported": version::MIN_SUPPORTED.to_string(),
+                "max_supported_exclusive": version::MAX_SUPPORTED_EXCLUSIVE.to_string(),
+                "warning": version::mismatch_warning(),
+                "selinux": selinux::SelinuxStatus::read(),
+            }));
+        }
+        SuSFSSubCommands::Status => {
+            let selinux = selinux::SelinuxStatus::read();
+            println!(
+                "selinux: context {}, expected {}, {}",
+                selinux.context.as_deref().unwrap_or("unknown"),
+                selinux.expected_context,
+                match selinux.enforcing {
+                    Some(true) => "enforcing",
+                    Some(false) => "permissive",
+                    None => "enforce status unknown",
+                }
+            );
+            if selinux.in_expected_domain() == Some(false) {
+                println!(
+                    "WARNING: not running in the KernelSU domain, SuSFS commands may be denied; run through su or check the ksud sepolicy rules"
+                );
+            }
+            println!("version: {}", api::version()?);
+            println!("variant: {}", api::variant()?);
+            println!(
+                "supported: {} up to (excluding) {}",
+                version::MIN_SUPPORTED,
+                version::MAX_SUPPORTED_EXCLUSIVE
+            );
+            if let Some(warning) = version::mismatch_warning() {
+                for line in warning.lines() {
+                    println!("WARNING: {line}");
+                }
+            }
+        }
+        SuSFSSubCommands::Verify => {
+            let verification = Config::read_or_default().verify()?;
+            if json_output {
+                *data = Some(serde_json::to_value(&verification)?);
+            } else {
+                verify_ops::print(&verification);
+                if !verification.is_consistent() {
+                    bail!(
+                        "{} SuSFS settings did not take effect",
+                        verification.discrepancies.len()
+                    );
+                }
+            }
+        }
+        SuSFSSubCommands::Apply { path, validate } => apply_file(&path, validate)?,
+        SuSFSSubCommands::OnUserSwitch { user_id } => init_event::on_user_switch(user_id)?,
+        SuSFSSubCommands::Log { follow, lines } => kmsg::tail(lines, follow)?,
+        SuSFSSubCommands::Reset { include_umount } => {
+            // the files are cleaned up either way, the toggles need SuSFS
+            reset(include_umount, args.skip_presence_check || api::detect())?;
+        }
+        SuSFSSubCommands::LogDump => audit::dump()?,
         SuSFSSubCommands::SlotInfo { boot_image } => {
             if let Some(path) = boot_image {
                 slot_info::analyze_boot_image(&path)?;
@@ -363,5 +1338,42 @@ pub fn run_main(args: SusfsArgs) -> Result<()> {
         }
     }
 
-    Ok(())
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The size add_sus_kstat_statically ends up with for `args`
+    fn kstat_size(args: &[&str]) -> Result<Option<i64>, clap::Error> {
+        let cli = SusfsParser::try_parse_from(
+            [
+                "ksu_susfs",
+                "add_sus_kstat_statically",
+                "/system/bin/app_process",
+            ]
+            .iter()
+            .chain(args),
+        )?;
+        let SuSFSSubCommands::AddSusKstatStatically { size, named, .. } = cli.arg.command else {
+            panic!("parsed into another subcommand");
+        };
+        Ok(named.size.or(size))
+    }
+
+    #[test]
+    fn kstat_fields_can_be_positional_or_named() {
+        let positional = kstat_size(&["default", "default", "default", "4096"]).unwrap();
+        assert_eq!(positional, Some(4096));
+        assert_eq!(kstat_size(&["--size", "4096"]).unwrap(), positional);
+        assert_eq!(kstat_size(&[]).unwrap(), None);
+    }
+
+    #[test]
+    fn kstat_field_cannot_be_given_both_ways() {
+        let err =
+            kstat_size(&["default", "default", "default", "4096", "--size", "8192"]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+    }
 }
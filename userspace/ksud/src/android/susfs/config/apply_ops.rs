@@ -1,6 +1,27 @@
-use crate::android::susfs::{api::prelude as api, config::model::Config, enums};
+use std::path::Path;
+
+use crate::android::susfs::{api::prelude as api, config::model::Config, enums, version};
 use anyhow::{Result, anyhow, bail};
 
+type ApplyStep = (&'static str, fn(&Config) -> Result<&Config>);
+
+/// Steps applied by [`Config::apply_all`], uname and toggles first, kstat before paths.
+const APPLY_ORDER: &[ApplyStep] = &[
+    ("uname", Config::apply_uname),
+    ("cmdline_or_bootconfig", Config::apply_cmdline_or_bootconfig),
+    ("avc_log_spoofing", Config::apply_avc_log_spoofing),
+    ("logging", Config::apply_logging),
+    (
+        "hide_sus_mnts_for_non_su_procs",
+        Config::apply_hide_sus_mnts_for_non_su_procs,
+    ),
+    ("initialize sus_kstat", Config::init_sus_kstat),
+    ("finalize sus_kstat", Config::final_sus_kstat),
+    ("sus_map", Config::apply_sus_map),
+    ("open_redirect", Config::apply_open_redirect),
+    ("sus_path", Config::apply_sus_path),
+];
+
 fn apply_multiply<I, T, F>(iterable: I, mut f: F) -> Result<()>
 where
     I: IntoIterator<Item = T>,
@@ -21,6 +42,33 @@ where
         .ok_or_else(|| anyhow!(errors.trim().to_string()))
 }
 
+/// Whether the kernel can have `command`, so the boot path doesn't issue unknown commands.
+fn kernel_has(command: &str) -> bool {
+    let supported = version::kernel_supports(command);
+    if !supported {
+        log::debug!("kernel SuSFS predates {command}, skip");
+    }
+    supported
+}
+
+/// Map a path in the owner's storage to the storage of `user_id`.
+fn user_storage_path(path: &str, user_id: u32) -> String {
+    const OWNER_STORAGE_ROOTS: &[(&str, &str)] = &[
+        ("/sdcard", "/storage/emulated"),
+        ("/storage/emulated/0", "/storage/emulated"),
+        ("/data/media/0", "/data/media"),
+    ];
+
+    for (owner_root, users_root) in OWNER_STORAGE_ROOTS {
+        if let Some(rest) = path.strip_prefix(owner_root)
+            && (rest.is_empty() || rest.starts_with('/'))
+        {
+            return format!("{users_root}/{user_id}{rest}");
+        }
+    }
+    path.to_string()
+}
+
 impl Config {
     pub fn apply_cmdline_or_bootconfig(&self) -> Result<&Self> {
         api::set_cmdline_or_bootconfig(&self.cmdline_or_bootconfig)?;
@@ -28,6 +76,9 @@ impl Config {
     }
 
     pub fn apply_avc_log_spoofing(&self) -> Result<&Self> {
+        if !kernel_has("enable_avc_log_spoofing") {
+            return Ok(self);
+        }
         api::enable_avc_log_spoofing(self.avc_log_spoofing)?;
         Ok(self)
     }
@@ -38,6 +89,9 @@ impl Config {
     }
 
     pub fn apply_hide_sus_mnts_for_non_su_procs(&self) -> Result<&Self> {
+        if !kernel_has("hide_sus_mnts_for_non_su_procs") {
+            return Ok(self);
+        }
         api::hide_sus_mnts_for_non_su_procs(self.hide_sus_mnts_for_non_su_procs)?;
         Ok(self)
     }
@@ -48,13 +102,43 @@ impl Config {
     }
 
     pub fn apply_sus_path(&self) -> Result<&Self> {
-        apply_multiply(&self.sus_path, |i| api::add_sus_path(&i.path, i.is_loop)).map(|_| self)
+        let loop_supported =
+            self.sus_path.iter().any(|i| i.is_loop) && kernel_has("add_sus_path_loop");
+        let items = self
+            .sus_path
+            .iter()
+            .filter(|i| loop_supported || !i.is_loop);
+        apply_multiply(items, |i| {
+            api::ignore_already_exists(api::add_sus_path(&i.path, i.is_loop), &i.path)
+        })
+        .map(|_| self)
+    }
+
+    /// Re-apply the sus_path_loop entries for Android user `user_id`.
+    ///
+    /// Entries under the owner's storage (`/sdcard`, `/storage/emulated/0`, `/data/media/0`)
+    /// are mapped to the same location in the given user's storage, already registered
+    /// entries count as success. Returns the paths that were applied.
+    pub fn apply_sus_path_loop_for_user(&self, user_id: u32) -> Result<Vec<String>> {
+        if !kernel_has("add_sus_path_loop") {
+            return Ok(Vec::new());
+        }
+        let paths: Vec<String> = self
+            .sus_path
+            .iter()
+            .filter(|i| i.is_loop)
+            .map(|i| user_storage_path(&i.path, user_id))
+            .collect();
+        apply_multiply(&paths, |path| {
+            api::ignore_already_exists(api::add_sus_path(path, true), path)
+        })?;
+        Ok(paths)
     }
 
     pub fn init_sus_kstat(&self) -> Result<&Self> {
         apply_multiply(&self.sus_kstat, |i| match i.spoof_type {
             enums::SusKstatType::Normal | enums::SusKstatType::FullClone => {
-                api::add_sus_kstat(&i.path)
+                api::add_sus_kstat(&i.path, true)
             }
             enums::SusKstatType::Statically => Ok(()),
         })
@@ -63,8 +147,8 @@ impl Config {
 
     pub fn final_sus_kstat(&self) -> Result<&Self> {
         apply_multiply(&self.sus_kstat, |i| match i.spoof_type {
-            enums::SusKstatType::Normal => api::update_sus_kstat(&i.path, false),
-            enums::SusKstatType::FullClone => api::update_sus_kstat(&i.path, true),
+            enums::SusKstatType::Normal => api::update_sus_kstat(&i.path, false, true),
+            enums::SusKstatType::FullClone => api::update_sus_kstat(&i.path, true, true),
             enums::SusKstatType::Statically => {
                 let Some(statically) = &i.statically else {
                     bail!(
@@ -94,12 +178,71 @@ impl Config {
 
     pub fn apply_open_redirect(&self) -> Result<&Self> {
         apply_multiply(&self.open_redirect, |i| {
-            api::add_open_redirect(&i.target_path, &i.redirected_path, &i.uid_scheme)
+            api::ignore_already_exists(
+                api::add_open_redirect(&i.target_path, &i.redirected_path, &i.uid_scheme),
+                &i.target_path,
+            )
         })
         .map(|_| self)
     }
 
     pub fn apply_sus_map(&self) -> Result<&Self> {
-        apply_multiply(&self.sus_map, |i| api::add_sus_map(i)).map(|_| self)
+        if !kernel_has("add_sus_map") {
+            return Ok(self);
+        }
+        apply_multiply(&self.sus_map, |i| {
+            api::ignore_already_exists(api::add_sus_map(i), i)
+        })
+        .map(|_| self)
+    }
+}
+
+impl Config {
+    /// Check every entry without touching the kernel, returning the problems found.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut check_exists = |what: &str, path: &str| {
+            if !Path::new(path).exists() {
+                problems.push(format!("{what}: path does not exist: {path}"));
+            }
+        };
+
+        if !self.cmdline_or_bootconfig.is_empty() {
+            check_exists("cmdline_or_bootconfig", &self.cmdline_or_bootconfig);
+        }
+        for i in &self.sus_path {
+            check_exists("sus_path", &i.path);
+        }
+        for i in &self.sus_kstat {
+            check_exists("sus_kstat", &i.path);
+        }
+        for i in &self.open_redirect {
+            check_exists("open_redirect", &i.target_path);
+            check_exists("open_redirect", &i.redirected_path);
+        }
+        for i in &self.sus_map {
+            check_exists("sus_map", i);
+        }
+        for i in &self.sus_kstat {
+            if matches!(i.spoof_type, enums::SusKstatType::Statically) && i.statically.is_none() {
+                problems.push(format!(
+                    "sus_kstat: statically values undefined for {}",
+                    i.path
+                ));
+            }
+        }
+
+        problems
+    }
+
+    /// Apply every section in order, returning the result of each step.
+    pub fn apply_all(&self) -> Vec<(&'static str, Result<()>)> {
+        APPLY_ORDER
+            .iter()
+            .map(|(name, step)| {
+                log::info!("susfs apply: {name}");
+                (*name, step(self).map(|_| ()))
+            })
+            .collect()
     }
 }
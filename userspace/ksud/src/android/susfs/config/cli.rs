@@ -5,7 +5,13 @@ use clap::{Subcommand, ValueEnum};
 use num_enum::TryFromPrimitive;
 use serde::Serialize;
 
-use crate::android::susfs::{config::model::Config, enums::UidScheme};
+use crate::{
+    android::{
+        config_lock::ConfigLock,
+        susfs::{config::model::Config, enums::UidScheme},
+    },
+    defs::SUSFS_CONFIG,
+};
 
 #[derive(Debug, Subcommand)]
 pub enum ConfigCommand {
@@ -42,6 +48,12 @@ pub enum ConfigCommand {
         #[command(subcommand)]
         command: ToggleConfigCommand,
     },
+    /// Snapshot files replaced by modules before mounting and spoof their kstat afterwards.
+    #[command(name = "auto_kstat_snapshot")]
+    AutoKstatSnapshot {
+        #[command(subcommand)]
+        command: ToggleConfigCommand,
+    },
     Uname {
         #[command(subcommand)]
         command: UnameConfigCommand,
@@ -153,6 +165,7 @@ enum BooleanField {
     AvcLogSpoofing,
     Logging,
     HideSusMntsForNonSuProcs,
+    AutoKstatSnapshot,
 }
 
 pub fn run(command: ConfigCommand) -> Result<()> {
@@ -171,6 +184,7 @@ pub fn run(command: ConfigCommand) -> Result<()> {
                 Some(path) => Config::read_from(path)?,
                 None => Config::default(),
             };
+            let _lock = ConfigLock::acquire(SUSFS_CONFIG)?;
             config.save()
         }
         ConfigCommand::CmdlineOrBootconfig { command } => run_string(command),
@@ -181,6 +195,9 @@ pub fn run(command: ConfigCommand) -> Result<()> {
         ConfigCommand::HideSusMntsForNonSuProcs { command } => {
             run_boolean(BooleanField::HideSusMntsForNonSuProcs, command)
         }
+        ConfigCommand::AutoKstatSnapshot { command } => {
+            run_boolean(BooleanField::AutoKstatSnapshot, command)
+        }
         ConfigCommand::Uname { command } => run_uname(command),
         ConfigCommand::SusPath { command } => run_sus_path(command),
         ConfigCommand::SusKstat { command } => run_sus_kstat(command),
@@ -190,6 +207,7 @@ pub fn run(command: ConfigCommand) -> Result<()> {
 }
 
 fn update_config(action: impl FnOnce(&mut Config) -> Result<()>) -> Result<()> {
+    let _lock = ConfigLock::acquire(SUSFS_CONFIG)?;
     let mut config = Config::read_or_default();
     action(&mut config)?;
     config.save()
@@ -207,6 +225,7 @@ fn run_boolean(field: BooleanField, command: ToggleConfigCommand) -> Result<()>
             BooleanField::AvcLogSpoofing => config.avc_log_spoofing,
             BooleanField::Logging => config.logging,
             BooleanField::HideSusMntsForNonSuProcs => config.hide_sus_mnts_for_non_su_procs,
+            BooleanField::AutoKstatSnapshot => config.auto_kstat_snapshot,
         };
         return print_json(&value);
     }
@@ -223,6 +242,9 @@ fn run_boolean(field: BooleanField, command: ToggleConfigCommand) -> Result<()>
             BooleanField::HideSusMntsForNonSuProcs => {
                 config.set_hide_sus_mnts_for_non_su_procs(enabled);
             }
+            BooleanField::AutoKstatSnapshot => {
+                config.set_auto_kstat_snapshot(enabled);
+            }
         }
         Ok(())
     })
@@ -3,3 +3,4 @@ pub mod cli;
 pub mod file_ops;
 pub mod model;
 pub mod set_ops;
+pub mod verify_ops;
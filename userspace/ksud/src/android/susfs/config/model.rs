@@ -14,6 +14,7 @@ pub(super) struct VersionProbe {
 }
 
 #[derive(Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub(super) version: u8,
     #[serde(default = "default_enabled")]
@@ -27,6 +28,8 @@ pub struct Config {
     pub(super) sus_kstat: HashSet<SusKstatItem>,
     pub(super) open_redirect: HashSet<OpenRedirectItem>,
     pub(super) sus_map: HashSet<String>,
+    #[serde(default)]
+    pub(super) auto_kstat_snapshot: bool,
 }
 
 impl Default for Config {
@@ -46,6 +49,7 @@ impl Default for Config {
             sus_kstat: HashSet::new(),
             open_redirect: HashSet::new(),
             sus_map: HashSet::new(),
+            auto_kstat_snapshot: false,
         }
     }
 }
@@ -54,6 +58,10 @@ impl Config {
     pub fn is_enabled(&self) -> bool {
         self.enabled
     }
+
+    pub fn is_auto_kstat_snapshot(&self) -> bool {
+        self.enabled && self.auto_kstat_snapshot
+    }
 }
 
 const fn default_enabled() -> bool {
@@ -34,6 +34,11 @@ impl Config {
         self
     }
 
+    pub fn set_auto_kstat_snapshot(&mut self, enabled: bool) -> &mut Self {
+        self.auto_kstat_snapshot = enabled;
+        self
+    }
+
     pub fn set_uname(&mut self, release: &str, version: &str) -> Result<&mut Self> {
         self.uname.version = version.to_string();
         self.uname.release = release.to_string();
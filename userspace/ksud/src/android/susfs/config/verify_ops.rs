@@ -0,0 +1,175 @@
+use anyhow::{Result, bail};
+use serde::Serialize;
+
+use crate::android::{
+    ksucalls::{self, KsuCallErrorKind, SusfsState},
+    susfs::{config::model::Config, version},
+};
+
+#[derive(Serialize)]
+pub struct Discrepancy {
+    pub item: &'static str,
+    pub expected: String,
+    pub kernel: String,
+}
+
+#[derive(Serialize)]
+pub struct Verification {
+    /// Whether the persisted configuration is applied at boot at all
+    pub config_enabled: bool,
+    pub kernel: SusfsState,
+    /// Entries or toggles the kernel lacks compared to the configuration
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+impl Verification {
+    pub fn is_consistent(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+}
+
+/// The kernel holds fewer entries than the configuration registers.
+///
+/// Modules and manual commands may register more, so only missing entries are reported.
+fn check_count(
+    discrepancies: &mut Vec<Discrepancy>,
+    item: &'static str,
+    expected: usize,
+    kernel: u32,
+) {
+    if (kernel as usize) < expected {
+        discrepancies.push(Discrepancy {
+            item,
+            expected: format!("at least {expected}"),
+            kernel: kernel.to_string(),
+        });
+    }
+}
+
+fn check_toggle(
+    discrepancies: &mut Vec<Discrepancy>,
+    item: &'static str,
+    expected: bool,
+    kernel: bool,
+) {
+    if expected != kernel {
+        discrepancies.push(Discrepancy {
+            item,
+            expected: expected.to_string(),
+            kernel: kernel.to_string(),
+        });
+    }
+}
+
+impl Config {
+    /// Query the kernel SuSFS state and compare it with this configuration
+    pub fn verify(&self) -> Result<Verification> {
+        let kernel = match ksucalls::get_susfs_state() {
+            Ok(state) => state,
+            Err(e) if e.kind() == KsuCallErrorKind::Unsupported => bail!(
+                "kernel cannot report its SuSFS state, verification is not possible on this kernel"
+            ),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut discrepancies = Vec::new();
+        if self.is_enabled() {
+            // the boot path skips loop entries on kernels that predate them
+            let loop_supported = version::kernel_supports("add_sus_path_loop");
+            let loops = self.sus_path.iter().filter(|i| i.is_loop).count();
+            check_count(
+                &mut discrepancies,
+                "sus_path",
+                self.sus_path.len() - loops,
+                kernel.sus_path,
+            );
+            if loop_supported {
+                check_count(
+                    &mut discrepancies,
+                    "sus_path_loop",
+                    loops,
+                    kernel.sus_path_loop,
+                );
+            }
+            check_count(
+                &mut discrepancies,
+                "sus_kstat",
+                self.sus_kstat.len(),
+                kernel.sus_kstat,
+            );
+            check_count(
+                &mut discrepancies,
+                "open_redirect",
+                self.open_redirect.len(),
+                kernel.open_redirect,
+            );
+            if version::kernel_supports("add_sus_map") {
+                check_count(
+                    &mut discrepancies,
+                    "sus_map",
+                    self.sus_map.len(),
+                    kernel.sus_map,
+                );
+            }
+            check_toggle(
+                &mut discrepancies,
+                "logging",
+                self.logging,
+                kernel.log_enabled,
+            );
+            if version::kernel_supports("hide_sus_mnts_for_non_su_procs") {
+                check_toggle(
+                    &mut discrepancies,
+                    "hide_sus_mnts_for_non_su_procs",
+                    self.hide_sus_mnts_for_non_su_procs,
+                    kernel.hide_sus_mnts_for_non_su_procs,
+                );
+            }
+            if version::kernel_supports("enable_avc_log_spoofing") {
+                check_toggle(
+                    &mut discrepancies,
+                    "avc_log_spoofing",
+                    self.avc_log_spoofing,
+                    kernel.avc_log_spoofing,
+                );
+            }
+        }
+
+        Ok(Verification {
+            config_enabled: self.is_enabled(),
+            kernel,
+            discrepancies,
+        })
+    }
+}
+
+/// Print `verification` for `ksud susfs verify`
+pub fn print(verification: &Verification) {
+    let kernel = &verification.kernel;
+    println!(
+        "kernel: {} sus_path, {} sus_path_loop, {} sus_mount, {} sus_kstat, {} open_redirect, {} sus_map",
+        kernel.sus_path,
+        kernel.sus_path_loop,
+        kernel.sus_mount,
+        kernel.sus_kstat,
+        kernel.open_redirect,
+        kernel.sus_map
+    );
+    println!(
+        "toggles: logging {}, hide_sus_mnts_for_non_su_procs {}, avc_log_spoofing {}",
+        kernel.log_enabled, kernel.hide_sus_mnts_for_non_su_procs, kernel.avc_log_spoofing
+    );
+    if !verification.config_enabled {
+        println!("persisted configuration is disabled, nothing to compare");
+        return;
+    }
+    if verification.is_consistent() {
+        println!("kernel matches the persisted configuration");
+    }
+    for d in &verification.discrepancies {
+        println!(
+            "MISMATCH {}: expected {}, kernel has {}",
+            d.item, d.expected, d.kernel
+        );
+    }
+}
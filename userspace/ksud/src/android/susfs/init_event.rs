@@ -5,7 +5,10 @@ use std::{
     os::fd::AsRawFd,
 };
 
-use crate::android::{susfs::config::model::Config, utils::daemonize};
+use crate::android::{
+    susfs::{auto_hide, config::model::Config, kstat_snapshot},
+    utils::daemonize,
+};
 
 fn is_fuse_mounted() -> bool {
     fs::metadata("/sdcard/Android").is_ok()
@@ -78,14 +81,73 @@ pub fn on_boot_completed() {
     handle_result(config.apply_open_redirect(), "open_redirect");
     handle_result(config.apply_sus_path(), "sus_path and sus_path_loop");
 
+    // confirm the hiding took, kernels that can't report their state are skipped quietly
+    match config.verify() {
+        Ok(verification) => {
+            for d in &verification.discrepancies {
+                log::warn!(
+                    "SUSFS {} expected {}, kernel has {}",
+                    d.item,
+                    d.expected,
+                    d.kernel
+                );
+            }
+        }
+        Err(e) => log::debug!("SUSFS verify skipped: {e}"),
+    }
+
     log::info!("SUSFS finished");
 }
 
+/// Snapshot files replaced by modules, called right before modules are mounted.
+pub fn on_pre_mount() {
+    let config = Config::read_or_default();
+    if !config.is_auto_kstat_snapshot() {
+        return;
+    }
+
+    handle_result(
+        kstat_snapshot::module_replaced_files().and_then(|files| kstat_snapshot::snapshot(&files)),
+        "snapshot kstat of module replaced files",
+    );
+}
+
+/// Spoof the snapshotted files, called right after modules are mounted.
+pub fn on_post_mount() {
+    let config = Config::read_or_default();
+    if !config.is_auto_kstat_snapshot() {
+        return;
+    }
+
+    handle_result(kstat_snapshot::apply(), "apply kstat snapshot");
+}
+
+/// Re-apply the per-user configuration after switching to Android user `user_id`.
+pub fn on_user_switch(user_id: u32) -> Result<()> {
+    let config = Config::read_or_default();
+    if !config.is_enabled() {
+        log::info!("SUSFS persisted configuration is disabled; skipping user {user_id} apply");
+        return Ok(());
+    }
+
+    let paths = config.apply_sus_path_loop_for_user(user_id)?;
+    for path in &paths {
+        log::info!("reapplied sus_path_loop {path} for user {user_id}");
+    }
+    log::info!(
+        "reapplied {} sus_path_loop entries for user {user_id}",
+        paths.len()
+    );
+    Ok(())
+}
+
 pub fn on_post_fs_data() {
     let config = Config::read_or_default();
 
     log::info!("on_post_fs_data triggered!");
 
+    auto_hide::register_default_sus_paths();
+
     if !config.is_enabled() {
         log::info!("SUSFS persisted configuration is disabled; skipping post-fs-data apply");
         return;
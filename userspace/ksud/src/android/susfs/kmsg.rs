@@ -0,0 +1,123 @@
+//! Read SuSFS messages from the kernel ring buffer.
+
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::{self, ErrorKind, Read},
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+};
+
+use anyhow::{Result, bail};
+
+const KMSG_PATH: &str = "/dev/kmsg";
+const SUSFS_LOG_PREFIX: &str = "susfs:";
+const READ_BUF_SIZE: usize = 8192;
+
+struct Record {
+    ts_usec: u64,
+    message: String,
+}
+
+impl Record {
+    /// Parse a `/dev/kmsg` record: `prio,seq,ts_usec,flags;message\n KEY=value...`
+    fn parse(raw: &[u8]) -> Option<Self> {
+        let raw = String::from_utf8_lossy(raw);
+        let (header, body) = raw.split_once(';')?;
+        let ts_usec = header.split(',').nth(2)?.parse().ok()?;
+        // continuation lines carry dictionary entries, not part of the message
+        let message = body.lines().next().unwrap_or_default().to_string();
+        Some(Self { ts_usec, message })
+    }
+}
+
+impl std::fmt::Display for Record {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{:5}.{:06}] {}",
+            self.ts_usec / 1_000_000,
+            self.ts_usec % 1_000_000,
+            self.message
+        )
+    }
+}
+
+fn open_kmsg() -> Result<File> {
+    match OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(KMSG_PATH)
+    {
+        Ok(file) => Ok(file),
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => bail!(
+            "permission denied opening {KMSG_PATH}: run this command as root, and if it still fails \
+             SELinux is blocking access, use `su -c ksud susfs log` from a root shell or read `dmesg` directly"
+        ),
+        Err(e) => bail!("failed to open {KMSG_PATH}: {e}"),
+    }
+}
+
+/// Read the next SuSFS record, or `None` once the buffer is drained.
+fn next_record(kmsg: &mut File, buf: &mut [u8]) -> Result<Option<Record>> {
+    loop {
+        match kmsg.read(buf) {
+            Ok(0) => return Ok(None),
+            Ok(n) => {
+                if let Some(record) = Record::parse(&buf[..n])
+                    && record.message.contains(SUSFS_LOG_PREFIX)
+                {
+                    return Ok(Some(record));
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+            // records were overwritten before we read them, the next read continues
+            Err(e) if e.raw_os_error() == Some(libc::EPIPE) => {}
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => bail!("failed to read {KMSG_PATH}: {e}"),
+        }
+    }
+}
+
+fn wait_readable(kmsg: &File) -> Result<()> {
+    let mut fds = libc::pollfd {
+        fd: kmsg.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    if unsafe { libc::poll(&raw mut fds, 1, -1) } < 0 {
+        let err = io::Error::last_os_error();
+        if err.kind() != ErrorKind::Interrupted {
+            bail!("failed to poll {KMSG_PATH}: {err}");
+        }
+    }
+    Ok(())
+}
+
+/// Print SuSFS kernel log lines, keeping only the last `lines` if given.
+///
+/// With `follow`, keep streaming new entries after the existing ones are printed.
+pub fn tail(lines: Option<usize>, follow: bool) -> Result<()> {
+    let mut kmsg = open_kmsg()?;
+    let mut buf = vec![0u8; READ_BUF_SIZE];
+
+    let mut backlog = VecDeque::new();
+    while let Some(record) = next_record(&mut kmsg, &mut buf)? {
+        if lines.is_some_and(|max| backlog.len() >= max) {
+            backlog.pop_front();
+        }
+        if lines != Some(0) {
+            backlog.push_back(record);
+        }
+    }
+    for record in backlog {
+        println!("{record}");
+    }
+
+    while follow {
+        wait_readable(&kmsg)?;
+        while let Some(record) = next_record(&mut kmsg, &mut buf)? {
+            println!("{record}");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,89 @@
+//! Snapshot stock kstat values before module mounts and spoof them after
+
+use std::{collections::BTreeMap, fs, io::Write, path::Path};
+
+use anyhow::{Context, Result, anyhow};
+use tempfile::NamedTempFile;
+
+use crate::{
+    android::{
+        config_lock::ConfigLock,
+        module::{ModuleType, conflict, foreach_module},
+        susfs::api::prelude::{self as api, KstatValues},
+    },
+    defs,
+};
+
+type Snapshot = BTreeMap<String, KstatValues>;
+
+fn read_snapshot() -> Result<Snapshot> {
+    let path = Path::new(defs::SUSFS_KSTAT_SNAPSHOT);
+    if !path.exists() {
+        return Ok(Snapshot::new());
+    }
+    let content = fs::read_to_string(path)?;
+    serde_json::from_str(&content)
+        .with_context(|| format!("invalid {}", defs::SUSFS_KSTAT_SNAPSHOT))
+}
+
+/// Write the snapshot through a renamed temp file, an interrupted write keeps the previous one
+fn write_snapshot(snapshot: &Snapshot) -> Result<()> {
+    let path = Path::new(defs::SUSFS_KSTAT_SNAPSHOT);
+    let content = serde_json::to_string_pretty(snapshot)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut temp_file = NamedTempFile::new_in(dir)?;
+    temp_file.write_all(content.as_bytes())?;
+    temp_file.as_file().sync_all()?;
+    temp_file
+        .persist(path)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(())
+}
+
+/// Record the current metadata of `paths`, they must not be mounted over yet.
+pub fn snapshot(paths: &[String]) -> Result<()> {
+    let _lock = ConfigLock::acquire(defs::SUSFS_KSTAT_SNAPSHOT)?;
+    let mut snapshot = read_snapshot()?;
+    for path in paths {
+        let md = fs::metadata(path).with_context(|| format!("Failed to stat {path}"))?;
+        snapshot.insert(path.clone(), KstatValues::from_metadata(&md));
+        log::info!("kstat snapshot: recorded {path}");
+    }
+    write_snapshot(&snapshot)
+}
+
+/// Spoof every snapshotted path with its recorded values, using the current inode as target.
+pub fn apply() -> Result<()> {
+    let snapshot = read_snapshot()?;
+    let mut errors = Vec::new();
+
+    for (path, original) in &snapshot {
+        match api::add_sus_kstat_from_snapshot(path, original) {
+            Ok(()) => log::info!("kstat snapshot: applied {path}"),
+            Err(e) => errors.push(format!("{path}: {e}")),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(errors.join("\n")))
+    }
+}
+
+/// Collect the existing files replaced by active, mounted modules, on /system and the
+/// partitions modules can overlay
+pub fn module_replaced_files() -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    foreach_module(ModuleType::Active, |module| {
+        files.extend(
+            conflict::mounted_files(module)
+                .into_iter()
+                .filter(|target| Path::new(target).is_file()),
+        );
+        Ok(())
+    })?;
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
@@ -1,8 +1,14 @@
 pub mod api;
+pub mod audit;
+pub mod auto_hide;
 pub mod cli;
 pub mod config;
 pub mod enums;
 pub mod init_event;
+pub mod kmsg;
+pub mod kstat_snapshot;
 mod macros;
+pub mod selinux;
 pub mod slot_info;
 mod utils;
+pub mod version;
@@ -0,0 +1,83 @@
+//! Explain SELinux denials of the SuSFS control syscall
+//!
+//! When ksud runs outside the KernelSU domain the reboot syscall is rejected with EPERM/EACCES,
+//! which otherwise looks exactly like a kernel without SuSFS.
+
+use std::{fs, sync::Once};
+
+use libc::{EACCES, EPERM};
+use serde::Serialize;
+
+/// The context ksud must run in to talk to SuSFS
+pub const EXPECTED_CONTEXT: &str = "u:r:ksu:s0";
+
+const CURRENT_CONTEXT_PATH: &str = "/proc/self/attr/current";
+const ENFORCE_PATH: &str = "/sys/fs/selinux/enforce";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SelinuxStatus {
+    /// Context of this process, None if it couldn't be read
+    pub context: Option<String>,
+    /// Whether SELinux is enforcing, None if it couldn't be read
+    pub enforcing: Option<bool>,
+    pub expected_context: &'static str,
+}
+
+impl SelinuxStatus {
+    pub fn read() -> Self {
+        let context = fs::read_to_string(CURRENT_CONTEXT_PATH)
+            .ok()
+            .map(|s| s.trim_end_matches(['\0', '\n']).to_string());
+        let enforcing = fs::read_to_string(ENFORCE_PATH)
+            .ok()
+            .map(|s| s.trim() == "1");
+        Self {
+            context,
+            enforcing,
+            expected_context: EXPECTED_CONTEXT,
+        }
+    }
+
+    /// Whether this process runs in the KernelSU domain, None if unknown
+    pub fn in_expected_domain(&self) -> Option<bool> {
+        self.context.as_deref().map(|c| c == EXPECTED_CONTEXT)
+    }
+
+    /// A hint for an EPERM/EACCES from the control syscall
+    pub fn hint(&self) -> String {
+        let context = self.context.as_deref().unwrap_or("unknown");
+        let enforcing = match self.enforcing {
+            Some(true) => "enforcing",
+            Some(false) => "permissive",
+            None => "unknown",
+        };
+        let mut hint = format!(
+            "the SuSFS control syscall was denied, this is likely SELinux rather than a missing SuSFS patch \
+             (context: {context}, expected: {EXPECTED_CONTEXT}, selinux: {enforcing})"
+        );
+        if self.in_expected_domain() == Some(false) {
+            hint.push_str(
+                "; ksud is not running in the KernelSU domain, run it through su or check the ksud sepolicy rules",
+            );
+        } else {
+            hint.push_str("; check the ksud sepolicy rules and the kernel log for avc denials");
+        }
+        hint
+    }
+}
+
+/// Whether a failed syscall's errno is a permission denial
+pub fn is_denial(errno: &std::io::Error) -> bool {
+    matches!(errno.raw_os_error(), Some(EPERM | EACCES))
+}
+
+static HINT_ONCE: Once = Once::new();
+
+/// Print the denial hint to stderr and the log, once per process
+pub fn explain_denial() {
+    HINT_ONCE.call_once(|| {
+        let hint = SelinuxStatus::read().hint();
+        eprintln!("HINT: {hint}");
+        log::warn!("{hint}");
+    });
+}
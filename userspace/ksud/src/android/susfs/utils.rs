@@ -1,3 +1,16 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    io::{self, Read, Seek},
+    os::{fd::AsRawFd, unix::fs::MetadataExt},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, bail};
+
+const PACKAGES_LIST: &str = "/data/system/packages.list";
+
 /// Write a &str to C-style char* with length cutdown.
 pub fn str_to_c_array<const N: usize>(s: &str, array: &mut [u8; N]) {
     let bytes = s.as_bytes();
@@ -12,3 +25,199 @@ pub fn c_array_to_string<const N: usize>(array: &[u8; N]) -> String {
     let bytes = &array[..len].to_vec();
     String::from_utf8(bytes.to_vec()).unwrap_or_else(|_| "<invalid>".to_string())
 }
+
+/// Get the metadata of `path`, following symlinks only if `follow_symlink` is set.
+pub fn fetch_metadata(path: &str, follow_symlink: bool) -> std::io::Result<fs::Metadata> {
+    if follow_symlink {
+        fs::metadata(path)
+    } else {
+        fs::symlink_metadata(path)
+    }
+}
+
+/// Find the distinct backing paths in /proc/<pid>/maps whose basename is `name`.
+pub fn find_mapped_library(name: &str, pid: i32) -> Result<Vec<String>> {
+    let maps_path = format!("/proc/{pid}/maps");
+    let maps =
+        fs::read_to_string(&maps_path).with_context(|| format!("Failed to read {maps_path}"))?;
+
+    let paths: BTreeSet<&str> = maps
+        .lines()
+        .filter_map(|line| line.splitn(6, ' ').nth(5).map(str::trim_start))
+        .filter(|path| path.starts_with('/'))
+        .filter(|path| Path::new(path).file_name().is_some_and(|f| f == name))
+        .collect();
+
+    Ok(paths.into_iter().map(str::to_string).collect())
+}
+
+/// Read a list of paths from a file, one per line, skipping blank lines and `#` comments.
+pub fn read_path_list(path: &Path) -> Result<Vec<String>> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// SELinux file types an untrusted_app domain can normally read.
+const APP_READABLE_FILE_TYPES: &[&str] = &["system_file", "vendor_file", "rootfs"];
+
+/// Describe why an untrusted_app process plausibly can't open `path` for reading.
+///
+/// Checks the file mode, search permission on every parent and the SELinux label when it can be read.
+pub fn app_readability_issues(path: &str) -> Result<Vec<String>> {
+    let path = fs::canonicalize(path).with_context(|| format!("Failed to resolve {path}"))?;
+    let metadata = fs::metadata(&path)?;
+    let mut issues = Vec::new();
+
+    if metadata.mode() & 0o004 == 0 {
+        issues.push(format!(
+            "{} is not world-readable (mode {:o}, owner {}:{})",
+            path.display(),
+            metadata.mode() & 0o7777,
+            metadata.uid(),
+            metadata.gid()
+        ));
+    }
+
+    for dir in path.ancestors().skip(1) {
+        if let Ok(dir_metadata) = fs::metadata(dir)
+            && dir_metadata.mode() & 0o001 == 0
+        {
+            issues.push(format!(
+                "{} is not searchable by others (mode {:o}, owner {}:{})",
+                dir.display(),
+                dir_metadata.mode() & 0o7777,
+                dir_metadata.uid(),
+                dir_metadata.gid()
+            ));
+        }
+    }
+
+    if let Ok(label) = crate::android::restorecon::lgetfilecon(&path) {
+        let label = label.trim_end_matches('\0');
+        let file_type = label.split(':').nth(2).unwrap_or_default();
+        if !APP_READABLE_FILE_TYPES.contains(&file_type) {
+            issues.push(format!(
+                "{} is labeled {label}, which untrusted_app usually can't read (expected e.g. u:object_r:system_file:s0)",
+                path.display()
+            ));
+        }
+    }
+
+    Ok(issues)
+}
+
+/// Whether `mountpoint` appears as a mount point in the given mountinfo content.
+fn has_mountpoint(mountinfo: &str, mountpoint: &str) -> bool {
+    mountinfo
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .any(|mnt| mnt.replace("\\040", " ") == mountpoint)
+}
+
+/// Wait until `mountpoint` is mounted, watching /proc/self/mountinfo for changes.
+///
+/// Returns `false` if it did not appear within `timeout`.
+pub fn wait_for_mount(mountpoint: &str, timeout: Duration) -> Result<bool> {
+    let mut file = fs::File::open("/proc/self/mountinfo")?;
+    let mut pfd = libc::pollfd {
+        fd: file.as_raw_fd(),
+        events: libc::POLLPRI | libc::POLLERR,
+        revents: 0,
+    };
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let mut mountinfo = String::new();
+        file.seek(io::SeekFrom::Start(0))?;
+        file.read_to_string(&mut mountinfo)?;
+        if has_mountpoint(&mountinfo, mountpoint) {
+            return Ok(true);
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(false);
+        }
+        let timeout_ms = i32::try_from(remaining.as_millis()).unwrap_or(i32::MAX);
+        if unsafe { libc::poll(&raw mut pfd, 1, timeout_ms) } < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                bail!("Failed to poll /proc/self/mountinfo: {err}");
+            }
+        }
+    }
+}
+
+/// Read /data/system/packages.list into package name -> data dir.
+pub fn read_packages_list() -> Result<HashMap<String, String>> {
+    let content = fs::read_to_string(PACKAGES_LIST)
+        .with_context(|| format!("Failed to read {PACKAGES_LIST}"))?;
+    Ok(content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = fields.next()?;
+            let data_dir = fields.nth(2)?;
+            Some((name.to_string(), data_dir.to_string()))
+        })
+        .collect())
+}
+
+/// Existing data directories of `package`: its private data dir and its external storage dirs.
+///
+/// Falls back to /data/data/<package> if the package is not in `packages`.
+pub fn package_data_dirs(packages: &HashMap<String, String>, package: &str) -> Vec<String> {
+    let data_dir = packages
+        .get(package)
+        .cloned()
+        .unwrap_or_else(|| format!("/data/data/{package}"));
+    let external = ["data", "obb", "media"]
+        .iter()
+        .map(|kind| format!("/storage/emulated/0/Android/{kind}/{package}"));
+
+    std::iter::once(data_dir)
+        .chain(external)
+        .filter(|dir| Path::new(dir).exists())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_follow_reads_a_dangling_symlink_itself() {
+        let dir = tempfile::tempdir().unwrap();
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(dir.path().join("missing"), &link).unwrap();
+        let link = link.to_str().unwrap();
+
+        let err = fetch_metadata(link, true).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let metadata = fetch_metadata(link, false).unwrap();
+        assert!(metadata.file_type().is_symlink());
+        assert_eq!(metadata.ino(), fs::symlink_metadata(link).unwrap().ino());
+    }
+
+    #[test]
+    fn follow_reads_the_symlink_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        fs::write(&target, "spoofed").unwrap();
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        let link = link.to_str().unwrap();
+
+        let followed = fetch_metadata(link, true).unwrap();
+        assert_eq!(followed.ino(), fs::metadata(&target).unwrap().ino());
+        let own = fetch_metadata(link, false).unwrap();
+        assert_ne!(own.ino(), followed.ino());
+    }
+}
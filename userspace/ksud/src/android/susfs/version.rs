@@ -0,0 +1,130 @@
+//! SuSFS kernel version compatibility checks
+
+use std::{
+    fmt::{self, Display},
+    sync::OnceLock,
+};
+
+use crate::android::susfs::api::prelude as api;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SusfsVersion {
+    major: u32,
+    minor: u32,
+    patch: u32,
+}
+
+impl SusfsVersion {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parse a version string like `v2.0.0`, ignoring any trailing suffix.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim().trim_start_matches('v');
+        let mut parts = s.splitn(3, '.').map(|part| {
+            part.chars()
+                .take_while(char::is_ascii_digit)
+                .collect::<String>()
+                .parse::<u32>()
+                .ok()
+        });
+        let major = parts.next()??;
+        let minor = parts.next().flatten().unwrap_or(0);
+        let patch = parts.next().flatten().unwrap_or(0);
+        Some(Self::new(major, minor, patch))
+    }
+}
+
+impl Display for SusfsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Oldest SuSFS version whose ABI matches ksud.
+pub const MIN_SUPPORTED: SusfsVersion = SusfsVersion::new(2, 0, 0);
+/// First SuSFS version that ksud is not known to work with.
+pub const MAX_SUPPORTED_EXCLUSIVE: SusfsVersion = SusfsVersion::new(2, 1, 0);
+
+/// Commands that only exist from a given SuSFS version on.
+const COMMAND_SINCE: &[(&str, SusfsVersion)] = &[
+    ("add_sus_path_loop", SusfsVersion::new(1, 5, 8)),
+    ("enable_avc_log_spoofing", SusfsVersion::new(1, 5, 10)),
+    ("add_sus_map", SusfsVersion::new(1, 5, 12)),
+    ("hide_sus_mnts_for_non_su_procs", SusfsVersion::new(2, 0, 0)),
+];
+
+pub fn is_supported(version: SusfsVersion) -> bool {
+    (MIN_SUPPORTED..MAX_SUPPORTED_EXCLUSIVE).contains(&version)
+}
+
+/// Commands known to be missing in the given version.
+pub fn missing_commands(version: SusfsVersion) -> Vec<&'static str> {
+    COMMAND_SINCE
+        .iter()
+        .filter(|(_, since)| version < *since)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// The running kernel's SuSFS version, queried once per process.
+fn kernel_version() -> Option<SusfsVersion> {
+    static KERNEL_VERSION: OnceLock<Option<SusfsVersion>> = OnceLock::new();
+    *KERNEL_VERSION.get_or_init(|| SusfsVersion::parse(&api::version().ok()?))
+}
+
+/// Whether the running kernel can have `command`, judged by its SuSFS version.
+///
+/// Commands not in the table, and kernels whose version can't be determined, are assumed to
+/// support it, so that unusual builds are still tried rather than silently skipped.
+pub fn kernel_supports(command: &str) -> bool {
+    let Some(version) = kernel_version() else {
+        return true;
+    };
+    COMMAND_SINCE
+        .iter()
+        .find(|(name, _)| *name == command)
+        .is_none_or(|(_, since)| version >= *since)
+}
+
+/// Describe why the running kernel's SuSFS is outside the supported range, if it is.
+///
+/// Returns `None` when the version is supported or SuSFS cannot be queried at all.
+pub fn mismatch_warning() -> Option<String> {
+    let raw = api::version().ok()?;
+    let Some(version) = SusfsVersion::parse(&raw) else {
+        return Some(format!(
+            "unable to parse kernel SuSFS version '{raw}', ksud supports {MIN_SUPPORTED} up to (excluding) {MAX_SUPPORTED_EXCLUSIVE}"
+        ));
+    };
+    if is_supported(version) {
+        return None;
+    }
+
+    let mut warning = format!(
+        "kernel SuSFS {version} is outside the range supported by ksud ({MIN_SUPPORTED} up to (excluding) {MAX_SUPPORTED_EXCLUSIVE}), commands may silently do nothing"
+    );
+    let missing = missing_commands(version);
+    if !missing.is_empty() {
+        warning.push_str(&format!(
+            "\nnot available in {version}: {}",
+            missing.join(", ")
+        ));
+    }
+    Some(warning)
+}
+
+/// Print the version mismatch warning to stderr, if any.
+pub fn warn_on_mismatch() {
+    if let Some(warning) = mismatch_warning() {
+        for line in warning.lines() {
+            eprintln!("WARNING: {line}");
+        }
+        log::warn!("{warning}");
+    }
+}
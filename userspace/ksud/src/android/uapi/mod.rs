@@ -1,3 +1,38 @@
 #![allow(nonstandard_style, unused, unsafe_op_in_unsafe_fn)]
 #![allow(clippy::all, clippy::pedantic, clippy::nursery)]
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+
+// Layout of the structs as the 64-bit kernel sees them. A 32-bit ksud talks to the same ioctl
+// handler through compat_ioctl, so every field is fixed-width and these hold on all targets.
+const _: () = {
+    use std::mem::{offset_of, size_of};
+
+    assert!(size_of::<root_profile>() == 248);
+    assert!(offset_of!(root_profile, capabilities) == 144);
+    assert!(offset_of!(root_profile, selinux_domain) == 168);
+    assert!(offset_of!(root_profile, flags) == 240);
+    assert!(size_of::<app_profile>() == 784);
+    assert!(offset_of!(app_profile, curr_uid) == 260);
+    assert!(offset_of!(app_profile, allow_su) == 264);
+
+    assert!(size_of::<ksu_get_info_cmd>() == 16);
+    assert!(size_of::<ksu_set_sepolicy_cmd>() == 16);
+    assert!(offset_of!(ksu_set_sepolicy_cmd, data) == 8);
+    assert!(size_of::<ksu_nuke_ext4_sysfs_cmd>() == 8);
+    assert!(size_of::<ksu_manage_try_umount_cmd>() == 16);
+    assert!(offset_of!(ksu_manage_try_umount_cmd, flags) == 8);
+    assert!(offset_of!(ksu_manage_try_umount_cmd, mode) == 12);
+    assert!(size_of::<ksu_umount_stat>() == 264);
+    assert!(size_of::<ksu_umount_add_ext>() == 24);
+    assert!(offset_of!(ksu_umount_add_ext, user_id) == 16);
+    assert!(size_of::<ksu_umount_batch_entry>() == 32);
+    assert!(offset_of!(ksu_umount_batch_entry, status) == 24);
+    assert!(size_of::<ksu_dynamic_manager_cmd>() == 72);
+    assert!(offset_of!(ksu_dynamic_manager_cmd, size) == 4);
+    assert!(size_of::<ksu_manager_entry>() == 5);
+    assert!(size_of::<ksu_get_managers_cmd>() == 4);
+    assert!(size_of::<ksu_set_uid_umount_cmd>() == 264);
+    assert!(size_of::<ksu_get_uid_umount_cmd>() == 8);
+    assert!(size_of::<ksu_get_susfs_state_cmd>() == 28);
+    assert!(size_of::<ksu_get_capabilities_cmd>() == 8);
+};
@@ -1,93 +1,3054 @@
-use std::{collections::HashMap, fs, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::{self, DirBuilder},
+    io::{Read, Write},
+    os::{fd::AsRawFd, unix::fs::DirBuilderExt},
+    path::Path,
+    str::FromStr,
+    sync::OnceLock,
+};
 
-use anyhow::Result;
-use log::info;
-use serde::{Deserialize, Serialize};
+use anyhow::{Context, Result, anyhow, bail};
+use log::{debug, error, info, warn};
+use regex_lite::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de, de::DeserializeOwned};
+use tempfile::NamedTempFile;
 
 use crate::{
-    android::ksucalls,
-    defs::{self, MountInfo},
+    android::{
+        config_lock::ConfigLock,
+        ksucalls::{self, KsuCallError, KsuCallErrorKind, UidUmountMode},
+        susfs::api::prelude as susfs,
+        uapi,
+    },
+    defs,
 };
 
-#[derive(Serialize, Default, Deserialize)]
+const CONFIG_DIR_MODE: u32 = 0o700;
+
+/// Environment variable overriding the config path, e.g. in recovery where /data is elsewhere
+const CONFIG_PATH_ENV: &str = "KSUD_UMOUNT_CONFIG";
+
+static CONFIG_PATH: OnceLock<String> = OnceLock::new();
+
+/// Use `path` as the umount config for this process, must be called before the config is used
+pub fn set_config_path(path: String) {
+    if let Err(path) = CONFIG_PATH.set(path) {
+        warn!("umount config path is already set, ignoring {path}");
+    }
+}
+
+/// The umount config: set with `set_config_path`, else `KSUD_UMOUNT_CONFIG`, else the default
+fn config_path() -> &'static str {
+    CONFIG_PATH.get_or_init(|| {
+        std::env::var(CONFIG_PATH_ENV)
+            .ok()
+            .filter(|path| !path.is_empty())
+            .unwrap_or_else(|| defs::UMOUNT_CONFIG_PATH.to_string())
+    })
+}
+
+/// umount(2) flags, with the short name used in the config and the `MNT_*` name, in bit order
+const MNT_FLAG_NAMES: [(u32, &str, &str); 4] = [
+    (1, "force", "MNT_FORCE"),
+    (2, "detach", "MNT_DETACH"),
+    (4, "expire", "MNT_EXPIRE"),
+    (8, "nofollow", "UMOUNT_NOFOLLOW"),
+];
+
+/// umount(2) flags, accepted as a number or as names like `detach|force`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UmountFlags(pub u32);
+
+impl UmountFlags {
+    /// Names of the set flags, None if some bits have no name
+    fn names(self, mnt: bool) -> Option<Vec<&'static str>> {
+        let mut names = Vec::new();
+        let mut rest = self.0;
+        for (bit, name, mnt_name) in MNT_FLAG_NAMES {
+            if self.0 & bit != 0 {
+                names.push(if mnt { mnt_name } else { name });
+                rest &= !bit;
+            }
+        }
+        (rest == 0).then_some(names)
+    }
+
+    /// Decode into `MNT_*` names joined with `|`, unknown bits are kept as hex.
+    fn describe(self) -> String {
+        let Some(names) = self.names(true) else {
+            return format!("0x{:x}", self.0);
+        };
+        if names.is_empty() {
+            "none".to_string()
+        } else {
+            names.join("|")
+        }
+    }
+}
+
+impl FromStr for UmountFlags {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut flags = 0;
+        for token in s.split(['|', ',']).map(str::trim) {
+            if token.is_empty() || token.eq_ignore_ascii_case("none") {
+                continue;
+            }
+            if let Ok(value) = token.parse::<u32>() {
+                flags |= value;
+                continue;
+            }
+            if let Some(hex) = token.strip_prefix("0x")
+                && let Ok(value) = u32::from_str_radix(hex, 16)
+            {
+                flags |= value;
+                continue;
+            }
+            let Some((bit, _, _)) = MNT_FLAG_NAMES.iter().find(|(_, name, mnt_name)| {
+                token.eq_ignore_ascii_case(name) || token.eq_ignore_ascii_case(mnt_name)
+            }) else {
+                bail!(
+                    "unknown umount flag '{token}', expected one of force, detach, expire, nofollow"
+                );
+            };
+            flags |= bit;
+        }
+        Ok(Self(flags))
+    }
+}
+
+impl fmt::Display for UmountFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.names(false) {
+            Some(names) if names.is_empty() => write!(f, "none"),
+            Some(names) => write!(f, "{}", names.join("|")),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/// Written by name when every bit has one, so that configs stay readable
+impl Serialize for UmountFlags {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.names(false).is_some() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_u32(self.0)
+        }
+    }
+}
+
+/// Older configs store the bare number
+impl<'de> Deserialize<'de> for UmountFlags {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(u32),
+            Names(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(flags) => Ok(Self(flags)),
+            Raw::Names(names) => names.parse().map_err(de::Error::custom),
+        }
+    }
+}
+
+/// When an entry applies, predicates joined with `&&`: `non_root_only`, `no_susfs`, `user_id = N`.
+///
+/// `no_susfs` is decided when the entry is registered, the others are passed to the kernel and
+/// checked for every process.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Condition {
+    /// Skip processes whose uid is granted root
+    non_root_only: bool,
+    /// Skip the entry when SuSFS is available, its mount hiding covers it
+    no_susfs: bool,
+    /// Only processes of this Android user
+    user_id: Option<u32>,
+}
+
+impl Condition {
+    /// The `KSU_UMOUNT_COND_*` bits the kernel has to check
+    fn kernel_cond(self) -> u32 {
+        let mut cond = 0;
+        if self.non_root_only {
+            cond |= uapi::KSU_UMOUNT_COND_NON_ROOT_ONLY_RUST;
+        }
+        if self.user_id.is_some() {
+            cond |= uapi::KSU_UMOUNT_COND_USER_ID_RUST;
+        }
+        cond
+    }
+
+    /// Evaluate the predicates that can be decided now, the kernel checks the rest
+    fn holds_at_registration(self) -> bool {
+        !(self.no_susfs && susfs::detect())
+    }
+}
+
+impl FromStr for Condition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut condition = Self::default();
+        for predicate in s.split("&&").map(str::trim) {
+            match predicate {
+                "non_root_only" => condition.non_root_only = true,
+                "no_susfs" => condition.no_susfs = true,
+                _ => {
+                    let Some(user_id) = predicate
+                        .strip_prefix("user_id")
+                        .and_then(|rest| rest.trim_start().strip_prefix('='))
+                    else {
+                        bail!(
+                            "unknown condition {predicate:?}, expected non_root_only, no_susfs or user_id = N"
+                        );
+                    };
+                    let user_id = user_id
+                        .trim()
+                        .parse()
+                        .with_context(|| format!("invalid user id in {predicate:?}"))?;
+                    condition.user_id = Some(user_id);
+                }
+            }
+        }
+        Ok(condition)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut predicates = Vec::new();
+        if self.non_root_only {
+            predicates.push("non_root_only".to_string());
+        }
+        if self.no_susfs {
+            predicates.push("no_susfs".to_string());
+        }
+        if let Some(user_id) = self.user_id {
+            predicates.push(format!("user_id = {user_id}"));
+        }
+        write!(f, "{}", predicates.join(" && "))
+    }
+}
+
+impl Serialize for Condition {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Condition {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Current config schema version
+///
+/// - 0: `{"paths": {"<path>": flags}}`, no version field, unordered
+/// - 1: `{"version": 1, "paths": [{"path", "flags", "susfs"}]}`, ordered
+const CONFIG_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
 struct Config {
-    paths: HashMap<String, u32>,
+    #[serde(default)]
+    version: u32,
+    /// Entries in unmount order, children must come before their parents
+    #[serde(deserialize_with = "deserialize_paths")]
+    paths: Vec<Entry>,
+    /// Add new module mounts to the config at boot
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    sync_modules: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            paths: Vec::new(),
+            sync_modules: false,
+        }
+    }
+}
+
+impl Config {
+    fn position(&self, path: &str) -> Option<usize> {
+        self.paths.iter().position(|entry| entry.path == path)
+    }
+}
+
+/// A configured umount entry.
+#[derive(Serialize, Deserialize, Clone)]
+struct Entry {
+    path: String,
+    flags: UmountFlags,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    susfs: bool,
+    /// Generated by ksud rather than added by the user
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    builtin: bool,
+    /// Added by the module mount sync
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    auto: bool,
+    /// Only apply the entry when this holds, always if None
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    condition: Option<Condition>,
+    /// Why the entry exists, free text kept as is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    /// Id of the module whose installation added the entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    origin: Option<String>,
+    /// The flags were chosen from the mount type rather than given
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    flags_auto: bool,
+}
+
+/// Value of an entry in the old `{"path": flags}` map format.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LegacyEntry {
+    Flags(UmountFlags),
+    Detailed {
+        flags: UmountFlags,
+        #[serde(default)]
+        susfs: bool,
+    },
+}
+
+/// Read `paths` as an ordered array, or from the old map format keeping its file order.
+fn deserialize_paths<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Entry>, D::Error> {
+    struct PathsVisitor;
+
+    impl<'de> de::Visitor<'de> for PathsVisitor {
+        type Value = Vec<Entry>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "an array of umount entries or a map of path to flags")
+        }
+
+        fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut paths = Vec::new();
+            while let Some(entry) = seq.next_element()? {
+                paths.push(entry);
+            }
+            Ok(paths)
+        }
+
+        fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut paths = Vec::new();
+            while let Some((path, legacy)) = map.next_entry::<String, LegacyEntry>()? {
+                let (flags, susfs) = match legacy {
+                    LegacyEntry::Flags(flags) => (flags, false),
+                    LegacyEntry::Detailed { flags, susfs } => (flags, susfs),
+                };
+                paths.push(Entry {
+                    path,
+                    flags,
+                    susfs,
+                    builtin: false,
+                    auto: false,
+                    condition: None,
+                    comment: None,
+                    origin: None,
+                    flags_auto: false,
+                });
+            }
+            Ok(paths)
+        }
+    }
+
+    deserializer.deserialize_any(PathsVisitor)
+}
+
+#[derive(Serialize)]
+struct EntryInfo {
+    path: String,
+    flags: u32,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    susfs: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    builtin: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    auto: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    condition: Option<Condition>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    origin: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    flags_auto: bool,
+}
+
+/// Parse a config that may contain `//` and `/* */` comments and trailing commas.
+///
+/// Configs are always written back as strict JSON, so comments don't survive a write.
+fn parse_config<T: DeserializeOwned>(buf: &str) -> serde_json::Result<T> {
+    serde_json::from_str(&strip_trailing_commas(&strip_comments(buf)))
+}
+
+fn strip_comments(buf: &str) -> String {
+    let mut out = String::with_capacity(buf.len());
+    let mut chars = buf.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            out.push(c);
+            match c {
+                '\\' => out.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                out.push(c);
+            }
+            ('/', Some('/')) => while chars.next_if(|&c| c != '\n').is_some() {},
+            ('/', Some('*')) => {
+                chars.next();
+                let mut prev = ' ';
+                for c in chars.by_ref() {
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+                out.push(' ');
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn strip_trailing_commas(buf: &str) -> String {
+    let mut out = String::with_capacity(buf.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in buf.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == ',' && buf[i + 1..].trim_start().starts_with([']', '}']) {
+            continue;
+        }
+        out.push(c);
+    }
+    out
 }
 
+/// Read the config, creating or migrating it first.
+///
+/// The caller must hold the `ConfigLock` across the read, modify and write, so that
+/// concurrent ksud invocations don't lose each other's changes. A config that doesn't parse
+/// is an error, so that nothing is ever written over it.
 fn read_config() -> Result<Config> {
     ensure_config()?;
-    let content = fs::read_to_string(defs::UMOUNT_CONFIG_PATH)?;
-    let config: Config = serde_json::from_str(&content).unwrap_or_default();
-    Ok(config)
+    let content = fs::read_to_string(config_path())?;
+    parse_config(&content).map_err(|e| {
+        anyhow!(
+            "umount config {} is damaged ({e}), fix it or bring back a backup with \
+             `ksud umount-config restore-backup`",
+            config_path()
+        )
+    })
+}
+
+/// Read the config for the boot-time load, salvaging what it can from a damaged one.
+///
+/// Returns whether the config was salvaged, such a config must not be written back.
+fn read_config_salvaging() -> Result<(Config, bool)> {
+    ensure_config()?;
+    let content = fs::read_to_string(config_path())?;
+    Ok(match parse_config(&content) {
+        Ok(config) => (config, false),
+        Err(e) => (salvage_config(&content, &e), true),
+    })
+}
+
+/// Recover what can be recovered from a config that doesn't parse.
+///
+/// Trailing garbage is cut off first, failing that `"path": "..."`, `"flags": ...` and legacy
+/// `"<path>": flags` pairs are picked out one by one. The result is saved next to the config as
+/// `.recovered` for inspection, the damaged file itself is left alone.
+fn salvage_config(content: &str, error: &serde_json::Error) -> Config {
+    let mut config = content
+        .match_indices('}')
+        .rev()
+        .find_map(|(pos, _)| parse_config::<Config>(&content[..=pos]).ok())
+        .unwrap_or_else(|| Config {
+            paths: extract_entries(content),
+            ..Config::default()
+        });
+    canonicalize(&mut config);
+
+    let recovered = format!("{}.recovered", config_path());
+    error!(
+        "umount config {} is damaged ({error}), salvaged {} entries into {recovered}, fix or replace the original",
+        config_path(),
+        config.paths.len()
+    );
+    match serde_json::to_string_pretty(&config) {
+        Ok(json) => {
+            if let Err(e) = fs::write(&recovered, json) {
+                warn!("failed to write {recovered}: {e}");
+            }
+        }
+        Err(e) => warn!("failed to serialize salvaged umount config: {e}"),
+    }
+    config
+}
+
+/// Pick entries out of text that is not valid JSON, in file order
+fn extract_entries(content: &str) -> Vec<Entry> {
+    const FLAGS: &str = r#"(\d+|"[^"]*")"#;
+    let path_re = Regex::new(r#""path"\s*:\s*"(/[^"]*)""#).expect("valid regex");
+    let flags_re = Regex::new(&format!(r#""flags"\s*:\s*{FLAGS}"#)).expect("valid regex");
+    let legacy_re = Regex::new(&format!(r#""(/[^"]*)"\s*:\s*{FLAGS}?"#)).expect("valid regex");
+
+    let parse_flags = |raw: &str| raw.trim_matches('"').parse::<UmountFlags>().ok();
+
+    // (position, path, flags) for entries, (position, flags) for a flags field of the last entry
+    let mut found: Vec<(usize, Option<&str>, Option<UmountFlags>)> = Vec::new();
+    for cap in path_re.captures_iter(content) {
+        found.push((
+            cap.get(0).map_or(0, |m| m.start()),
+            cap.get(1).map(|m| m.as_str()),
+            None,
+        ));
+    }
+    for cap in legacy_re.captures_iter(content) {
+        let flags = cap.get(2).and_then(|m| parse_flags(m.as_str()));
+        found.push((
+            cap.get(0).map_or(0, |m| m.start()),
+            cap.get(1).map(|m| m.as_str()),
+            flags,
+        ));
+    }
+    for cap in flags_re.captures_iter(content) {
+        let flags = cap.get(1).and_then(|m| parse_flags(m.as_str()));
+        found.push((cap.get(0).map_or(0, |m| m.start()), None, flags));
+    }
+    found.sort_by_key(|(pos, _, _)| *pos);
+
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut has_flags = false;
+    for (_, path, flags) in found {
+        match path {
+            Some(path) => {
+                has_flags = flags.is_some();
+                entries.push(Entry {
+                    path: path.to_string(),
+                    flags: flags.unwrap_or_default(),
+                    susfs: false,
+                    builtin: false,
+                    auto: false,
+                    condition: None,
+                    comment: None,
+                    origin: None,
+                    flags_auto: false,
+                });
+            }
+            None => {
+                if let (Some(entry), Some(flags), false) = (entries.last_mut(), flags, has_flags) {
+                    entry.flags = flags;
+                    has_flags = true;
+                }
+            }
+        }
+    }
+    entries
 }
 
+/// Read the config for callers that don't write it back
+fn read_config_locked() -> Result<Config> {
+    let _lock = ConfigLock::acquire(config_path())?;
+    read_config()
+}
+
+/// Number of previous configs kept by `write_config`
+const BACKUP_COUNT: usize = 5;
+
+/// Path of the `index`th most recent backup, 0 being the newest
+fn backup_path(index: usize) -> String {
+    if index == 0 {
+        format!("{}.bak", config_path())
+    } else {
+        format!("{}.bak.{index}", config_path())
+    }
+}
+
+/// Move the current config to backup 0, shifting older backups and dropping the oldest
+fn rotate_backups(path: &Path) -> Result<()> {
+    for index in (1..BACKUP_COUNT).rev() {
+        let older = backup_path(index - 1);
+        if Path::new(&older).exists() {
+            fs::rename(&older, backup_path(index))?;
+        }
+    }
+    fs::copy(path, backup_path(0))?;
+    Ok(())
+}
+
+/// Write the config atomically, so that an interrupted write never leaves a truncated file.
+///
+/// The previous content is rotated into the backups first, unless it is unchanged.
 fn write_config(config: &Config) -> Result<()> {
+    let path = Path::new(config_path());
     let content = serde_json::to_string_pretty(config)?;
-    fs::write(defs::UMOUNT_CONFIG_PATH, content)?;
+    match fs::read_to_string(path) {
+        Ok(current) if current == content => return Ok(()),
+        Ok(_) => {
+            if let Err(e) = rotate_backups(path) {
+                warn!("failed to back up {}: {e}", path.display());
+            }
+        }
+        Err(_) => {}
+    }
+
+    write_atomic(path, &content)
+}
+
+/// Replace `path` with `content` through a renamed temp file, so readers see the old or the new
+/// content and never a partial one
+fn write_atomic(path: &Path, content: &str) -> Result<()> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut temp_file = NamedTempFile::new_in(dir)?;
+    temp_file.write_all(content.as_bytes())?;
+    temp_file.flush()?;
+    temp_file.as_file().sync_all()?;
+    temp_file
+        .persist(path)
+        .with_context(|| format!("failed to write {}", path.display()))?;
     Ok(())
 }
 
-pub fn load_umount_config() -> Result<()> {
-    let json_raw = read_config()?;
+/// Register every configured entry with the kernel.
+///
+/// Failing entries are collected and summarized, the load only fails when nothing could be
+/// registered. With `strict` the first failure aborts the load instead.
+pub fn load_umount_config(strict: bool) -> Result<()> {
+    // a fresh boot starts with an empty kernel list
+    let _ = fs::remove_file(defs::UMOUNT_APPLIED_PATH);
+    let config = {
+        let _lock = ConfigLock::acquire(config_path())?;
+        let (mut config, salvaged) = read_config_salvaging()?;
+        if canonicalize(&mut config) && !salvaged {
+            write_config(&config)?;
+        }
+        config
+    };
+    register(config.paths, strict)
+}
+
+/// Normalize entry paths and merge entries that end up with the same path.
+///
+/// Merged entries keep the first position and the union of the flags. Returns whether the
+/// config changed.
+fn canonicalize(config: &mut Config) -> bool {
+    let mut changed = false;
+    let mut merged: Vec<Entry> = Vec::with_capacity(config.paths.len());
+    for mut entry in config.paths.drain(..) {
+        match normalize_path(&entry.path) {
+            Ok(path) if path != entry.path => {
+                info!("Normalized umount entry {} to {path}", entry.path);
+                entry.path = path;
+                changed = true;
+            }
+            Ok(_) => {}
+            Err(e) => warn!("{e}"),
+        }
+        if let Some(existing) = merged.iter_mut().find(|e| e.path == entry.path) {
+            info!(
+                "Merged duplicate umount entry {} ({} into {})",
+                entry.path, entry.flags, existing.flags
+            );
+            existing.flags = UmountFlags(existing.flags.0 | entry.flags.0);
+            existing.susfs |= entry.susfs;
+            if existing.comment.is_none() {
+                existing.comment = entry.comment;
+            }
+            if existing.condition != entry.condition {
+                // applying to either set of processes is only covered by applying always
+                existing.condition = None;
+            }
+            changed = true;
+            continue;
+        }
+        merged.push(entry);
+    }
+    config.paths = merged;
+    changed
+}
+
+/// The kernel already has this path, e.g. when reloading without a reboot
+fn is_already_registered(e: &anyhow::Error) -> bool {
+    KsuCallError::of(e).is_some_and(|e| e.errno == libc::EEXIST)
+}
+
+/// Add `path` to the kernel umount list, passing the entry's condition down when it has one.
+///
+/// Kernels without conditional entries get the path unconditionally.
+fn add_to_kernel(path: &str, flags: UmountFlags, condition: Option<Condition>) -> Result<()> {
+    let cond = condition.map_or(0, Condition::kernel_cond);
+    if cond == 0 {
+        return ksucalls::umount_list_add(path, flags.0);
+    }
+    let user_id = condition.and_then(|c| c.user_id).unwrap_or(0);
+    match ksucalls::umount_list_add_ext(path, flags.0, cond, user_id) {
+        Err(e) if KsuCallError::of(&e).is_some_and(|e| e.kind() == KsuCallErrorKind::Invalid) => {
+            warn!(
+                "kernel lacks umount conditions, {path} is unmounted regardless of its condition"
+            );
+            ksucalls::umount_list_add(path, flags.0)
+        }
+        result => result,
+    }
+}
+
+/// Expand `path` to the mount points it matches when it is a pattern.
+///
+/// `mount_points` caches the mount table across calls.
+fn expand(path: String, mount_points: &mut Option<Vec<String>>) -> Result<Vec<String>> {
+    if !is_pattern(&path) {
+        return Ok(vec![path]);
+    }
+    if mount_points.is_none() {
+        *mount_points = Some(read_mount_points()?);
+    }
+    let matches: Vec<String> = mount_points
+        .iter()
+        .flatten()
+        .filter(|mnt| glob_match(&path, mnt))
+        .cloned()
+        .collect();
+    if matches.is_empty() {
+        warn!("umount pattern {path} matches no mount point");
+    } else {
+        info!("umount pattern {path} expands to {}", matches.join(", "));
+    }
+    Ok(matches)
+}
+
+/// Paths ksud added to the kernel umount list from the config since boot
+fn read_applied() -> Vec<String> {
+    fs::read_to_string(defs::UMOUNT_APPLIED_PATH)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn write_applied(applied: &[String]) {
+    let content = serde_json::Value::from(applied).to_string();
+    if let Err(e) = fs::write(defs::UMOUNT_APPLIED_PATH, content) {
+        warn!("failed to record applied umount entries: {e}");
+    }
+}
+
+/// A path to add to the kernel umount list, with its flags and condition
+type Target = (String, UmountFlags, Option<Condition>);
+
+/// Add `targets` to the kernel, returning each one's result and how many kernel calls it took.
+///
+/// Kernels that support it get the entries in batches, otherwise or when a batch call fails
+/// they are added one by one.
+fn add_all_to_kernel(targets: &[Target]) -> (Vec<Result<()>>, usize) {
+    if targets.len() > 1 && ksucalls::umount_list_can_batch() {
+        let entries: Vec<ksucalls::UmountAdd> = targets
+            .iter()
+            .map(|(path, flags, condition)| ksucalls::UmountAdd {
+                path,
+                flags: flags.0,
+                cond: condition.map_or(0, Condition::kernel_cond),
+                user_id: condition.and_then(|c| c.user_id).unwrap_or(0),
+            })
+            .collect();
+        match ksucalls::umount_list_add_batch(&entries) {
+            Ok(statuses) => {
+                let calls = targets
+                    .len()
+                    .div_ceil(uapi::KSU_UMOUNT_BATCH_MAX_RUST as usize);
+                let results = statuses
+                    .into_iter()
+                    .map(|status| match -status {
+                        0 => Ok(()),
+                        errno => Err(KsuCallError {
+                            request: uapi::KSU_IOCTL_MANAGE_TRY_UMOUNT_RUST,
+                            errno,
+                        }
+                        .into()),
+                    })
+                    .collect();
+                return (results, calls);
+            }
+            Err(e) => warn!("batched umount list add failed, adding entries one by one: {e}"),
+        }
+    }
+    let results = targets
+        .iter()
+        .map(|(path, flags, condition)| add_to_kernel(path, *flags, *condition))
+        .collect();
+    (results, targets.len())
+}
+
+/// Put targets listed in unmount order into the order they have to be registered in.
+///
+/// The kernel adds every entry at the head of its list and unmounts from the head, so the target
+/// to unmount first has to be registered last.
+fn registration_order<T>(mut targets: Vec<T>) -> Vec<T> {
+    targets.reverse();
+    targets
+}
+
+/// The kernel targets of `entries` in registration order, with those that request SuSFS hiding.
+///
+/// Entries whose condition doesn't hold are left out, patterns are expanded against
+/// `mount_points`.
+fn registration_targets(
+    entries: Vec<Entry>,
+    mount_points: &mut Option<Vec<String>>,
+) -> Result<(Vec<Target>, HashSet<String>)> {
+    let mut targets = Vec::new();
+    let mut susfs_targets = HashSet::new();
+    for Entry {
+        path,
+        flags,
+        susfs,
+        condition,
+        ..
+    } in entries
+    {
+        if let Some(condition) = condition
+            && !condition.holds_at_registration()
+        {
+            debug!("skip umount entry {path}, condition {condition} does not hold");
+            continue;
+        }
+        for target in expand(path, mount_points)? {
+            if susfs {
+                susfs_targets.insert(target.clone());
+            }
+            targets.push((target, flags, condition));
+        }
+    }
+    Ok((registration_order(targets), susfs_targets))
+}
+
+fn register(entries: Vec<Entry>, strict: bool) -> Result<()> {
     let mut count = 0;
+    let mut susfs_count = 0;
+    let mut failures = Vec::new();
+    let mut applied = read_applied();
 
-    for (path, flags) in json_raw.paths {
-        ksucalls::umount_list_add(path.as_str(), flags)?;
+    let (targets, susfs_targets) = registration_targets(entries, &mut None)?;
+    let (results, calls) = add_all_to_kernel(&targets);
+    for ((target, ..), result) in targets.into_iter().zip(results) {
+        if let Err(e) = result
+            && !is_already_registered(&e)
+        {
+            if KsuCallError::of(&e).is_some_and(|e| e.kind() == KsuCallErrorKind::Unsupported) {
+                warn!("kernel has no umount list, skip the umount config");
+                return Ok(());
+            }
+            if strict {
+                return Err(e.context(format!("failed to add {target} to the umount list")));
+            }
+            warn!("failed to add {target} to the umount list: {e}");
+            failures.push(format!("{target} ({e})"));
+            continue;
+        }
+        if susfs_targets.contains(&target) {
+            susfs_count += 1;
+        }
+        if !applied.contains(&target) {
+            applied.push(target);
+        }
         count += 1;
     }
-    info!("Loaded {count} umount entries from config");
+    debug!("umount list registration took {calls} kernel calls");
+
+    write_applied(&applied);
+
+    let total = count + failures.len();
+    if failures.is_empty() {
+        info!("Loaded {count} umount entries from config");
+    } else {
+        warn!(
+            "Loaded {count}/{total} umount entries from config, {} failed: {}",
+            failures.len(),
+            failures.join(", ")
+        );
+    }
+
+    if susfs_count > 0 {
+        hide_susfs_mounts(susfs_count);
+    }
+    if count == 0 && total > 0 {
+        bail!("none of the {total} umount entries could be loaded");
+    }
     Ok(())
 }
 
-pub fn list_umount() -> Result<()> {
-    let json_raw = read_config()?;
+/// Empty the kernel umount list and register the config from scratch, as done at boot.
+///
+/// Unlike `reload_umount` this also drops kernel entries ksud didn't add.
+pub fn rebuild_umount() -> Result<()> {
+    let _lock = ConfigLock::acquire(config_path())?;
+    let config = read_config()?;
+    ksucalls::umount_list_clear().context("failed to clear the kernel umount list")?;
+    let _ = fs::remove_file(defs::UMOUNT_APPLIED_PATH);
+    register(config.paths, false)
+}
 
-    let output: Vec<MountInfo> = json_raw
-        .paths
+/// Sync the kernel umount list with the config without rebooting.
+///
+/// Entries ksud added before but no longer configured are removed, missing ones are added and
+/// ones with changed flags are re-added. Kernel entries ksud didn't add are left alone.
+///
+/// Added and re-added entries keep their configured order among themselves, but are unmounted
+/// before the entries the kernel already has; `reload --rebuild` restores the configured order
+/// of all.
+///
+/// Kernels that can't report their list are diffed against the recorded applied entries instead,
+/// flag changes are not noticed then.
+pub fn reload_umount() -> Result<()> {
+    let _lock = ConfigLock::acquire(config_path())?;
+    let config = read_config()?;
+    let kernel: Option<HashMap<String, u32>> = ksucalls::umount_list_get()?.map(|list| {
+        list.into_iter()
+            .map(|info| (info.path, info.flags))
+            .collect()
+    });
+    if kernel.is_none() {
+        warn!("kernel cannot report its umount list, reloading against the applied entries");
+    }
+
+    let mut mount_points = None;
+    let mut desired = Vec::new();
+    for entry in config.paths {
+        if let Some(condition) = entry.condition
+            && !condition.holds_at_registration()
+        {
+            debug!(
+                "skip umount entry {}, condition {condition} does not hold",
+                entry.path
+            );
+            continue;
+        }
+        for target in expand(entry.path, &mut mount_points)? {
+            if !desired.iter().any(|(path, _, _)| *path == target) {
+                desired.push((target, entry.flags, entry.condition));
+            }
+        }
+    }
+
+    let mut added = Vec::new();
+    let mut updated = Vec::new();
+    let mut removed = Vec::new();
+    let mut failed = Vec::new();
+
+    for path in read_applied() {
+        if kernel
+            .as_ref()
+            .is_none_or(|kernel| kernel.contains_key(&path))
+            && !desired.iter().any(|(target, _, _)| *target == path)
+        {
+            match ksucalls::umount_list_del(&path) {
+                Ok(()) => removed.push(path),
+                Err(e) => failed.push(format!("{path} ({e})")),
+            }
+        }
+    }
+
+    let mut applied = Vec::new();
+    for (path, flags, condition) in registration_order(desired) {
+        let (result, list) = match kernel.as_ref().and_then(|kernel| kernel.get(&path)) {
+            Some(&current) if current == flags.0 => {
+                applied.push(path);
+                continue;
+            }
+            Some(_) => (
+                ksucalls::umount_list_del(&path)
+                    .and_then(|()| add_to_kernel(&path, flags, condition)),
+                &mut updated,
+            ),
+            None => (add_to_kernel(&path, flags, condition), &mut added),
+        };
+        match result {
+            Ok(()) => {
+                list.push(path.clone());
+                applied.push(path);
+            }
+            // only without the kernel list: it was there already
+            Err(e) if is_already_registered(&e) => applied.push(path),
+            Err(e) => failed.push(format!("{path} ({e})")),
+        }
+    }
+    write_applied(&applied);
+    added.reverse();
+    updated.reverse();
+
+    for (label, paths) in [
+        ("added", &added),
+        ("updated", &updated),
+        ("removed", &removed),
+    ] {
+        for path in paths {
+            println!("{label}: {path}");
+        }
+    }
+    for failure in &failed {
+        eprintln!("WARNING: failed: {failure}");
+    }
+    println!(
+        "Reloaded umount config: {} added, {} updated, {} removed, {} failed",
+        added.len(),
+        updated.len(),
+        removed.len(),
+        failed.len()
+    );
+    Ok(())
+}
+
+/// Whether a configured path is a pattern to expand against the mount table
+fn is_pattern(path: &str) -> bool {
+    path.contains(['*', '?'])
+}
+
+/// Match `path` against a glob `pattern`, `*` and `?` don't match `/`.
+fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let path = path.as_bytes();
+    let (mut p, mut s) = (0, 0);
+    let mut backtrack = None;
+
+    while s < path.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, s));
+                p += 1;
+                continue;
+            }
+            Some(&c) if (c == b'?' && path[s] != b'/') || c == path[s] => {
+                p += 1;
+                s += 1;
+                continue;
+            }
+            _ => {}
+        }
+        match backtrack {
+            Some((star, matched)) if path[matched] != b'/' => {
+                backtrack = Some((star, matched + 1));
+                p = star + 1;
+                s = matched + 1;
+            }
+            _ => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Mount points of the current mount namespace, in mount order
+fn read_mount_points() -> Result<Vec<String>> {
+    Ok(read_mounts()?
         .into_iter()
-        .map(|(path, flags)| MountInfo { path, flags })
-        .collect();
+        .map(|mount| mount.mount_point)
+        .collect())
+}
+
+struct MountEntry {
+    mount_point: String,
+    /// Directory of the source filesystem that is mounted, for bind mounts
+    root: String,
+    source: String,
+    fs_type: String,
+    /// In a shared peer group, unmounts propagate to the peers
+    shared: bool,
+}
+
+/// Mounts of the current mount namespace from /proc/self/mountinfo, in mount order
+fn read_mounts() -> Result<Vec<MountEntry>> {
+    read_mounts_of("self")
+}
+
+/// Mounts of the mount namespace `pid` is in, in mount order
+fn read_mounts_of(pid: &str) -> Result<Vec<MountEntry>> {
+    let path = format!("/proc/{pid}/mountinfo");
+    let mountinfo = fs::read_to_string(&path).with_context(|| format!("failed to read {path}"))?;
+    Ok(mountinfo
+        .lines()
+        .filter_map(|line| {
+            let (mount_fields, super_fields) = line.split_once(" - ")?;
+            let mut fields = mount_fields.split(' ');
+            let root = fields.nth(3)?;
+            let mount_point = fields.next()?;
+            // skip the mount options, the optional fields follow
+            let shared = fields.skip(1).any(|field| field.starts_with("shared:"));
+            let mut super_fields = super_fields.split(' ');
+            let fs_type = super_fields.next()?;
+            let source = super_fields.next()?;
+            Some(MountEntry {
+                mount_point: mount_point.replace("\\040", " "),
+                root: root.replace("\\040", " "),
+                source: source.replace("\\040", " "),
+                fs_type: fs_type.to_string(),
+                shared,
+            })
+        })
+        .collect())
+}
+
+/// Pick umount flags for `path` from how it is mounted.
+///
+/// Overlay and tmpfs mounts, shared mounts and paths not mounted yet get MNT_DETACH, plain
+/// bind mounts and other filesystems get no flags.
+fn default_flags(path: &str) -> UmountFlags {
+    let detach = UmountFlags(libc::MNT_DETACH as u32);
+    let mounts = match read_mounts() {
+        Ok(mounts) => mounts,
+        Err(e) => {
+            warn!("failed to read mounts to choose flags for {path}: {e}");
+            return detach;
+        }
+    };
+    // the last mount on a path is the one that is visible
+    let Some(mount) = mounts.iter().rev().find(|mount| {
+        if is_pattern(path) {
+            glob_match(path, &mount.mount_point)
+        } else {
+            mount.mount_point == path
+        }
+    }) else {
+        info!("{path} is not mounted, defaulting to {detach}");
+        return detach;
+    };
+    let flags = if matches!(mount.fs_type.as_str(), "overlay" | "tmpfs") || mount.shared {
+        detach
+    } else {
+        UmountFlags::default()
+    };
+    info!(
+        "{path} is a {} mount{}, chose flags {flags}",
+        mount.fs_type,
+        if mount.shared { " (shared)" } else { "" }
+    );
+    flags
+}
+
+/// Mount source used for module mounts made by KernelSU and its metamodules
+const KSU_MOUNT_SOURCE: &str = "KSU";
+
+/// Entries for the mounts made for modules, children before their parents.
+fn builtin_entries() -> Vec<Entry> {
+    let mounts = match read_mounts() {
+        Ok(mounts) => mounts,
+        Err(e) => {
+            warn!("failed to read mounts for the default umount entries: {e}");
+            return Vec::new();
+        }
+    };
+    let mut entries: Vec<Entry> = Vec::new();
+    for mount in mounts
+        .into_iter()
+        .rev()
+        .filter(|mount| mount.source == KSU_MOUNT_SOURCE)
+    {
+        if entries.iter().any(|entry| entry.path == mount.mount_point) {
+            continue;
+        }
+        entries.push(Entry {
+            path: mount.mount_point,
+            flags: UmountFlags(libc::MNT_DETACH as u32),
+            susfs: false,
+            builtin: true,
+            auto: false,
+            condition: None,
+            comment: None,
+            origin: None,
+            flags_auto: false,
+        });
+    }
+    entries
+}
+
+/// Whether a mount was made for a module, by source or by living under the module directory
+fn is_module_mount(mount: &MountEntry) -> bool {
+    let module_dir = defs::MODULE_DIR.trim_end_matches('/');
+    // bind mounts from /data show their root relative to the data partition
+    let module_root = module_dir.trim_start_matches("/data");
+    mount.source == KSU_MOUNT_SOURCE
+        || mount.mount_point.starts_with(defs::MODULE_DIR)
+        || mount.root.starts_with(module_dir)
+        || mount.root.starts_with(module_root)
+}
+
+/// Add module mounts that no entry covers yet, marked as `auto`.
+///
+/// Returns the paths that were added.
+fn sync_module_mounts(config: &mut Config) -> Result<Vec<String>> {
+    // children before their parents, like the builtin entries
+    let mut uncovered: Vec<String> = Vec::new();
+    for mount in read_mounts()?.into_iter().rev().filter(is_module_mount) {
+        let covered = config.paths.iter().any(|entry| {
+            entry.path == mount.mount_point
+                || (is_pattern(&entry.path) && glob_match(&entry.path, &mount.mount_point))
+        });
+        if !covered && !uncovered.contains(&mount.mount_point) {
+            uncovered.push(mount.mount_point);
+        }
+    }
+
+    let flags = UmountFlags(libc::MNT_DETACH as u32);
+    let mut registered = HashSet::new();
+    for path in registration_order(uncovered.clone()) {
+        if let Err(e) = ksucalls::umount_list_add(&path, flags.0) {
+            warn!("failed to add module mount {path} to the umount list: {e}");
+            continue;
+        }
+        registered.insert(path);
+    }
+
+    let mut added = Vec::new();
+    for path in uncovered {
+        if !registered.contains(&path) {
+            continue;
+        }
+        info!("Adding module mount {path} to the umount config");
+        config.paths.push(Entry {
+            path: path.clone(),
+            flags,
+            susfs: false,
+            builtin: false,
+            auto: true,
+            condition: None,
+            comment: None,
+            origin: None,
+            flags_auto: false,
+        });
+        added.push(path);
+    }
+    if !added.is_empty() {
+        let mut applied = read_applied();
+        applied.extend(added.iter().cloned());
+        write_applied(&applied);
+    }
+    Ok(added)
+}
+
+/// Sync module mounts into the config at boot, when enabled with `sync-modules --enable`.
+pub fn sync_module_mounts_on_boot() -> Result<()> {
+    let _lock = ConfigLock::acquire(config_path())?;
+    let mut config = read_config()?;
+    if !config.sync_modules {
+        return Ok(());
+    }
+    let added = sync_module_mounts(&mut config)?;
+    if !added.is_empty() {
+        write_config(&config)?;
+        info!("Added {} module mounts to the umount config", added.len());
+    }
+    Ok(())
+}
+
+/// Add uncovered module mounts now, or switch syncing them at boot on or off.
+pub fn sync_modules(enable: Option<bool>) -> Result<()> {
+    let _lock = ConfigLock::acquire(config_path())?;
+    let mut config = read_config()?;
+    if let Some(enable) = enable {
+        config.sync_modules = enable;
+        write_config(&config)?;
+        println!(
+            "Module mount sync at boot {}",
+            if enable { "enabled" } else { "disabled" }
+        );
+        return Ok(());
+    }
+
+    let added = sync_module_mounts(&mut config)?;
+    if !added.is_empty() {
+        write_config(&config)?;
+    }
+    for path in &added {
+        println!("added: {path}");
+    }
+    println!("Added {} module mounts to the umount config", added.len());
+    Ok(())
+}
+
+/// Record the mount targets of an installed module, replacing what an earlier install added.
+///
+/// Targets that already have an entry are left to it. Returns the number of entries added.
+pub fn add_module_entries(module_id: &str, targets: &[String]) -> Result<usize> {
+    let _lock = ConfigLock::acquire(config_path())?;
+    let mut config = read_config()?;
+    config
+        .paths
+        .retain(|entry| entry.origin.as_deref() != Some(module_id));
 
-    let json_output = serde_json::to_string(&output)?;
-    println!("{json_output}");
+    let mut added = 0;
+    for target in targets {
+        let path = match normalize_path(target) {
+            Ok(path) => path,
+            Err(e) => {
+                warn!("skip umount target {target} of module {module_id}: {e}");
+                continue;
+            }
+        };
+        if config.position(&path).is_some() {
+            continue;
+        }
+        config.paths.push(Entry {
+            path,
+            flags: UmountFlags(libc::MNT_DETACH as u32),
+            susfs: false,
+            builtin: false,
+            auto: false,
+            condition: None,
+            comment: None,
+            origin: Some(module_id.to_string()),
+            flags_auto: false,
+        });
+        added += 1;
+    }
+    write_config(&config)?;
+    Ok(added)
+}
 
+/// Drop the entries the installation of `module_id` added.
+pub fn remove_module_entries(module_id: &str) -> Result<()> {
+    let _lock = ConfigLock::acquire(config_path())?;
+    let mut config = read_config()?;
+    let count = config.paths.len();
+    config
+        .paths
+        .retain(|entry| entry.origin.as_deref() != Some(module_id));
+    if config.paths.len() != count {
+        info!(
+            "Removed {} umount entries of module {module_id}",
+            count - config.paths.len()
+        );
+        write_config(&config)?;
+    }
     Ok(())
 }
 
-pub fn add_umount(target_path: &str, flags: u32) -> Result<()> {
+/// Consecutive boots after which `prune` drops an entry whose path is gone
+const PRUNE_AFTER_BOOTS: u32 = 3;
+
+/// Consecutive boots each configured path was missing, recorded by `record_missing_on_boot`
+fn read_missing() -> HashMap<String, u32> {
+    fs::read_to_string(defs::UMOUNT_MISSING_PATH)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+/// Whether an entry's path neither exists nor matches a mount point
+fn is_missing(entry: &Entry, mount_points: &[String]) -> bool {
+    if is_pattern(&entry.path) {
+        return !mount_points.iter().any(|mnt| glob_match(&entry.path, mnt));
+    }
+    !Path::new(&entry.path).exists() && !mount_points.contains(&entry.path)
+}
+
+/// The consecutive boots each of `paths` has been missing, counting this one on top of
+/// `previous`; paths that showed up again drop out
+fn count_missing<'a>(
+    paths: &'a [Entry],
+    previous: &HashMap<String, u32>,
+    mount_points: &[String],
+) -> HashMap<&'a str, u32> {
+    paths
+        .iter()
+        .filter(|entry| !entry.builtin && is_missing(entry, mount_points))
+        .map(|entry| {
+            let boots = previous.get(&entry.path).copied().unwrap_or(0) + 1;
+            (entry.path.as_str(), boots)
+        })
+        .collect()
+}
+
+/// Split `paths` into the entries to prune and those to keep
+fn partition_stale(
+    paths: Vec<Entry>,
+    missing: &HashMap<String, u32>,
+    mount_points: &[String],
+) -> (Vec<Entry>, Vec<Entry>) {
+    paths.into_iter().partition(|entry| {
+        !entry.builtin
+            && missing.get(&entry.path).copied().unwrap_or(0) >= PRUNE_AFTER_BOOTS
+            // it may have come back since the last boot
+            && is_missing(entry, mount_points)
+    })
+}
+
+/// Count, per path, the consecutive boots it was missing, resetting paths that showed up again.
+pub fn record_missing_on_boot() {
+    let config = match read_config_locked() {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("failed to read umount config to track missing paths: {e}");
+            return;
+        }
+    };
+    let mount_points = match read_mount_points() {
+        Ok(mount_points) => mount_points,
+        Err(e) => {
+            warn!("failed to read mount points to track missing paths: {e}");
+            return;
+        }
+    };
+    let missing = count_missing(&config.paths, &read_missing(), &mount_points);
+    let content = serde_json::to_string(&missing).unwrap_or_default();
+    if let Err(e) = write_atomic(Path::new(defs::UMOUNT_MISSING_PATH), &content) {
+        warn!("failed to record missing umount paths: {e:#}");
+    }
+}
+
+/// Remove entries whose path has been missing for `PRUNE_AFTER_BOOTS` consecutive boots.
+///
+/// Builtin entries are never pruned. With `dry_run` the entries are only listed.
+pub fn prune_umount(dry_run: bool) -> Result<()> {
+    let missing = read_missing();
+
+    let _lock = ConfigLock::acquire(config_path())?;
+    let mut config = read_config()?;
+    let mount_points = read_mount_points()?;
+    let (stale, kept) = partition_stale(config.paths, &missing, &mount_points);
+    config.paths = kept;
+
+    let action = if dry_run { "would prune" } else { "pruned" };
+    for entry in &stale {
+        println!(
+            "{action}: {} (missing for {} boots)",
+            entry.path, missing[&entry.path]
+        );
+    }
+    if !dry_run && !stale.is_empty() {
+        drop_from_kernel(&stale);
+        write_config(&config)?;
+    }
+    println!(
+        "{} {} stale umount entries, {} kept",
+        if dry_run { "Would prune" } else { "Pruned" },
+        stale.len(),
+        config.paths.len()
+    );
+    Ok(())
+}
+
+/// Any process running as `uid`
+fn find_pid_of_uid(uid: u32) -> Option<u32> {
+    fs::read_dir("/proc").ok()?.flatten().find_map(|entry| {
+        let pid: u32 = entry.file_name().to_str()?.parse().ok()?;
+        let status = fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+        let real_uid: u32 = status
+            .lines()
+            .find_map(|line| line.strip_prefix("Uid:"))?
+            .split_whitespace()
+            .next()?
+            .parse()
+            .ok()?;
+        (real_uid == uid).then_some(pid)
+    })
+}
+
+/// Show which mounts of a process the configured entries would unmount, changing nothing.
+pub fn simulate_umount(pid: Option<u32>, uid: Option<u32>) -> Result<()> {
+    let pid = match (pid, uid) {
+        (Some(pid), _) => pid,
+        (None, Some(uid)) => {
+            find_pid_of_uid(uid).with_context(|| format!("no process is running as uid {uid}"))?
+        }
+        (None, None) => bail!("either --pid or --uid is required"),
+    };
+    let mounts = read_mounts_of(&pid.to_string())?;
+    let config = read_config_locked()?;
+
+    if let Some(uid) = uid {
+        match ksucalls::uid_should_umount(uid) {
+            Ok(true) => println!("uid {uid}: kernel unmounts for this uid"),
+            Ok(false) => println!("uid {uid}: kernel does NOT unmount for this uid (App Profile)"),
+            Err(e) => println!("uid {uid}: unknown whether the kernel unmounts ({e})"),
+        }
+    }
+
+    let mut covered = HashSet::new();
+    let mut unmatched = Vec::new();
+    for entry in &config.paths {
+        let matches: Vec<&MountEntry> = mounts
+            .iter()
+            .filter(|mount| {
+                if is_pattern(&entry.path) {
+                    glob_match(&entry.path, &mount.mount_point)
+                } else {
+                    entry.path == mount.mount_point
+                }
+            })
+            .collect();
+        if matches.is_empty() {
+            unmatched.push(entry.path.as_str());
+        }
+        for mount in matches {
+            if covered.insert(mount.mount_point.as_str()) {
+                println!("unmounted: {} (by {})", mount.mount_point, entry.path);
+            }
+        }
+    }
+    for mount in mounts.iter().filter(|mount| is_module_mount(mount)) {
+        if !covered.contains(mount.mount_point.as_str()) {
+            println!("remains: {} (source {})", mount.mount_point, mount.source);
+        }
+    }
+    for path in unmatched {
+        println!("unmatched: {path}");
+    }
+    Ok(())
+}
+
+/// Outcome of replaying one umount in the throwaway namespace
+fn umount_outcome(path: &str, flags: UmountFlags) -> String {
+    let Ok(c_path) = std::ffi::CString::new(path) else {
+        return "failed (path contains NUL)".to_string();
+    };
+    if unsafe { libc::umount2(c_path.as_ptr(), flags.0 as libc::c_int) } == 0 {
+        return "ok".to_string();
+    }
+    let err = std::io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EBUSY) => "busy (EBUSY)".to_string(),
+        // umount2 reports a path that is not a mount point as EINVAL
+        Some(libc::EINVAL | libc::ENOENT) => "not mounted".to_string(),
+        _ => format!("failed ({err})"),
+    }
+}
+
+/// Replay the configured umounts in a private mount namespace and report each result.
+///
+/// The namespace is made recursively private before anything is unmounted, so nothing
+/// propagates back to the real mounts, and ksud switches back to its original namespace at the end.
+pub fn test_umount() -> Result<()> {
+    anyhow::ensure!(
+        rustix::process::getuid().as_raw() == 0,
+        "must be run as root"
+    );
+    let config = read_config_locked()?;
+
+    let original = fs::File::open("/proc/self/ns/mnt")
+        .context("failed to open the current mount namespace")?;
+    if unsafe { libc::unshare(libc::CLONE_NEWNS) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .context("failed to unshare the mount namespace");
+    }
+
+    let root = c"/";
+    let result = if unsafe {
+        libc::mount(
+            std::ptr::null(),
+            root.as_ptr(),
+            std::ptr::null(),
+            libc::MS_REC | libc::MS_PRIVATE,
+            std::ptr::null(),
+        )
+    } != 0
+    {
+        Err(std::io::Error::last_os_error())
+            .context("failed to make the test namespace private, not unmounting anything")
+    } else {
+        let mut mount_points = None;
+        let mut replayed = Ok(());
+        for entry in config.paths {
+            if let Some(condition) = entry.condition
+                && !condition.holds_at_registration()
+            {
+                println!(
+                    "{}: skipped (condition {condition} does not hold)",
+                    entry.path
+                );
+                continue;
+            }
+            let flags = entry.flags;
+            let targets = match expand(entry.path, &mut mount_points) {
+                Ok(targets) => targets,
+                Err(e) => {
+                    replayed = Err(e);
+                    break;
+                }
+            };
+            for target in targets {
+                println!("{target}: {}", umount_outcome(&target, flags));
+            }
+        }
+        replayed
+    };
+
+    // the test namespace is private, staying in it only matters for the rest of this process
+    if unsafe { libc::setns(original.as_raw_fd(), libc::CLONE_NEWNS) } != 0 {
+        warn!(
+            "failed to return to the original mount namespace: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    result
+}
+
+/// Hide mounts flagged with `susfs` from processes where unmounting didn't happen.
+///
+/// SuSFS has no per-path sus mount registration, mounts made by ksud are already
+/// tracked as sus mounts by the kernel, so this only has to switch on hiding them
+/// for non-su processes.
+fn hide_susfs_mounts(count: usize) {
+    if !susfs::detect() {
+        warn!("{count} umount entries request SuSFS hiding, but SuSFS is not available");
+        return;
+    }
+    match susfs::hide_sus_mnts_for_non_su_procs(true) {
+        Ok(()) => info!("Enabled SuSFS mount hiding for {count} umount entries"),
+        Err(e) => warn!("failed to enable SuSFS mount hiding: {e}"),
+    }
+}
+
+/// List umount entries from the config file, or the live kernel list with `kernel`.
+pub fn list_umount(kernel: bool, json: bool) -> Result<()> {
+    let kernel_list = if kernel {
+        let list = ksucalls::umount_list_get()?;
+        if list.is_none() {
+            eprintln!(
+                "WARNING: kernel cannot report its umount list, listing the configuration file"
+            );
+        }
+        list
+    } else {
+        None
+    };
+    let output: Vec<EntryInfo> = if let Some(kernel_list) = kernel_list {
+        kernel_list
+            .into_iter()
+            .map(|info| EntryInfo {
+                path: info.path,
+                flags: info.flags,
+                susfs: false,
+                builtin: false,
+                auto: false,
+                condition: None,
+                comment: None,
+                origin: None,
+                flags_auto: false,
+            })
+            .collect()
+    } else {
+        read_config_locked()?
+            .paths
+            .into_iter()
+            .map(|entry| EntryInfo {
+                path: entry.path,
+                flags: entry.flags.0,
+                susfs: entry.susfs,
+                builtin: entry.builtin,
+                auto: entry.auto,
+                condition: entry.condition,
+                comment: entry.comment,
+                origin: entry.origin,
+                flags_auto: entry.flags_auto,
+            })
+            .collect()
+    };
+
+    if json {
+        println!("{}", serde_json::to_string(&output)?);
+        return Ok(());
+    }
+
+    for entry in output {
+        let susfs = if entry.susfs { " susfs" } else { "" };
+        let builtin = if entry.builtin { " builtin" } else { "" };
+        let auto = if entry.auto { " auto" } else { "" };
+        let flags_auto = if entry.flags_auto { " flags_auto" } else { "" };
+        let condition = entry
+            .condition
+            .map(|condition| format!(" if {condition}"))
+            .unwrap_or_default();
+        let origin = entry
+            .origin
+            .map(|origin| format!(" origin={origin}"))
+            .unwrap_or_default();
+        let comment = entry
+            .comment
+            .map(|comment| format!(" # {comment}"))
+            .unwrap_or_default();
+        println!(
+            "{} flags={} ({}){flags_auto}{susfs}{builtin}{auto}{origin}{condition}{comment}",
+            entry.path,
+            entry.flags,
+            UmountFlags(entry.flags).describe()
+        );
+    }
+    Ok(())
+}
+
+/// Print how often the kernel applied each umount list entry
+pub fn umount_stats(json: bool) -> Result<()> {
+    let Some(stats) = ksucalls::umount_list_stats()? else {
+        if json {
+            println!("null");
+        } else {
+            println!("umount stats: not supported by kernel");
+        }
+        return Ok(());
+    };
+
+    if json {
+        println!("{}", serde_json::to_string(&stats)?);
+        return Ok(());
+    }
+
+    let width = stats
+        .iter()
+        .map(|stat| stat.path.len())
+        .max()
+        .unwrap_or(0)
+        .max("PATH".len());
+    println!("{:<width$}  {:>8}  LAST UID", "PATH", "HITS");
+    for stat in stats {
+        let last_uid = stat
+            .last_uid
+            .map_or_else(|| "-".to_string(), |uid| uid.to_string());
+        println!("{:<width$}  {:>8}  {last_uid}", stat.path, stat.hits);
+    }
+    Ok(())
+}
+
+/// Validate a mount point path and bring it into the form the kernel compares against.
+///
+/// The path must be absolute and free of `..`, NUL and newlines. Repeated slashes, `.`
+/// components and the trailing slash are dropped.
+fn normalize_path(path: &str) -> Result<String> {
+    if !path.starts_with('/') {
+        bail!("umount path must be absolute: {path:?}");
+    }
+    if path.contains(['\0', '\n']) {
+        bail!("umount path must not contain NUL or newline: {path:?}");
+    }
+    let mut normalized = String::with_capacity(path.len());
+    for component in path.split('/').filter(|c| !c.is_empty() && *c != ".") {
+        if component == ".." {
+            bail!("umount path must not contain '..': {path:?}");
+        }
+        normalized.push('/');
+        normalized.push_str(component);
+    }
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+    Ok(normalized)
+}
+
+/// Add or replace an entry, choosing the flags from the mount type when `flags` is None.
+///
+/// The entry is registered with the kernel first, the config only changes once the kernel took it.
+pub fn add_umount(
+    target_path: &str,
+    flags: Option<UmountFlags>,
+    susfs: bool,
+    condition: Option<Condition>,
+    comment: Option<String>,
+) -> Result<()> {
+    let target_path = &normalize_path(target_path)?;
+    let _lock = ConfigLock::acquire(config_path())?;
     let mut json_raw = read_config()?;
-    json_raw.paths.insert(target_path.to_string(), flags);
+    let mut entry = Entry {
+        path: target_path.to_string(),
+        flags: flags.unwrap_or_else(|| default_flags(target_path)),
+        susfs,
+        builtin: false,
+        auto: false,
+        condition,
+        comment,
+        origin: None,
+        flags_auto: flags.is_none(),
+    };
+    let index = json_raw.position(target_path);
+
+    // the kernel goes first, the config is left alone when it refuses the entry
+    let mut applied = read_applied();
+    let mut mount_points = None;
+    if let Some(index) = index {
+        // the kernel keeps the flags a path was first added with
+        unregister_entry(&json_raw.paths[index], &mut applied)?;
+    }
+    if let Err(e) = register_entry(&entry, &mut mount_points, &mut applied) {
+        if let Some(index) = index
+            && let Err(e) = register_entry(&json_raw.paths[index], &mut mount_points, &mut applied)
+        {
+            warn!("failed to put {target_path} back into the umount list: {e:#}");
+        }
+        write_applied(&applied);
+        return Err(e);
+    }
+    write_applied(&applied);
+
+    match index {
+        Some(index) => {
+            // re-adding without --comment keeps the reason recorded before
+            if entry.comment.is_none() {
+                entry.comment = json_raw.paths[index].comment.take();
+            }
+            json_raw.paths[index] = entry;
+        }
+        None => json_raw.paths.push(entry),
+    }
     write_config(&json_raw)
 }
 
+/// Parse a `<path> [flags]` line of a batch add, lines without flags get `flags`.
+fn parse_batch_line(
+    line: &str,
+    flags: Option<UmountFlags>,
+) -> Result<(String, Option<UmountFlags>)> {
+    let (path, flags) = match line.split_once(char::is_whitespace) {
+        Some((path, line_flags)) => (path, Some(line_flags.trim().parse()?)),
+        None => (line, flags),
+    };
+    Ok((normalize_path(path)?, flags))
+}
+
+/// Register a new entry with the kernel, unless its condition rules it out for now
+fn register_entry(
+    entry: &Entry,
+    mount_points: &mut Option<Vec<String>>,
+    applied: &mut Vec<String>,
+) -> Result<()> {
+    if let Some(condition) = entry.condition
+        && !condition.holds_at_registration()
+    {
+        return Ok(());
+    }
+    for target in expand(entry.path.clone(), mount_points)? {
+        if let Err(e) = add_to_kernel(&target, entry.flags, entry.condition)
+            && !is_already_registered(&e)
+        {
+            return Err(e.context(format!("failed to add {target} to the umount list")));
+        }
+        if !applied.contains(&target) {
+            applied.push(target);
+        }
+    }
+    Ok(())
+}
+
+/// Drop an entry from the kernel umount list, patterns by the paths they were applied as
+fn unregister_entry(entry: &Entry, applied: &mut Vec<String>) -> Result<()> {
+    let targets: Vec<String> = if is_pattern(&entry.path) {
+        applied
+            .iter()
+            .filter(|path| glob_match(&entry.path, path))
+            .cloned()
+            .collect()
+    } else {
+        vec![entry.path.clone()]
+    };
+    for target in targets {
+        ksucalls::umount_list_del(&target)
+            .with_context(|| format!("failed to drop {target} from the umount list"))?;
+        applied.retain(|path| *path != target);
+    }
+    Ok(())
+}
+
+/// Add the entries listed in `file`, or stdin if None, one `<path> [flags]` per line.
+///
+/// Every line is validated like a single add and registered with the kernel, the config is
+/// written once at the end. Blank lines and `#` comments are skipped, as are paths already
+/// configured. Lines without flags get `flags`, or flags chosen from the mount type when it is
+/// None. `susfs`, `condition` and `comment` apply to all.
+pub fn add_umount_batch(
+    file: Option<&Path>,
+    flags: Option<UmountFlags>,
+    susfs: bool,
+    condition: Option<Condition>,
+    comment: Option<String>,
+) -> Result<()> {
+    let content = match file {
+        Some(file) => fs::read_to_string(file)
+            .with_context(|| format!("failed to read {}", file.display()))?,
+        None => {
+            let mut content = String::new();
+            std::io::stdin()
+                .read_to_string(&mut content)
+                .context("failed to read from stdin")?;
+            content
+        }
+    };
+
+    let _lock = ConfigLock::acquire(config_path())?;
+    let mut json_raw = read_config()?;
+    let mut applied = read_applied();
+    let mut mount_points = None;
+    let mut added = 0;
+    let mut duplicates = 0;
+    let mut failures = Vec::new();
+    let mut pending: Vec<(usize, Entry)> = Vec::new();
+
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let number = number + 1;
+        let (path, line_flags) = match parse_batch_line(line, flags) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                failures.push((number, e.to_string()));
+                continue;
+            }
+        };
+        if json_raw.position(&path).is_some() || pending.iter().any(|(_, entry)| entry.path == path)
+        {
+            info!("{path} is already configured, skipped");
+            duplicates += 1;
+            continue;
+        }
+        let entry = Entry {
+            flags: line_flags.unwrap_or_else(|| default_flags(&path)),
+            path,
+            susfs,
+            builtin: false,
+            auto: false,
+            condition,
+            comment: comment.clone(),
+            origin: None,
+            flags_auto: line_flags.is_none(),
+        };
+        pending.push((number, entry));
+    }
+
+    let mut registered = HashSet::new();
+    for (number, entry) in registration_order(pending.iter().collect::<Vec<_>>()) {
+        match register_entry(entry, &mut mount_points, &mut applied) {
+            Ok(()) => {
+                registered.insert(*number);
+            }
+            Err(e) => failures.push((*number, format!("{e:#}"))),
+        }
+    }
+    for (number, entry) in pending {
+        if registered.contains(&number) {
+            json_raw.paths.push(entry);
+            added += 1;
+        }
+    }
+
+    write_applied(&applied);
+    write_config(&json_raw)?;
+    if susfs && added > 0 {
+        hide_susfs_mounts(added);
+    }
+
+    failures.sort_by_key(|(number, _)| *number);
+    for (number, failure) in &failures {
+        warn!("line {number}: {failure}");
+        println!("line {number}: {failure}");
+    }
+    println!(
+        "Added {added} umount entries, skipped {duplicates} duplicates, {} failed",
+        failures.len()
+    );
+    if added == 0 && !failures.is_empty() {
+        bail!(
+            "none of the {} umount entries could be added",
+            failures.len()
+        );
+    }
+    Ok(())
+}
+
+/// Remove an entry from the kernel umount list, then from the config
 pub fn del_umount(target_path: &str) -> Result<()> {
+    let target_path = &normalize_path(target_path)?;
+    let _lock = ConfigLock::acquire(config_path())?;
+    if !Path::new(config_path()).exists() {
+        warn!(
+            "{} does not exist, nothing to delete for {target_path}",
+            config_path()
+        );
+        return Ok(());
+    }
     let mut json_raw = read_config()?;
-    if json_raw.paths.remove(target_path).is_some() {
+    if let Some(index) = json_raw.position(target_path) {
+        // the kernel goes first, the entry stays configured when it refuses
+        let mut applied = read_applied();
+        let result = unregister_entry(&json_raw.paths[index], &mut applied);
+        write_applied(&applied);
+        result?;
+        if json_raw.paths.remove(index).builtin {
+            info!("{target_path} is a default entry, restore-defaults brings it back");
+        }
         write_config(&json_raw)?;
     }
     Ok(())
 }
 
+/// Regenerate the builtin entries, keeping user entries as they are.
+pub fn restore_default_umount() -> Result<()> {
+    let _lock = ConfigLock::acquire(config_path())?;
+    let mut json_raw = read_config()?;
+    json_raw.paths.retain(|entry| !entry.builtin);
+    let mut added = 0;
+    for entry in builtin_entries() {
+        if json_raw.position(&entry.path).is_none() {
+            json_raw.paths.push(entry);
+            added += 1;
+        }
+    }
+    write_config(&json_raw)?;
+    println!("Restored {added} default umount entries");
+    Ok(())
+}
+
+const PACKAGES_LIST: &str = "/data/system/packages.list";
+const PER_USER_RANGE: u32 = 100_000;
+
+#[derive(Serialize)]
+struct PackageUmount {
+    user_id: u32,
+    uid: u32,
+    umount: bool,
+}
+
+const fn uid_umount_mode_name(mode: UidUmountMode) -> &'static str {
+    match mode {
+        UidUmountMode::Off => "off",
+        UidUmountMode::On => "on",
+        UidUmountMode::Default => "default",
+    }
+}
+
+/// The package owning `uid`, the first one for shared uids
+pub fn package_of_uid(uid: u32) -> Result<Option<String>> {
+    let packages = fs::read_to_string(PACKAGES_LIST)
+        .with_context(|| format!("failed to read {PACKAGES_LIST}"))?;
+    Ok(packages.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let name = fields.next()?;
+        let app_uid = fields.next()?.parse::<u32>().ok()?;
+        (app_uid % PER_USER_RANGE == uid % PER_USER_RANGE).then(|| name.to_string())
+    }))
+}
+
+/// Change only the umount part of `uid`'s App Profile, owned by `package`.
+fn set_uid_umount(uid: u32, package: &str, mode: UidUmountMode) -> Result<()> {
+    if let Ok(state) = ksucalls::get_uid_umount(uid)
+        && state.allow_su
+    {
+        bail!("uid {uid} is granted root, modules are never unmounted for it");
+    }
+    match ksucalls::set_uid_umount(uid, package, mode) {
+        Err(e)
+            if KsuCallError::of(&e).is_some_and(|e| e.kind() == KsuCallErrorKind::Unsupported) =>
+        {
+            bail!(
+                "kernel cannot change umount per uid; toggle \"Umount modules\" in the App Profile of {package} instead"
+            )
+        }
+        result => result.with_context(|| format!("failed to set umount for uid {uid}")),
+    }?;
+    info!(
+        "set umount for uid {uid} ({package}) to {}",
+        uid_umount_mode_name(mode)
+    );
+    Ok(())
+}
+
+/// Print whether modules are unmounted for `package`, for every user it is installed for.
+///
+/// The decision comes from the package's App Profile ("Umount modules"). With `mode` only that
+/// part of the profile is changed first, for every user.
+pub fn package_umount(package: &str, mode: Option<UidUmountMode>, json: bool) -> Result<()> {
+    let packages = fs::read_to_string(PACKAGES_LIST)
+        .with_context(|| format!("failed to read {PACKAGES_LIST}"))?;
+    let Some(app_uid) = packages.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let name = fields.next()?;
+        let uid = fields.next()?;
+        if name == package {
+            uid.parse::<u32>().ok()
+        } else {
+            None
+        }
+    }) else {
+        bail!("package {package} is not installed");
+    };
+    let app_id = app_uid % PER_USER_RANGE;
+
+    let mut user_ids: Vec<u32> = fs::read_dir("/data/user")
+        .map(|dir| {
+            dir.flatten()
+                .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+                .filter(|user_id: &u32| {
+                    Path::new(&format!("/data/user/{user_id}/{package}")).exists()
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    if user_ids.is_empty() {
+        user_ids.push(app_uid / PER_USER_RANGE);
+    }
+    user_ids.sort_unstable();
+
+    let mut result = Vec::new();
+    for user_id in user_ids {
+        let uid = user_id * PER_USER_RANGE + app_id;
+        if let Some(mode) = mode {
+            set_uid_umount(uid, package, mode)?;
+        }
+        let umount = ksucalls::uid_should_umount(uid)
+            .with_context(|| format!("failed to query umount for uid {uid}"))?;
+        result.push(PackageUmount {
+            user_id,
+            uid,
+            umount,
+        });
+    }
+
+    if json {
+        println!("{}", serde_json::to_string(&result)?);
+        return Ok(());
+    }
+    for entry in result {
+        let state = if entry.umount { "unmounted" } else { "kept" };
+        println!(
+            "{package} user {} (uid {}): modules {state}",
+            entry.user_id, entry.uid
+        );
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct UidUmount {
+    uid: u32,
+    package: Option<String>,
+    /// None on kernels that can only report the effective setting
+    mode: Option<&'static str>,
+    allow_su: Option<bool>,
+    umount: bool,
+}
+
+/// Print whether modules are unmounted for `uid`, after changing only that part of its App
+/// Profile when `mode` is given.
+///
+/// The profile is keyed by the package currently owning the uid, so a profile left behind by a
+/// package that had the uid before a reinstall is replaced rather than inherited.
+pub fn uid_umount(uid: u32, mode: Option<UidUmountMode>, json: bool) -> Result<()> {
+    let package = package_of_uid(uid)?;
+    if let Some(mode) = mode {
+        let Some(package) = &package else {
+            bail!("uid {uid} belongs to no installed package");
+        };
+        set_uid_umount(uid, package, mode)?;
+    }
+
+    let status = match ksucalls::get_uid_umount(uid) {
+        Ok(state) => UidUmount {
+            uid,
+            package,
+            mode: Some(uid_umount_mode_name(state.mode)),
+            allow_su: Some(state.allow_su),
+            umount: state.umount,
+        },
+        Err(e) if e.kind() == KsuCallErrorKind::Unsupported => UidUmount {
+            uid,
+            package,
+            mode: None,
+            allow_su: None,
+            umount: ksucalls::uid_should_umount(uid)
+                .with_context(|| format!("failed to query umount for uid {uid}"))?,
+        },
+        Err(e) => return Err(e).with_context(|| format!("failed to query umount for uid {uid}")),
+    };
+
+    if json {
+        println!("{}", serde_json::to_string(&status)?);
+        return Ok(());
+    }
+    let state = if status.umount { "unmounted" } else { "kept" };
+    let mut detail = status
+        .mode
+        .map(|mode| format!("mode {mode}"))
+        .unwrap_or_default();
+    if status.allow_su == Some(true) {
+        detail.push_str(", granted root");
+    }
+    println!(
+        "uid {uid} ({}): modules {state}{}",
+        status.package.as_deref().unwrap_or("unknown package"),
+        if detail.is_empty() {
+            String::new()
+        } else {
+            format!(" ({detail})")
+        }
+    );
+    Ok(())
+}
+
+/// Move an entry to `index`, entries are unmounted in config order.
+pub fn move_umount(target_path: &str, index: usize) -> Result<()> {
+    let target_path = &normalize_path(target_path)?;
+    let _lock = ConfigLock::acquire(config_path())?;
+    let mut json_raw = read_config()?;
+    let Some(current) = json_raw.position(target_path) else {
+        bail!("{target_path} is not in the umount config");
+    };
+    let entry = json_raw.paths.remove(current);
+    let index = index.min(json_raw.paths.len());
+    json_raw.paths.insert(index, entry);
+    write_config(&json_raw)
+}
+
+/// Drop every configured entry from the kernel umount list, then empty the config.
+///
+/// Only entries from the config are removed, mounts the kernel tracks on its own stay.
 pub fn wipe_umount() -> Result<()> {
+    let _lock = ConfigLock::acquire(config_path())?;
     let mut json_raw = read_config()?;
+
+    let (removed, refused) = drop_from_kernel(&json_raw.paths);
+
+    let count = json_raw.paths.len();
     json_raw.paths.clear();
-    write_config(&json_raw)
+    write_config(&json_raw)?;
+    println!(
+        "Removed {count} umount config entries, {removed} dropped from the kernel, {refused} refused"
+    );
+    Ok(())
+}
+
+/// Remove `entries` from the kernel umount list, returning how many were removed and refused.
+fn drop_from_kernel(entries: &[Entry]) -> (usize, usize) {
+    let mut kernel_paths = None;
+    let mut removed = 0;
+    let mut refused = 0;
+    for Entry { path, .. } in entries {
+        let targets = if is_pattern(path) {
+            if kernel_paths.is_none() {
+                kernel_paths = Some(ksucalls::umount_list_list().unwrap_or_default());
+            }
+            kernel_paths
+                .iter()
+                .flatten()
+                .filter(|info| glob_match(path, &info.path))
+                .map(|info| info.path.clone())
+                .collect()
+        } else {
+            vec![path.clone()]
+        };
+        for target in targets {
+            match ksucalls::umount_list_del(&target) {
+                Ok(()) => removed += 1,
+                Err(e) => {
+                    refused += 1;
+                    warn!("kernel refused to drop {target} from the umount list: {e}");
+                    eprintln!("WARNING: kernel refused to drop {target}: {e}");
+                }
+            }
+        }
+    }
+
+    (removed, refused)
+}
+
+/// Write the current config, with its schema version, to `file`.
+pub fn export_umount(file: &Path) -> Result<()> {
+    let json_raw = read_config_locked()?;
+    let content = serde_json::to_string_pretty(&json_raw)?;
+    fs::write(file, content).with_context(|| format!("failed to write {}", file.display()))?;
+    println!(
+        "Exported {} umount entries to {}",
+        json_raw.paths.len(),
+        file.display()
+    );
+    Ok(())
+}
+
+/// Import entries from an exported config, registering them with the kernel.
+///
+/// With `replace` the current entries are dropped, otherwise entries missing from `file`
+/// are kept and the imported ones take precedence. A merge is written only once the kernel
+/// took every imported entry.
+pub fn import_umount(file: &Path, replace: bool) -> Result<()> {
+    let buf =
+        fs::read_to_string(file).with_context(|| format!("failed to read {}", file.display()))?;
+    let version = config_version(&buf);
+    if version > u64::from(CONFIG_VERSION) {
+        bail!(
+            "{} has config version {version}, but this ksud only understands up to {CONFIG_VERSION}",
+            file.display()
+        );
+    }
+    let mut imported: Config = parse_config(&buf)
+        .with_context(|| format!("{} is not a valid umount config", file.display()))?;
+
+    for entry in &mut imported.paths {
+        entry.path = normalize_path(&entry.path)?;
+    }
+    let mut seen = HashSet::new();
+    for entry in &imported.paths {
+        if !seen.insert(entry.path.as_str()) {
+            bail!(
+                "{} appears more than once in {}",
+                entry.path,
+                file.display()
+            );
+        }
+    }
+
+    let _lock = ConfigLock::acquire(config_path())?;
+    let mut json_raw = read_config()?;
+    let count = imported.paths.len();
+    if replace {
+        let dropped: Vec<Entry> = json_raw
+            .paths
+            .iter()
+            .filter(|entry| !seen.contains(entry.path.as_str()))
+            .cloned()
+            .collect();
+        drop_from_kernel(&dropped);
+        json_raw.paths.clone_from(&imported.paths);
+        if let Err(e) = register(imported.paths, false) {
+            warn!("{e}");
+        }
+    } else {
+        // the kernel goes first, the config is left alone when it refuses an entry
+        register_import(&json_raw, &imported.paths)?;
+        for entry in imported.paths {
+            match json_raw.position(&entry.path) {
+                Some(index) => json_raw.paths[index] = entry,
+                None => json_raw.paths.push(entry),
+            }
+        }
+    }
+    write_config(&json_raw)?;
+    println!("Imported {count} umount entries from {}", file.display());
+    Ok(())
+}
+
+/// Register imported entries over the configured ones, all of them or none.
+///
+/// The kernel keeps the flags a path was first added with, so an entry registered with other
+/// flags or another condition is dropped and added again. When the kernel refuses an entry, the
+/// ones registered before it are dropped and whatever they replaced is put back.
+fn register_import(config: &Config, imported: &[Entry]) -> Result<()> {
+    let kernel: HashMap<String, u32> = ksucalls::umount_list_get()
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|info| (info.path, info.flags))
+        .collect();
+    let mut applied = read_applied();
+    let mut mount_points = None;
+    // what each replaced entry was registered as, None when it wasn't there
+    let mut replaced: Vec<(&Entry, Option<Entry>)> = Vec::new();
+    let mut failure = None;
+    for entry in registration_order(imported.iter().collect()) {
+        let previous = match config.position(&entry.path) {
+            Some(index) => Some(config.paths[index].clone()),
+            // registered by someone else, e.g. a module script
+            None => kernel.get(&entry.path).map(|&flags| Entry {
+                flags: UmountFlags(flags),
+                condition: None,
+                ..entry.clone()
+            }),
+        };
+        let result = match previous {
+            Some(previous)
+                if previous.flags == entry.flags && previous.condition == entry.condition =>
+            {
+                register_entry(entry, &mut mount_points, &mut applied)
+            }
+            previous => {
+                let result = match &previous {
+                    Some(previous) => unregister_entry(previous, &mut applied),
+                    None => Ok(()),
+                };
+                replaced.push((entry, previous));
+                result.and_then(|()| register_entry(entry, &mut mount_points, &mut applied))
+            }
+        };
+        if let Err(e) = result {
+            failure = Some(e);
+            break;
+        }
+    }
+
+    if let Some(e) = failure {
+        for (entry, previous) in replaced.into_iter().rev() {
+            let undo = unregister_entry(entry, &mut applied).and_then(|()| match &previous {
+                Some(previous) => register_entry(previous, &mut mount_points, &mut applied),
+                None => Ok(()),
+            });
+            if let Err(e) = undo {
+                warn!("failed to undo the import of {}: {e:#}", entry.path);
+            }
+        }
+        write_applied(&applied);
+        return Err(e);
+    }
+    write_applied(&applied);
+
+    let susfs_count = imported.iter().filter(|entry| entry.susfs).count();
+    if susfs_count > 0 {
+        hide_susfs_mounts(susfs_count);
+    }
+    Ok(())
+}
+
+/// Swap a backup made by `write_config` back in and sync the kernel list with it.
+pub fn restore_backup(index: usize) -> Result<()> {
+    if index >= BACKUP_COUNT {
+        bail!("only the last {BACKUP_COUNT} configs are kept, index must be below {BACKUP_COUNT}");
+    }
+    let backup = backup_path(index);
+    let buf = fs::read_to_string(&backup).with_context(|| format!("failed to read {backup}"))?;
+    let version = config_version(&buf);
+    if version > u64::from(CONFIG_VERSION) {
+        bail!(
+            "{backup} has config version {version}, but this ksud only understands up to {CONFIG_VERSION}"
+        );
+    }
+    let mut config: Config = parse_config(&buf)
+        .with_context(|| format!("{backup} is not a valid umount config, not restoring it"))?;
+    config.version = CONFIG_VERSION;
+
+    {
+        let _lock = ConfigLock::acquire(config_path())?;
+        write_config(&config)?;
+    }
+    println!(
+        "Restored {} umount entries from {backup}",
+        config.paths.len()
+    );
+    reload_umount()
+}
+
+/// Schema version a config file claims, 0 when it has none
+fn config_version(buf: &str) -> u64 {
+    parse_config::<serde_json::Value>(buf)
+        .ok()
+        .and_then(|config| config.get("version")?.as_u64())
+        .unwrap_or(0)
 }
 
 fn ensure_config() -> Result<()> {
-    let path = Path::new(defs::UMOUNT_CONFIG_PATH);
+    let path = Path::new(config_path());
 
     if !path.exists() {
-        let content = serde_json::to_string_pretty(&Config::default())?;
-        fs::write(path, content)?;
-        return Ok(());
+        if let Some(parent) = path.parent()
+            && !parent.exists()
+        {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(CONFIG_DIR_MODE)
+                .create(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        let config = Config {
+            paths: builtin_entries(),
+            ..Config::default()
+        };
+        info!(
+            "Creating umount config at {} with {} default entries",
+            path.display(),
+            config.paths.len()
+        );
+        return write_config(&config);
     }
 
     let buf = fs::read_to_string(path)?;
-    if buf.trim().is_empty() || serde_json::from_str::<Config>(&buf).is_err() {
-        let content = serde_json::to_string_pretty(&Config::default())?;
-        fs::write(path, content)?;
+    let version = config_version(&buf);
+    if version > u64::from(CONFIG_VERSION) {
+        bail!(
+            "{} has config version {version}, but this ksud only understands up to {CONFIG_VERSION}; \
+             update KernelSU or remove the file to start over",
+            path.display()
+        );
+    }
+    match parse_config::<Config>(&buf) {
+        Ok(mut config) if version < u64::from(CONFIG_VERSION) => {
+            let backup = format!("{}.v{version}.bak", config_path());
+            fs::copy(path, &backup).with_context(|| format!("failed to back up to {backup}"))?;
+            info!(
+                "Migrating {} from version {version} to {CONFIG_VERSION}, original saved as {backup}",
+                path.display()
+            );
+            config.version = CONFIG_VERSION;
+            write_config(&config)?;
+        }
+        Ok(_) => {}
+        // read_config reports it, the damaged file stays for the user to fix
+        Err(_) => {}
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    fn entry(path: &str) -> Entry {
+        Entry {
+            path: path.to_string(),
+            flags: UmountFlags::default(),
+            susfs: false,
+            builtin: false,
+            auto: false,
+            condition: None,
+            comment: None,
+            origin: None,
+            flags_auto: false,
+        }
+    }
+
+    /// Point the config at a scratch file holding `content`, other tests stay off it while the
+    /// guard is held
+    fn scratch_config(content: &str) -> std::sync::MutexGuard<'static, ()> {
+        static SERIAL: std::sync::Mutex<()> = std::sync::Mutex::new(());
+        static DIR: OnceLock<tempfile::TempDir> = OnceLock::new();
+        let guard = SERIAL
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let dir = DIR.get_or_init(|| tempfile::tempdir().unwrap());
+        CONFIG_PATH.get_or_init(|| {
+            dir.path()
+                .join("umount.json")
+                .to_string_lossy()
+                .into_owned()
+        });
+        for file in fs::read_dir(dir.path()).unwrap().flatten() {
+            fs::remove_file(file.path()).unwrap();
+        }
+        fs::write(config_path(), content).unwrap();
+        guard
+    }
+
+    /// Cut off in the middle of the second entry
+    const DAMAGED: &str = r#"{"version": 1, "paths": [{"path": "/system/app/Foo", "flags": "detach"}, {"path": "/vendor", "flags": "#;
+
+    /// The order the kernel unmounts in once `registered` was added one by one: `list_add` puts
+    /// every entry at the head of the list, and the list is walked from the head
+    fn kernel_unmount_order(registered: &[Target]) -> Vec<String> {
+        let mut list = VecDeque::new();
+        for (path, ..) in registered {
+            list.push_front(path.clone());
+        }
+        list.into_iter().collect()
+    }
+
+    #[test]
+    fn kernel_unmounts_in_config_order() {
+        let entries = vec![
+            entry("/system/app/Foo"),
+            entry("/system/app"),
+            entry("/vendor/bin/*"),
+            entry("/vendor"),
+        ];
+        let mut mount_points = Some(vec![
+            "/vendor/bin/a".to_string(),
+            "/vendor/bin/b".to_string(),
+        ]);
+        let (targets, _) = registration_targets(entries, &mut mount_points).unwrap();
+        assert_eq!(
+            kernel_unmount_order(&targets),
+            [
+                "/system/app/Foo",
+                "/system/app",
+                "/vendor/bin/a",
+                "/vendor/bin/b",
+                "/vendor"
+            ]
+        );
+    }
+
+    #[test]
+    fn damaged_config_is_salvaged_only_for_boot() {
+        let _config = scratch_config(DAMAGED);
+
+        let Err(error) = read_config() else {
+            panic!("a damaged config must not read as valid");
+        };
+        assert!(error.to_string().contains(config_path()));
+
+        let (config, salvaged) = read_config_salvaging().unwrap();
+        assert!(salvaged);
+        let paths: Vec<_> = config
+            .paths
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry.flags))
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                ("/system/app/Foo", UmountFlags(libc::MNT_DETACH as u32)),
+                ("/vendor", UmountFlags::default())
+            ]
+        );
+        assert!(Path::new(&format!("{}.recovered", config_path())).exists());
+        assert_eq!(fs::read_to_string(config_path()).unwrap(), DAMAGED);
+    }
+
+    #[test]
+    fn writers_leave_a_damaged_config_alone() {
+        let _config = scratch_config(DAMAGED);
+
+        assert!(move_umount("/vendor", 0).is_err());
+        assert!(restore_default_umount().is_err());
+        assert!(sync_modules(Some(true)).is_err());
+
+        assert_eq!(fs::read_to_string(config_path()).unwrap(), DAMAGED);
+        assert!(!Path::new(&backup_path(0)).exists());
+    }
+
+    const CONFIG: &str = r#"{"version": 1, "paths": [{"path": "/vendor", "flags": "detach"}]}"#;
+
+    fn configured_paths() -> Vec<String> {
+        read_config()
+            .unwrap()
+            .paths
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect()
+    }
+
+    #[test]
+    fn add_and_del_reach_the_kernel_and_the_file() {
+        let _config = scratch_config(CONFIG);
+        let _kernel = ksucalls::mock_kernel();
+
+        add_umount("/system/app/Foo", Some(UmountFlags(2)), false, None, None).unwrap();
+        assert_eq!(configured_paths(), ["/vendor", "/system/app/Foo"]);
+        del_umount("/vendor").unwrap();
+        assert_eq!(configured_paths(), ["/system/app/Foo"]);
+
+        let modes: Vec<u8> = ksucalls::MOCK
+            .calls()
+            .iter()
+            .map(|(_, payload)| payload[12])
+            .collect();
+        assert_eq!(
+            modes,
+            [uapi::KSU_UMOUNT_ADD_RUST, uapi::KSU_UMOUNT_DEL_RUST]
+        );
+    }
+
+    #[test]
+    fn kernel_refusal_leaves_the_file_alone() {
+        let _config = scratch_config(CONFIG);
+        let _kernel = ksucalls::mock_kernel();
+
+        ksucalls::MOCK.reply(Err(std::io::Error::from_raw_os_error(libc::ENOSPC)), vec![]);
+        assert!(add_umount("/system/app/Foo", Some(UmountFlags(2)), false, None, None).is_err());
+        ksucalls::MOCK.reply(Err(std::io::Error::from_raw_os_error(libc::EPERM)), vec![]);
+        assert!(del_umount("/vendor").is_err());
+
+        assert_eq!(fs::read_to_string(config_path()).unwrap(), CONFIG);
+    }
+
+    #[test]
+    fn version_0_map_is_migrated_with_a_backup() {
+        const V0: &str =
+            r#"{"paths": {"/system/app/Foo": 2, "/vendor": {"flags": 1, "susfs": true}}}"#;
+        let _config = scratch_config(V0);
+
+        let config = read_config().unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        let paths: Vec<_> = config
+            .paths
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry.flags, entry.susfs))
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                ("/system/app/Foo", UmountFlags(2), false),
+                ("/vendor", UmountFlags(1), true)
+            ]
+        );
+        let migrated = fs::read_to_string(config_path()).unwrap();
+        assert_eq!(config_version(&migrated), u64::from(CONFIG_VERSION));
+        assert_eq!(
+            fs::read_to_string(format!("{}.v0.bak", config_path())).unwrap(),
+            V0
+        );
+    }
+
+    #[test]
+    fn current_version_is_read_as_is() {
+        let _config = scratch_config(CONFIG);
+
+        assert_eq!(configured_paths(), ["/vendor"]);
+        assert_eq!(fs::read_to_string(config_path()).unwrap(), CONFIG);
+        assert!(!Path::new(&format!("{}.v1.bak", config_path())).exists());
+    }
+
+    #[test]
+    fn newer_version_is_refused() {
+        const FUTURE: &str =
+            r#"{"version": 99, "paths": [{"path": "/vendor", "flags": 0, "mode": "lazy"}]}"#;
+        let _config = scratch_config(FUTURE);
+
+        let Err(error) = read_config() else {
+            panic!("a config from a newer ksud must not be read");
+        };
+        assert!(error.to_string().contains("version 99"));
+        assert_eq!(fs::read_to_string(config_path()).unwrap(), FUTURE);
+    }
+
+    const COMMENTED: &str = r#"{
+        // kept out of the banking app
+        "version": 1,
+        "paths": [
+            /* its overlay */
+            {"path": "/system/app/Foo", "flags": "detach", "comment": "see https://example.org//foo"},
+            {"path": "/vendor", "flags": 0,},
+        ],
+    }"#;
+
+    fn kernel_umount_modes() -> Vec<u8> {
+        ksucalls::MOCK
+            .calls()
+            .iter()
+            .filter(|(request, _)| *request == uapi::KSU_IOCTL_MANAGE_TRY_UMOUNT_RUST)
+            .map(|(_, payload)| payload[12])
+            .collect()
+    }
+
+    #[test]
+    fn commented_config_is_loaded_as_is() {
+        let _config = scratch_config(COMMENTED);
+        let _kernel = ksucalls::mock_kernel();
+
+        load_umount_config(true).unwrap();
+
+        assert_eq!(kernel_umount_modes(), [uapi::KSU_UMOUNT_ADD_RUST; 2]);
+        assert_eq!(fs::read_to_string(config_path()).unwrap(), COMMENTED);
+    }
+
+    #[test]
+    fn commented_config_is_written_back_as_strict_json() {
+        let _config = scratch_config(COMMENTED);
+        let _kernel = ksucalls::mock_kernel();
+
+        add_umount("/odm", Some(UmountFlags(2)), false, None, None).unwrap();
+        del_umount("/vendor").unwrap();
+
+        let written = fs::read_to_string(config_path()).unwrap();
+        let config: Config = serde_json::from_str(&written).unwrap();
+        let paths: Vec<_> = config
+            .paths
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry.comment.as_deref()))
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                ("/system/app/Foo", Some("see https://example.org//foo")),
+                ("/odm", None)
+            ]
+        );
+    }
+
+    #[test]
+    fn concurrent_adds_keep_every_entry() {
+        const WRITERS: usize = 8;
+        let _config = scratch_config(CONFIG);
+        let _kernel = ksucalls::mock_kernel();
+
+        std::thread::scope(|scope| {
+            for i in 0..WRITERS {
+                scope.spawn(move || {
+                    let path = format!("/system/app/App{i}");
+                    add_umount(&path, Some(UmountFlags(2)), false, None, None).unwrap();
+                });
+            }
+        });
+
+        let paths = configured_paths();
+        assert_eq!(paths.len(), WRITERS + 1);
+        for i in 0..WRITERS {
+            assert!(paths.contains(&format!("/system/app/App{i}")));
+        }
+    }
+
+    #[test]
+    fn missing_config_is_created_at_the_configured_path() {
+        let _config = scratch_config(CONFIG);
+        let _kernel = ksucalls::mock_kernel();
+        fs::remove_file(config_path()).unwrap();
+
+        load_umount_config(true).unwrap();
+
+        let created: Config =
+            serde_json::from_str(&fs::read_to_string(config_path()).unwrap()).unwrap();
+        assert_eq!(created.version, CONFIG_VERSION);
+    }
+
+    #[test]
+    fn exported_config_imports_back_unchanged() {
+        const DETAILED: &str = r#"{"version": 1, "paths": [{"path": "/vendor", "flags": "detach", "condition": "non_root_only", "comment": "vendor overlay"}]}"#;
+        let _config = scratch_config(DETAILED);
+        let _kernel = ksucalls::mock_kernel();
+        let export_dir = tempfile::tempdir().unwrap();
+        let exported = export_dir.path().join("umount.json");
+
+        export_umount(&exported).unwrap();
+        fs::write(config_path(), r#"{"version": 1, "paths": []}"#).unwrap();
+        import_umount(&exported, false).unwrap();
+
+        let config = read_config().unwrap();
+        let paths: Vec<_> = config
+            .paths
+            .iter()
+            .map(|entry| {
+                (
+                    entry.path.as_str(),
+                    entry.flags,
+                    entry.condition.map(|c| c.to_string()),
+                    entry.comment.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            paths,
+            [(
+                "/vendor",
+                UmountFlags(libc::MNT_DETACH as u32),
+                Some("non_root_only".to_string()),
+                Some("vendor overlay")
+            )]
+        );
+    }
+
+    /// Write `content` to a file to import, kept until the returned dir is dropped
+    fn import_file(content: &str) -> (tempfile::TempDir, std::path::PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("import.json");
+        fs::write(&file, content).unwrap();
+        (dir, file)
+    }
+
+    #[test]
+    fn import_readds_entries_whose_flags_changed() {
+        let _config = scratch_config(CONFIG);
+        let _kernel = ksucalls::mock_kernel();
+        let (_dir, file) =
+            import_file(r#"{"version": 1, "paths": [{"path": "/vendor", "flags": "force"}]}"#);
+
+        import_umount(&file, false).unwrap();
+
+        assert_eq!(
+            kernel_umount_modes(),
+            [
+                uapi::KSU_UMOUNT_GETSIZE_NEW_RUST,
+                uapi::KSU_UMOUNT_DEL_RUST,
+                uapi::KSU_UMOUNT_ADD_RUST
+            ]
+        );
+        assert_eq!(read_config().unwrap().paths[0].flags, UmountFlags(1));
+    }
+
+    #[test]
+    fn refused_import_leaves_the_file_alone_and_undoes_the_kernel() {
+        let _config = scratch_config(CONFIG);
+        let _kernel = ksucalls::mock_kernel();
+        let (_dir, file) = import_file(
+            r#"{"version": 1, "paths": [{"path": "/vendor", "flags": "force"}, {"path": "/system/app/Foo", "flags": "detach"}]}"#,
+        );
+        // list query, add of Foo, drop of the old /vendor, then the new /vendor is refused
+        for _ in 0..3 {
+            ksucalls::MOCK.reply(Ok(0), vec![]);
+        }
+        ksucalls::MOCK.reply(Err(std::io::Error::from_raw_os_error(libc::ENOSPC)), vec![]);
+
+        assert!(import_umount(&file, false).is_err());
+
+        assert_eq!(
+            kernel_umount_modes(),
+            [
+                uapi::KSU_UMOUNT_GETSIZE_NEW_RUST,
+                uapi::KSU_UMOUNT_ADD_RUST,
+                uapi::KSU_UMOUNT_DEL_RUST,
+                uapi::KSU_UMOUNT_ADD_RUST,
+                // undo: the old /vendor goes back, Foo goes away
+                uapi::KSU_UMOUNT_DEL_RUST,
+                uapi::KSU_UMOUNT_ADD_RUST,
+                uapi::KSU_UMOUNT_DEL_RUST,
+            ]
+        );
+        assert_eq!(fs::read_to_string(config_path()).unwrap(), CONFIG);
+    }
+
+    fn app_targets(count: usize) -> Vec<Target> {
+        (0..count)
+            .map(|i| (format!("/system/app/App{i}"), UmountFlags(2), None))
+            .collect()
+    }
+
+    #[test]
+    fn batched_registration_cuts_kernel_calls() {
+        let _kernel = ksucalls::mock_kernel();
+        let targets = app_targets(150);
+
+        let (results, calls) = add_all_to_kernel(&targets);
+        assert!(results.iter().all(Result::is_ok));
+        let one_by_one = kernel_umount_modes();
+        assert_eq!(one_by_one, [uapi::KSU_UMOUNT_ADD_RUST; 150]);
+        assert_eq!(calls, one_by_one.len());
+
+        ksucalls::MOCK.reset();
+        let caps = uapi::KSU_CAP_UMOUNT_BATCH_RUST.to_ne_bytes().to_vec();
+        ksucalls::MOCK.reply(Ok(0), caps);
+        let (results, calls) = add_all_to_kernel(&targets);
+        assert_eq!(results.len(), targets.len());
+        assert!(results.iter().all(Result::is_ok));
+        let batched = kernel_umount_modes();
+        assert_eq!(batched, [uapi::KSU_UMOUNT_ADD_BATCH_RUST; 3]);
+        assert_eq!(calls, batched.len());
+    }
+
+    #[test]
+    fn rejected_batch_falls_back_to_one_by_one() {
+        let _kernel = ksucalls::mock_kernel();
+        let targets = app_targets(4);
+        let caps = uapi::KSU_CAP_UMOUNT_BATCH_RUST.to_ne_bytes().to_vec();
+        ksucalls::MOCK.reply(Ok(0), caps);
+        ksucalls::MOCK.reply(Err(std::io::Error::from_raw_os_error(libc::EINVAL)), vec![]);
+
+        let (results, calls) = add_all_to_kernel(&targets);
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(calls, 4);
+        let mut expected = vec![uapi::KSU_UMOUNT_ADD_BATCH_RUST];
+        expected.extend([uapi::KSU_UMOUNT_ADD_RUST; 4]);
+        assert_eq!(kernel_umount_modes(), expected);
+    }
+
+    #[test]
+    fn load_stops_quietly_without_a_kernel_umount_list() {
+        let _config = scratch_config(COMMENTED);
+        let _kernel = ksucalls::mock_kernel();
+        ksucalls::MOCK.reply(Ok(0), vec![]);
+        ksucalls::MOCK.reply(Err(std::io::Error::from_raw_os_error(libc::ENOTTY)), vec![]);
+
+        load_umount_config(true).unwrap();
+
+        assert_eq!(kernel_umount_modes(), [uapi::KSU_UMOUNT_ADD_RUST]);
+    }
+
+    #[test]
+    fn conditions_parse_and_print_back() {
+        let condition: Condition = " user_id = 10 &&non_root_only".parse().unwrap();
+        assert_eq!(condition.to_string(), "non_root_only && user_id = 10");
+        assert_eq!(
+            condition.kernel_cond(),
+            uapi::KSU_UMOUNT_COND_NON_ROOT_ONLY_RUST | uapi::KSU_UMOUNT_COND_USER_ID_RUST
+        );
+        assert!(condition.holds_at_registration());
+        assert_eq!(
+            condition.to_string().parse::<Condition>().unwrap(),
+            condition
+        );
+
+        assert!("non_root".parse::<Condition>().is_err());
+        assert!("user_id = owner".parse::<Condition>().is_err());
+    }
+
+    #[test]
+    fn conditional_entries_reach_the_kernel_with_their_condition() {
+        let _kernel = ksucalls::mock_kernel();
+        let mut conditional = entry("/vendor");
+        conditional.condition = Some("user_id = 10".parse().unwrap());
+
+        let (targets, _) = registration_targets(vec![conditional], &mut None).unwrap();
+        let (results, _) = add_all_to_kernel(&targets);
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(kernel_umount_modes(), [uapi::KSU_UMOUNT_ADD_EXT_RUST]);
+    }
+
+    #[test]
+    fn missing_counts_grow_and_reset() {
+        let dir = tempfile::tempdir().unwrap();
+        let present = dir.path().to_string_lossy().into_owned();
+        let mut builtin = entry("/ksu_test_missing/builtin");
+        builtin.builtin = true;
+        let paths = vec![
+            entry(&present),
+            entry("/ksu_test_missing/gone"),
+            entry("/ksu_test_missing/new"),
+            entry("/mnt/ksu_test/*"),
+            builtin,
+        ];
+        let previous = HashMap::from([
+            (present.clone(), 2),
+            ("/ksu_test_missing/gone".to_string(), 2),
+        ]);
+        let mount_points = ["/mnt/ksu_test/a".to_string()];
+
+        let missing = count_missing(&paths, &previous, &mount_points);
+
+        assert_eq!(
+            missing,
+            HashMap::from([("/ksu_test_missing/gone", 3), ("/ksu_test_missing/new", 1)])
+        );
+    }
+
+    #[test]
+    fn prune_only_takes_entries_missing_long_enough() {
+        let mut builtin = entry("/ksu_test_missing/builtin");
+        builtin.builtin = true;
+        let paths = vec![
+            entry("/ksu_test_missing/stale"),
+            entry("/ksu_test_missing/recent"),
+            entry("/ksu_test_missing/back"),
+            builtin,
+        ];
+        let missing = HashMap::from([
+            ("/ksu_test_missing/stale".to_string(), PRUNE_AFTER_BOOTS),
+            (
+                "/ksu_test_missing/recent".to_string(),
+                PRUNE_AFTER_BOOTS - 1,
+            ),
+            ("/ksu_test_missing/back".to_string(), PRUNE_AFTER_BOOTS),
+            ("/ksu_test_missing/builtin".to_string(), PRUNE_AFTER_BOOTS),
+        ]);
+        // mounted again since the last boot
+        let mount_points = ["/ksu_test_missing/back".to_string()];
+
+        let (stale, kept) = partition_stale(paths, &missing, &mount_points);
+
+        let stale: Vec<_> = stale.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(stale, ["/ksu_test_missing/stale"]);
+        assert_eq!(kept.len(), 3);
+    }
+}
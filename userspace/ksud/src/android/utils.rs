@@ -106,13 +106,27 @@ pub fn getprop(prop: &str) -> Option<String> {
     android_properties::getprop(prop).value()
 }
 
-pub fn is_safe_mode() -> bool {
-    let safemode = getprop("persist.sys.safemode")
+/// Whether Android itself boots into safe mode
+pub fn is_system_safe_mode() -> bool {
+    getprop("persist.sys.safemode")
         .as_ref()
         .is_some_and(|prop| prop == "1")
         || getprop("ro.sys.safemode")
             .as_ref()
-            .is_some_and(|prop| prop == "1");
+            .is_some_and(|prop| prop == "1")
+}
+
+/// Whether `ksud safemode enable-next-boot` put this boot into safe mode
+pub fn is_requested_safe_mode() -> bool {
+    Path::new(defs::SAFE_MODE_THIS_BOOT).exists()
+}
+
+pub fn is_safe_mode() -> bool {
+    if is_requested_safe_mode() {
+        log::warn!("safemode: requested by `ksud safemode enable-next-boot`");
+        return true;
+    }
+    let safemode = is_system_safe_mode();
     log::info!("safemode: {safemode}");
     if safemode {
         return true;
@@ -122,6 +136,34 @@ pub fn is_safe_mode() -> bool {
     safemode
 }
 
+/// Put this boot into safe mode when the previous one asked for it, must run before anything
+/// checks `is_safe_mode` at post-fs-data
+pub fn apply_safe_mode_request() {
+    let _ = remove_file(defs::SAFE_MODE_THIS_BOOT);
+    if !Path::new(defs::SAFE_MODE_NEXT_BOOT).exists() {
+        return;
+    }
+    match std::fs::rename(defs::SAFE_MODE_NEXT_BOOT, defs::SAFE_MODE_THIS_BOOT) {
+        std::result::Result::Ok(()) => log::warn!(
+            "safe mode requested for this boot, modules, umount and susfs configs are not loaded"
+        ),
+        Err(e) => log::error!("failed to apply the safe mode request: {e}"),
+    }
+}
+
+/// Request safe mode for the next boot only, or withdraw the request
+pub fn request_safe_mode_next_boot(enable: bool) -> Result<()> {
+    if enable {
+        ensure_dir_exists(defs::WORKING_DIR)?;
+        return ensure_file_exists(defs::SAFE_MODE_NEXT_BOOT);
+    }
+    match remove_file(defs::SAFE_MODE_NEXT_BOOT) {
+        Err(e) if e.kind() != NotFound => Err(Error::from(e))
+            .with_context(|| format!("failed to remove {}", defs::SAFE_MODE_NEXT_BOOT)),
+        _ => Ok(()),
+    }
+}
+
 pub fn get_zip_uncompressed_size(zip_path: &str) -> Result<u64> {
     let mut zip = zip::ZipArchive::new(std::fs::File::open(zip_path)?)?;
     let total: u64 = (0..zip.len())
@@ -65,7 +65,7 @@ mod android {
         }
     }
 
-    fn parse_kmi(version: &str) -> Result<String> {
+    pub fn parse_kmi(version: &str) -> Result<String> {
         let re = Regex::new(r"(.* )?(\d+\.\d+)(\S+)?(android\d+)(.*)")?;
         let cap = re
             .captures(version)
@@ -8,7 +8,12 @@ mod android {
     pub const BINARY_DIR: &str = concatcp!(WORKING_DIR, "bin/");
     pub const LIBRARY_DIR: &str = concatcp!(WORKING_DIR, "lib/");
     pub const LOG_DIR: &str = concatcp!(WORKING_DIR, "log/");
+    pub const MODULE_LOG_DIR: &str = concatcp!(LOG_DIR, "modules/");
+    pub const TEMP_DIR: &str = concatcp!(WORKING_DIR, "tmp/");
     pub const SULOGD_LOCK_PATH: &str = concatcp!(WORKING_DIR, "sulogd.lock");
+    pub const HEARTBEAT_LOCK_PATH: &str = concatcp!(WORKING_DIR, "heartbeat.lock");
+    pub const HEARTBEAT_STATUS_PATH: &str = concatcp!(WORKING_DIR, ".heartbeat");
+    pub const HEARTBEAT_HOOK_PATH: &str = concatcp!(WORKING_DIR, "heartbeat-fail.sh");
 
     pub const PROFILE_DIR: &str = concatcp!(WORKING_DIR, "profile/");
     pub const PROFILE_SELINUX_DIR: &str = concatcp!(PROFILE_DIR, "selinux/");
@@ -23,6 +28,8 @@ mod android {
     pub const MODULE_DIR: &str = concatcp!(ADB_DIR, "modules/");
     pub const MODULE_UPDATE_DIR: &str = concatcp!(ADB_DIR, "modules_update/");
     pub const METAMODULE_DIR: &str = concatcp!(ADB_DIR, "metamodule/");
+    pub const MODULE_KEYS_DIR: &str = concatcp!(WORKING_DIR, "keys/");
+    pub const MODULE_SNAPSHOT_DIR: &str = concatcp!(WORKING_DIR, "module_snapshots/");
 
     // Prefer /metadata/watchdog/ when present, else /metadata
     pub const PREINIT_DIR_WATCHDOG: &str = "/metadata/watchdog/ksu/";
@@ -55,15 +62,36 @@ mod android {
     pub const KSU_BACKUP_FILE_PREFIX: &str = "ksu_backup_";
     pub const BACKUP_FILENAME: &str = "stock_image.sha1";
     pub const UMOUNT_CONFIG_PATH: &str = concatcp!(WORKING_DIR, ".umount");
+    pub const UMOUNT_APPLIED_PATH: &str = concatcp!(WORKING_DIR, ".umount_applied");
+    pub const UMOUNT_MISSING_PATH: &str = concatcp!(WORKING_DIR, ".umount_missing");
+
+    pub const BOOT_STAGES_PATH: &str = concatcp!(WORKING_DIR, ".boot_stages");
+    pub const MODULE_MOUNT_STATUS_PATH: &str = concatcp!(WORKING_DIR, ".module_mount_status");
+    pub const MODULES_DISABLED_ALL_PATH: &str = concatcp!(WORKING_DIR, ".modules_disabled_all");
+    pub const SAFE_MODE_NEXT_BOOT: &str = concatcp!(WORKING_DIR, ".safemode_next_boot");
+    pub const SAFE_MODE_THIS_BOOT: &str = concatcp!(WORKING_DIR, ".safemode_this_boot");
 
     pub const DYNAMIC_MANAGER: &str = concatcp!(WORKING_DIR, ".dynamic_manager");
+    pub const ALLOWLIST_PATH: &str = concatcp!(WORKING_DIR, ".allowlist");
     pub const SUSFS_CONFIG: &str = concatcp!(WORKING_DIR, ".susfs.json");
+    pub const SUSFS_NO_AUTO_HIDE: &str = concatcp!(WORKING_DIR, ".no_auto_susfs");
+    pub const SUSFS_AUTO_HIDE_EXTRA: &str = concatcp!(WORKING_DIR, ".auto_susfs.json");
+    pub const SUSFS_KSTAT_SNAPSHOT: &str = concatcp!(WORKING_DIR, ".susfs_kstat_snapshot.json");
 
     #[derive(Serialize)]
     pub struct MountInfo {
         pub path: String,
         pub flags: u32,
     }
+
+    #[derive(Serialize)]
+    pub struct UmountStat {
+        pub path: String,
+        /// Times the kernel unmounted it for a process
+        pub hits: u32,
+        /// Uid of the last such process, None if never
+        pub last_uid: Option<u32>,
+    }
 }
 
 pub const VERSION_CODE: &str = include_str!(concat!(env!("OUT_DIR"), "/VERSION_CODE"));